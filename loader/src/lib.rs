@@ -2,6 +2,7 @@ use std::sync::Arc;
 use pyo3::prelude::*;
 use pyo3::exceptions::{PyKeyError, PyIndexError, PyValueError};
 use pyo3::{PyMappingProtocol, PyIterProtocol, wrap_pyfunction};
+use pyo3::types::PyDict;
 use numpy::ToPyArray;
 use pythonize::pythonize;
 use kilonova::app;
@@ -9,6 +10,7 @@ use kilonova::io;
 use kilonova::mesh;
 use kilonova::physics;
 use kilonova::products;
+use kilonova::profile;
 
 
 
@@ -38,6 +40,7 @@ struct RadialProfile {
 #[pyclass]
 struct BlockProducts {
     block_products: products::BlockProducts,
+    hydro: app::AnyHydro,
 }
 
 
@@ -66,6 +69,13 @@ impl App {
         Ok(pythonize(py, &self.app.tasks)?)
     }
 
+    /// A list of dicts of the global reductions (total mass, energy,
+    /// momentum, etc.) recorded each time a checkpoint was written
+    #[getter]
+    fn time_series(&self, py: Python) -> PyResult<PyObject> {
+        Ok(pythonize(py, &self.app.time_series)?)
+    }
+
     /// Generate a products::Products instance, from the app state, which
     /// contains geometric and primitive data to help in post-processing.
     fn make_products(&self) -> Products {
@@ -93,6 +103,21 @@ impl Products {
         Ok(pythonize(py, &self.products.config)?)
     }
 
+    /// A dict of the global totals (mass, energy, momentum, etc.) at the
+    /// time of these products
+    #[getter]
+    fn reductions(&self, py: Python) -> PyResult<PyObject> {
+        Ok(pythonize(py, &self.products.reductions)?)
+    }
+
+    /// A list of the tracer particles, each a dict with its id, mass,
+    /// position, and history. The history is a list of dicts with the time,
+    /// position, mass density, gas pressure, and scalar at the tracer.
+    #[getter]
+    fn tracers(&self, py: Python) -> PyResult<PyObject> {
+        Ok(pythonize(py, &self.products.tracers)?)
+    }
+
     /// A way to access radial profiles of the hydrodynamic data. In Python
     /// code, typing `products.radial_profile[10].scalar` would return a 1D
     /// numpy array of the scalar concentration for the zones at polar index
@@ -102,6 +127,90 @@ impl Products {
         RadialProfileGetter{products: self.products.clone()}
     }
 
+    /// The data of all the blocks concatenated into global arrays, as a dict
+    /// with the keys `radial_vertices` and `polar_vertices` (1D numpy arrays
+    /// of Nr + 1 and Nq + 1 vertices), and `comoving_mass_density`,
+    /// `gas_pressure`, `radial_four_velocity`, `polar_four_velocity`,
+    /// `scalar`, and `shock_flag` (2D numpy arrays of shape (Nr, Nq)).
+    #[getter]
+    fn stitched(&self, py: Python) -> PyResult<PyObject> {
+        let stitched = stitch(&self.products)?;
+        let dict = PyDict::new(py);
+
+        dict.set_item("radial_vertices", stitched.radial_vertices.to_pyarray(py))?;
+        dict.set_item("polar_vertices", stitched.polar_vertices.to_pyarray(py))?;
+        dict.set_item("comoving_mass_density", stitched.primitive.map(|p| p.mass_density).to_pyarray(py))?;
+        dict.set_item("gas_pressure", stitched.primitive.map(|p| p.gas_pressure).to_pyarray(py))?;
+        dict.set_item("radial_four_velocity", stitched.primitive.map(|p| p.velocity_r).to_pyarray(py))?;
+        dict.set_item("polar_four_velocity", stitched.primitive.map(|p| p.velocity_q).to_pyarray(py))?;
+        dict.set_item("scalar", stitched.scalar.to_pyarray(py))?;
+        dict.set_item("shock_flag", stitched.shock_flag.to_pyarray(py))?;
+        Ok(dict.to_object(py))
+    }
+
+    /// Return a polar profile of the hydrodynamic data at the given radius,
+    /// as a dict of 1D numpy arrays with the keys `theta` (the polar zone
+    /// centers), `comoving_mass_density`, `gas_pressure`,
+    /// `radial_four_velocity`, `polar_four_velocity`, and `scalar`. The
+    /// data are interpolated in log(r) between the two radial zones
+    /// straddling the radius, across block boundaries.
+    fn polar_profile(&self, py: Python, radius: f64) -> PyResult<PyObject> {
+        let fields = [
+            ("comoving_mass_density", profile::Field::Rho),
+            ("gas_pressure", profile::Field::Pre),
+            ("radial_four_velocity", profile::Field::Ur),
+            ("polar_four_velocity", profile::Field::Uq),
+            ("scalar", profile::Field::Scalar),
+        ];
+        let rows = profile::polar_profile(&self.products, radius, &fields.iter().map(|f| f.1).collect::<Vec<_>>());
+        let column = |n: usize| ndarray::Array::from(rows.iter().map(|row| row[n]).collect::<Vec<_>>());
+        let dict = PyDict::new(py);
+
+        dict.set_item("theta", column(0).to_pyarray(py))?;
+
+        for (n, (name, _)) in fields.iter().enumerate() {
+            dict.set_item(name, column(n + 1).to_pyarray(py))?;
+        }
+        Ok(dict.to_object(py))
+    }
+
+    /// Return a dict of the energy distributions: the isotropic-equivalent
+    /// energy `isotropic_energy` in `num_theta` uniform polar bins centered
+    /// at `theta`, and the kinetic energy `kinetic_energy_above` of the
+    /// material faster than each of the four-velocities `gamma_beta`.
+    /// Energies are in erg, and exclude the rest mass energy.
+    fn energy_distributions(&self, py: Python, num_theta: usize, gamma_beta: Vec<f64>) -> PyResult<PyObject> {
+        match self.products.energy_distributions(num_theta, &gamma_beta) {
+            Ok(distributions) => Ok(pythonize(py, &distributions)?),
+            Err(e) => Err(PyValueError::new_err(format!("{}", e))),
+        }
+    }
+
+    /// Return a dict of the cumulative mass distributions: the lab-frame
+    /// mass `mass_above[k][n]` of the material in each of `num_theta`
+    /// uniform polar bins centered at `theta[k]` which is faster than the
+    /// four-velocity `gamma_beta[n]` (or the velocity `velocity[n]` in
+    /// cm/s), and the total `total_mass_above[n]` over all bins. Masses are
+    /// in g.
+    fn mass_distributions(&self, py: Python, num_theta: usize, gamma_beta: Vec<f64>) -> PyResult<PyObject> {
+        match self.products.mass_distributions(num_theta, &gamma_beta) {
+            Ok(distributions) => Ok(pythonize(py, &distributions)?),
+            Err(e) => Err(PyValueError::new_err(format!("{}", e))),
+        }
+    }
+
+    /// Return a copy of these products with the velocity data boosted into
+    /// the frame of an observer moving along the polar axis with velocity
+    /// beta (in units of c). Zone coordinates and the time are not
+    /// transformed.
+    fn boosted(&self, beta: f64) -> PyResult<Products> {
+        if beta.abs() >= 1.0 {
+            Err(PyValueError::new_err("beta must be in (-1, 1)"))
+        } else {
+            Ok(Products{products: Arc::new(self.products.boosted(beta))})
+        }
+    }
+
     /// Write this products instance to a CBOR file on disk, with the given
     /// name.
     fn save(&self, filename: &str) -> PyResult<()> {
@@ -116,90 +225,72 @@ impl Products {
 
 
 // ============================================================================
+fn stitch(products: &products::Products) -> PyResult<products::StitchedProducts> {
+    products.stitched().map_err(|e| PyValueError::new_err(format!("{}", e)))
+}
+
 impl RadialProfile {
 
-    fn sorted_keys(&self) -> Vec<&(i32, usize)> {
-        let mut block_indexes: Vec<_> = self.products.blocks.keys().collect();
-        block_indexes.sort();
-        block_indexes
-    }
-
-    fn concat_vertices(&self) -> ndarray::Array<f64, ndarray::Ix1> {
-        let arrays: Vec<_> = self
-            .sorted_keys()
-            .iter()
-            .map(|i| self
-                .products
-                .blocks[i]
-                .radial_vertices
-                .slice(ndarray::s![..-1]))
-            .collect();
-        ndarray::concatenate(ndarray::Axis(0), &arrays).unwrap()
-    }
-
-    fn concat_scalar(&self) -> ndarray::Array<f64, ndarray::Ix1> {
-        let arrays: Vec<_> = self
-            .sorted_keys()
-            .iter()
-            .map(|i| self
-                .products
-                .blocks[i]
-                .scalar
-                .slice(ndarray::s![.., self.polar_index]))
-            .collect();
-        ndarray::concatenate(ndarray::Axis(0), &arrays).unwrap()
-    }
-
-    fn concat_map_primitive<F>(&self, f: F) -> ndarray::Array<f64, ndarray::Ix1>
+    fn stitched(&self) -> PyResult<products::StitchedProducts> {
+        let stitched = stitch(&self.products)?;
+
+        if self.polar_index >= stitched.primitive.ncols() {
+            Err(PyIndexError::new_err("invalid polar index"))
+        } else {
+            Ok(stitched)
+        }
+    }
+
+    fn concat_vertices(&self) -> PyResult<ndarray::Array<f64, ndarray::Ix1>> {
+        let stitched = self.stitched()?;
+        let n = stitched.radial_vertices.len();
+        Ok(stitched.radial_vertices.slice(ndarray::s![..n - 1]).to_owned())
+    }
+
+    fn concat_scalar(&self) -> PyResult<ndarray::Array<f64, ndarray::Ix1>> {
+        Ok(self.stitched()?.scalar.column(self.polar_index).to_owned())
+    }
+
+    fn concat_map_primitive<F>(&self, f: F) -> PyResult<ndarray::Array<f64, ndarray::Ix1>>
     where
         F: Fn(&physics::AnyPrimitive) -> f64
     {
-        let arrays: Vec<_> = self
-            .sorted_keys()
-            .iter()
-            .map(|i| self
-                .products
-                .blocks[i]
-                .primitive
-                .slice(ndarray::s![.., self.polar_index])
-                .map(&f))
-            .collect();
-        let arrays: Vec<_> = arrays.iter().map(|a| a.view()).collect();
-        ndarray::concatenate(ndarray::Axis(0), &arrays).unwrap()
+        Ok(self.stitched()?.primitive.column(self.polar_index).map(f))
     }
 }
 
 #[pymethods]
 impl RadialProfile {
 
+    /// The inner vertex of each radial zone
     #[getter]
-    fn vertices(&self, py: Python) -> PyObject {
-        self.concat_vertices().to_pyarray(py).to_object(py)
+    fn vertices(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_vertices()?.to_pyarray(py).to_object(py))
     }
 
     #[getter]
-    fn scalar(&self, py: Python) -> PyObject {
-        self.concat_scalar().to_pyarray(py).to_object(py)
+    fn scalar(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_scalar()?.to_pyarray(py).to_object(py))
     }
 
     #[getter]
-    fn radial_four_velocity(&self, py: Python) -> PyObject {
-        self.concat_map_primitive(|p| p.velocity_r).to_pyarray(py).to_object(py)
+    fn radial_four_velocity(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_map_primitive(|p| p.velocity_r)?.to_pyarray(py).to_object(py))
     }
 
     #[getter]
-    fn polar_four_velocity(&self, py: Python) -> PyObject {
-        self.concat_map_primitive(|p| p.velocity_q).to_pyarray(py).to_object(py)
+    fn polar_four_velocity(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_map_primitive(|p| p.velocity_q)?.to_pyarray(py).to_object(py))
     }
 
     #[getter]
-    fn comoving_mass_density(&self, py: Python) -> PyObject {
-        self.concat_map_primitive(|p| p.mass_density).to_pyarray(py).to_object(py)
+    fn comoving_mass_density(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_map_primitive(|p| p.mass_density)?.to_pyarray(py).to_object(py))
     }
 
     #[getter]
-    fn gas_pressure(&self, py: Python) -> PyObject {
-        self.concat_map_primitive(|p| p.gas_pressure).to_pyarray(py).to_object(py)
+    fn gas_pressure(&self, py: Python) -> PyResult<PyObject> {
+        Ok(self.concat_map_primitive(|p| p.gas_pressure)?.to_pyarray(py).to_object(py))
     }
 }
 
@@ -210,7 +301,7 @@ impl RadialProfile {
 #[pymethods]
 impl RadialProfileGetter {
     #[getter]
-    fn vertices(&self, py: Python) -> PyObject {
+    fn vertices(&self, py: Python) -> PyResult<PyObject> {
         (RadialProfile{products: self.products.clone(), polar_index: 0}).vertices(py)
     }
 }
@@ -241,7 +332,7 @@ impl PyMappingProtocol for Products {
 
     fn __getitem__(&self, key: mesh::BlockIndex) -> PyResult<BlockProducts> {
         if let Some(b) = self.products.blocks.get(&key) {
-            Ok(BlockProducts{block_products: b.clone()})
+            Ok(BlockProducts{block_products: b.clone(), hydro: self.products.config.hydro.clone()})
         } else {
             pyo3::Python::with_gil(|py| {
                 Err(PyErr::from_instance(PyKeyError::new_err("polar index is out of bounds").instance(py)))
@@ -313,6 +404,12 @@ impl BlockProducts {
         self.block_products.scalar.to_pyarray(py).to_object(py)
     }
 
+    /// Boolean array which is true in zones identified as shocked
+    #[getter]
+    fn shock_flag(&self, py: Python) -> PyObject {
+        self.block_products.shock_flag.to_pyarray(py).to_object(py)
+    }
+
     #[getter]
     fn radial_four_velocity(&self, py: Python) -> PyObject {
         self.map_primitive(|p| p.velocity_r).to_pyarray(py).to_object(py)
@@ -332,6 +429,48 @@ impl BlockProducts {
     fn gas_pressure(&self, py: Python) -> PyObject {
         self.map_primitive(|p| p.gas_pressure).to_pyarray(py).to_object(py)
     }
+
+    /// The Lorentz factor (1 for Newtonian hydro)
+    #[getter]
+    fn lorentz_factor(&self, py: Python) -> PyObject {
+        self.block_products.map_derived(&self.hydro, |d| d.lorentz_factor).to_pyarray(py).to_object(py)
+    }
+
+    /// The specific enthalpy e + p / rho (erg / g), excluding the rest mass
+    #[getter]
+    fn specific_enthalpy(&self, py: Python) -> PyObject {
+        self.block_products.map_derived(&self.hydro, |d| d.specific_enthalpy).to_pyarray(py).to_object(py)
+    }
+
+    /// The temperature, as kT / m = p / rho (erg / g)
+    #[getter]
+    fn temperature(&self, py: Python) -> PyObject {
+        self.block_products.map_derived(&self.hydro, |d| d.temperature).to_pyarray(py).to_object(py)
+    }
+
+    /// The specific internal energy (erg / g), from the equation of state
+    #[getter]
+    fn specific_internal_energy(&self, py: Python) -> PyObject {
+        self.block_products.map_derived(&self.hydro, |d| d.specific_internal_energy).to_pyarray(py).to_object(py)
+    }
+
+    /// The volume-integrated conserved quantities in each zone, as an array
+    /// of shape (ni, nj, 4) whose last axis is (lab-frame mass, radial
+    /// momentum, polar momentum, energy), or None if the products were not
+    /// written with `products_contents: {conserved: true}`
+    #[getter]
+    fn conserved(&self, py: Python) -> Option<PyObject> {
+        self.block_products.conserved.as_ref().map(|u| {
+            ndarray::Array::from_shape_fn((u.dim().0, u.dim().1, 4), |(i, j, k)| u[(i, j)][k]).to_pyarray(py).to_object(py)
+        })
+    }
+
+    /// The volume of each zone (cm^3), or None if the products were not
+    /// written with `products_contents: {cell_volumes: true}`
+    #[getter]
+    fn cell_volumes(&self, py: Python) -> Option<PyObject> {
+        self.block_products.cell_volumes.as_ref().map(|dv| dv.to_pyarray(py).to_object(py))
+    }
 }
 
 
@@ -346,9 +485,19 @@ fn app(filename: &str) -> PyResult<App> {
     }
 }
 
-#[pyfunction]
-fn products(filename: &str) -> PyResult<Products> {
-    match io::read_cbor(filename) {
+/// Load a products file. If `blocks` is given as a list of block indexes
+/// (i, j), only those blocks are loaded, and if `header_only` is true, no
+/// blocks are loaded, for queries of the time, configuration, and
+/// reductions of large files which use little memory. The whole file is
+/// still read, so these take about as long as loading all of it.
+#[pyfunction(blocks = "None", header_only = "false")]
+fn products(filename: &str, blocks: Option<Vec<mesh::BlockIndex>>, header_only: bool) -> PyResult<Products> {
+    let result = match (blocks, header_only) {
+        (_, true) => products::Products::read_selected(filename, &[]),
+        (Some(blocks), false) => products::Products::read_selected(filename, &blocks),
+        (None, false) => io::read_cbor(filename),
+    };
+    match result {
         Ok(products) => Ok(Products{products: Arc::new(products)}),
         Err(e)       => Err(PyValueError::new_err(format!("{}", e))),
     }
@@ -357,10 +506,30 @@ fn products(filename: &str) -> PyResult<Products> {
 
 
 
+/// Return the flux of (mass, radial momentum, polar momentum, energy)
+/// through a radial interface between the primitive states pl and pr, each
+/// given as (mass_density, velocity_r, velocity_q, gas_pressure). The solver
+/// is one of euler_hlle, srhd_hlle, or srhd_hllc, and gamma is the gamma-law
+/// index. For the srhd solvers, the velocities are four-velocities.
+#[pyfunction]
+fn riemann(pl: (f64, f64, f64, f64), pr: (f64, f64, f64, f64), solver: &str, gamma: f64) -> PyResult<(f64, f64, f64, f64)> {
+    let pl = physics::AnyPrimitive{mass_density: pl.0, velocity_r: pl.1, velocity_q: pl.2, gas_pressure: pl.3};
+    let pr = physics::AnyPrimitive{mass_density: pr.0, velocity_r: pr.1, velocity_q: pr.2, gas_pressure: pr.3};
+
+    match physics::riemann_flux(solver, gamma, &pl, &pr) {
+        Ok([mass, momentum_r, momentum_q, energy]) => Ok((mass, momentum_r, momentum_q, energy)),
+        Err(e)   => Err(PyValueError::new_err(format!("{}", e))),
+    }
+}
+
+
+
+
 // ============================================================================
 #[pymodule]
 fn knc_loader(_: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(app, m)?)?;
     m.add_function(wrap_pyfunction!(products, m)?)?;
+    m.add_function(wrap_pyfunction!(riemann, m)?)?;
     Ok(())
 }
@@ -3,6 +3,7 @@ pub static VERSION_AND_BUILD: &str = git_version::git_version!(prefix=concat!("v
 
 
 use std::{
+    collections::BTreeMap,
     ffi::OsStr,
     fs::{File, read_to_string},
     path::Path,
@@ -14,19 +15,35 @@ use serde::{
 use yaml_patch::Patch;
 
 
-use crate::mesh::Mesh;
+use crate::expression::Reduction;
+use crate::mesh::{BlockIndex, Mesh};
 use crate::models::{
     HaloKilonova,
     JetInCloud,
     JetInStar,
     WindShock,
     KineticBomb,
+    HotSwap,
+    Scripted,
+    TableModel,
+    StructuredJet,
+    MagnetarWind,
+    TwoComponentJet,
+    PrecessingJet,
+    Composite,
+    TwoComponentKilonova,
+    SupernovaEjecta,
+    Afterglow,
+    PulsarWindNebula,
+    RelativisticBomb,
 };
 use crate::physics::{
+    AnyConserved,
     AnyPrimitive,
     RelativisticHydro,
     NewtonianHydro,
 };
+use crate::reductions::{OpeningAngle, Reductions};
 use crate::state::State;
 use crate::traits::{
     Conserved,
@@ -34,6 +51,7 @@ use crate::traits::{
     InitialModel,
 };
 use crate::tasks::Tasks;
+use crate::tracers;
 use crate::io;
 
 
@@ -73,6 +91,19 @@ pub enum AnyModel {
     JetInStar(JetInStar),
     WindShock(WindShock),
     KineticBomb(KineticBomb),
+    HotSwap(HotSwap),
+    Scripted(Scripted),
+    TableModel(TableModel),
+    StructuredJet(StructuredJet),
+    MagnetarWind(MagnetarWind),
+    TwoComponentJet(TwoComponentJet),
+    PrecessingJet(PrecessingJet),
+    Composite(Composite),
+    TwoComponentKilonova(TwoComponentKilonova),
+    SupernovaEjecta(SupernovaEjecta),
+    Afterglow(Afterglow),
+    PulsarWindNebula(PulsarWindNebula),
+    RelativisticBomb(RelativisticBomb),
 }
 
 
@@ -127,21 +158,223 @@ pub struct Control {
     /// post-processing if needed.
     pub products_interval: Option<f64>,
 
+    /// Optional data included in products files in addition to the
+    /// primitive fields, e.g. `{conserved: true, cell_volumes: true}`.
+    /// Defaults to none.
+    #[serde(default)]
+    pub products_contents: ProductsContents,
+
+    /// Factor by which the zones are coarsened in each direction in the
+    /// products files written every `products_interval`, so that frequent
+    /// lightweight snapshots can be written for movies. Each coarse zone
+    /// is the volume-weighted average of the zones it covers. Defaults to
+    /// 1 (no coarsening).
+    #[serde(default = "Control::default_products_downsample")]
+    pub products_downsample: usize,
+
+    /// Range of radii (r_min, r_max) outside of which blocks are omitted
+    /// from products files, e.g. `[0.0, 1e12]`. Blocks which overlap the
+    /// range are included in full. The reductions are still computed over
+    /// the whole mesh. If omitted or nil, defaults to all blocks.
+    #[serde(default)]
+    pub products_radial_range: Option<(f64, f64)>,
+
+    /// The time between reports of the wall time spent updating each block,
+    /// broken down by phase of the update. Each report is printed as a
+    /// summary, and written per block to profile.NNNN.csv in the output
+    /// directory. If omitted or nil, defaults to no profiling reports.
+    #[serde(default)]
+    pub profile_interval: Option<f64>,
+
+    /// The time between appending the global totals (mass, energy, radial
+    /// momentum, and scalar mass) to time_series.csv in the output
+    /// directory, for monitoring conservation without writing checkpoints.
+    /// If omitted or nil, defaults to no time series output.
+    #[serde(default)]
+    pub time_series_interval: Option<f64>,
+
+    /// The time between appending the outermost shocked radius in each
+    /// polar angle bin to shock_radius.dat in the output directory, for
+    /// measuring blast wave trajectories and jet breakout times. If omitted
+    /// or nil, defaults to no shock radius output.
+    #[serde(default)]
+    pub shock_radius_interval: Option<f64>,
+
     /// The number of iterations between performing side-effects
     pub fold: usize,
 
-    /// Number of worker threads on the Tokio runtime. If omitted or nil,
+    /// Number of worker threads on the parallel runtime. If omitted or nil,
     /// defaults to 2x the number of physical cores.
     pub num_threads: Option<usize>,
 
-    /// Deprecated
+    /// The parallel runtime used to update the blocks: [tokio | rayon]. The
+    /// rayon runtime avoids spawning tasks on each Runge-Kutta stage, and is
+    /// easier to profile. Defaults to tokio.
+    #[serde(default)]
+    pub runtime: ParallelRuntime,
+
+    /// Pin each worker thread to its own CPU core, so that the operating
+    /// system does not migrate the workers between cores. Blocks are not
+    /// bound to a worker, so this does not keep their arrays in memory local
+    /// to the worker which updates them. Defaults to false.
+    #[serde(default)]
+    pub pin_threads: bool,
+
+    /// Write checkpoints on a background thread, so that the solver is not
+    /// stalled while large files are written. The solution arrays are
+    /// shared with the writer rather than copied, and at most one
+    /// checkpoint is written at a time. Defaults to false.
+    #[serde(default)]
+    pub async_output: bool,
+
+    /// Check after every fold that the time step did not exceed the signal
+    /// crossing time of any zone, and stop the run with a hydro error (and
+    /// a crash checkpoint) at the worst zone if it did. This converts every
+    /// block to primitive variables once more per fold, so it is meant for
+    /// testing new hydrodynamics or meshes. Defaults to false.
+    #[serde(default)]
+    pub check_cfl: bool,
+
+    /// Compress CBOR checkpoint and products files with zstd, at the
+    /// default level (3) unless `compression_level` is given. The name is
+    /// historical: snappy is no longer used. Compressed files are detected
+    /// when read, so they can be restarted from or loaded like any other.
+    /// Defaults to false.
     #[serde(default)]
     pub snappy_compression: bool,
 
+    /// The zstd compression level (1 to 22) of CBOR checkpoint and products
+    /// files. Giving a level enables compression. If omitted or nil,
+    /// defaults to no compression, or to level 3 if `snappy_compression` is
+    /// true.
+    #[serde(default)]
+    pub compression_level: Option<i32>,
+
+    /// The format of products files: [cbor | hdf5]. HDF5 files have one
+    /// group per block, and can be read by h5py, yt, and VisIt without the
+    /// custom loader. Checkpoints are always written as CBOR, which runs are
+    /// restarted from; with hdf5, a copy of each checkpoint is also written
+    /// as HDF5. HDF5 output requires the code to be built with the hdf5
+    /// feature. Defaults to cbor.
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
     /// The directory where data file will be output. If omitted or nil,
-    /// defaults to a the current directory.
+    /// defaults to a the current directory. This may also be an s3:// or
+    /// gs:// URI (if the code is built with the object-store feature), in
+    /// which case checkpoints, products, and config.yaml are uploaded to
+    /// object storage, with credentials from the environment. The files
+    /// which are appended to as the run goes (time series, probes, etc.)
+    /// are then written to the current directory, and uploaded with each
+    /// checkpoint.
     #[serde(default = "Control::default_output_directory")]
     pub output_directory: String,
+
+    /// Additional user-defined reductions, keyed by name, and recorded in
+    /// the time series and products. Each is an expression over the
+    /// primitive fields, e.g. `integral(rho * (u > 0.1))`.
+    #[serde(default)]
+    pub custom_reductions: BTreeMap<String, Reduction>,
+
+    /// Optional measurement of the jet or cocoon opening angle versus
+    /// radius, recorded in the time series and products, e.g.
+    /// `{field: scalar, threshold: 0.1}`.
+    #[serde(default)]
+    pub opening_angle: Option<OpeningAngle>,
+
+    /// Cuts on the four-velocity (gamma beta) for which the
+    /// isotropic-equivalent energy versus polar angle is recorded in the
+    /// time series and products, e.g. `[1.0, 3.0, 10.0]`.
+    #[serde(default)]
+    pub isotropic_energy_cuts: Vec<f64>,
+
+    /// Reduction of the time step applied after restarting with changed
+    /// hydrodynamics parameters (e.g. plm_theta, Riemann solver, or
+    /// Runge-Kutta order), which can otherwise crash the primitive variable
+    /// recovery at strong shocks.
+    #[serde(default)]
+    pub safety_ramp: SafetyRamp,
+
+    /// Fixed (r, theta) points at which the interpolated primitive fields
+    /// and scalar concentration are sampled after every iteration, and
+    /// appended to probes.dat in the output directory
+    #[serde(default)]
+    pub probes: Vec<(f64, f64)>,
+
+    /// Number of Lagrangian tracer particles seeded in the initial state,
+    /// each representing an equal share of its mass. Tracers move with the
+    /// fluid each iteration, and the density, pressure, and scalar at each
+    /// tracer are recorded every `fold` iterations. They are written to
+    /// checkpoints and products. Defaults to no tracers.
+    #[serde(default)]
+    pub num_tracers: usize,
+}
+
+
+
+
+/**
+ * The thread pool on which the blocks are updated
+ */
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParallelRuntime {
+    Tokio,
+    Rayon,
+}
+
+
+
+
+/**
+ * The format of products files, and of the optional copy of each checkpoint
+ */
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Cbor,
+    Hdf5,
+}
+
+
+
+
+/**
+ * A time step reduction which is ramped away over a number of iterations
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SafetyRamp {
+
+    /// Number of iterations over which the time step is ramped up to its
+    /// full (CFL-limited) value
+    pub iterations: usize,
+
+    /// Fraction of the full time step at the start of the ramp
+    pub initial_factor: f64,
+}
+
+
+
+
+/**
+ * Optional per-zone data included in products, in addition to the
+ * primitive fields. Together, the conserved quantities and the cell volumes
+ * allow exact mass and energy integrals in post-processing.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProductsContents {
+
+    /// Include the volume-integrated conserved quantities in each zone:
+    /// (lab-frame mass, radial momentum, polar momentum, energy), in the
+    /// code units of the reductions
+    #[serde(default)]
+    pub conserved: bool,
+
+    /// Include the volume (cm^3) of each zone
+    #[serde(default)]
+    pub cell_volumes: bool,
 }
 
 impl Control {
@@ -154,6 +387,72 @@ impl Control {
     fn default_output_directory() -> String {
         ".".into()
     }
+    fn default_products_downsample() -> usize {
+        1
+    }
+
+    /**
+     * Return the directory of the files which are appended to as the run
+     * goes: the output directory, or the current directory if the output
+     * directory is in object storage, in which case they are uploaded there
+     * with each checkpoint.
+     */
+    pub fn log_directory(&self) -> &str {
+        if io::is_remote(&self.output_directory) {
+            "."
+        } else {
+            &self.output_directory
+        }
+    }
+
+    /**
+     * Return the zstd compression level of CBOR output files, or None if
+     * they are not compressed.
+     */
+    pub fn compression_level(&self) -> Option<i32> {
+        match (self.compression_level, self.snappy_compression) {
+            (Some(level), _) => Some(level),
+            (None, true) => Some(io::DEFAULT_COMPRESSION_LEVEL),
+            (None, false) => None,
+        }
+    }
+}
+
+impl Default for ParallelRuntime {
+    fn default() -> Self {
+        Self::Tokio
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self::Cbor
+    }
+}
+
+impl Default for SafetyRamp {
+    fn default() -> Self {
+        Self {
+            iterations: 100,
+            initial_factor: 0.1,
+        }
+    }
+}
+
+impl SafetyRamp {
+
+    /**
+     * Return the factor by which the time step is reduced at the given
+     * iteration of a ramp with the given total length. The factor increases
+     * linearly from `initial_factor` to 1.
+     */
+    pub fn dt_factor(&self, iteration: usize, iterations: usize) -> f64 {
+        if iteration >= iterations {
+            1.0
+        } else {
+            self.initial_factor + (1.0 - self.initial_factor) * iteration as f64 / iterations as f64
+        }
+    }
 }
 
 
@@ -166,7 +465,18 @@ impl Control {
 #[serde(deny_unknown_fields)]
 pub struct Configuration {
     pub hydro: AnyHydro,
+
+    /// The model which gives the initial condition at the start time
     pub model: AnyModel,
+
+    /// Optional model which, if given, replaces the model once the initial
+    /// condition is generated: it supplies the boundary data, the new
+    /// blocks added at the moving excision surfaces, and the source terms.
+    /// Checkpoints record it as the model, since the initial condition is
+    /// then contained in the solution state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boundary_model: Option<AnyModel>,
+
     pub mesh: Mesh,
     pub control: Control,
 }
@@ -183,6 +493,36 @@ pub struct App {
     pub tasks: Tasks,
     pub config: Configuration,
     pub version: String,
+
+    /// Reductions recorded each time a checkpoint was written
+    #[serde(default)]
+    pub time_series: Vec<Reductions>,
+
+    /// Details of the error which stopped the run, if this is a crash
+    /// checkpoint
+    #[serde(default)]
+    pub crash: Option<Crash>,
+}
+
+
+
+
+/**
+ * Details of an error which stopped a run, recorded in the crash checkpoint
+ * along with the last state known to be good
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Crash {
+
+    /// The error message
+    pub message: String,
+
+    /// The (r, theta) position of the zone where a hydro error occurred
+    pub position: Option<(f64, f64)>,
+
+    /// The index of the block containing that zone, and of the zone within
+    /// the block, on the mesh of the last good state
+    pub zone: Option<(BlockIndex, (usize, usize))>,
 }
 
 
@@ -209,6 +549,37 @@ impl Control {
         if self.products_interval.unwrap_or(0.0) < 0.0 {
             anyhow::bail!("products_interval <= 0.0")
         }
+        if self.products_downsample == 0 {
+            anyhow::bail!("products_downsample must be >= 1")
+        }
+        if let Some((r0, r1)) = self.products_radial_range {
+            if r0 >= r1 {
+                anyhow::bail!("products_radial_range must be increasing")
+            }
+        }
+        if self.profile_interval.unwrap_or(1.0) <= 0.0 {
+            anyhow::bail!("profile_interval <= 0.0")
+        }
+        if self.time_series_interval.unwrap_or(1.0) <= 0.0 {
+            anyhow::bail!("time_series_interval <= 0.0")
+        }
+        if self.shock_radius_interval.unwrap_or(1.0) <= 0.0 {
+            anyhow::bail!("shock_radius_interval <= 0.0")
+        }
+        if let Some(level) = self.compression_level {
+            if !(1..=22).contains(&level) {
+                anyhow::bail!("compression_level must be in 1..=22")
+            }
+        }
+        if io::is_remote(&self.output_directory) && !cfg!(feature = "object-store") {
+            anyhow::bail!("an s3:// or gs:// output_directory requires the code to be built with --features object-store")
+        }
+        if self.output_format == OutputFormat::Hdf5 && !cfg!(feature = "hdf5") {
+            anyhow::bail!("output_format: hdf5 requires the code to be built with --features hdf5")
+        }
+        if self.safety_ramp.initial_factor <= 0.0 || self.safety_ramp.initial_factor > 1.0 {
+            anyhow::bail!("safety_ramp.initial_factor must be in (0, 1]")
+        }
         Ok(())
     }
 }
@@ -226,6 +597,19 @@ impl InitialModel for AnyModel {
             AnyModel::JetInStar(m)    => m.validate(),
             AnyModel::WindShock(m)    => m.validate(),
             AnyModel::KineticBomb(m) => m.validate(),
+            AnyModel::HotSwap(m)      => m.validate(),
+            AnyModel::Scripted(m)     => m.validate(),
+            AnyModel::TableModel(m)   => m.validate(),
+            AnyModel::StructuredJet(m) => m.validate(),
+            AnyModel::MagnetarWind(m)  => m.validate(),
+            AnyModel::TwoComponentJet(m) => m.validate(),
+            AnyModel::PrecessingJet(m)   => m.validate(),
+            AnyModel::Composite(m)       => m.validate(),
+            AnyModel::TwoComponentKilonova(m) => m.validate(),
+            AnyModel::SupernovaEjecta(m)      => m.validate(),
+            AnyModel::Afterglow(m)            => m.validate(),
+            AnyModel::PulsarWindNebula(m)     => m.validate(),
+            AnyModel::RelativisticBomb(m)     => m.validate(),
         }
     }
 
@@ -236,6 +620,19 @@ impl InitialModel for AnyModel {
             AnyModel::JetInStar(m)    => m.primitive_at(coordinate, time),
             AnyModel::WindShock(m)    => m.primitive_at(coordinate, time),
             AnyModel::KineticBomb(m)  => m.primitive_at(coordinate, time),
+            AnyModel::HotSwap(m)      => m.primitive_at(coordinate, time),
+            AnyModel::Scripted(m)     => m.primitive_at(coordinate, time),
+            AnyModel::TableModel(m)   => m.primitive_at(coordinate, time),
+            AnyModel::StructuredJet(m) => m.primitive_at(coordinate, time),
+            AnyModel::MagnetarWind(m)  => m.primitive_at(coordinate, time),
+            AnyModel::TwoComponentJet(m) => m.primitive_at(coordinate, time),
+            AnyModel::PrecessingJet(m)   => m.primitive_at(coordinate, time),
+            AnyModel::Composite(m)       => m.primitive_at(coordinate, time),
+            AnyModel::TwoComponentKilonova(m) => m.primitive_at(coordinate, time),
+            AnyModel::SupernovaEjecta(m)      => m.primitive_at(coordinate, time),
+            AnyModel::Afterglow(m)            => m.primitive_at(coordinate, time),
+            AnyModel::PulsarWindNebula(m)     => m.primitive_at(coordinate, time),
+            AnyModel::RelativisticBomb(m)     => m.primitive_at(coordinate, time),
         } 
     }
 
@@ -246,6 +643,111 @@ impl InitialModel for AnyModel {
             AnyModel::JetInStar(m)    => m.scalar_at(coordinate, time),
             AnyModel::WindShock(m)    => m.scalar_at(coordinate, time),
             AnyModel::KineticBomb(m)  => m.scalar_at(coordinate, time),
+            AnyModel::HotSwap(m)      => m.scalar_at(coordinate, time),
+            AnyModel::Scripted(m)     => m.scalar_at(coordinate, time),
+            AnyModel::TableModel(m)   => m.scalar_at(coordinate, time),
+            AnyModel::StructuredJet(m) => m.scalar_at(coordinate, time),
+            AnyModel::MagnetarWind(m)  => m.scalar_at(coordinate, time),
+            AnyModel::TwoComponentJet(m) => m.scalar_at(coordinate, time),
+            AnyModel::PrecessingJet(m)   => m.scalar_at(coordinate, time),
+            AnyModel::Composite(m)       => m.scalar_at(coordinate, time),
+            AnyModel::TwoComponentKilonova(m) => m.scalar_at(coordinate, time),
+            AnyModel::SupernovaEjecta(m)      => m.scalar_at(coordinate, time),
+            AnyModel::Afterglow(m)            => m.scalar_at(coordinate, time),
+            AnyModel::PulsarWindNebula(m)     => m.scalar_at(coordinate, time),
+            AnyModel::RelativisticBomb(m)     => m.scalar_at(coordinate, time),
+        }
+    }
+
+    fn scalar_injection_at(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        match self {
+            AnyModel::HaloKilonova(m) => m.scalar_injection_at(coordinate, time),
+            AnyModel::JetInCloud(m)   => m.scalar_injection_at(coordinate, time),
+            AnyModel::JetInStar(m)    => m.scalar_injection_at(coordinate, time),
+            AnyModel::WindShock(m)    => m.scalar_injection_at(coordinate, time),
+            AnyModel::KineticBomb(m)  => m.scalar_injection_at(coordinate, time),
+            AnyModel::HotSwap(m)      => m.scalar_injection_at(coordinate, time),
+            AnyModel::Scripted(m)     => m.scalar_injection_at(coordinate, time),
+            AnyModel::TableModel(m)   => m.scalar_injection_at(coordinate, time),
+            AnyModel::StructuredJet(m) => m.scalar_injection_at(coordinate, time),
+            AnyModel::MagnetarWind(m)  => m.scalar_injection_at(coordinate, time),
+            AnyModel::TwoComponentJet(m) => m.scalar_injection_at(coordinate, time),
+            AnyModel::PrecessingJet(m)   => m.scalar_injection_at(coordinate, time),
+            AnyModel::Composite(m)       => m.scalar_injection_at(coordinate, time),
+            AnyModel::TwoComponentKilonova(m) => m.scalar_injection_at(coordinate, time),
+            AnyModel::SupernovaEjecta(m)      => m.scalar_injection_at(coordinate, time),
+            AnyModel::Afterglow(m)            => m.scalar_injection_at(coordinate, time),
+            AnyModel::PulsarWindNebula(m)     => m.scalar_injection_at(coordinate, time),
+            AnyModel::RelativisticBomb(m)     => m.scalar_injection_at(coordinate, time),
+        }
+    }
+
+    fn conserved_source_at(&self, coordinate: (f64, f64), time: f64) -> AnyConserved {
+        match self {
+            AnyModel::HaloKilonova(m) => m.conserved_source_at(coordinate, time),
+            AnyModel::JetInCloud(m)   => m.conserved_source_at(coordinate, time),
+            AnyModel::JetInStar(m)    => m.conserved_source_at(coordinate, time),
+            AnyModel::WindShock(m)    => m.conserved_source_at(coordinate, time),
+            AnyModel::KineticBomb(m)  => m.conserved_source_at(coordinate, time),
+            AnyModel::HotSwap(m)      => m.conserved_source_at(coordinate, time),
+            AnyModel::Scripted(m)     => m.conserved_source_at(coordinate, time),
+            AnyModel::TableModel(m)   => m.conserved_source_at(coordinate, time),
+            AnyModel::StructuredJet(m) => m.conserved_source_at(coordinate, time),
+            AnyModel::MagnetarWind(m)  => m.conserved_source_at(coordinate, time),
+            AnyModel::TwoComponentJet(m) => m.conserved_source_at(coordinate, time),
+            AnyModel::PrecessingJet(m)   => m.conserved_source_at(coordinate, time),
+            AnyModel::Composite(m)       => m.conserved_source_at(coordinate, time),
+            AnyModel::TwoComponentKilonova(m) => m.conserved_source_at(coordinate, time),
+            AnyModel::SupernovaEjecta(m)      => m.conserved_source_at(coordinate, time),
+            AnyModel::Afterglow(m)            => m.conserved_source_at(coordinate, time),
+            AnyModel::PulsarWindNebula(m)     => m.conserved_source_at(coordinate, time),
+            AnyModel::RelativisticBomb(m)     => m.conserved_source_at(coordinate, time),
+        }
+    }
+
+    fn regulate(&mut self, measured: &Reductions) {
+        match self {
+            AnyModel::HaloKilonova(m) => m.regulate(measured),
+            AnyModel::JetInCloud(m)   => m.regulate(measured),
+            AnyModel::JetInStar(m)    => m.regulate(measured),
+            AnyModel::WindShock(m)    => m.regulate(measured),
+            AnyModel::KineticBomb(m)  => m.regulate(measured),
+            AnyModel::HotSwap(m)      => m.regulate(measured),
+            AnyModel::Scripted(m)     => m.regulate(measured),
+            AnyModel::TableModel(m)   => m.regulate(measured),
+            AnyModel::StructuredJet(m) => m.regulate(measured),
+            AnyModel::MagnetarWind(m)  => m.regulate(measured),
+            AnyModel::TwoComponentJet(m) => m.regulate(measured),
+            AnyModel::PrecessingJet(m)   => m.regulate(measured),
+            AnyModel::Composite(m)       => m.regulate(measured),
+            AnyModel::TwoComponentKilonova(m) => m.regulate(measured),
+            AnyModel::SupernovaEjecta(m)      => m.regulate(measured),
+            AnyModel::Afterglow(m)            => m.regulate(measured),
+            AnyModel::PulsarWindNebula(m)     => m.regulate(measured),
+            AnyModel::RelativisticBomb(m)     => m.regulate(measured),
+        }
+    }
+
+    fn needs_regulation(&self) -> bool {
+        match self {
+            AnyModel::HaloKilonova(m) => m.needs_regulation(),
+            AnyModel::JetInCloud(m)   => m.needs_regulation(),
+            AnyModel::JetInStar(m)    => m.needs_regulation(),
+            AnyModel::WindShock(m)    => m.needs_regulation(),
+            AnyModel::KineticBomb(m)  => m.needs_regulation(),
+            AnyModel::HotSwap(m)      => m.needs_regulation(),
+            AnyModel::Scripted(m)     => m.needs_regulation(),
+            AnyModel::TableModel(m)   => m.needs_regulation(),
+            AnyModel::StructuredJet(m) => m.needs_regulation(),
+            AnyModel::MagnetarWind(m)  => m.needs_regulation(),
+            AnyModel::TwoComponentJet(m) => m.needs_regulation(),
+            AnyModel::PrecessingJet(m)   => m.needs_regulation(),
+            AnyModel::Composite(m)       => m.needs_regulation(),
+            AnyModel::TwoComponentKilonova(m) => m.needs_regulation(),
+            AnyModel::SupernovaEjecta(m)      => m.needs_regulation(),
+            AnyModel::Afterglow(m)            => m.needs_regulation(),
+            AnyModel::PulsarWindNebula(m)     => m.needs_regulation(),
+            AnyModel::RelativisticBomb(m)     => m.needs_regulation(),
         }
     }
 }
@@ -265,6 +767,7 @@ impl Configuration {
         Configuration {
             hydro: hydro.clone().into(),
             model: model.clone().into(),
+            boundary_model: None,
             mesh: mesh.clone(),
             control: control.clone(),
         }
@@ -273,11 +776,24 @@ impl Configuration {
     pub fn validate(&self) -> anyhow::Result<()> {
         self.hydro.validate()?;
         self.model.validate()?;
+
+        if let Some(boundary_model) = &self.boundary_model {
+            boundary_model.validate()?;
+        }
         self.mesh.validate(self.control.start_time)?;
         self.control.validate()?;
         Ok(())
     }
 
+    /**
+     * Split this config into the hydro, the model which drives the run after
+     * the initial condition (the boundary model if one is given), the mesh,
+     * and the control.
+     */
+    pub fn into_run_parts(self) -> (AnyHydro, AnyModel, Mesh, Control) {
+        (self.hydro, self.boundary_model.unwrap_or(self.model), self.mesh, self.control)
+    }
+
     /**
      * Patch this config struct with inputs from the command line. The inputs
      * can be names of YAML files or key=value pairs.
@@ -309,6 +825,23 @@ impl App {
         Ok(self)
     }
 
+    /**
+     * Load the configuration of a preset or a config.yaml file, without
+     * generating the solution state, and patch it with inputs from the
+     * command line.
+     */
+    pub fn config_from_preset_or_file(input: &str, overrides: Vec<String>) -> Result<Configuration, Error> {
+        let mut config: Configuration = match Self::presets().into_iter().find(|(key, _)| *key == input) {
+            Some((_, yaml)) => serde_yaml::from_str(yaml)?,
+            None => match Path::new(input).extension().and_then(OsStr::to_str) {
+                Some("yaml") => serde_yaml::from_str(&read_to_string(input)?)?,
+                _ => return Err(Error::UnknownInputType(input.to_string())),
+            },
+        };
+        config.patch_from(overrides)?;
+        Ok(config)
+    }
+
     /**
      * Construct a new App instance from a user configuration.
      */
@@ -319,14 +852,19 @@ impl App {
         let geometry = config.mesh.grid_blocks_geometry(config.control.start_time);
         let state = match &config.hydro {
             AnyHydro::Newtonian(hydro) => {
-                State::from_model(&config.model, hydro, &geometry, config.control.start_time).into()
+                let mut state = State::from_model(&config.model, hydro, &geometry, config.control.start_time, config.mesh.quadrature_points);
+                state.tracers = tracers::seed(&state, &geometry, config.control.num_tracers);
+                state.into()
             },
             AnyHydro::Relativistic(hydro) => {
-                State::from_model(&config.model, hydro, &geometry, config.control.start_time).into()
+                let mut state = State::from_model(&config.model, hydro, &geometry, config.control.start_time, config.mesh.quadrature_points);
+                state.tracers = tracers::seed(&state, &geometry, config.control.num_tracers);
+                state.into()
             },
         };
         let tasks = Tasks::new(config.control.start_time);
-        Ok(Self{state, tasks, config, version: VERSION_AND_BUILD.to_string()})
+        let time_series = Vec::new();
+        Ok(Self{state, tasks, config, version: VERSION_AND_BUILD.to_string(), time_series, crash: None})
     }
 
     /**
@@ -362,10 +900,48 @@ impl App {
         Self::from_file(input, overrides)
     }
 
+    /**
+     * Return this app with its solution state conservatively remapped onto
+     * a new mesh (see [`State::remap`]), and with the new mesh in its
+     * configuration. This allows e.g. resolution studies to branch from an
+     * existing run. Fails if the new mesh has a different coordinate system.
+     */
+    pub fn remap(self, new_mesh: Mesh) -> anyhow::Result<Self> {
+        if new_mesh.geometry != self.config.mesh.geometry {
+            anyhow::bail!("cannot remap between different mesh geometries")
+        }
+        let Configuration{hydro, model, boundary_model, mesh, control} = self.config;
+        let driving_model = boundary_model.as_ref().unwrap_or(&model);
+        let state = match (&self.state, &hydro) {
+            (AnyState::Newtonian(state), AnyHydro::Newtonian(hydro)) => {
+                state.remap(driving_model, hydro, &mesh, &new_mesh).into()
+            },
+            (AnyState::Relativistic(state), AnyHydro::Relativistic(hydro)) => {
+                state.remap(driving_model, hydro, &mesh, &new_mesh).into()
+            },
+            _ => unreachable!(),
+        };
+        Ok(Self {
+            state,
+            tasks: self.tasks,
+            config: Configuration{hydro, model, boundary_model, mesh: new_mesh, control},
+            version: self.version,
+            time_series: self.time_series,
+            crash: self.crash,
+        })
+    }
+
     /**
      * Construct a new App instance from references to the member variables.
      */
-    pub fn package<H, M, C>(state: &State<C>, tasks: &Tasks, hydro: &H, model: &M, mesh: &Mesh, control: &Control) -> Self
+    pub fn package<H, M, C>(
+        state: &State<C>,
+        tasks: &Tasks,
+        time_series: &[Reductions],
+        hydro: &H,
+        model: &M,
+        mesh: &Mesh,
+        control: &Control) -> Self
     where
         H: Hydrodynamics<Conserved = C>,
         M: InitialModel,
@@ -379,6 +955,8 @@ impl App {
             tasks: tasks.clone(),
             config: Configuration::package(hydro, model, mesh, control),
             version: VERSION_AND_BUILD.to_string(),
+            time_series: time_series.to_vec(),
+            crash: None,
         }
     }
 
@@ -389,6 +967,16 @@ impl App {
             ("halo_kilonova", include_str!("../setups/halo_kilonova.yaml")),
             ("wind_shock", include_str!("../setups/wind_shock.yaml")),
             ("kinetic_bomb", include_str!("../setups/kinetic_bomb.yaml")),
+            ("scripted", include_str!("../setups/scripted.yaml")),
+            ("structured_jet", include_str!("../setups/structured_jet.yaml")),
+            ("magnetar_wind", include_str!("../setups/magnetar_wind.yaml")),
+            ("two_component_jet", include_str!("../setups/two_component_jet.yaml")),
+            ("precessing_jet", include_str!("../setups/precessing_jet.yaml")),
+            ("two_component_kilonova", include_str!("../setups/two_component_kilonova.yaml")),
+            ("supernova_ejecta", include_str!("../setups/supernova_ejecta.yaml")),
+            ("afterglow", include_str!("../setups/afterglow.yaml")),
+            ("pulsar_wind_nebula", include_str!("../setups/pulsar_wind_nebula.yaml")),
+            ("relativistic_bomb", include_str!("../setups/relativistic_bomb.yaml")),
         ]
     }
 }
@@ -0,0 +1,223 @@
+use ndarray::{Array, ArcArray, ArrayView, ArrayViewMut, Axis, Ix2};
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, Direction};
+use crate::traits::{Hydrodynamics, Primitive};
+
+
+
+
+/**
+ * A fixed primitive state and scalar concentration, to be imposed in the
+ * ghost zones of a radial boundary
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedState {
+
+    /// The primitive state: [velocity_r, velocity_q, mass_density, gas_pressure]
+    pub primitive: AnyPrimitive,
+
+    /// The scalar concentration
+    #[serde(default)]
+    pub scalar: f64,
+}
+
+
+
+
+/**
+ * Boundary condition at the inner or outer radial edge of the mesh
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RadialBoundary {
+
+    /// Ghost zones are generated from the initial model at the current time
+    InflowFromModel,
+
+    /// Ghost zones are copies of the zones on the edge of the mesh
+    Outflow,
+
+    /// Ghost zones mirror the zones on the edge of the mesh, with the radial
+    /// velocity reversed
+    Reflecting,
+
+    /// Ghost zones have a fixed primitive state and scalar concentration
+    Fixed(FixedState),
+}
+
+
+
+
+/**
+ * Boundary condition at the polar edges of the mesh
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolarBoundary {
+
+    /// Ghost zones mirror the zones on the edge of the mesh, with the polar
+    /// velocity reversed
+    Reflecting,
+
+    /// Ghost zones on one polar edge are copies of the zones on the other
+    PeriodicWedge,
+}
+
+
+
+
+/**
+ * The boundary conditions on each surface of the mesh
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Boundaries {
+
+    /// Condition at the inner radial boundary
+    #[serde(default = "Boundaries::default_radial")]
+    pub inner: RadialBoundary,
+
+    /// Condition at the outer radial boundary
+    #[serde(default = "Boundaries::default_radial")]
+    pub outer: RadialBoundary,
+
+    /// Condition at the polar boundaries
+    #[serde(default = "Boundaries::default_polar")]
+    pub polar: PolarBoundary,
+}
+
+
+
+
+/**
+ * Enum for the inner or outer side of a block
+ */
+#[derive(Clone, Copy)]
+pub enum Side {
+    Inner,
+    Outer,
+}
+
+
+
+
+// ============================================================================
+impl Boundaries {
+    fn default_radial() -> RadialBoundary {
+        RadialBoundary::InflowFromModel
+    }
+    fn default_polar() -> PolarBoundary {
+        PolarBoundary::Reflecting
+    }
+}
+
+impl Default for Boundaries {
+    fn default() -> Self {
+        Self {
+            inner: Self::default_radial(),
+            outer: Self::default_radial(),
+            polar: Self::default_polar(),
+        }
+    }
+}
+
+impl RadialBoundary {
+
+    /**
+     * Return true if the ghost zones for this boundary are generated as
+     * blocks from the initial model.
+     */
+    pub fn is_from_model(&self) -> bool {
+        matches!(self, RadialBoundary::InflowFromModel)
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return a primitive state with the velocity component in the given
+ * direction reversed.
+ */
+pub fn reflect<H, P>(hydro: &H, p: &P, direction: Direction) -> P
+where
+    H: Hydrodynamics<Primitive = P>,
+    P: Primitive,
+{
+    let a = hydro.any(p);
+    let a = match direction {
+        Direction::Radial => AnyPrimitive{velocity_r: -a.velocity_r, ..a},
+        Direction::Polar  => AnyPrimitive{velocity_q: -a.velocity_q, ..a},
+    };
+    hydro.interpret(&a)
+}
+
+/**
+ * Return the primitive states and scalar densities of the two ghost zones on
+ * the given side of a block at the radial edge of the mesh, ordered from
+ * inner to outer. Returns None if the boundary condition is
+ * `InflowFromModel`, whose ghost zones come from blocks generated by the
+ * initial model.
+ */
+pub fn radial_ghost_zones<H, P>(
+    boundary: &RadialBoundary,
+    side: Side,
+    p: ArrayView<P, Ix2>,
+    s: ArrayView<f64, Ix2>,
+    hydro: &H) -> Option<(ArcArray<P, Ix2>, ArcArray<f64, Ix2>)>
+where
+    H: Hydrodynamics<Primitive = P>,
+    P: Primitive,
+{
+    let (nr, nq) = p.dim();
+    let mirror = match side {
+        Side::Inner => [1, 0],
+        Side::Outer => [nr - 1, nr - 2],
+    };
+    let edge = match side {
+        Side::Inner => [0, 0],
+        Side::Outer => [nr - 1, nr - 1],
+    };
+
+    match boundary {
+        RadialBoundary::InflowFromModel => {
+            None
+        }
+        RadialBoundary::Outflow => {
+            Some((p.select(Axis(0), &edge).to_shared(), s.select(Axis(0), &edge).to_shared()))
+        }
+        RadialBoundary::Reflecting => {
+            let pg = p.select(Axis(0), &mirror).mapv(|p| reflect(hydro, &p, Direction::Radial));
+            Some((pg.to_shared(), s.select(Axis(0), &mirror).to_shared()))
+        }
+        RadialBoundary::Fixed(fixed) => {
+            let pg = Array::from_elem((2, nq), hydro.interpret(&fixed.primitive));
+            let sg = Array::from_elem((2, nq), fixed.scalar * fixed.primitive.mass_density);
+            Some((pg.to_shared(), sg.to_shared()))
+        }
+    }
+}
+
+/**
+ * Fill the ghost zones in the first and last columns of the given array,
+ * according to the polar boundary condition. The `reflect` function is
+ * applied to the mirrored zones of a reflecting boundary.
+ */
+pub fn fill_polar_ghost_zones<T, F>(mut a: ArrayViewMut<T, Ix2>, boundary: &PolarBoundary, reflect: F)
+where
+    T: Copy,
+    F: Fn(&T) -> T,
+{
+    let (nr, nq) = (a.dim().0, a.dim().1 - 2);
+
+    for i in 0..nr {
+        let (lower, upper) = match boundary {
+            PolarBoundary::Reflecting => (reflect(&a[(i, 1)]), reflect(&a[(i, nq)])),
+            PolarBoundary::PeriodicWedge => (a[(i, nq)], a[(i, 1)]),
+        };
+        a[(i, 0)] = lower;
+        a[(i, nq + 1)] = upper;
+    }
+}
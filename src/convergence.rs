@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use crate::mesh::{BlockIndex, GridGeometry, Mesh};
+use crate::physics::HydroError;
+use crate::reductions::Reductions;
+use crate::state::State;
+use crate::traits::{Conserved, Hydrodynamics};
+
+
+
+
+/**
+ * A scalar measurement of the final solution state, which is compared
+ * between resolutions in a convergence study
+ */
+#[derive(Clone, Copy)]
+pub enum Observable {
+    ShockRadius,
+    Mass,
+    Energy,
+    RadialMomentum,
+    ScalarMass,
+}
+
+
+
+
+// ============================================================================
+impl FromStr for Observable {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shock_radius"    => Ok(Observable::ShockRadius),
+            "mass"            => Ok(Observable::Mass),
+            "energy"          => Ok(Observable::Energy),
+            "radial_momentum" => Ok(Observable::RadialMomentum),
+            "scalar_mass"     => Ok(Observable::ScalarMass),
+            _ => anyhow::bail!("unknown observable '{}' (choose from shock_radius, mass, energy, radial_momentum, scalar_mass)", s),
+        }
+    }
+}
+
+impl Observable {
+
+    /**
+     * The name of this observable, as written in the convergence report.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Observable::ShockRadius    => "shock_radius",
+            Observable::Mass           => "mass",
+            Observable::Energy         => "energy",
+            Observable::RadialMomentum => "radial_momentum",
+            Observable::ScalarMass     => "scalar_mass",
+        }
+    }
+
+    /**
+     * Measure this observable on the given state. The totals are taken from
+     * the same reductions as are recorded in the time series. The shock
+     * radius is NaN if no zones are shocked.
+     */
+    pub fn measure<H, C>(&self, state: &State<C>, hydro: &H, geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<f64, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>,
+        C: Conserved,
+    {
+        if let Observable::ShockRadius = self {
+            return Ok(state.outermost_shock_radius(hydro, geometry)?.unwrap_or(f64::NAN))
+        }
+        let reductions = Reductions::from_state(state, geometry);
+
+        Ok(match self {
+            Observable::ShockRadius    => unreachable!(),
+            Observable::Mass           => reductions.mass,
+            Observable::Energy         => reductions.energy,
+            Observable::RadialMomentum => reductions.radial_momentum,
+            Observable::ScalarMass     => reductions.scalar_mass,
+        })
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return a copy of the mesh with the resolution increased by a factor of
+ * 2^level in each direction. The number of radial zones per decade is
+ * doubled if it is given (otherwise it follows the polar zones), and the
+ * number of polar zones is doubled unless it is 1. The block size is
+ * unchanged, so the finer meshes have more blocks.
+ */
+pub fn refined_mesh(mesh: &Mesh, level: u32) -> Mesh {
+    let factor = 2usize.pow(level);
+    let mut mesh = mesh.clone();
+
+    if let Some(num_radial_zones) = mesh.num_radial_zones {
+        mesh.num_radial_zones = Some(num_radial_zones * factor);
+    }
+    if mesh.num_polar_zones > 1 {
+        mesh.num_polar_zones *= factor;
+    }
+    mesh
+}
+
+/**
+ * Return the observed order of convergence from each three successive
+ * values of an observable, measured at resolutions which double from one
+ * to the next. This is log2 of the ratio of successive differences, and is
+ * NaN if the finer difference is zero.
+ */
+pub fn convergence_orders(values: &[f64]) -> Vec<f64> {
+    values
+        .windows(3)
+        .map(|v| ((v[1] - v[0]) / (v[2] - v[1])).abs().log2())
+        .map(|p| if p.is_finite() { p } else { f64::NAN })
+        .collect()
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn second_order_errors_give_order_two() {
+        let values: Vec<_> = (0..4).map(|n| 1.0 + 0.5 * 4f64.powi(-n)).collect();
+
+        for order in convergence_orders(&values) {
+            assert!((order - 2.0).abs() < 1e-10);
+        }
+    }
+}
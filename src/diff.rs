@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use crate::mesh::BlockIndex;
+use crate::products::{BlockProducts, Products};
+
+
+
+
+/**
+ * The largest relative difference in a field between two products, and
+ * where it occurs
+ */
+pub struct FieldDifference {
+
+    /// Name of the field
+    pub field: String,
+
+    /// The largest relative difference |a - b| / max(|a|, |b|) over all
+    /// zones. Values which are NaN in one file but not the other are
+    /// infinitely different.
+    pub relative_error: f64,
+
+    /// The block and the zone (or vertex) index of the largest difference,
+    /// if the field has any zones
+    pub location: Option<(BlockIndex, (usize, usize))>,
+
+    /// The values in the two files at that location
+    pub values: (f64, f64),
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the relative difference of two values, which is zero if they are
+ * equal (including both NaN), and infinite if only one is NaN.
+ */
+pub fn relative_error(a: f64, b: f64) -> f64 {
+    if a == b || (a.is_nan() && b.is_nan()) {
+        0.0
+    } else if a.is_nan() || b.is_nan() {
+        f64::INFINITY
+    } else {
+        (a - b).abs() / a.abs().max(b.abs())
+    }
+}
+
+/**
+ * Compare two products field by field, returning the largest relative
+ * difference in the time and in each of the vertex and zone fields. The
+ * conserved quantities and cell volumes are compared if both products
+ * include them. Fails if the products do not have the same blocks, or the
+ * blocks do not have the same shape.
+ */
+pub fn compare(a: &Products, b: &Products) -> anyhow::Result<Vec<FieldDifference>> {
+    let mut indexes: Vec<_> = a.blocks.keys().copied().collect();
+    indexes.sort_unstable();
+
+    if indexes.len() != b.blocks.len() || indexes.iter().any(|index| !b.blocks.contains_key(index)) {
+        anyhow::bail!("the files do not have the same blocks")
+    }
+
+    let mut differences: BTreeMap<&str, FieldDifference> = BTreeMap::new();
+    let mut record = |field: &'static str, index: BlockIndex, zone: (usize, usize), x: f64, y: f64| {
+        let error = relative_error(x, y);
+        let entry = differences.entry(field).or_insert_with(|| FieldDifference{
+            field: field.to_string(),
+            relative_error: 0.0,
+            location: None,
+            values: (x, y),
+        });
+        if entry.location.is_none() || error > entry.relative_error {
+            entry.relative_error = error;
+            entry.location = Some((index, zone));
+            entry.values = (x, y);
+        }
+    };
+
+    for index in indexes {
+        let (p, q) = (&a.blocks[&index], &b.blocks[&index]);
+
+        if p.radial_vertices.len() != q.radial_vertices.len() || p.polar_vertices.len() != q.polar_vertices.len() {
+            anyhow::bail!("block {:?} has a different shape in the two files", index)
+        }
+        for (i, (&x, &y)) in p.radial_vertices.iter().zip(q.radial_vertices.iter()).enumerate() {
+            record("radial_vertices", index, (i, 0), x, y);
+        }
+        for (j, (&x, &y)) in p.polar_vertices.iter().zip(q.polar_vertices.iter()).enumerate() {
+            record("polar_vertices", index, (0, j), x, y);
+        }
+        for (zone, x) in p.primitive.indexed_iter() {
+            let y = &q.primitive[zone];
+            record("mass_density", index, zone, x.mass_density, y.mass_density);
+            record("gas_pressure", index, zone, x.gas_pressure, y.gas_pressure);
+            record("velocity_r", index, zone, x.velocity_r, y.velocity_r);
+            record("velocity_q", index, zone, x.velocity_q, y.velocity_q);
+            record("scalar", index, zone, p.scalar[zone], q.scalar[zone]);
+        }
+        compare_optional(p, q, index, &mut record);
+    }
+
+    let time = FieldDifference{
+        field: "time".to_string(),
+        relative_error: relative_error(a.time, b.time),
+        location: None,
+        values: (a.time, b.time),
+    };
+    Ok(std::iter::once(time).chain(differences.into_iter().map(|(_, d)| d)).collect())
+}
+
+/**
+ * Compare the conserved quantities and cell volumes of two blocks, where
+ * both include them.
+ */
+fn compare_optional<F>(p: &BlockProducts, q: &BlockProducts, index: BlockIndex, record: &mut F)
+where
+    F: FnMut(&'static str, BlockIndex, (usize, usize), f64, f64) {
+
+    if let (Some(u), Some(v)) = (&p.conserved, &q.conserved) {
+        for (zone, x) in u.indexed_iter() {
+            let y = &v[zone];
+            record("lab_frame_mass", index, zone, x[0], y[0]);
+            record("radial_momentum", index, zone, x[1], y[1]);
+            record("polar_momentum", index, zone, x[2], y[2]);
+            record("energy", index, zone, x[3], y[3]);
+        }
+    }
+    if let (Some(u), Some(v)) = (&p.cell_volumes, &q.cell_volumes) {
+        for (zone, &x) in u.indexed_iter() {
+            record("cell_volumes", index, zone, x, v[zone]);
+        }
+    }
+}
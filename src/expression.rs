@@ -0,0 +1,688 @@
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::Chars;
+use serde::{Serialize, Deserialize};
+use crate::physics::AnyPrimitive;
+
+
+
+
+// ============================================================================
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum Error {
+
+    #[error("unexpected character '{0}'")]
+    UnexpectedCharacter(char),
+
+    #[error("unexpected token '{0}'")]
+    UnexpectedToken(String),
+
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+
+    #[error("unknown variable '{0}'")]
+    UnknownVariable(String),
+
+    #[error("unknown function '{0}'")]
+    UnknownFunction(String),
+
+    #[error("function '{0}' takes {1} argument(s)")]
+    WrongNumberOfArguments(String, usize),
+
+    #[error("expression must have the form integral(...), average(...), maximum(...), or minimum(...)")]
+    MissingReduction,
+
+    #[error("variable '{0}' is not available in a formula (use r, q, and t)")]
+    ZoneVariable(String),
+}
+
+
+
+
+/**
+ * The per-zone data a user expression may refer to
+ */
+pub struct Zone<'a> {
+    pub primitive: &'a AnyPrimitive,
+    pub scalar: f64,
+    pub coordinate: (f64, f64),
+    pub time: f64,
+}
+
+
+
+
+/**
+ * Variables which can appear in an expression: `rho` (comoving mass density),
+ * `pre` (gas pressure), `ur` and `uq` (radial and polar velocity or
+ * gamma-beta), `u` (magnitude of the velocity), `scalar` (the scalar
+ * concentration), the zone coordinates `r` and `q`, and the time `t`.
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum Variable {
+    Rho,
+    Pre,
+    Ur,
+    Uq,
+    U,
+    Scalar,
+    R,
+    Q,
+    T,
+}
+
+
+
+
+/**
+ * Functions which can be called in an expression
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum Function {
+    Sqrt,
+    Exp,
+    Log,
+    Log10,
+    Abs,
+    Sin,
+    Cos,
+    Min,
+    Max,
+}
+
+
+
+
+/**
+ * Arithmetic and comparison operators
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+
+
+
+/**
+ * A parsed arithmetic expression, evaluated zone-by-zone. Comparisons
+ * evaluate to 1.0 if true and 0.0 if false, so they can be used as masks.
+ */
+#[derive(Clone, Debug)]
+pub enum Expression {
+    Number(f64),
+    Variable(Variable),
+    Negate(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+
+
+
+/**
+ * The way an expression is combined over the zones of the mesh
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+
+    /// The volume integral
+    Integral,
+
+    /// The volume-weighted average
+    Average,
+
+    /// The largest value on any zone
+    Maximum,
+
+    /// The smallest value on any zone
+    Minimum,
+}
+
+
+
+
+/**
+ * A user-defined reduction, written in the configuration as a string, e.g.
+ * `integral(rho * (u > 0.1))`.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Reduction {
+    source: String,
+    operation: Operation,
+    expression: Expression,
+}
+
+
+
+
+/**
+ * A user-defined function of position and time, written in the
+ * configuration as a string, e.g. `1e-3 * (r / 1e10)^-2 * exp(-t / 10)`. It
+ * may refer only to the variables `r`, `q`, and `t`.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Formula {
+    source: String,
+    expression: Expression,
+}
+
+
+
+
+// ============================================================================
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent_sign = (c == '-' || c == '+') && text.ends_with(['e', 'E']);
+                if c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign {
+                    text.push(c);
+                    chars.next();
+                } else {
+                    break
+                }
+            }
+            tokens.push(Token::Number(text.parse().map_err(|_| Error::UnexpectedToken(text))?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' {
+                    text.push(c);
+                    chars.next();
+                } else {
+                    break
+                }
+            }
+            tokens.push(Token::Ident(text));
+        } else {
+            chars.next();
+            let symbol = match (c, chars.peek()) {
+                ('<', Some('=')) => { chars.next(); "<=" }
+                ('>', Some('=')) => { chars.next(); ">=" }
+                ('<', _) => "<",
+                ('>', _) => ">",
+                ('+', _) => "+",
+                ('-', _) => "-",
+                ('*', _) => "*",
+                ('/', _) => "/",
+                ('^', _) => "^",
+                ('(', _) => "(",
+                (')', _) => ")",
+                (',', _) => ",",
+                _ => return Err(Error::UnexpectedCharacter(c)),
+            };
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+
+
+
+// ============================================================================
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        let token = self.tokens.get(self.position).cloned().ok_or(Error::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            token => Err(Error::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(Error::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression, Error> {
+        let lhs = self.additive()?;
+        let operator = if self.accept("<=") {
+            BinaryOperator::LessEqual
+        } else if self.accept(">=") {
+            BinaryOperator::GreaterEqual
+        } else if self.accept("<") {
+            BinaryOperator::Less
+        } else if self.accept(">") {
+            BinaryOperator::Greater
+        } else {
+            return Ok(lhs)
+        };
+        Ok(Expression::Binary(operator, Box::new(lhs), Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Expression, Error> {
+        let mut lhs = self.multiplicative()?;
+        loop {
+            let operator = if self.accept("+") {
+                BinaryOperator::Add
+            } else if self.accept("-") {
+                BinaryOperator::Sub
+            } else {
+                return Ok(lhs)
+            };
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expression, Error> {
+        let mut lhs = self.unary()?;
+        loop {
+            let operator = if self.accept("*") {
+                BinaryOperator::Mul
+            } else if self.accept("/") {
+                BinaryOperator::Div
+            } else {
+                return Ok(lhs)
+            };
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, Error> {
+        if self.accept("-") {
+            Ok(Expression::Negate(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expression, Error> {
+        let base = self.primary()?;
+        if self.accept("^") {
+            Ok(Expression::Binary(BinaryOperator::Pow, Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expression>, Error> {
+        let mut args = vec![self.comparison()?];
+        while self.accept(",") {
+            args.push(self.comparison()?);
+        }
+        self.expect(")")?;
+        Ok(args)
+    }
+
+    fn primary(&mut self) -> Result<Expression, Error> {
+        match self.next()? {
+            Token::Number(x) => Ok(Expression::Number(x)),
+            Token::Symbol("(") => {
+                let expression = self.comparison()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            Token::Ident(name) => {
+                if self.accept("(") {
+                    let function = Function::from_name(&name)?;
+                    let args = self.arguments()?;
+                    if args.len() != function.num_arguments() {
+                        return Err(Error::WrongNumberOfArguments(name, function.num_arguments()))
+                    }
+                    Ok(Expression::Call(function, args))
+                } else {
+                    Ok(Expression::Variable(Variable::from_name(&name)?))
+                }
+            }
+            token => Err(Error::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(x) => write!(f, "{}", x),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Symbol(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Variable {
+    fn name(&self) -> &'static str {
+        match self {
+            Variable::Rho    => "rho",
+            Variable::Pre    => "pre",
+            Variable::Ur     => "ur",
+            Variable::Uq     => "uq",
+            Variable::U      => "u",
+            Variable::Scalar => "scalar",
+            Variable::R      => "r",
+            Variable::Q      => "q",
+            Variable::T      => "t",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "rho"    => Ok(Variable::Rho),
+            "pre"    => Ok(Variable::Pre),
+            "ur"     => Ok(Variable::Ur),
+            "uq"     => Ok(Variable::Uq),
+            "u"      => Ok(Variable::U),
+            "scalar" => Ok(Variable::Scalar),
+            "r"      => Ok(Variable::R),
+            "q"      => Ok(Variable::Q),
+            "t"      => Ok(Variable::T),
+            _ => Err(Error::UnknownVariable(name.to_string())),
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "sqrt"  => Ok(Function::Sqrt),
+            "exp"   => Ok(Function::Exp),
+            "log"   => Ok(Function::Log),
+            "log10" => Ok(Function::Log10),
+            "abs"   => Ok(Function::Abs),
+            "sin"   => Ok(Function::Sin),
+            "cos"   => Ok(Function::Cos),
+            "min"   => Ok(Function::Min),
+            "max"   => Ok(Function::Max),
+            _ => Err(Error::UnknownFunction(name.to_string())),
+        }
+    }
+
+    fn num_arguments(&self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            _ => 1,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl Expression {
+
+    /**
+     * Parse an expression from a string.
+     */
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut parser = Parser{tokens: tokenize(source)?, position: 0};
+        let expression = parser.comparison()?;
+        parser.finish()?;
+        Ok(expression)
+    }
+
+    /**
+     * Return the variables referred to in this expression, in the order they
+     * appear, with repeats.
+     */
+    pub fn variables(&self) -> Vec<Variable> {
+        match self {
+            Expression::Number(_) => Vec::new(),
+            Expression::Variable(v) => vec![*v],
+            Expression::Negate(a) => a.variables(),
+            Expression::Binary(_, a, b) => [a.variables(), b.variables()].concat(),
+            Expression::Call(_, args) => args.iter().flat_map(Expression::variables).collect(),
+        }
+    }
+
+    /**
+     * Evaluate this expression on the data in a single zone.
+     */
+    pub fn evaluate(&self, zone: &Zone) -> f64 {
+        match self {
+            Expression::Number(x) => *x,
+            Expression::Variable(v) => match v {
+                Variable::Rho    => zone.primitive.mass_density,
+                Variable::Pre    => zone.primitive.gas_pressure,
+                Variable::Ur     => zone.primitive.velocity_r,
+                Variable::Uq     => zone.primitive.velocity_q,
+                Variable::U      => zone.primitive.velocity_r.hypot(zone.primitive.velocity_q),
+                Variable::Scalar => zone.scalar,
+                Variable::R      => zone.coordinate.0,
+                Variable::Q      => zone.coordinate.1,
+                Variable::T      => zone.time,
+            },
+            Expression::Negate(a) => -a.evaluate(zone),
+            Expression::Binary(operator, a, b) => {
+                let (a, b) = (a.evaluate(zone), b.evaluate(zone));
+                let mask = |x: bool| if x { 1.0 } else { 0.0 };
+                match operator {
+                    BinaryOperator::Add          => a + b,
+                    BinaryOperator::Sub          => a - b,
+                    BinaryOperator::Mul          => a * b,
+                    BinaryOperator::Div          => a / b,
+                    BinaryOperator::Pow          => a.powf(b),
+                    BinaryOperator::Less         => mask(a < b),
+                    BinaryOperator::Greater      => mask(a > b),
+                    BinaryOperator::LessEqual    => mask(a <= b),
+                    BinaryOperator::GreaterEqual => mask(a >= b),
+                }
+            }
+            Expression::Call(function, args) => {
+                let x = args[0].evaluate(zone);
+                match function {
+                    Function::Sqrt  => x.sqrt(),
+                    Function::Exp   => x.exp(),
+                    Function::Log   => x.ln(),
+                    Function::Log10 => x.log10(),
+                    Function::Abs   => x.abs(),
+                    Function::Sin   => x.sin(),
+                    Function::Cos   => x.cos(),
+                    Function::Min   => x.min(args[1].evaluate(zone)),
+                    Function::Max   => x.max(args[1].evaluate(zone)),
+                }
+            }
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl Reduction {
+
+    /**
+     * Parse a reduction from a string of the form `operation(expression)`,
+     * where the operation is one of `integral`, `average`, `maximum`, or
+     * `minimum`.
+     */
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut parser = Parser{tokens: tokenize(source)?, position: 0};
+        let operation = match parser.next()? {
+            Token::Ident(name) if name == "integral" => Operation::Integral,
+            Token::Ident(name) if name == "average"  => Operation::Average,
+            Token::Ident(name) if name == "maximum"  => Operation::Maximum,
+            Token::Ident(name) if name == "minimum"  => Operation::Minimum,
+            _ => return Err(Error::MissingReduction),
+        };
+        parser.expect("(")?;
+        let expression = parser.comparison()?;
+        parser.expect(")")?;
+        parser.finish()?;
+        Ok(Self{source: source.to_string(), operation, expression})
+    }
+
+    /**
+     * Combine the values of the expression over a sequence of zones, each
+     * given along with its volume.
+     */
+    pub fn evaluate<'a, I>(&self, zones: I) -> f64
+    where
+        I: IntoIterator<Item = (Zone<'a>, f64)>
+    {
+        let values = zones.into_iter().map(|(zone, dv)| (self.expression.evaluate(&zone), dv));
+
+        match self.operation {
+            Operation::Integral => values.map(|(x, dv)| x * dv).sum(),
+            Operation::Average  => {
+                let (total, volume) = values.fold((0.0, 0.0), |(t, v), (x, dv)| (t + x * dv, v + dv));
+                total / volume
+            }
+            Operation::Maximum  => values.map(|(x, _)| x).fold(f64::NEG_INFINITY, f64::max),
+            Operation::Minimum  => values.map(|(x, _)| x).fold(f64::INFINITY, f64::min),
+        }
+    }
+}
+
+impl TryFrom<String> for Reduction {
+    type Error = Error;
+    fn try_from(source: String) -> Result<Self, Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Reduction> for String {
+    fn from(reduction: Reduction) -> Self {
+        reduction.source
+    }
+}
+
+
+
+
+// ============================================================================
+impl Formula {
+
+    /**
+     * Parse a formula from a string. Fails if the expression refers to any
+     * variables other than the coordinates and the time.
+     */
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let expression = Expression::parse(source)?;
+
+        for variable in expression.variables() {
+            if !matches!(variable, Variable::R | Variable::Q | Variable::T) {
+                return Err(Error::ZoneVariable(variable.name().to_string()))
+            }
+        }
+        Ok(Self{source: source.to_string(), expression})
+    }
+
+    /**
+     * Evaluate this formula at the given (r, theta) coordinate and time.
+     */
+    pub fn evaluate(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        let primitive = AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 0.0, gas_pressure: 0.0};
+        self.expression.evaluate(&Zone{primitive: &primitive, scalar: 0.0, coordinate, time})
+    }
+}
+
+impl Default for Formula {
+    fn default() -> Self {
+        Self::parse("0").unwrap()
+    }
+}
+
+impl TryFrom<String> for Formula {
+    type Error = Error;
+    fn try_from(source: String) -> Result<Self, Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Formula> for String {
+    fn from(formula: Formula) -> Self {
+        formula.source
+    }
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone_eval(source: &str) -> f64 {
+        let primitive = AnyPrimitive{velocity_r: 3.0, velocity_q: 4.0, mass_density: 2.0, gas_pressure: 0.5};
+        let zone = Zone{primitive: &primitive, scalar: 0.1, coordinate: (10.0, 0.0), time: 1.0};
+        Expression::parse(source).unwrap().evaluate(&zone)
+    }
+
+    #[test]
+    fn expressions_respect_precedence() {
+        assert_eq!(zone_eval("1 + 2 * 3"), 7.0);
+        assert_eq!(zone_eval("-2 ^ 2"), -4.0);
+        assert_eq!(zone_eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(zone_eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(zone_eval("1e-1 * 10"), 1.0);
+    }
+
+    #[test]
+    fn expressions_use_zone_data() {
+        assert_eq!(zone_eval("rho * (u > 4.9)"), 2.0);
+        assert_eq!(zone_eval("rho * (u > 5.1)"), 0.0);
+        assert_eq!(zone_eval("max(pre, scalar) * r"), 5.0);
+    }
+
+    #[test]
+    fn malformed_reductions_are_rejected() {
+        assert!(Reduction::parse("integral(rho * (u > 0.1))").is_ok());
+        assert!(Reduction::parse("rho * u").is_err());
+        assert!(Reduction::parse("integral(rho * w)").is_err());
+        assert!(Reduction::parse("integral(rho").is_err());
+        assert!(Reduction::parse("integral(min(rho))").is_err());
+    }
+
+    #[test]
+    fn formulas_depend_only_on_position_and_time() {
+        assert_eq!(Formula::parse("r * cos(q) + t").unwrap().evaluate((2.0, 0.0), 3.0), 5.0);
+        assert!(Formula::parse("rho * r").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+use std::io::Write;
+use crate::app::AnyHydro;
+use crate::mesh::CoordinateSystem;
+use crate::products::Products;
+
+
+
+
+/**
+ * A zone of the solution, placed at its homologous velocity coordinate
+ * v = r / t, for export to radiative transfer codes
+ */
+pub struct EjectaZone {
+
+    /// Homologous velocity of the zone centroid (cm/s)
+    pub velocity: f64,
+
+    /// Polar angle of the zone centroid
+    pub theta: f64,
+
+    /// Comoving mass density at the output time (g/cm^3)
+    pub mass_density: f64,
+
+    /// Passive scalar concentration, which is the electron fraction in
+    /// models which inject it as such
+    pub electron_fraction: f64,
+
+    /// Lab-frame mass in the zone (g)
+    pub mass: f64,
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the zones of the given products as a table in homologous
+ * coordinates, ordered by block and then by zone. If `time` is given, the
+ * densities are extrapolated to that time assuming free expansion, so that
+ * the mass at fixed velocity is unchanged; otherwise they are at the time of
+ * the products. Only spherical meshes are supported.
+ */
+pub fn ejecta_table(products: &Products, time: Option<f64>) -> anyhow::Result<Vec<EjectaZone>> {
+    if products.config.mesh.geometry != CoordinateSystem::Spherical {
+        anyhow::bail!("homologous tables can only be made from spherical meshes")
+    }
+    if products.time <= 0.0 {
+        anyhow::bail!("homologous tables require a positive simulation time")
+    }
+    let relativistic = matches!(products.config.hydro, AnyHydro::Relativistic(_));
+    let density_factor = (products.time / time.unwrap_or(products.time)).powi(3);
+
+    let mut indexes: Vec<_> = products.blocks.keys().copied().collect();
+    indexes.sort_unstable();
+
+    let mut rows = Vec::new();
+
+    for index in indexes {
+        let block = &products.blocks[&index];
+        let rv = &block.radial_vertices;
+        let qv = &block.polar_vertices;
+
+        for ((i, j), p) in block.primitive.indexed_iter() {
+            let volume = 2.0 * PI * (rv[i + 1].powi(3) - rv[i].powi(3)) / 3.0 * (qv[j].cos() - qv[j + 1].cos());
+            let lorentz_factor = if relativistic {
+                (1.0 + p.velocity_r.powi(2) + p.velocity_q.powi(2)).sqrt()
+            } else {
+                1.0
+            };
+            rows.push(EjectaZone{
+                velocity: (rv[i] * rv[i + 1]).sqrt() / products.time,
+                theta: 0.5 * (qv[j] + qv[j + 1]),
+                mass_density: p.mass_density * density_factor,
+                electron_fraction: block.scalar[(i, j)],
+                mass: p.mass_density * lorentz_factor * volume,
+            });
+        }
+    }
+    Ok(rows)
+}
+
+/**
+ * Write an ejecta table as comma-separated values, with a comment line
+ * giving the time of the densities, and a header line.
+ */
+pub fn write_csv<W: Write>(writer: &mut W, time: f64, rows: &[EjectaZone]) -> std::io::Result<()> {
+    writeln!(writer, "# t = {:.12e} s", time)?;
+    writeln!(writer, "v,theta,rho,ye,mass")?;
+
+    for row in rows {
+        writeln!(writer, "{:.12e},{:.12e},{:.12e},{:.12e},{:.12e}",
+            row.velocity,
+            row.theta,
+            row.mass_density,
+            row.electron_fraction,
+            row.mass)?;
+    }
+    Ok(())
+}
@@ -1,4 +1,14 @@
-use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use serde::{Serialize, Serializer, Deserialize};
+use crate::app::{App, OutputFormat};
+use crate::products::Products;
+#[cfg(feature = "object-store")]
+use std::sync::Arc;
+#[cfg(feature = "object-store")]
+use object_store::ObjectStore;
+#[cfg(feature = "object-store")]
+use tokio::io::AsyncWriteExt;
 
 
 
@@ -15,6 +25,99 @@ pub enum Error {
 
     #[error("{0}")]
     IO(#[from] std::io::Error),
+
+    #[cfg(feature = "hdf5")]
+    #[error("{0}")]
+    Hdf5(#[from] hdf5::Error),
+
+    #[error("{0}")]
+    Unsupported(&'static str),
+
+    #[error("{0} is corrupt: its checksum does not match its contents")]
+    Corrupt(String),
+
+    #[cfg(feature = "object-store")]
+    #[error("{0}")]
+    ObjectStore(#[from] object_store::Error),
+}
+
+/// The zstd compression level used if compression is enabled without a level
+pub static DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// The first bytes of a zstd frame, which identify a compressed file
+static ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The first bytes of the footer appended to CBOR files, which is followed
+/// by the CRC32 checksum (4 bytes) and length (8 bytes) of the contents,
+/// both little-endian
+static FOOTER_MAGIC: [u8; 8] = *b"kncCRC32";
+
+/// The length in bytes of the footer
+const FOOTER_SIZE: u64 = 20;
+
+/// The object storage clients, which are built on the first write there
+#[cfg(feature = "object-store")]
+static OBJECT_STORE_CLIENTS: std::sync::Mutex<Option<ObjectStoreClients>> = std::sync::Mutex::new(None);
+
+
+
+
+/**
+ * The runtime used to make object storage requests, and a client for each
+ * bucket, keyed by its URI (e.g. s3://bucket)
+ */
+#[cfg(feature = "object-store")]
+struct ObjectStoreClients {
+    runtime: Arc<tokio::runtime::Runtime>,
+    stores: HashMap<String, Arc<dyn ObjectStore>>,
+}
+
+/**
+ * A blocking writer to a multipart upload to object storage
+ */
+#[cfg(feature = "object-store")]
+struct MultipartWriter {
+    runtime: Arc<tokio::runtime::Runtime>,
+    inner: Box<dyn tokio::io::AsyncWrite + Send + Unpin>,
+}
+
+/**
+ * A writer which computes the CRC32 checksum and length of the data passing
+ * through it
+ */
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    length: u64,
+}
+
+/**
+ * A reader which computes the CRC32 checksum of the data passing through it
+ */
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+/**
+ * The checksum and length of the contents of a CBOR file, read from its
+ * footer
+ */
+struct Footer {
+    checksum: u32,
+    length: u64,
+}
+
+
+
+
+/**
+ * Data which can be written to an HDF5 file by [`write_hdf5`]. The method is
+ * only present in builds with the hdf5 feature.
+ */
+pub trait WriteHdf5 {
+    #[cfg(feature = "hdf5")]
+    fn write_hdf5_to(&self, file: &hdf5::File) -> hdf5::Result<()>;
 }
 
 
@@ -22,14 +125,558 @@ pub enum Error {
 
 // ============================================================================
 pub fn write_cbor<T: Serialize>(value: &T, path_str: &str) -> Result<(), Error> {
+    write_cbor_compressed(value, path_str, None)
+}
+
+/**
+ * Write a CBOR file, compressed with zstd at the given level if it is not
+ * None. The file is written atomically (see [`write_atomically`]), or if the
+ * target is in object storage (see [`is_remote`]), it is streamed there
+ * directly, without a local copy.
+ */
+pub fn write_cbor_compressed<T: Serialize>(value: &T, path_str: &str, level: Option<i32>) -> Result<(), Error> {
     println!("write {}", path_str);
-    let file = std::fs::File::create(&path_str)?;
-    let mut buffer = std::io::BufWriter::new(file);
-    Ok(ciborium::ser::into_writer(&value, &mut buffer)?)
+
+    if is_remote(path_str) {
+        return write_remote(path_str, |writer| {
+            let mut buffer = encode_cbor(value, std::io::BufWriter::new(writer), level)?;
+            Ok(buffer.flush()?)
+        })
+    }
+    write_atomically(path_str, |temp_path| {
+        let file = std::fs::File::create(temp_path)?;
+        let mut buffer = encode_cbor(value, std::io::BufWriter::new(file), level)?;
+        buffer.flush()?;
+        buffer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    })
 }
 
+/**
+ * Encode a value as CBOR to a writer, compressed with zstd at the given level
+ * if it is not None, and followed by the checksum footer. Returns the writer.
+ */
+fn encode_cbor<T: Serialize, W: Write>(value: &T, writer: W, level: Option<i32>) -> Result<W, Error> {
+    let mut writer = ChecksumWriter::new(writer);
+
+    let writer = match level {
+        Some(level) => {
+            let mut encoder = zstd::stream::write::Encoder::new(writer, level)?;
+            ciborium::ser::into_writer(&value, &mut encoder)?;
+            encoder.finish()?
+        }
+        None => {
+            ciborium::ser::into_writer(&value, &mut writer)?;
+            writer
+        }
+    };
+    Ok(writer.write_footer()?)
+}
+
+/**
+ * Write a file by calling the given function on a temporary path in the
+ * same directory, and then renaming it to the target path if the function
+ * succeeds, or removing it otherwise. The rename is atomic, so a job which
+ * is interrupted while writing never leaves a truncated file at the target
+ * path, and an existing file there is only replaced by a complete one. If
+ * the target is in object storage (see [`is_remote`]), the temporary file
+ * is in the system temporary directory, and is uploaded to the target,
+ * which is also atomic. This is for files which must be written to a path,
+ * like HDF5 files; CBOR files are streamed to object storage instead.
+ */
+pub fn write_atomically<F>(path_str: &str, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&str) -> Result<(), Error>
+{
+    let temp_path = if is_remote(path_str) {
+        let name = path_str.rsplit('/').next().unwrap_or_default();
+        std::env::temp_dir().join(format!("{}.{}.tmp", name, std::process::id())).to_string_lossy().into_owned()
+    } else {
+        format!("{}.{}.tmp", path_str, std::process::id())
+    };
+
+    let result = write(&temp_path).and_then(|()| {
+        if is_remote(path_str) {
+            upload(&temp_path, path_str)
+        } else {
+            Ok(std::fs::rename(&temp_path, path_str)?)
+        }
+    });
+    std::fs::remove_file(&temp_path).ok();
+    result
+}
+
+/**
+ * Return true if the given path is an object storage URI: s3:// (Amazon S3)
+ * or gs:// (Google Cloud Storage).
+ */
+pub fn is_remote(path_str: &str) -> bool {
+    path_str.starts_with("s3://") || path_str.starts_with("gs://")
+}
+
+/**
+ * Create an output directory if it is local, and does not already exist.
+ * Directories in object storage need not be created.
+ */
+pub fn create_output_directory(path_str: &str) -> Result<(), Error> {
+    if !is_remote(path_str) {
+        std::fs::create_dir_all(path_str)?;
+    }
+    Ok(())
+}
+
+/**
+ * Upload a local file to an object storage URI (see [`write_remote`]).
+ */
+pub fn upload(local_path: &str, uri: &str) -> Result<(), Error> {
+    let mut file = std::fs::File::open(local_path)?;
+
+    write_remote(uri, |writer| {
+        std::io::copy(&mut file, writer)?;
+        Ok(())
+    })
+}
+
+/**
+ * Write an object to an object storage URI, by calling the given function on
+ * a writer which streams it in parts. The object only appears once all of
+ * it has been written, and if the function fails, the upload is aborted, so
+ * an existing object is only replaced by a complete one. The bucket
+ * credentials and region are taken from the environment, e.g.
+ * AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and AWS_DEFAULT_REGION for S3,
+ * or GOOGLE_SERVICE_ACCOUNT for Google Cloud Storage.
+ */
+#[cfg(feature = "object-store")]
+pub fn write_remote<F>(uri: &str, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>
+{
+    let (runtime, store, path) = object_store(uri)?;
+    let (id, inner) = runtime.block_on(store.put_multipart(&path))?;
+    let mut writer = MultipartWriter{runtime: runtime.clone(), inner};
+
+    let result = write(&mut writer).and_then(|()| Ok(runtime.block_on(writer.inner.shutdown())?));
+
+    if result.is_err() {
+        runtime.block_on(store.abort_multipart(&path, &id)).ok();
+    }
+    result
+}
+
+#[cfg(not(feature = "object-store"))]
+pub fn write_remote<F>(_uri: &str, _write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>
+{
+    Err(Error::Unsupported("object storage output requires the code to be built with --features object-store"))
+}
+
+/**
+ * Return the runtime used for object storage requests, the client for the
+ * bucket of the given URI, and the path of the object in the bucket. The
+ * runtime and clients are built on first use, and reused by later writes.
+ */
+#[cfg(feature = "object-store")]
+fn object_store(uri: &str) -> Result<(Arc<tokio::runtime::Runtime>, Arc<dyn ObjectStore>, object_store::path::Path), Error> {
+    let (scheme, rest) = uri.split_at(5);
+    let (bucket, key) = rest.split_once('/').ok_or(Error::Unsupported("object storage URIs must be of the form s3://bucket/key"))?;
+    let mut clients = OBJECT_STORE_CLIENTS.lock().unwrap();
+
+    if clients.is_none() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build()?;
+        *clients = Some(ObjectStoreClients{runtime: Arc::new(runtime), stores: HashMap::new()});
+    }
+    let clients = clients.as_mut().unwrap();
+    let uri_prefix = format!("{}{}", scheme, bucket);
+
+    if !clients.stores.contains_key(&uri_prefix) {
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3://" => Arc::new(object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket).build()?),
+            _       => Arc::new(object_store::gcp::GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket).build()?),
+        };
+        clients.stores.insert(uri_prefix.clone(), store);
+    }
+    Ok((clients.runtime.clone(), clients.stores[&uri_prefix].clone(), object_store::path::Path::from(key)))
+}
+
+/**
+ * Read a CBOR file, which may be compressed with zstd. If the file has a
+ * checksum footer, the checksum is verified, and if it does not match, the
+ * file is reported as corrupt (even if its contents could not be decoded).
+ * Files written by older versions of the code have no footer, and are read
+ * without verification.
+ */
 pub fn read_cbor<T: for<'de> Deserialize<'de>>(path_str: &str) -> Result<T, Error> {
-    let file = std::fs::File::open(path_str)?;
-    let buffer = std::io::BufReader::new(file);
-    Ok(ciborium::de::from_reader(buffer)?)
+    let mut file = std::fs::File::open(path_str)?;
+
+    match read_footer(&mut file)? {
+        None => decode(std::io::BufReader::new(file))?.0,
+        Some(footer) => {
+            let reader = ChecksumReader::new(file.take(footer.length));
+            let (value, mut rest) = decode(std::io::BufReader::new(reader))?;
+            std::io::copy(&mut rest, &mut std::io::sink())?;
+
+            if rest.into_inner().checksum() != footer.checksum {
+                return Err(Error::Corrupt(path_str.to_string()))
+            }
+            value
+        }
+    }
+}
+
+/**
+ * Decode a CBOR value from a reader, which may be compressed with zstd.
+ * Returns the result of decoding, and the reader, positioned after the
+ * compressed data.
+ */
+fn decode<T: for<'de> Deserialize<'de>, R: BufRead>(mut reader: R) -> Result<(Result<T, Error>, R), Error> {
+    if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        let mut decoder = zstd::stream::read::Decoder::with_buffer(reader)?;
+        let value = ciborium::de::from_reader(&mut decoder).map_err(Error::from);
+        Ok((value, decoder.finish()))
+    } else {
+        let value = ciborium::de::from_reader(&mut reader).map_err(Error::from);
+        Ok((value, reader))
+    }
+}
+
+/**
+ * Return the footer of a CBOR file, or None if it has none, and rewind the
+ * file to the start.
+ */
+fn read_footer(file: &mut std::fs::File) -> Result<Option<Footer>, Error> {
+    let size = file.metadata()?.len();
+    let mut footer = None;
+
+    if size >= FOOTER_SIZE {
+        let mut bytes = [0; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(size - FOOTER_SIZE))?;
+        file.read_exact(&mut bytes)?;
+
+        let mut checksum = [0; 4];
+        let mut length = [0; 8];
+        checksum.copy_from_slice(&bytes[8..12]);
+        length.copy_from_slice(&bytes[12..20]);
+
+        if bytes[..8] == FOOTER_MAGIC && u64::from_le_bytes(length) == size - FOOTER_SIZE {
+            footer = Some(Footer{checksum: u32::from_le_bytes(checksum), length: size - FOOTER_SIZE})
+        }
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(footer)
+}
+
+// ============================================================================
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self{inner, hasher: crc32fast::Hasher::new(), length: 0}
+    }
+
+    /**
+     * Append the footer, which is not itself included in the checksum, and
+     * return the inner writer.
+     */
+    fn write_footer(mut self) -> std::io::Result<W> {
+        self.inner.write_all(&FOOTER_MAGIC)?;
+        self.inner.write_all(&self.hasher.finalize().to_le_bytes())?;
+        self.inner.write_all(&self.length.to_le_bytes())?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.length += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "object-store")]
+impl Write for MultipartWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.runtime.block_on(self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.runtime.block_on(self.inner.flush())
+    }
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self{inner, hasher: crc32fast::Hasher::new()}
+    }
+
+    fn checksum(self) -> u32 {
+        self.hasher.finalize()
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+
+
+
+#[cfg(feature = "hdf5")]
+pub fn write_hdf5<T: WriteHdf5>(value: &T, path_str: &str) -> Result<(), Error> {
+    println!("write {}", path_str);
+
+    write_atomically(path_str, |temp_path| {
+        let file = hdf5::File::create(temp_path)?;
+        Ok(value.write_hdf5_to(&file)?)
+    })
+}
+
+#[cfg(not(feature = "hdf5"))]
+pub fn write_hdf5<T: WriteHdf5>(_value: &T, _path_str: &str) -> Result<(), Error> {
+    Err(Error::Unsupported("HDF5 output requires the code to be built with --features hdf5"))
+}
+
+/**
+ * Write a products file in the given format, to the path given without its
+ * extension, which is `.cbor` or `.h5`. CBOR files are compressed with zstd
+ * at the given level if it is not None.
+ */
+pub fn write_output<T: Serialize + WriteHdf5>(value: &T, path_stem: &str, format: OutputFormat, compression_level: Option<i32>) -> Result<(), Error> {
+    match format {
+        OutputFormat::Cbor => write_cbor_compressed(value, &format!("{}.cbor", path_stem), compression_level),
+        OutputFormat::Hdf5 => write_hdf5(value, &format!("{}.h5", path_stem)),
+    }
+}
+
+/**
+ * Write a checkpoint to the path given without its extension. Checkpoints
+ * are always written as CBOR (`.cbor`), which runs are restarted from, and
+ * compressed with zstd at the given level if it is not None. If the format
+ * is HDF5, a copy is also written next to it as an HDF5 file (`.h5`).
+ */
+pub fn write_checkpoint(app: &App, path_stem: &str, format: OutputFormat, compression_level: Option<i32>) -> Result<(), Error> {
+    write_cbor_compressed(app, &format!("{}.cbor", path_stem), compression_level)?;
+
+    if format == OutputFormat::Hdf5 {
+        write_hdf5(app, &format!("{}.h5", path_stem))?;
+    }
+    Ok(())
+}
+
+/**
+ * Serialize a hash map with its entries ordered by key. The iteration order
+ * of a hash map differs from run to run, so this is used for the block maps
+ * in checkpoints and products, in order that repeated runs write identical
+ * files. The format is that of any other map, so deserialization is
+ * unaffected.
+ */
+pub fn serialize_sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+
+
+
+// ============================================================================
+#[cfg(feature = "hdf5")]
+mod hdf5_output {
+
+    use hdf5::types::VarLenUnicode;
+    use ndarray::{ArcArray, Ix2};
+    use crate::app::{AnyState, Configuration};
+    use crate::mesh::BlockIndex;
+    use crate::state::State;
+    use crate::traits::Conserved;
+    use super::*;
+
+    fn block_name(index: BlockIndex) -> String {
+        format!("{}_{}", index.0, index.1)
+    }
+
+    fn write_array<T: hdf5::H5Type + Clone>(group: &hdf5::Group, name: &str, shape: &[usize], data: impl Iterator<Item = T>) -> hdf5::Result<()> {
+        group.new_dataset::<T>().create(name, shape)?.write_raw(&data.collect::<Vec<_>>())
+    }
+
+    fn write_f64(group: &hdf5::Group, name: &str, value: f64) -> hdf5::Result<()> {
+        group.new_dataset::<f64>().create(name, ())?.write_scalar(&value)
+    }
+
+    fn write_str(group: &hdf5::Group, name: &str, value: &str) -> hdf5::Result<()> {
+        let value: VarLenUnicode = value.parse().map_err(|e: hdf5::types::StringError| hdf5::Error::from(e.to_string()))?;
+        group.new_dataset::<VarLenUnicode>().create(name, ())?.write_scalar(&value)
+    }
+
+    fn write_header(file: &hdf5::File, time: f64, version: &str, config: &Configuration) -> hdf5::Result<()> {
+        let config = serde_yaml::to_string(config).map_err(|e| hdf5::Error::from(e.to_string()))?;
+        write_f64(file, "time", time)?;
+        write_str(file, "version", version)?;
+        write_str(file, "config", &config)
+    }
+
+    fn write_conserved<C: Conserved>(group: &hdf5::Group, conserved: &ArcArray<C, Ix2>) -> hdf5::Result<()> {
+        let (ni, nj) = conserved.dim();
+        let data = conserved.iter().flat_map(|u| vec![u.lab_frame_mass(), u.radial_momentum(), u.polar_momentum(), u.energy()]);
+        write_array(group, "conserved", &[ni, nj, 4], data)
+    }
+
+    fn write_state<C: Conserved>(file: &hdf5::File, state: &State<C>, config: &Configuration) -> hdf5::Result<()> {
+        let geometry = state.geometry(&config.mesh);
+        let blocks = file.create_group("blocks")?;
+        let mut indexes: Vec<_> = state.solution.keys().copied().collect();
+        indexes.sort_unstable();
+
+        for index in indexes {
+            let block = &state.solution[&index];
+            let group = blocks.create_group(&block_name(index))?;
+            let (rv, qv) = (&geometry[&index].radial_vertices, &geometry[&index].polar_vertices);
+            let (ni, nj) = block.scalar_mass.dim();
+            write_array(&group, "radial_vertices", &[rv.len()], rv.iter().copied())?;
+            write_array(&group, "polar_vertices", &[qv.len()], qv.iter().copied())?;
+            write_conserved(&group, &block.conserved)?;
+            write_array(&group, "scalar_mass", &[ni, nj], block.scalar_mass.iter().copied())?;
+        }
+        Ok(())
+    }
+
+    /**
+     * A checkpoint is written with the time, version, and configuration (as
+     * YAML) at the root, and a group per block under `blocks`, named `i_j`
+     * for block index (i, j), with the vertices, the volume-integrated
+     * conserved quantities (lab-frame mass, radial momentum, polar
+     * momentum, energy) in an array of shape (ni, nj, 4), and the scalar
+     * mass.
+     */
+    impl WriteHdf5 for App {
+        fn write_hdf5_to(&self, file: &hdf5::File) -> hdf5::Result<()> {
+            match &self.state {
+                AnyState::Newtonian(state) => {
+                    write_header(file, state.time, &self.version, &self.config)?;
+                    write_state(file, state, &self.config)
+                }
+                AnyState::Relativistic(state) => {
+                    write_header(file, state.time, &self.version, &self.config)?;
+                    write_state(file, state, &self.config)
+                }
+            }
+        }
+    }
+
+    /**
+     * A products file is written with the time, version, and configuration
+     * (as YAML) at the root, and a group per block under `blocks`, named
+     * `i_j` for block index (i, j), with a dataset for each per-zone field.
+     */
+    impl WriteHdf5 for Products {
+        fn write_hdf5_to(&self, file: &hdf5::File) -> hdf5::Result<()> {
+            write_header(file, self.time, &self.version, &self.config)?;
+
+            let blocks = file.create_group("blocks")?;
+            let mut indexes: Vec<_> = self.blocks.keys().copied().collect();
+            indexes.sort_unstable();
+
+            for index in indexes {
+                let block = &self.blocks[&index];
+                let group = blocks.create_group(&block_name(index))?;
+                let (rv, qv) = (&block.radial_vertices, &block.polar_vertices);
+                let (ni, nj) = block.primitive.dim();
+                write_array(&group, "radial_vertices", &[rv.len()], rv.iter().copied())?;
+                write_array(&group, "polar_vertices", &[qv.len()], qv.iter().copied())?;
+                write_array(&group, "mass_density", &[ni, nj], block.primitive.iter().map(|p| p.mass_density))?;
+                write_array(&group, "gas_pressure", &[ni, nj], block.primitive.iter().map(|p| p.gas_pressure))?;
+                write_array(&group, "velocity_r", &[ni, nj], block.primitive.iter().map(|p| p.velocity_r))?;
+                write_array(&group, "velocity_q", &[ni, nj], block.primitive.iter().map(|p| p.velocity_q))?;
+                write_array(&group, "scalar", &[ni, nj], block.scalar.iter().copied())?;
+
+                if block.shock_flag.dim() == (ni, nj) {
+                    write_array(&group, "shock_flag", &[ni, nj], block.shock_flag.iter().copied())?;
+                }
+                if let Some(conserved) = &block.conserved {
+                    let data = conserved.iter().flat_map(|u| u.iter().copied().collect::<Vec<_>>());
+                    write_array(&group, "conserved", &[ni, nj, 4], data)?;
+                }
+                if let Some(cell_volumes) = &block.cell_volumes {
+                    write_array(&group, "cell_volumes", &[ni, nj], cell_volumes.iter().copied())?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "hdf5"))]
+impl WriteHdf5 for App {}
+
+#[cfg(not(feature = "hdf5"))]
+impl WriteHdf5 for Products {}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("kilonova-io-test.{}.{}.cbor", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn value() -> Vec<f64> {
+        (0..1000).map(|i| i as f64 * 0.5).collect()
+    }
+
+    #[test]
+    fn plain_file_round_trips() {
+        let path = temp_path("plain");
+        write_cbor(&value(), &path).unwrap();
+        let read: Vec<f64> = read_cbor(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, value());
+    }
+
+    #[test]
+    fn compressed_file_round_trips() {
+        let path = temp_path("compressed");
+        write_cbor_compressed(&value(), &path, Some(DEFAULT_COMPRESSION_LEVEL)).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let read: Vec<f64> = read_cbor(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(bytes.starts_with(&ZSTD_MAGIC));
+        assert_eq!(read, value());
+    }
+
+    #[test]
+    fn file_with_a_flipped_byte_is_corrupt() {
+        let path = temp_path("corrupt");
+        write_cbor(&value(), &path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[bytes.len() / 2] ^= 0x01;
+        std::fs::write(&path, &bytes).unwrap();
+        let result = read_cbor::<Vec<f64>>(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::Corrupt(_))));
+    }
+
+    #[test]
+    fn legacy_file_without_a_footer_is_read() {
+        let path = temp_path("legacy");
+        ciborium::ser::into_writer(&value(), std::fs::File::create(&path).unwrap()).unwrap();
+        let read: Vec<f64> = read_cbor(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, value());
+    }
 }
@@ -1,13 +1,24 @@
 pub mod app;
+pub mod boundary;
+pub mod convergence;
+pub mod diff;
+pub mod expression;
 pub mod galmod;
+pub mod homologous;
 pub mod io;
 pub mod lookup_table;
 pub mod lookup_table_v2;
 pub mod mesh;
 pub mod models;
+pub mod photosphere;
 pub mod physics;
+pub mod probes;
 pub mod products;
+pub mod profiling;
+pub mod profile;
+pub mod reductions;
 pub mod scheme;
 pub mod state;
 pub mod tasks;
+pub mod tracers;
 pub mod traits;
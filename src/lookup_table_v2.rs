@@ -16,6 +16,9 @@ pub enum Error {
 
     #[error("the table must have at least two rows")]
     TableTooSmall,
+
+    #[error("the table must list every point of a rectangular grid, with the second column varying fastest")]
+    IrregularGrid,
 }
 
 /// Encapsulates the logic to read and sample a tabulated function of a single
@@ -76,6 +79,12 @@ impl<const NUM_COLS: usize> LookupTable<NUM_COLS> {
         Self::from_ascii_table(&read_to_string(filename)?)
     }
 
+    /// Return the lowest and highest values of the left-most column. The
+    /// table can be sampled at `x` if `x0 < x <= x1`.
+    pub fn domain(&self) -> (f64, f64) {
+        (self.rows.first().unwrap()[0], self.rows.last().unwrap()[0])
+    }
+
     /// Return a fixed-length array of data at the given independent variable
     /// value `x`. The result is interpolated linearly between the two nearest
     /// tabulated points. This function panics if `x` is out of range (not
@@ -135,6 +144,109 @@ impl<const NUM_COLS: usize> LookupTable<NUM_COLS> {
     }
 }
 
+/// Encapsulates the logic to read and sample a tabulated function of two
+/// variables, given on a rectangular grid. Each row holds the two coordinates
+/// followed by `NUM_COLS - 2` floats, and the rows are ordered by the first
+/// coordinate, then by the second. Sampling the function returns an array of
+/// length `NUM_COLS`, whose first two elements are the (clamped) sample
+/// coordinates.
+///
+#[derive(Clone)]
+pub struct LookupTable2d<const NUM_COLS: usize> {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    rows: Vec<[f64; NUM_COLS]>,
+}
+
+impl<const NUM_COLS: usize> LookupTable2d<NUM_COLS> {
+    /// Return a lookup table from a `Vec` of rows. A `TableTooSmall` error is
+    /// returned if there are fewer than 2 grid points along either
+    /// coordinate, `UnorderedTable` if either coordinate does not increase
+    /// monotonically, and `IrregularGrid` if the rows do not cover a
+    /// rectangular grid in the expected order.
+    pub fn from_rows(rows: Vec<[f64; NUM_COLS]>) -> Result<Self, Error> {
+        let ny = rows.iter().take_while(|row| row[0] == rows[0][0]).count();
+
+        if ny < 2 || rows.len() < 2 * ny {
+            return Err(Error::TableTooSmall)
+        }
+        if rows.len() % ny != 0 {
+            return Err(Error::IrregularGrid)
+        }
+        let x: Vec<_> = rows.iter().step_by(ny).map(|row| row[0]).collect();
+        let y: Vec<_> = rows[..ny].iter().map(|row| row[1]).collect();
+
+        if x.windows(2).any(|w| w[1] <= w[0]) || y.windows(2).any(|w| w[1] <= w[0]) {
+            return Err(Error::UnorderedTable)
+        }
+        for (k, row) in rows.iter().enumerate() {
+            if row[0] != x[k / ny] || row[1] != y[k % ny] {
+                return Err(Error::IrregularGrid)
+            }
+        }
+        Ok(Self { x, y, rows })
+    }
+
+    /// Create a `LookupTable2d` by reading a string of ASCII data, with
+    /// whitespace-separated floats consumed in groups of `NUM_COLS`. An
+    /// `IrregularGrid` error is returned if the number of floats is not a
+    /// multiple of `NUM_COLS`.
+    pub fn from_ascii_table(contents: &str) -> Result<Self, Error> {
+        let values: Vec<f64> = contents.split_whitespace().map(|x| x.parse()).collect::<Result<_, _>>()?;
+
+        if values.len() % NUM_COLS != 0 {
+            return Err(Error::IrregularGrid)
+        }
+        let rows = values
+            .chunks(NUM_COLS)
+            .map(|chunk| {
+                let mut row = [0.0; NUM_COLS];
+                row.copy_from_slice(chunk);
+                row
+            })
+            .collect();
+        Self::from_rows(rows)
+    }
+
+    /// Convenience method to load the contents of an ASCII file and pass the
+    /// resulting string to `LookupTable2d::from_ascii_table`.
+    pub fn from_ascii_file(filename: &str) -> Result<Self, Error> {
+        Self::from_ascii_table(&read_to_string(filename)?)
+    }
+
+    /// Return a fixed-length array of data at the given point, bilinearly
+    /// interpolated between the four nearest tabulated points. Points outside
+    /// the table are clamped to its edges.
+    pub fn sample(&self, x: f64, y: f64) -> [f64; NUM_COLS] {
+        let (i, wx) = Self::bracket(&self.x, x);
+        let (j, wy) = Self::bracket(&self.y, y);
+        let ny = self.y.len();
+        let corners = [
+            (i * ny + j, (1.0 - wx) * (1.0 - wy)),
+            ((i + 1) * ny + j, wx * (1.0 - wy)),
+            (i * ny + j + 1, (1.0 - wx) * wy),
+            ((i + 1) * ny + j + 1, wx * wy),
+        ];
+        let mut result = [0.0; NUM_COLS];
+
+        for (k, w) in corners.iter() {
+            for (r, v) in result.iter_mut().zip(self.rows[*k].iter()) {
+                *r += w * v;
+            }
+        }
+        result
+    }
+
+    /// Return the index of the grid point at or below the clamped value of x,
+    /// and the weight of the grid point above it.
+    fn bracket(v: &[f64], x: f64) -> (usize, f64) {
+        let n = v.len();
+        let x = x.max(v[0]).min(v[n - 1]);
+        let i = v.partition_point(|&y| y <= x).max(1).min(n - 1) - 1;
+        (i, (x - v[i]) / (v[i + 1] - v[i]))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,6 +282,16 @@ mod tests {
         assert_eq!(table.indexes_straddling(1.5), (1, 2));
     }
 
+    #[test]
+    fn lookup_table_2d_is_exact_for_bilinear_functions() {
+        let f = |x: f64, y: f64| 1.0 + 2.0 * x + 3.0 * y + x * y;
+        let rows = (0..3).flat_map(|i| (0..4).map(move |j| (i as f64, j as f64 * 0.5))).map(|(x, y)| [x, y, f(x, y)]).collect();
+        let table = LookupTable2d::from_rows(rows).unwrap();
+        assert!(f64::abs(table.sample(1.3, 0.7)[2] - f(1.3, 0.7)) < 1e-12);
+        assert!(f64::abs(table.sample(5.0, -1.0)[2] - f(2.0, 0.0)) < 1e-12);
+        assert!(LookupTable2d::from_rows(vec![[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 0.0, 1.0]]).is_err());
+    }
+
     #[test]
     fn lookup_table_can_be_sampled_at_tabulated_points() {
         let table = LookupTable::from_rows(vec![[0.0, 0.1], [1.0, 0.2], [2.0, 0.3]]).unwrap();
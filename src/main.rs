@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use kilonova::*;
 use app::{
     AnyHydro,
@@ -5,16 +7,34 @@ use app::{
     AnyState,
     App,
     Configuration,
+    Crash,
     Control,
+    OutputFormat,
+    ParallelRuntime,
+};
+use convergence::{
+    Observable,
 };
 use mesh::{
+    BlockIndex,
+    GridGeometry,
     Mesh,
 };
+use physics::{
+    HydroError,
+};
 use products::{
     Products,
 };
+use profiling::{
+    Profile,
+};
+use reductions::{
+    Reductions,
+};
 use state::{
     State,
+    append_shock_radius_profile,
 };
 use traits::{
     Conserved,
@@ -29,11 +49,53 @@ use tasks::{
 
 
 // ============================================================================
-fn side_effects<C, M, H>(state: &State<C>, tasks: &mut Tasks, hydro: &H, model: &M, mesh: &Mesh, control: &Control)
+/**
+ * A checkpoint file which may be in the process of being written on a
+ * background thread. If this is dropped with the write still pending, e.g.
+ * when the run fails, the write is waited for, so that the checkpoint is not
+ * lost (and its temporary file left behind) when the process exits.
+ */
+#[derive(Default)]
+struct PendingWrite(Option<std::thread::JoinHandle<Result<(), io::Error>>>);
+
+impl PendingWrite {
+
+    /**
+     * Wait for a pending checkpoint write to finish, and return its error if
+     * it failed.
+     */
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(handle) = self.0.take() {
+            handle.join().map_err(|_| anyhow::anyhow!("the checkpoint writer thread panicked"))??;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if let Err(error) = self.finish() {
+            println!("checkpoint write failed: {}", error);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn side_effects<C, M, H>(
+    state: &mut State<C>,
+    tasks: &mut Tasks,
+    time_series: &mut Vec<Reductions>,
+    pending_write: &mut PendingWrite,
+    profile: &mut Profile,
+    geometry: &HashMap<BlockIndex, GridGeometry>,
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    control: &Control)
     -> anyhow::Result<()>
 where
-    H: Hydrodynamics<Conserved = C>,
-    M: InitialModel,
+    H: Hydrodynamics<Conserved = C> + Sync,
+    M: InitialModel + Sync,
     C: Conserved,
     AnyHydro: From<H>,
     AnyModel: From<M>,
@@ -41,59 +103,210 @@ where
 {
     if tasks.iteration_message.next_time <= state.time {
         let time = tasks.iteration_message.advance(0.0);
-        let mzps = 1e-6 * state.total_zones() as f64 / time * control.fold as f64;
+        let mzps = 1e-6 * (state.zone_updates - tasks.zone_updates_at_last_message) as f64 / time;
+        tasks.zone_updates_at_last_message = state.zone_updates;
         if tasks.iteration_message.count_this_run > 1 {
             println!("[{:05}] t={:.5} blocks={} Mzps={:.2})", state.iteration, state.time, state.solution.len(), mzps);
         }
     }
 
+    if let Some(profile_interval) = control.profile_interval {
+        if tasks.report_progress.next_time <= state.time {
+            let wall = tasks.report_progress.advance(profile_interval);
+            if tasks.report_progress.count_this_run > 1 {
+                let filename = format!("{}/profile.{:04}.csv", control.log_directory(), tasks.report_progress.count - 1);
+                profile.print_summary(wall);
+                std::fs::create_dir_all(control.log_directory())?;
+                println!("write {}", filename);
+                profile.write_csv(&mut std::io::BufWriter::new(std::fs::File::create(&filename)?))?;
+
+                if io::is_remote(&control.output_directory) {
+                    io::upload(&filename, &format!("{}/profile.{:04}.csv", control.output_directory, tasks.report_progress.count - 1))?;
+                }
+            }
+            *profile = Profile::default();
+        }
+    }
+
+    if let Some(time_series_interval) = control.time_series_interval {
+        if tasks.time_series.next_time <= state.time {
+            tasks.time_series.advance(time_series_interval);
+            std::fs::create_dir_all(control.log_directory())?;
+            Reductions::from_state(state, geometry).append_totals(&format!("{}/time_series.csv", control.log_directory()))?;
+        }
+    }
+
+    if let Some(shock_radius_interval) = control.shock_radius_interval {
+        if tasks.shock_radius.next_time <= state.time {
+            tasks.shock_radius.advance(shock_radius_interval);
+            let profile = state.shock_radius_profile(hydro, geometry)?;
+            std::fs::create_dir_all(control.log_directory())?;
+            append_shock_radius_profile(&format!("{}/shock_radius.dat", control.log_directory()), state.time, &profile)?;
+        }
+    }
+
+    if let Some(limit) = &state.time_step_limit {
+        std::fs::create_dir_all(control.log_directory())?;
+        limit.append(&format!("{}/timestep.dat", control.log_directory()), state.time)?;
+    }
+
+    if !state.probe_samples.is_empty() {
+        std::fs::create_dir_all(control.log_directory())?;
+
+        for (time, samples) in state.probe_samples.drain(..) {
+            probes::append(&format!("{}/probes.dat", control.log_directory()), time, &samples)?;
+        }
+    }
+
     if let Some(products_interval) = control.products_interval {
         if tasks.write_products.next_time <= state.time {
             tasks.write_products.advance(products_interval);
-            let filename = format!("{}/prods.{:04}.cbor", control.output_directory, tasks.write_products.count - 1);
+            let filename = format!("{}/prods.{:04}", control.output_directory, tasks.write_products.count - 1);
             let config = Configuration::package(hydro, model, mesh, control);
-            let products = Products::try_from_state(state, hydro, &config)?;
-            std::fs::create_dir_all(&control.output_directory)?;
-            io::write_cbor(&products, &filename)?;
+            let products = Products::try_from_state(state, hydro, &config)?.downsampled(control.products_downsample);
+            io::create_output_directory(&control.output_directory)?;
+            io::write_output(&products, &filename, control.output_format, control.compression_level())?;
         }
     }
 
     if tasks.write_checkpoint.next_time <= state.time {
         tasks.write_checkpoint.advance(control.checkpoint_interval);
-        let filename = format!("{}/chkpt.{:04}.cbor", control.output_directory, tasks.write_checkpoint.count - 1);
-        let app = App::package(state, tasks, hydro, model, mesh, control);
-        std::fs::create_dir_all(&control.output_directory)?;
-        io::write_cbor(&app, &filename)?;
+        let filename = format!("{}/chkpt.{:04}", control.output_directory, tasks.write_checkpoint.count - 1);
+        time_series.push(Reductions::from_state(state, geometry)
+            .try_with_custom(state, hydro, geometry, &control.custom_reductions)?
+            .try_with_opening_angle(state, hydro, geometry, &control.opening_angle)?
+            .try_with_isotropic_energy(state, hydro, geometry, &control.isotropic_energy_cuts)?);
+        let app = App::package(state, tasks, time_series, hydro, model, mesh, control);
+        io::create_output_directory(&control.output_directory)?;
+
+        if control.async_output {
+            pending_write.finish()?;
+            let (format, level) = (control.output_format, control.compression_level());
+            pending_write.0 = Some(std::thread::spawn(move || io::write_checkpoint(&app, &filename, format, level)));
+        } else {
+            io::write_checkpoint(&app, &filename, control.output_format, control.compression_level())?;
+        }
+        upload_log_files(control)?;
     }
 
     Ok(())
 }
 
+/**
+ * If the output directory is in object storage, upload the files which are
+ * appended to as the run goes (see [`Control::log_directory`]) to it. They
+ * are uploaded with each checkpoint, replacing the previous upload.
+ */
+fn upload_log_files(control: &Control) -> anyhow::Result<()> {
+    if io::is_remote(&control.output_directory) {
+        for name in ["time_series.csv", "shock_radius.dat", "timestep.dat", "probes.dat"] {
+            let filename = format!("{}/{}", control.log_directory(), name);
+
+            if std::path::Path::new(&filename).exists() {
+                io::upload(&filename, &format!("{}/{}", control.output_directory, name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Pass through the result of a step of the run. If it failed with a hydro
+ * error (e.g. a failed conversion to primitive), first write the given
+ * state, which is the last one known to be good, to crash.cbor in the output
+ * directory, along with the error and the block and zone where it occurred.
+ * The crash checkpoint can be inspected, or restarted from like any other.
+ */
+#[allow(clippy::too_many_arguments)]
+fn crash_on_hydro_error<T, C, M, H>(
+    result: anyhow::Result<T>,
+    state: &State<C>,
+    tasks: &Tasks,
+    time_series: &[Reductions],
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    control: &Control)
+    -> anyhow::Result<T>
+where
+    H: Hydrodynamics<Conserved = C>,
+    M: InitialModel,
+    C: Conserved,
+    AnyHydro: From<H>,
+    AnyModel: From<M>,
+    AnyState: From<State<C>>,
+{
+    let error = match result {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+    if let Some(hydro_error) = error.downcast_ref::<HydroError>() {
+        let position = hydro_error.position();
+        let zone = mesh::locate_zone(&state.geometry(mesh), position);
+        let filename = format!("{}/crash.cbor", control.output_directory);
+        let app = App {
+            crash: Some(Crash{message: format!("{:#}", error), position: Some(position), zone}),
+            ..App::package(state, tasks, time_series, hydro, model, mesh, control)
+        };
+        if let Some((index, zone)) = zone {
+            println!("crash: hydro error in block {:?} zone {:?}", index, zone);
+        }
+        io::create_output_directory(&control.output_directory)?;
+        io::write_cbor(&app, &filename)?;
+    }
+    Err(error)
+}
+
 
 
 
 // ============================================================================
-fn run<C, M, H>(mut state: State<C>, mut tasks: Tasks, hydro: H, model: M, mesh: Mesh, control: Control)
+#[allow(clippy::too_many_arguments)]
+fn run<C, M, H>(
+    mut state: State<C>,
+    mut tasks: Tasks,
+    mut time_series: Vec<Reductions>,
+    hydro: H,
+    mut model: M,
+    mesh: Mesh,
+    control: Control,
+    ramp_iterations: usize)
     -> anyhow::Result<()>
 where
-    H: Hydrodynamics<Conserved = C>,
-    M: InitialModel,
+    H: Hydrodynamics<Conserved = C> + Sync,
+    M: InitialModel + Sync,
     C: Conserved,
     AnyHydro: From<H>,
     AnyModel: From<M>,
     AnyState: From<State<C>>,
 {
-    let mut block_geometry = mesh.grid_blocks_geometry(state.time);
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(control.num_threads())
-        .build()?;
+    let mut ramp_iteration = 0;
+    let mut block_geometry = state.geometry(&mesh);
+    let runtimes = build_runtimes(&control)?;
+    let mut pending_write = PendingWrite::default();
+    let mut profile = Profile::default();
 
     while state.time < control.final_time {
-        side_effects(&state, &mut tasks, &hydro, &model, &mesh, &control)?;
-        state = scheme::advance(state, &hydro, &model, &mesh, &mut block_geometry, &runtime, control.fold)?;
+        let result = side_effects(&mut state, &mut tasks, &mut time_series, &mut pending_write, &mut profile, &block_geometry, &hydro, &model, &mesh, &control);
+        crash_on_hydro_error(result, &state, &tasks, &time_series, &hydro, &model, &mesh, &control)?;
+        if model.needs_regulation() {
+            model.regulate(&Reductions::from_state(&state, &block_geometry));
+        }
+        let dt_factor = control.safety_ramp.dt_factor(ramp_iteration, ramp_iterations);
+        let fold_profile = if control.profile_interval.is_some() { Some(&mut profile) } else { None };
+
+        // The state is consumed by the update, so a copy is kept for the
+        // crash checkpoint. The solution arrays are shared, not copied.
+        let last_good = state.clone();
+        let result = advance_fold(state, &hydro, &model, &mesh, &mut block_geometry, &control, &runtimes, dt_factor, fold_profile);
+        state = crash_on_hydro_error(result, &last_good, &tasks, &time_series, &hydro, &model, &mesh, &control)?;
+        ramp_iteration += control.fold;
     }
 
-    side_effects(&state, &mut tasks, &hydro, &model, &mesh, &control)?;
+    let result = side_effects(&mut state, &mut tasks, &mut time_series, &mut pending_write, &mut profile, &block_geometry, &hydro, &model, &mesh, &control);
+    crash_on_hydro_error(result, &state, &tasks, &time_series, &hydro, &model, &mesh, &control)?;
+    pending_write.finish()?;
+    upload_log_files(&control)?;
 
     Ok(())
 }
@@ -101,6 +314,647 @@ where
 
 
 
+// ============================================================================
+/**
+ * Build the tokio runtime and the rayon thread pool, with the number of
+ * threads and the thread pinning given in the control options. Only the one
+ * selected by the runtime option is used to advance the solution.
+ */
+fn build_runtimes(control: &Control) -> anyhow::Result<(tokio::runtime::Runtime, rayon::ThreadPool)> {
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    let mut pool = rayon::ThreadPoolBuilder::new().num_threads(control.num_threads());
+    runtime.worker_threads(control.num_threads());
+
+    if control.pin_threads {
+        let next_core = AtomicUsize::new(0);
+        runtime.on_thread_start(move || pin_to_core(next_core.fetch_add(1, Ordering::Relaxed)));
+        pool = pool.start_handler(pin_to_core);
+    }
+    Ok((runtime.build()?, pool.build()?))
+}
+
+/**
+ * Advance the state by `control.fold` iterations, on the parallel runtime
+ * selected in the control options, adding the block update times to the
+ * profile if one is given. If `control.check_cfl` is set, the time step of
+ * the last iteration is then checked against the signal crossing times.
+ */
+#[allow(clippy::too_many_arguments)]
+fn advance_fold<C, M, H>(
+    state: State<C>,
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    geometry: &mut HashMap<BlockIndex, GridGeometry>,
+    control: &Control,
+    runtimes: &(tokio::runtime::Runtime, rayon::ThreadPool),
+    dt_factor: f64,
+    profile: Option<&mut Profile>) -> anyhow::Result<State<C>>
+where
+    H: Hydrodynamics<Conserved = C> + Sync,
+    M: InitialModel + Sync,
+    C: Conserved,
+{
+    let (runtime, pool) = runtimes;
+
+    let state = match control.runtime {
+        ParallelRuntime::Tokio => {
+            scheme::advance(state, hydro, model, mesh, geometry, runtime, control.fold, dt_factor, &control.probes, profile)?
+        }
+        ParallelRuntime::Rayon => {
+            pool.install(|| scheme::advance_rayon(state, hydro, model, mesh, geometry, control.fold, dt_factor, &control.probes, profile))?
+        }
+    };
+    if control.check_cfl {
+        if let Some(limit) = &state.time_step_limit {
+            state.check_cfl(hydro, mesh, geometry, limit.dt)?;
+        }
+    }
+    Ok(state)
+}
+
+/**
+ * Pin the current thread to the n-th CPU core (modulo the number of cores),
+ * if the core IDs can be determined on this platform.
+ */
+fn pin_to_core(n: usize) {
+    if let Some(core_ids) = core_affinity::get_core_ids() {
+        if !core_ids.is_empty() {
+            core_affinity::set_for_current(core_ids[n % core_ids.len()]);
+        }
+    }
+}
+
+fn print_mesh_summary(mesh: &Mesh, time: f64) {
+    let summary = mesh.summary(time);
+    println!("mesh blocks ......... {}", summary.num_blocks);
+    println!("mesh zones .......... {}", summary.num_zones);
+    println!("dr / r .............. {:.4}", summary.dr_over_r);
+    println!("zones per decade .... {:.1}", summary.zones_per_decade);
+    println!("estimated memory .... {:.1} MB", summary.memory_bytes as f64 / 1e6);
+}
+
+fn mesh_info(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args.into_iter();
+    let input = args.next().ok_or_else(|| anyhow::anyhow!("usage: kilonova mesh-info <input.yaml|chkpt.cbor|preset> [group.key=value] [...]"))?;
+
+    let (mesh, time) = if input.ends_with(".cbor") {
+        let app = App::from_file(&input, args.collect())?;
+        let time = match &app.state {
+            AnyState::Newtonian(state) => state.time,
+            AnyState::Relativistic(state) => state.time,
+        };
+        (app.config.mesh, time)
+    } else {
+        let config = App::config_from_preset_or_file(&input, args.collect())?;
+        (config.mesh, config.control.start_time)
+    };
+    mesh.validate(time)?;
+    print_mesh_summary(&mesh, time);
+    Ok(())
+}
+
+
+
+
+// ============================================================================
+/**
+ * Read a products file, or a checkpoint, which is converted to products. The
+ * file is read as a checkpoint only if it could not be decoded as products;
+ * other errors, such as a checksum mismatch, are returned as they are.
+ */
+fn read_products_or_checkpoint(filename: &str) -> anyhow::Result<Products> {
+    match io::read_cbor::<Products>(filename) {
+        Ok(products) => Ok(products),
+        Err(io::Error::CiboriumDe(_)) => Ok(Products::try_from_app(&App::from_file(filename, vec![])?)?),
+        Err(error) => Err(error.into()),
+    }
+}
+
+
+
+
+// ============================================================================
+fn sample(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args.into_iter();
+    let usage = "usage: kilonova sample <input.yaml|preset> <output.cbor> [group.key=value] [...]";
+    let input = args.next().ok_or_else(|| anyhow::anyhow!(usage))?;
+    let output = args.next().ok_or_else(|| anyhow::anyhow!(usage))?;
+
+    if input.ends_with(".cbor") {
+        anyhow::bail!("sample requires a config file or preset, not a checkpoint")
+    }
+    let app = App::from_preset_or_file(&input, args.collect())?.validate()?;
+    print_mesh_summary(&app.config.mesh, app.config.control.start_time);
+    println!("write {}", output);
+    Ok(io::write_cbor(&Products::try_from_app(&app)?, &output)?)
+}
+
+
+
+
+// ============================================================================
+fn products(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input: Option<String> = None;
+    let mut output = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or_else(|| anyhow::anyhow!("-o requires a value"))?),
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova products <chkpt.cbor> [-o prods.cbor|prods.h5]"))?;
+    let app = App::from_file(&input, vec![])?;
+    let control = &app.config.control;
+
+    let format = match &output {
+        Some(output) if output.ends_with(".h5") => OutputFormat::Hdf5,
+        Some(_) => OutputFormat::Cbor,
+        None => control.output_format,
+    };
+    let output = output.unwrap_or_else(|| {
+        let path = std::path::Path::new(&input);
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let name = name.strip_suffix(".cbor").unwrap_or(name);
+        let name = name.strip_prefix("chkpt.").unwrap_or(name);
+        let extension = match format {
+            OutputFormat::Cbor => "cbor",
+            OutputFormat::Hdf5 => "h5",
+        };
+        path.with_file_name(format!("prods.{}.{}", name, extension)).to_string_lossy().into_owned()
+    });
+
+    let products = Products::try_from_app(&app)?.downsampled(control.products_downsample);
+
+    match format {
+        OutputFormat::Cbor => io::write_cbor_compressed(&products, &output, control.compression_level())?,
+        OutputFormat::Hdf5 => io::write_hdf5(&products, &output)?,
+    }
+    Ok(())
+}
+
+
+
+
+// ============================================================================
+fn profile(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut theta = 0.0;
+    let mut radius = None;
+    let mut fields = vec![profile::Field::Rho, profile::Field::Pre, profile::Field::Ur];
+    let mut output = "profile.csv".to_string();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--theta" => theta = args.next().ok_or_else(|| anyhow::anyhow!("--theta requires a value"))?.parse()?,
+            "--radius" => radius = Some(args.next().ok_or_else(|| anyhow::anyhow!("--radius requires a value"))?.parse()?),
+            "--fields" => fields = args.next().ok_or_else(|| anyhow::anyhow!("--fields requires a value"))?
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            "-o" => output = args.next().ok_or_else(|| anyhow::anyhow!("-o requires a value"))?,
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova profile <file.cbor> [--theta 0.1 | --radius 1e10] [--fields rho,pre,ur] [-o profile.csv]"))?;
+
+    let products = read_products_or_checkpoint(&input)?;
+    let (coordinate, rows) = match radius {
+        Some(radius) => ("theta", profile::polar_profile(&products, radius, &fields)),
+        None => ("r", profile::radial_profile(&products, theta, &fields)),
+    };
+
+    println!("write {}", output);
+    let mut buffer = std::io::BufWriter::new(std::fs::File::create(&output)?);
+    Ok(profile::write_csv(&mut buffer, coordinate, &fields, &rows)?)
+}
+
+
+
+
+// ============================================================================
+fn homologous(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut time = None;
+    let mut output = "ejecta.csv".to_string();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--time" => time = Some(args.next().ok_or_else(|| anyhow::anyhow!("--time requires a value"))?.parse()?),
+            "-o" => output = args.next().ok_or_else(|| anyhow::anyhow!("-o requires a value"))?,
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova homologous <file.cbor> [--time 86400] [-o ejecta.csv]"))?;
+
+    let products = read_products_or_checkpoint(&input)?;
+    let rows = homologous::ejecta_table(&products, time)?;
+
+    println!("write {}", output);
+    let mut buffer = std::io::BufWriter::new(std::fs::File::create(&output)?);
+    Ok(homologous::write_csv(&mut buffer, time.unwrap_or(products.time), &rows)?)
+}
+
+
+
+
+// ============================================================================
+fn regrid(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut num_theta = None;
+    let mut log_r: Option<Vec<f64>> = None;
+    let mut output = "regrid.cbor".to_string();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--num-theta" => num_theta = Some(args.next().ok_or_else(|| anyhow::anyhow!("--num-theta requires a value"))?.parse()?),
+            "--log-r" => log_r = Some(args.next().ok_or_else(|| anyhow::anyhow!("--log-r requires a value"))?
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()?),
+            "-o" => output = args.next().ok_or_else(|| anyhow::anyhow!("-o requires a value"))?,
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova regrid <file.cbor> [--num-theta 64] [--log-r r_min,r_max,num_bins] [-o regrid.cbor]"))?;
+
+    let mut products = read_products_or_checkpoint(&input)?;
+    if let Some(num_theta) = num_theta {
+        products = products.polar_regridded(num_theta);
+    }
+    if let Some(log_r) = log_r {
+        if log_r.len() != 3 || log_r[0] <= 0.0 || log_r[2] < 1.0 {
+            anyhow::bail!("--log-r requires three values: r_min,r_max,num_bins, with r_min > 0")
+        }
+        let num_bins = log_r[2] as usize;
+        let bins: Vec<_> = (0..=num_bins).map(|k| log_r[0] * (log_r[1] / log_r[0]).powf(k as f64 / num_bins as f64)).collect();
+        products = products.radial_rebinned(&bins)?;
+    }
+    Ok(io::write_cbor(&products, &output)?)
+}
+
+
+
+
+// ============================================================================
+fn extrapolate(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut times = Vec::new();
+    let mut output_directory = ".".to_string();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--times" => times = args.next().ok_or_else(|| anyhow::anyhow!("--times requires a value"))?
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            "-d" => output_directory = args.next().ok_or_else(|| anyhow::anyhow!("-d requires a value"))?,
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova extrapolate <file.cbor> --times 1e5,1e6 [-d output_directory]"))?;
+
+    let products = read_products_or_checkpoint(&input)?;
+
+    for (count, &time) in times.iter().enumerate() {
+        if time < products.time {
+            anyhow::bail!("cannot extrapolate to t={:.4e}, before the products time t={:.4e}", time, products.time)
+        }
+        let filename = format!("{}/extrap.{:04}.cbor", output_directory, count);
+        io::write_cbor(&products.extrapolated(time), &filename)?;
+    }
+    Ok(())
+}
+
+
+
+
+// ============================================================================
+fn light_curve(args: Vec<String>) -> anyhow::Result<()> {
+    let mut inputs = Vec::new();
+    let mut opacity = photosphere::Opacity{kappa: 10.0, lanthanide_kappa: None, ye_threshold: 0.25};
+    let mut output = "light_curve.csv".to_string();
+    let mut rays_output = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kappa" => opacity.kappa = args.next().ok_or_else(|| anyhow::anyhow!("--kappa requires a value"))?.parse()?,
+            "--lanthanide-kappa" => opacity.lanthanide_kappa = Some(args.next().ok_or_else(|| anyhow::anyhow!("--lanthanide-kappa requires a value"))?.parse()?),
+            "--ye-threshold" => opacity.ye_threshold = args.next().ok_or_else(|| anyhow::anyhow!("--ye-threshold requires a value"))?.parse()?,
+            "--rays" => rays_output = Some(args.next().ok_or_else(|| anyhow::anyhow!("--rays requires a value"))?),
+            "-o" => output = args.next().ok_or_else(|| anyhow::anyhow!("-o requires a value"))?,
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        anyhow::bail!("usage: kilonova light-curve <file.cbor> [...] [--kappa 10] [--lanthanide-kappa 30] [--ye-threshold 0.25] [--rays photosphere.csv] [-o light_curve.csv]")
+    }
+    if opacity.kappa <= 0.0 || opacity.lanthanide_kappa.unwrap_or(1.0) <= 0.0 {
+        anyhow::bail!("opacities must be positive")
+    }
+
+    let mut photospheres = inputs.iter().map(|input| {
+        let products = read_products_or_checkpoint(input)?;
+        photosphere::photosphere(&products, &opacity)
+    }).collect::<anyhow::Result<Vec<_>>>()?;
+
+    photospheres.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+
+    if let Some(rays_output) = rays_output {
+        println!("write {}", rays_output);
+        let mut buffer = std::io::BufWriter::new(std::fs::File::create(&rays_output)?);
+        photosphere::write_rays(&mut buffer, &photospheres)?;
+    }
+    println!("write {}", output);
+    let mut buffer = std::io::BufWriter::new(std::fs::File::create(&output)?);
+    Ok(photosphere::write_light_curve(&mut buffer, &photospheres)?)
+}
+
+
+
+
+// ============================================================================
+fn diff(args: Vec<String>) -> anyhow::Result<()> {
+    let mut inputs = Vec::new();
+    let mut tol = 1e-12;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tol" => tol = args.next().ok_or_else(|| anyhow::anyhow!("--tol requires a value"))?.parse()?,
+            _ if inputs.len() < 2 => inputs.push(arg),
+            _ => anyhow::bail!("unexpected argument '{}'", arg),
+        }
+    }
+    if inputs.len() != 2 {
+        anyhow::bail!("usage: kilonova diff <a.cbor> <b.cbor> [--tol 1e-12]")
+    }
+    let differences = diff::compare(&read_products_or_checkpoint(&inputs[0])?, &read_products_or_checkpoint(&inputs[1])?)?;
+    let mut failed = 0;
+
+    println!("{:<20} {:>12} {:>24} {:>20} {:>20}", "field", "rel. error", "location", "a", "b");
+
+    for d in &differences {
+        let location = match d.location {
+            Some((index, zone)) => format!("{:?} {:?}", index, zone),
+            None => "-".to_string(),
+        };
+        let flag = if d.relative_error > tol { failed += 1; " *" } else { "" };
+        println!("{:<20} {:>12.3e} {:>24} {:>20.12e} {:>20.12e}{}", d.field, d.relative_error, location, d.values.0, d.values.1, flag);
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} fields differ by more than {:.1e}", failed, differences.len(), tol)
+    }
+    println!();
+    println!("all fields agree to within {:.1e}", tol);
+    Ok(())
+}
+
+
+
+
+// ============================================================================
+fn converge(args: Vec<String>) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut levels = 3;
+    let mut observable = Observable::ShockRadius;
+    let mut overrides = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--levels" => levels = args.next().ok_or_else(|| anyhow::anyhow!("--levels requires a value"))?.parse()?,
+            "--observable" => observable = args.next().ok_or_else(|| anyhow::anyhow!("--observable requires a value"))?.parse()?,
+            _ if input.is_none() => input = Some(arg),
+            _ => overrides.push(arg),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow::anyhow!("usage: kilonova converge <input.yaml|preset> [--levels 3] [--observable shock_radius] [group.key=value] [...]"))?;
+
+    if levels < 2 {
+        anyhow::bail!("a convergence study requires at least 2 levels")
+    }
+    let mut zones = Vec::new();
+    let mut values = Vec::new();
+
+    for level in 0..levels {
+        let mut config = App::config_from_preset_or_file(&input, overrides.clone())?;
+        config.mesh = convergence::refined_mesh(&config.mesh, level);
+
+        let App{state, config, ..} = App::from_config(config, vec![])?.validate()?;
+        let (hydro, model, mesh, control) = config.into_run_parts();
+        let num_zones = mesh.summary(control.start_time).num_zones;
+
+        println!("level {}: {} zones", level, num_zones);
+
+        let value = match (state, hydro) {
+            (AnyState::Newtonian(state), AnyHydro::Newtonian(hydro)) => {
+                evolve_and_measure(state, hydro, model, mesh, control, observable)?
+            },
+            (AnyState::Relativistic(state), AnyHydro::Relativistic(hydro)) => {
+                evolve_and_measure(state, hydro, model, mesh, control, observable)?
+            },
+            _ => unreachable!(),
+        };
+        zones.push(num_zones);
+        values.push(value);
+    }
+    let orders = convergence::convergence_orders(&values);
+
+    println!();
+    println!("{:>6} {:>12} {:>20} {:>8}", "level", "zones", observable.name(), "order");
+
+    for (level, (num_zones, value)) in zones.iter().zip(&values).enumerate() {
+        let order = match level.checked_sub(2) {
+            Some(n) => format!("{:.3}", orders[n]),
+            None => "-".to_string(),
+        };
+        println!("{:>6} {:>12} {:>20.12e} {:>8}", level, num_zones, value, order);
+    }
+    Ok(())
+}
+
+/**
+ * Run the state to the final time with no side effects, and return the
+ * value of the observable on the final state.
+ */
+fn evolve_and_measure<C, M, H>(
+    mut state: State<C>,
+    hydro: H,
+    mut model: M,
+    mesh: Mesh,
+    control: Control,
+    observable: Observable) -> anyhow::Result<f64>
+where
+    H: Hydrodynamics<Conserved = C> + Sync,
+    M: InitialModel + Sync,
+    C: Conserved,
+{
+    let mut block_geometry = state.geometry(&mesh);
+    let runtimes = build_runtimes(&control)?;
+
+    while state.time < control.final_time {
+        if model.needs_regulation() {
+            model.regulate(&Reductions::from_state(&state, &block_geometry));
+        }
+        state = advance_fold(state, &hydro, &model, &mesh, &mut block_geometry, &control, &runtimes, 1.0, None)?;
+    }
+    Ok(observable.measure(&state, &hydro, &block_geometry)?)
+}
+
+
+
+
+// ============================================================================
+/**
+ * Start or restart a run from a config file, preset, or checkpoint, with the
+ * given overrides, and the --remap flag if restarting on a different mesh.
+ */
+fn launch(input: String, args: Vec<String>) -> anyhow::Result<()> {
+    let remap = args.iter().any(|arg| arg == "--remap");
+    let overrides: Vec<_> = args.into_iter().filter(|arg| arg != "--remap").collect();
+
+    let (app, hydro_changed) = if input.ends_with(".cbor") {
+        let app = App::from_file(&input, vec![])?;
+        let mut config = app.config.clone();
+        config.patch_from(overrides)?;
+        let hydro_changed = serde_yaml::to_string(&config.hydro)? != serde_yaml::to_string(&app.config.hydro)?;
+
+        if remap {
+            let new_mesh = config.mesh.clone();
+            (App{config: Configuration{mesh: app.config.mesh, ..config}, ..app}.remap(new_mesh)?, hydro_changed)
+        } else {
+            (App{config, ..app}, hydro_changed)
+        }
+    } else if remap {
+        anyhow::bail!("--remap can only be used when restarting from a checkpoint")
+    } else {
+        (App::from_preset_or_file(&input, overrides)?, false)
+    };
+    let App{state, tasks, config, time_series, ..} = app.validate()?;
+    let config_yaml = serde_yaml::to_string(&config)?;
+
+    for line in config_yaml.split("\n").skip(1) {
+        println!("{}", line);
+    }
+    println!();
+
+    write_effective_config(&config_yaml, &config.control.output_directory, &input)?;
+
+    let (hydro, model, mesh, control) = config.into_run_parts();
+
+    println!("worker threads ...... {}", control.num_threads());
+    println!("pin threads ......... {}", control.pin_threads);
+    println!("compute cores ....... {}", num_cpus::get());
+    println!();
+
+    let time = match &state {
+        AnyState::Newtonian(state) => state.time,
+        AnyState::Relativistic(state) => state.time,
+    };
+    print_mesh_summary(&mesh, time);
+    println!();
+
+    let ramp_iterations = if hydro_changed {
+        println!("hydro parameters changed on restart: ramping up the time step over {} iterations", control.safety_ramp.iterations);
+        println!();
+        control.safety_ramp.iterations
+    } else {
+        0
+    };
+
+    match (state, hydro) {
+        (AnyState::Newtonian(state), AnyHydro::Newtonian(hydro)) => {
+            run(state, tasks, time_series, hydro, model, mesh, control, ramp_iterations)
+        },
+        (AnyState::Relativistic(state), AnyHydro::Relativistic(hydro)) => {
+            run(state, tasks, time_series, hydro, model, mesh, control, ramp_iterations)
+        },
+        _ => unreachable!(),
+    }
+}
+
+
+
+
+/**
+ * Write the fully resolved configuration of a run, including any overrides
+ * and defaults, to config.yaml in the output directory, so that the run
+ * directory documents itself. This is skipped if that file is the input
+ * file of the run, which would otherwise be overwritten.
+ */
+fn write_effective_config(config_yaml: &str, output_directory: &str, input: &str) -> anyhow::Result<()> {
+    let filename = format!("{}/config.yaml", output_directory);
+    let path = std::path::Path::new(&filename);
+
+    if path.exists() && path.canonicalize()? == std::path::Path::new(input).canonicalize().unwrap_or_default() {
+        println!("not writing {}, which is the input file", filename);
+        println!();
+        return Ok(())
+    }
+    io::create_output_directory(output_directory)?;
+    println!("write {}", filename);
+    Ok(io::write_atomically(&filename, |temp_path| Ok(std::fs::write(temp_path, config_yaml)?))?)
+}
+
+/**
+ * Return the path of the checkpoint in the given directory with the largest
+ * number, ignoring crash checkpoints and incomplete (temporary) files.
+ */
+fn latest_checkpoint(directory: &str) -> anyhow::Result<String> {
+    if io::is_remote(directory) {
+        anyhow::bail!("cannot resume from object storage: download the checkpoint, and restart from it")
+    }
+    let mut latest: Option<(usize, std::path::PathBuf)> = None;
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("chkpt."))
+            .and_then(|name| name.strip_suffix(".cbor"))
+            .and_then(|number| number.parse::<usize>().ok());
+
+        if let Some(number) = number {
+            if latest.as_ref().map_or(true, |(n, _)| number > *n) {
+                latest = Some((number, path));
+            }
+        }
+    }
+    match latest {
+        Some((_, path)) => Ok(path.to_string_lossy().into_owned()),
+        None => anyhow::bail!("no checkpoints found in {}", directory),
+    }
+}
+
+/**
+ * Restart a run from the latest checkpoint in the given output directory.
+ */
+fn resume(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args.into_iter();
+    let directory = args.next().ok_or_else(|| anyhow::anyhow!("usage: kilonova resume <output_directory> [group.key=value] [...] [--remap]"))?;
+    let input = latest_checkpoint(&directory)?;
+    println!("resume from {}", input);
+    println!();
+    launch(input, args.collect())
+}
+
+
+
+
 // ============================================================================
 fn main() -> anyhow::Result<()> {
 
@@ -111,7 +965,7 @@ fn main() -> anyhow::Result<()> {
 
     match std::env::args().nth(1) {
         None => {
-            println!("usage: kilonova <input.yaml|chkpt.cbor|preset> [opts.yaml|group.key=value] [...]");
+            println!("usage: kilonova <input.yaml|chkpt.cbor|preset> [opts.yaml|group.key=value] [...] [--remap]");
             println!();
             println!("These are the preset model setups:");
             println!();
@@ -120,32 +974,98 @@ fn main() -> anyhow::Result<()> {
             }
             println!();
             println!("To run any of these presets, run e.g. `kilonova jet_in_star`.");
+            println!();
+            println!("To restart from a checkpoint on a different mesh, pass the mesh overrides");
+            println!("with --remap, e.g. `kilonova chkpt.0010.cbor mesh.block_size=200 --remap`.");
+            println!();
+            println!("To restart from the latest checkpoint in an output directory:");
+            println!();
+            println!("  kilonova resume <output_directory> [group.key=value] [...] [--remap]");
+            println!();
+            println!("To convert a checkpoint to a products file, as written every products_interval");
+            println!("(defaults to prods.XXXX.cbor or prods.XXXX.h5, by output_format, alongside chkpt.XXXX.cbor):");
+            println!();
+            println!("  kilonova products <chkpt.cbor> [-o prods.cbor|prods.h5]");
+            println!();
+            println!("To export a radial profile (at a polar angle), or a polar profile (at a radius)");
+            println!("from a products or checkpoint file:");
+            println!();
+            println!("  kilonova profile <file.cbor> [--theta 0.1 | --radius 1e10] [--fields rho,pre,ur,uq,scalar] [-o profile.csv]");
+            println!();
+            println!("To export the ejecta density and electron fraction in homologous coordinates");
+            println!("(v = r / t), optionally extrapolated to a later time:");
+            println!();
+            println!("  kilonova homologous <file.cbor> [--time 86400] [-o ejecta.csv]");
+            println!();
+            println!("To remap products onto uniform polar bins, and logarithmic radial bins:");
+            println!();
+            println!("  kilonova regrid <file.cbor> [--num-theta 64] [--log-r r_min,r_max,num_bins] [-o regrid.cbor]");
+            println!();
+            println!("To extrapolate products to later times, assuming free expansion:");
+            println!();
+            println!("  kilonova extrapolate <file.cbor> --times 1e5,1e6 [-d output_directory]");
+            println!();
+            println!("To estimate the photosphere along each radial ray, and a bolometric light curve,");
+            println!("from a sequence of products or checkpoint files, with a grey opacity (cm^2/g)");
+            println!("which is optionally higher in material with Ye below a threshold:");
+            println!();
+            println!("  kilonova light-curve <file.cbor> [...] [--kappa 10] [--lanthanide-kappa 30] [--ye-threshold 0.25] [--rays photosphere.csv] [-o light_curve.csv]");
+            println!();
+            println!("To sample the initial condition of a setup to a products file, without running:");
+            println!();
+            println!("  kilonova sample <input.yaml|preset> <output.cbor> [group.key=value] [...]");
+            println!();
+            println!("To report the size of a mesh without running:");
+            println!();
+            println!("  kilonova mesh-info <input.yaml|chkpt.cbor|preset> [group.key=value] [...]");
+            println!();
+            println!("To compare two products or checkpoint files field by field, and fail if any");
+            println!("field differs by more than a relative tolerance:");
+            println!();
+            println!("  kilonova diff <a.cbor> <b.cbor> [--tol 1e-12]");
+            println!();
+            println!("To run a setup at successively doubled resolutions, and report the order of");
+            println!("convergence of an observable (shock_radius, mass, energy, radial_momentum,");
+            println!("scalar_mass) at the final time:");
+            println!();
+            println!("  kilonova converge <input.yaml|preset> [--levels 3] [--observable shock_radius] [group.key=value] [...]");
             Ok(())
         }
+        Some(command) if command == "products" => {
+            products(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "profile" => {
+            profile(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "sample" => {
+            sample(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "mesh-info" => {
+            mesh_info(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "regrid" => {
+            regrid(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "extrapolate" => {
+            extrapolate(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "homologous" => {
+            homologous(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "light-curve" => {
+            light_curve(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "diff" => {
+            diff(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "converge" => {
+            converge(std::env::args().skip(2).collect())
+        }
+        Some(command) if command == "resume" => {
+            resume(std::env::args().skip(2).collect())
+        }
         Some(input) => {
-            let overrides = std::env::args().skip(2).collect();
-            let App{state, tasks, config, ..} = App::from_preset_or_file(&input, overrides)?.validate()?;
-
-            for line in serde_yaml::to_string(&config)?.split("\n").skip(1) {
-                println!("{}", line);
-            }
-            println!();
-
-            let Configuration{hydro, model, mesh, control} = config;
-
-            println!("worker threads ...... {}", control.num_threads());
-            println!("compute cores ....... {}", num_cpus::get());
-            println!();
-
-            match (state, hydro) {
-                (AnyState::Newtonian(state), AnyHydro::Newtonian(hydro)) => {
-                    run(state, tasks, hydro, model, mesh, control)
-                },
-                (AnyState::Relativistic(state), AnyHydro::Relativistic(hydro)) => {
-                    run(state, tasks, hydro, model, mesh, control)
-                },
-                _ => unreachable!(),
-            }
+            launch(input, std::env::args().skip(2).collect())
         }
     }
 }
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::ops::Range;
 use ndarray::{ArcArray, Array, Ix1, Ix2};
 use serde::{Serialize, Deserialize};
+use crate::boundary::Boundaries;
+use crate::physics::{AnyConserved, AnyPrimitive};
 
 
 
@@ -14,6 +17,14 @@ pub type BlockIndex = (i32, usize);
 
 
 
+/// A rough estimate of the memory needed per zone: the conserved and scalar
+/// data with a Runge-Kutta stage copy, the cached geometry, and the
+/// primitives, gradients, and fluxes allocated during a block update
+pub static BYTES_PER_ZONE: usize = 320;
+
+
+
+
 /**
  * A struct to hold cached calculations of geometric primitives relevant to
  * spherical polar geometry
@@ -26,6 +37,40 @@ pub struct GridGeometry {
     pub polar_face_areas:  ArcArray<f64, Ix2>,
     pub cell_volumes:      ArcArray<f64, Ix2>,
     pub cell_centers:      ArcArray<(f64, f64), Ix2>,
+    pub coordinates:       CoordinateSystem,
+}
+
+
+
+
+/**
+ * The coordinate system of the mesh. In either system, the "radial"
+ * coordinate is the one which is divided into blocks, and the "polar"
+ * coordinate is the other one.
+ */
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSystem {
+
+    /// Spherical polar (r, theta) coordinates
+    Spherical,
+
+    /// Cylindrical (R, z) coordinates: the radial coordinate is the
+    /// cylindrical radius R, and the polar coordinate is z
+    Cylindrical,
+}
+
+
+
+
+/**
+ * The blocks of a geometry map, grouped into columns by polar block index
+ * and sorted by radius in each, so that the block containing a point can be
+ * found by a binary search of its column, rather than of every block
+ */
+pub struct BlockLocator<'a> {
+    geometry: &'a HashMap<BlockIndex, GridGeometry>,
+    columns: Vec<Vec<BlockIndex>>,
 }
 
 
@@ -38,12 +83,104 @@ impl GridGeometry {
      * Return a 2D array of the smallest linear dimension of each grid cell.
      */
     pub fn cell_linear_dimension(&self) -> Array<f64, Ix2> {
+        self.cell_linear_dimension_merged(0)
+    }
+
+    /**
+     * Return a 2D array of the smallest linear dimension of each grid cell,
+     * where the polar width of each of the zones merged at a polar axis (see
+     * [`GridGeometry::polar_axis_zones`]) is that of the merged zone.
+     */
+    pub fn cell_linear_dimension_merged(&self, merge_zones: usize) -> Array<f64, Ix2> {
+        let nq = self.polar_vertices.len() - 1;
+        let mut polar_widths: Vec<_> = (0..nq).map(|j| self.polar_vertices[j + 1] - self.polar_vertices[j]).collect();
+
+        for range in self.polar_axis_zones(merge_zones) {
+            let width = self.polar_vertices[range.end] - self.polar_vertices[range.start];
+            polar_widths[range].fill(width);
+        }
+
         Array::from_shape_fn(self.cell_centers.dim(), |(i, j)| {
             let dr = self.radial_vertices[i + 1] - self.radial_vertices[i];
-            let dq = self.polar_vertices[j + 1] - self.polar_vertices[j];
-            dr.min(dq * self.radial_vertices[i])
+            let dq = polar_widths[j];
+            match self.coordinates {
+                CoordinateSystem::Spherical   => dr.min(dq * self.radial_vertices[i]),
+                CoordinateSystem::Cylindrical => dr.min(dq),
+            }
         })
     }
+
+    /**
+     * Return the ranges of polar zone indexes which are merged at each polar
+     * axis touched by this block: the `count` zones nearest the axis, or half
+     * of the block's polar zones if it has fewer. No zones are merged if
+     * `count` is less than 2, or if the coordinates are not spherical.
+     */
+    pub fn polar_axis_zones(&self, count: usize) -> Vec<Range<usize>> {
+        let nq = self.polar_vertices.len() - 1;
+        let count = count.min(nq / 2);
+        let mut ranges = Vec::new();
+
+        if self.coordinates == CoordinateSystem::Spherical && count > 1 {
+            if self.polar_vertices[0].abs() < 1e-12 {
+                ranges.push(0..count)
+            }
+            if (self.polar_vertices[nq] - PI).abs() < 1e-12 {
+                ranges.push(nq - count..nq)
+            }
+        }
+        ranges
+    }
+
+    /**
+     * Return the coordinates and weights of a tensor-product Gauss-Legendre
+     * quadrature rule with `n` points per dimension (1, 2, or 3) for the
+     * zone at the given index. The weights include the volume element, and
+     * are normalized to sum to one, so that a weighted sum of samples is a
+     * volume average. If `n` is 1, the single point is the zone centroid.
+     */
+    pub fn quadrature(&self, index: (usize, usize), n: usize) -> Vec<((f64, f64), f64)> {
+        let (nodes, weights): (&[f64], &[f64]) = match n {
+            1 => return vec![(self.cell_centers[index], 1.0)],
+            2 => (&[-0.5773502691896258, 0.5773502691896258], &[1.0, 1.0]),
+            3 => (&[-0.7745966692414834, 0.0, 0.7745966692414834], &[5.0 / 9.0, 8.0 / 9.0, 5.0 / 9.0]),
+            _ => panic!("quadrature is only implemented for 1, 2, or 3 points"),
+        };
+        let (i, j) = index;
+        let (r0, r1) = (self.radial_vertices[i], self.radial_vertices[i + 1]);
+        let (q0, q1) = (self.polar_vertices[j], self.polar_vertices[j + 1]);
+        let mut points = Vec::with_capacity(n * n);
+
+        for (xr, wr) in nodes.iter().zip(weights) {
+            for (xq, wq) in nodes.iter().zip(weights) {
+                let r = 0.5 * (r0 + r1) + 0.5 * (r1 - r0) * xr;
+                let q = 0.5 * (q0 + q1) + 0.5 * (q1 - q0) * xq;
+                let dv = match self.coordinates {
+                    CoordinateSystem::Spherical   => r * r * q.sin(),
+                    CoordinateSystem::Cylindrical => r,
+                };
+                points.push(((r, q), wr * wq * dv));
+            }
+        }
+        let total: f64 = points.iter().map(|(_, w)| w).sum();
+        points.into_iter().map(|(c, w)| (c, w / total)).collect()
+    }
+
+    /**
+     * Return a copy of this geometry with all radii multiplied by the given
+     * factor.
+     */
+    pub fn scaled(&self, a: f64) -> Self {
+        Self {
+            radial_vertices:   self.radial_vertices.mapv(|r| r * a).to_shared(),
+            radial_face_areas: self.radial_face_areas.mapv(|da| da * a * a).to_shared(),
+            polar_vertices:    self.polar_vertices.clone(),
+            polar_face_areas:  self.polar_face_areas.mapv(|da| da * a * a).to_shared(),
+            cell_volumes:      self.cell_volumes.mapv(|dv| dv * a * a * a).to_shared(),
+            cell_centers:      self.cell_centers.mapv(|(r, q)| (r * a, q)).to_shared(),
+            coordinates:       self.coordinates,
+        }
+    }
 }
 
 
@@ -72,6 +209,76 @@ pub struct SphericalPolarGrid {
     pub extent: SphericalPolarExtent,
     pub num_zones_r: usize,
     pub num_zones_q: usize,
+    pub polar_grading: PolarGrading,
+    pub coordinates: CoordinateSystem,
+}
+
+
+
+
+/**
+ * Distribution of the polar zone vertices between the lower and upper polar
+ * edges of the mesh
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolarGrading {
+
+    /// Zones are uniformly spaced in theta
+    Uniform,
+
+    /// Zones are uniformly spaced in cos(theta), so each zone subtends the
+    /// same solid angle. This concentrates resolution near the equator.
+    Cosine,
+
+    /// Zones are clustered toward both polar edges by a tanh stretching
+    /// function. The ratio of the widest (middle) to narrowest (edge) zone
+    /// is cosh^2(clustering), e.g. a clustering of 1.82 gives a ratio of
+    /// about 10.
+    Tanh { clustering: f64 },
+}
+
+
+
+
+/**
+ * Static refinement of the polar zones in the inner blocks of the mesh
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolarRefinement {
+
+    /// Factor by which the polar zone count is multiplied in refined blocks:
+    /// 2 or 4
+    pub factor: usize,
+
+    /// Blocks whose outer radius is at or below this radius are refined
+    pub radius: f64,
+}
+
+
+
+
+/**
+ * Sizes of a mesh at a given time, which are reported at startup so that a
+ * badly sized mesh is caught before a run is under way
+ */
+pub struct MeshSummary {
+
+    /// Number of blocks between the excision surfaces
+    pub num_blocks: usize,
+
+    /// Total number of zones in those blocks
+    pub num_zones: usize,
+
+    /// Fractional radial zone spacing
+    pub dr_over_r: f64,
+
+    /// Number of radial zones per decade in radius
+    pub zones_per_decade: f64,
+
+    /// Estimated memory use in bytes (see [`BYTES_PER_ZONE`])
+    pub memory_bytes: usize,
 }
 
 
@@ -102,14 +309,96 @@ pub struct Mesh {
     /// makes the zones square.
     pub num_radial_zones: Option<usize>,
 
-    /// Number of zones from pole to pole
+    /// Number of zones from theta_min to theta_max
     pub num_polar_zones: usize,
 
+    /// Polar angle of the lower edge of the mesh. Defaults to 0.
+    #[serde(default)]
+    pub theta_min: f64,
+
+    /// Polar angle of the upper edge of the mesh. Defaults to pi. Use e.g.
+    /// pi/2 with a reflecting polar boundary for an equatorial symmetry
+    /// plane, or a smaller value for a jet-only wedge.
+    #[serde(default = "Mesh::default_theta_max")]
+    pub theta_max: f64,
+
     /// Number of radial zones in each block
     pub block_size: usize,
 
     /// Time after which the mesh excision starts
     pub excision_delay: Option<f64>,
+
+    /// Number of independent radial rays, which is only used when
+    /// num_polar_zones is 1. The rays are conical sectors which evenly divide
+    /// the range theta_min to theta_max, and in which the model is
+    /// evaluated, but with no polar coupling between rays. Defaults to 1,
+    /// which is a single ray on the equator.
+    #[serde(default = "Mesh::default_num_rays")]
+    pub num_rays: usize,
+
+    /// Number of Gauss-Legendre points per dimension (1, 2, or 3) used to
+    /// volume-average the model data in each zone, when generating initial
+    /// and boundary data. Defaults to 1, which samples the zone centroid.
+    #[serde(default = "Mesh::default_quadrature_points")]
+    pub quadrature_points: usize,
+
+    /// Boundary conditions on the radial and polar edges of the mesh. The
+    /// radial boundaries default to `inflow_from_model`, and the polar
+    /// boundaries to `reflecting`.
+    #[serde(default)]
+    pub boundaries: Boundaries,
+
+    /// Distribution of the polar zones between theta_min and theta_max.
+    /// Defaults to `uniform`.
+    #[serde(default)]
+    pub polar_grading: PolarGrading,
+
+    /// Optional static refinement of the polar zones in the inner blocks,
+    /// where the jet is narrow. Fluxes through the radial faces between
+    /// refined and unrefined blocks are computed at the fine resolution,
+    /// so that the update is conservative.
+    #[serde(default)]
+    pub polar_refinement: Option<PolarRefinement>,
+
+    /// If given, the mesh expands homologously, with every vertex moving at
+    /// the velocity v = r / t. All radii in this configuration, including
+    /// those of the excision surfaces, are then comoving: they are the
+    /// physical radii at this reference time.
+    #[serde(default)]
+    pub homologous_reference_time: Option<f64>,
+
+    /// The coordinate system: `spherical` (the default) or `cylindrical`.
+    /// In cylindrical coordinates, the blocks are divided logarithmically in
+    /// the cylindrical radius, and the polar zones are between z_min and
+    /// z_max. Models are evaluated at the equivalent spherical coordinates,
+    /// and their vector components are rotated into the cylindrical basis.
+    #[serde(default)]
+    pub geometry: CoordinateSystem,
+
+    /// Lower edge of the mesh in z, for cylindrical coordinates
+    #[serde(default)]
+    pub z_min: f64,
+
+    /// Upper edge of the mesh in z, for cylindrical coordinates
+    #[serde(default)]
+    pub z_max: f64,
+
+    /// If given, the outer excision surface tracks the outermost shocked
+    /// radius (measured at the start of each fold), staying ahead of it by
+    /// this fraction of its radius, instead of moving at the
+    /// outer_excision_speed. Blocks are then only added ahead of the blast
+    /// wave, and not throughout the vacuum beyond it.
+    #[serde(default)]
+    pub outer_excision_shock_margin: Option<f64>,
+
+    /// Number of zones next to each polar axis which are merged in every
+    /// radial row. After each update, the total conserved quantities and
+    /// scalar mass of the merged zones are redistributed among them in
+    /// proportion to their volumes, and the time step is set by the width
+    /// of the merged zone rather than by the narrow zones at the axis.
+    /// Defaults to 0, which disables merging. Spherical meshes only.
+    #[serde(default)]
+    pub polar_merge_zones: usize,
 }
 
 
@@ -122,6 +411,20 @@ fn cell_volume(c0: (f64, f64), c1: (f64, f64)) -> f64
     2.0 * PI * (c1.0.powi(3) - c0.0.powi(3)) / 3.0 * dcost
 }
 
+fn cylindrical_cell_volume(c0: (f64, f64), c1: (f64, f64)) -> f64
+{
+    PI * (c1.0 * c1.0 - c0.0 * c0.0) * (c1.1 - c0.1)
+}
+
+fn cylindrical_face_area(c0: (f64, f64), c1: (f64, f64)) -> f64
+{
+    if c0.0 == c1.0 {
+        2.0 * PI * c0.0 * (c1.1 - c0.1)
+    } else {
+        PI * (c1.0 * c1.0 - c0.0 * c0.0)
+    }
+}
+
 fn face_area(c0: (f64, f64), c1: (f64, f64)) -> f64
 {
     let s0 = c0.0 * f64::sin(c0.1);
@@ -136,18 +439,213 @@ fn face_area(c0: (f64, f64), c1: (f64, f64)) -> f64
 
 
 
+
+// ============================================================================
+/**
+ * Return the index of the block containing the given (r, theta) position,
+ * and of the zone within it, or None if the position is not on the mesh.
+ * Blocks are searched linearly; to locate many positions, use a
+ * [`BlockLocator`]. If the position is on the boundary between blocks, the
+ * one with the lowest index is returned.
+ */
+pub fn locate_zone(geometry: &HashMap<BlockIndex, GridGeometry>, (r, q): (f64, f64)) -> Option<(BlockIndex, (usize, usize))> {
+    let contains = |v: &[f64], x: f64| v[0] <= x && x <= v[v.len() - 1];
+    let zone = |v: &[f64], x: f64| v.partition_point(|&y| y <= x).clamp(1, v.len() - 1) - 1;
+
+    geometry
+        .iter()
+        .filter(|(_, g)| {
+            contains(g.radial_vertices.as_slice().unwrap(), r) && contains(g.polar_vertices.as_slice().unwrap(), q)
+        })
+        .min_by_key(|(index, _)| **index)
+        .map(|(&index, g)| {
+            let i = zone(g.radial_vertices.as_slice().unwrap(), r);
+            let j = zone(g.polar_vertices.as_slice().unwrap(), q);
+            (index, (i, j))
+        })
+}
+
+
+
+
+// ============================================================================
+impl<'a> BlockLocator<'a> {
+
+    /**
+     * Index the blocks of the given geometry map.
+     */
+    pub fn new(geometry: &'a HashMap<BlockIndex, GridGeometry>) -> Self {
+        let mut columns: HashMap<usize, Vec<BlockIndex>> = HashMap::new();
+
+        for &index in geometry.keys() {
+            columns.entry(index.1).or_default().push(index);
+        }
+        let mut columns: Vec<_> = columns.into_iter().map(|(_, column)| column).collect();
+
+        for column in &mut columns {
+            column.sort_unstable();
+        }
+        Self{geometry, columns}
+    }
+
+    /**
+     * Return the same as [`locate_zone`] for the indexed geometry map: the
+     * index of the block containing the given (r, theta) position, and of
+     * the zone within it, or None if the position is not on the mesh.
+     */
+    pub fn locate_zone(&self, (r, q): (f64, f64)) -> Option<(BlockIndex, (usize, usize))> {
+        let contains = |v: &[f64], x: f64| v[0] <= x && x <= v[v.len() - 1];
+        let zone = |v: &[f64], x: f64| v.partition_point(|&y| y <= x).clamp(1, v.len() - 1) - 1;
+        let outer_radius = |index: &BlockIndex| {
+            let rv = &self.geometry[index].radial_vertices;
+            rv[rv.len() - 1]
+        };
+
+        self.columns
+            .iter()
+            .filter_map(|column| {
+                let index = *column.get(column.partition_point(|index| outer_radius(index) < r))?;
+                let g = &self.geometry[&index];
+                let rv = g.radial_vertices.as_slice().unwrap();
+                let qv = g.polar_vertices.as_slice().unwrap();
+
+                if contains(rv, r) && contains(qv, q) {
+                    Some((index, (zone(rv, r), zone(qv, q))))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(index, _)| *index)
+    }
+}
+
+
+
+
+// ============================================================================
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        CoordinateSystem::Spherical
+    }
+}
+
+impl CoordinateSystem {
+
+    /**
+     * Return the spherical (r, theta) coordinates of a point given in this
+     * coordinate system.
+     */
+    pub fn to_spherical(self, coordinate: (f64, f64)) -> (f64, f64) {
+        match self {
+            CoordinateSystem::Spherical => coordinate,
+            CoordinateSystem::Cylindrical => {
+                let (rc, z) = coordinate;
+                ((rc * rc + z * z).sqrt(), rc.atan2(z))
+            }
+        }
+    }
+
+    /**
+     * Return the components, in the basis of this coordinate system, of a
+     * vector with the given spherical (r, theta) components at a point.
+     */
+    pub fn vector_from_spherical(self, coordinate: (f64, f64), v: (f64, f64)) -> (f64, f64) {
+        match self {
+            CoordinateSystem::Spherical => v,
+            CoordinateSystem::Cylindrical => {
+                let (_, q) = self.to_spherical(coordinate);
+                let (vr, vq) = v;
+                (vr * q.sin() + vq * q.cos(), vr * q.cos() - vq * q.sin())
+            }
+        }
+    }
+
+    /**
+     * Convert a primitive state whose velocity has spherical components to
+     * one with components in this coordinate system.
+     */
+    pub fn primitive_from_spherical(self, coordinate: (f64, f64), p: AnyPrimitive) -> AnyPrimitive {
+        let (velocity_r, velocity_q) = self.vector_from_spherical(coordinate, (p.velocity_r, p.velocity_q));
+        AnyPrimitive{velocity_r, velocity_q, ..p}
+    }
+
+    /**
+     * Convert a conserved source whose momentum has spherical components to
+     * one with components in this coordinate system.
+     */
+    pub fn conserved_from_spherical(self, coordinate: (f64, f64), u: AnyConserved) -> AnyConserved {
+        let (momentum_r, momentum_q) = self.vector_from_spherical(coordinate, (u.momentum_r, u.momentum_q));
+        AnyConserved{momentum_r, momentum_q, ..u}
+    }
+}
+
+
+
+
+// ============================================================================
+impl Default for PolarGrading {
+    fn default() -> Self {
+        PolarGrading::Uniform
+    }
+}
+
+impl PolarGrading {
+
+    /**
+     * Return the polar angle at the fractional distance `x` between the
+     * polar edges q0 and q1, where x=0 and x=1 map to q0 and q1 respectively.
+     * Values of x outside [0, 1] are mapped to the mirror images of the
+     * interior vertices across the nearest edge, so that ghost zones
+     * have the same widths as the zones they reflect.
+     */
+    pub fn theta(&self, q0: f64, q1: f64, x: f64) -> f64 {
+        if x < 0.0 {
+            return 2.0 * q0 - self.theta(q0, q1, -x)
+        }
+        if x > 1.0 {
+            return 2.0 * q1 - self.theta(q0, q1, 2.0 - x)
+        }
+        match self {
+            PolarGrading::Uniform => {
+                q0 + (q1 - q0) * x
+            }
+            PolarGrading::Cosine => {
+                f64::acos(q0.cos() + (q1.cos() - q0.cos()) * x)
+            }
+            PolarGrading::Tanh { clustering: a } => {
+                let f = 0.5 * (1.0 + f64::tanh(a * (2.0 * x - 1.0)) / a.tanh());
+                q0 + (q1 - q0) * f
+            }
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let PolarGrading::Tanh { clustering } = self {
+            if *clustering <= 0.0 {
+                anyhow::bail!("the tanh polar grading clustering must be positive")
+            }
+        }
+        Ok(())
+    }
+}
+
+
+
+
 // ============================================================================
 impl SphericalPolarExtent {
 
     /**
      * Create a grid from this r-theta area with the given number of zones in
-     * the radial and polar directions.
+     * the radial and polar directions, and the given polar zone grading.
      */
-    pub fn grid(&self, num_zones_r: usize, num_zones_q: usize) -> SphericalPolarGrid {
+    pub fn grid(&self, num_zones_r: usize, num_zones_q: usize, polar_grading: PolarGrading, coordinates: CoordinateSystem) -> SphericalPolarGrid {
         SphericalPolarGrid{
             extent: self.clone(),
             num_zones_r,
             num_zones_q,
+            polar_grading,
+            coordinates,
         }
     }
 
@@ -186,6 +684,29 @@ impl SphericalPolarExtent {
         let c1 = (self.outer_radius, self.lower_theta);
         face_area(c0, c1)
     }
+
+    /**
+     * Return the volume, inner radial face area, lower polar face area, and
+     * centroid of this extent, in the given coordinate system. In
+     * cylindrical coordinates, the radii are cylindrical radii and the
+     * polar angles are z coordinates.
+     */
+    pub fn geometry_in(&self, coordinates: CoordinateSystem) -> (f64, f64, f64, (f64, f64)) {
+        match coordinates {
+            CoordinateSystem::Spherical => {
+                (self.volume(), self.face_area_r(), self.face_area_q(), self.centroid())
+            }
+            CoordinateSystem::Cylindrical => {
+                let c0 = (self.inner_radius, self.lower_theta);
+                let c1 = (self.outer_radius, self.upper_theta);
+                let volume = cylindrical_cell_volume(c0, c1);
+                let face_area_r = cylindrical_face_area(c0, (self.inner_radius, self.upper_theta));
+                let face_area_q = cylindrical_face_area(c0, (self.outer_radius, self.lower_theta));
+                let centroid = (0.5 * (c0.0 + c1.0), 0.5 * (c0.1 + c1.1));
+                (volume, face_area_r, face_area_q, centroid)
+            }
+        }
+    }
 }
 
 
@@ -203,9 +724,8 @@ impl SphericalPolarGrid {
         let (y0, y1) = (self.extent.inner_radius.log(10.0), self.extent.outer_radius.log(10.0));
         let (q0, q1) = (self.extent.lower_theta, self.extent.upper_theta);
         let dy = (y1 - y0) / self.num_zones_r as f64;
-        let dq = (q1 - q0) / self.num_zones_q as f64;
         let y = y0 + dy * i as f64;
-        let q = q0 + dq * j as f64;
+        let q = self.polar_grading.theta(q0, q1, j as f64 / self.num_zones_q as f64);
         (f64::powf(10.0, y), q)
     }
 
@@ -248,10 +768,11 @@ impl SphericalPolarGrid {
         let nq = self.num_zones_q;
         let radial_vertices   = ArcArray::from_shape_fn(nr + 1, |i| self.vertex_coordinate(i, 0).0);
         let polar_vertices    = ArcArray::from_shape_fn(nq + 1, |j| self.vertex_coordinate(0, j).1);
-        let radial_face_areas = ArcArray::from_shape_fn((nr + 1, nq), |index| self.zone(index).face_area_r());
-        let polar_face_areas  = ArcArray::from_shape_fn((nr, nq + 1), |index| self.zone(index).face_area_q());
-        let cell_volumes      = ArcArray::from_shape_fn((nr, nq), |index| self.zone(index).volume());
-        let cell_centers      = ArcArray::from_shape_fn((nr, nq), |index| self.zone(index).centroid());
+        let c = self.coordinates;
+        let radial_face_areas = ArcArray::from_shape_fn((nr + 1, nq), |index| self.zone(index).geometry_in(c).1);
+        let polar_face_areas  = ArcArray::from_shape_fn((nr, nq + 1), |index| self.zone(index).geometry_in(c).2);
+        let cell_volumes      = ArcArray::from_shape_fn((nr, nq), |index| self.zone(index).geometry_in(c).0);
+        let cell_centers      = ArcArray::from_shape_fn((nr, nq), |index| self.zone(index).geometry_in(c).3);
 
         GridGeometry{
             radial_vertices,
@@ -260,6 +781,7 @@ impl SphericalPolarGrid {
             polar_face_areas,
             cell_volumes,
             cell_centers,
+            coordinates: c,
         }
     }
 }
@@ -270,6 +792,18 @@ impl SphericalPolarGrid {
 // ============================================================================
 impl Mesh {
 
+    fn default_theta_max() -> f64 {
+        PI
+    }
+
+    fn default_num_rays() -> usize {
+        1
+    }
+
+    fn default_quadrature_points() -> usize {
+        1
+    }
+
     pub fn validate(&self, time: f64) -> anyhow::Result<()> {
         if self.reference_radius <= 0.0 || self.inner_radius < 0.0 || self.outer_radius < 0.0 {
             anyhow::bail!("all radii must be positive")
@@ -286,6 +820,9 @@ impl Mesh {
         if self.outer_excision_speed < self.inner_excision_speed {
             anyhow::bail!("outer_excision_speed < inner_excision_speed (the IES would eventually overtake the OES)")
         }
+        if self.outer_excision_shock_margin.map_or(false, |margin| margin < 0.0) {
+            anyhow::bail!("the outer excision shock margin must be non-negative")
+        }
         if self.block_size < 2 {
             anyhow::bail!("must have at least 2 radial zones per block")
         }
@@ -295,22 +832,88 @@ impl Mesh {
         if self.num_polar_zones == 1 && self.num_radial_zones.is_none() {
             anyhow::bail!("num_radial_zones is not optional when num_polar_zones=1")            
         }
+        if self.theta_min < 0.0 || self.theta_max > PI || self.theta_min >= self.theta_max {
+            anyhow::bail!("must have 0 <= theta_min < theta_max <= pi")
+        }
+        if self.num_rays == 0 || (self.num_rays > 1 && self.num_polar_zones != 1) {
+            anyhow::bail!("num_rays must be 1, unless num_polar_zones=1")
+        }
+        if !(1..=3).contains(&self.quadrature_points) {
+            anyhow::bail!("quadrature_points must be 1, 2, or 3")
+        }
+        self.polar_grading.validate()?;
+
+        if let Some(refinement) = &self.polar_refinement {
+            if refinement.factor != 2 && refinement.factor != 4 {
+                anyhow::bail!("the polar refinement factor must be 2 or 4")
+            }
+            if refinement.radius <= 0.0 {
+                anyhow::bail!("the polar refinement radius must be positive")
+            }
+            if self.num_polar_zones == 1 {
+                anyhow::bail!("polar refinement requires num_polar_zones > 1")
+            }
+        }
+        if let Some(reference_time) = self.homologous_reference_time {
+            if reference_time <= 0.0 || time <= 0.0 {
+                anyhow::bail!("a homologous mesh requires a positive reference time and start time")
+            }
+        }
+        if self.geometry == CoordinateSystem::Cylindrical {
+            if self.z_min >= self.z_max {
+                anyhow::bail!("cylindrical geometry requires z_min < z_max")
+            }
+            if self.num_radial_zones.is_none() || self.num_rays != 1 || self.is_homologous() {
+                anyhow::bail!("cylindrical geometry requires num_radial_zones, and does not support num_rays or homologous expansion")
+            }
+        }
+        if self.polar_merge_zones > 1 {
+            if self.geometry != CoordinateSystem::Spherical {
+                anyhow::bail!("polar_merge_zones requires spherical geometry")
+            }
+            if self.theta_min > 0.0 && self.theta_max < PI {
+                anyhow::bail!("polar_merge_zones requires the mesh to extend to a polar axis")
+            }
+            let block_polar_zones = self.grid_blocks(time).values().map(|grid| grid.num_zones_q).min().unwrap_or(0);
+
+            if 2 * self.polar_merge_zones > block_polar_zones {
+                anyhow::bail!("polar_merge_zones must be at most half of the number of polar zones in each block")
+            }
+        }
         Ok(())
     }
 
     /**
-     * Return the smallest grid spacing on the given block.
+     * Return the smallest grid spacing on the given block, at the given time.
      */
-    pub fn smallest_spacing(&self, index: BlockIndex) -> f64 {
+    pub fn smallest_spacing(&self, index: BlockIndex, time: f64) -> f64 {
         let zone = self.subgrid(index).zone((0, 0));
-        zone.outer_radius - zone.inner_radius
+        (zone.outer_radius - zone.inner_radius) * self.expansion_factor(time)
+    }
+
+    /**
+     * Return true if the mesh expands homologously.
+     */
+    pub fn is_homologous(&self) -> bool {
+        self.homologous_reference_time.is_some()
+    }
+
+    /**
+     * Return the ratio of physical to comoving radii at the given time. This
+     * is 1 unless the mesh expands homologously.
+     */
+    pub fn expansion_factor(&self, time: f64) -> f64 {
+        match self.homologous_reference_time {
+            Some(reference_time) => time / reference_time,
+            None => 1.0,
+        }
     }
 
     /**
      * Return true if either of the IES or the OES have non-zero speeds.
      */
     pub fn moving_excision_surfaces(&self) -> bool {
-        self.inner_excision_speed > 0.0 || self.outer_excision_speed > 0.0
+        self.inner_excision_speed > 0.0 || self.outer_excision_speed > 0.0 || self.outer_excision_shock_margin.is_some()
     }
 
     /**
@@ -335,13 +938,25 @@ impl Mesh {
         self.outer_radius + (time - t_start).max(0.0) * self.outer_excision_speed
     }
 
+    /**
+     * Radius of the OES when it tracks the shock, given the outermost shocked
+     * radius (in the mesh's comoving coordinates), or `None` if this mesh
+     * does not have an `outer_excision_shock_margin`. The OES does not move
+     * inside the `outer_radius`, or before any zones are shocked.
+     */
+    pub fn shock_tracking_excision_surface(&self, shock_radius: Option<f64>) -> Option<f64> {
+        self.outer_excision_shock_margin.map(|margin| {
+            shock_radius.map_or(self.outer_radius, |r| self.outer_radius.max(r * (1.0 + margin)))
+        })
+    }
+
     /**
      * Return the radial zone spacing, dlogr = log(r1 / r0).
      */
     pub fn zone_dlogr(&self) -> f64 {
         match self.num_radial_zones {
             Some(nr) => 1.0 / nr as f64,
-            None => PI / self.num_polar_zones as f64,
+            None => (self.theta_max - self.theta_min) / self.num_polar_zones as f64,
         }
     }
 
@@ -357,10 +972,15 @@ impl Mesh {
      */
     pub fn subgrid_extent(&self, index: BlockIndex) -> SphericalPolarExtent {
 
-        let (q0, q1) = if self.num_polar_zones == 1 {
+        let (q0, q1) = if self.geometry == CoordinateSystem::Cylindrical {
+            (self.z_min, self.z_max)
+        } else if self.num_polar_zones == 1 && self.num_rays > 1 {
+            let dq = (self.theta_max - self.theta_min) / self.num_rays as f64;
+            (self.theta_min + dq * index.1 as f64, self.theta_min + dq * (index.1 + 1) as f64)
+        } else if self.num_polar_zones == 1 {
             (PI * 0.5 - self.zone_dlogr(), PI * 0.5 + self.zone_dlogr())
         } else {
-            (0.0, PI)
+            (self.theta_min, self.theta_max)
         };
 
         SphericalPolarExtent {
@@ -371,38 +991,93 @@ impl Mesh {
         }
     }
 
+    /**
+     * Return the number of polar zones in the block at the given index,
+     * which is larger than `num_polar_zones` for blocks inside the polar
+     * refinement radius.
+     */
+    pub fn num_polar_zones_at(&self, index: BlockIndex) -> usize {
+        match &self.polar_refinement {
+            Some(refinement) if self.subgrid_extent(index).outer_radius <= refinement.radius => {
+                self.num_polar_zones * refinement.factor
+            }
+            _ => self.num_polar_zones,
+        }
+    }
+
     /**
      * Return the subgrid object at the given index.
      */
     pub fn subgrid(&self, index: BlockIndex) -> SphericalPolarGrid {
-        self.subgrid_extent(index).grid(self.block_size, self.num_polar_zones)
+        self.subgrid_extent(index).grid(self.block_size, self.num_polar_zones_at(index), self.polar_grading.clone(), self.geometry)
+    }
+
+    /**
+     * Return the geometry of the subgrid at the given index, at the given
+     * time.
+     */
+    pub fn subgrid_geometry(&self, index: BlockIndex, time: f64) -> GridGeometry {
+        let geometry = self.subgrid(index).geometry();
+
+        if self.is_homologous() {
+            geometry.scaled(self.expansion_factor(time))
+        } else {
+            geometry
+        }
     }
 
     /**
-     * Return a map of the subgrid objects on this mesh.
+     * Return a map of the subgrid objects on this mesh. For a homologous
+     * mesh, these are in comoving coordinates.
      */
     pub fn grid_blocks(&self, time: f64) -> HashMap<BlockIndex, SphericalPolarGrid> {
         let mut blocks = HashMap::new();
-        for i in 0.. {
-            let index = (i, 0);
-            let extent = self.subgrid_extent(index);
-
-            if extent.inner_radius >= self.outer_excision_surface(time) {
-                break
-            } else {
-                blocks.insert(index, extent.grid(self.block_size, self.num_polar_zones));
+        for j in 0..self.num_rays {
+            for i in 0.. {
+                let index = (i, j);
+                let extent = self.subgrid_extent(index);
+
+                if extent.inner_radius >= self.outer_excision_surface(time) {
+                    break
+                } else {
+                    blocks.insert(index, extent.grid(self.block_size, self.num_polar_zones_at(index), self.polar_grading.clone(), self.geometry));
+                }
             }
         }
         blocks
     }
 
     /**
-     * Return a map of the subgrid geometry objects on this mesh (for convenience).
+     * Return the sizes of this mesh at the given time, counting the blocks
+     * which are not fully within the inner excision surface.
+     */
+    pub fn summary(&self, time: f64) -> MeshSummary {
+        let ies = self.inner_excision_surface(time);
+        let zones: Vec<_> = self.grid_blocks(time)
+            .values()
+            .filter(|grid| grid.extent.outer_radius >= ies)
+            .map(|grid| grid.num_zones_r * grid.num_zones_q)
+            .collect();
+        let num_zones = zones.iter().sum();
+
+        MeshSummary{
+            num_blocks: zones.len(),
+            num_zones,
+            dr_over_r: self.zone_dlogr().exp() - 1.0,
+            zones_per_decade: f64::ln(10.0) / self.zone_dlogr(),
+            memory_bytes: num_zones * BYTES_PER_ZONE,
+        }
+    }
+
+    /**
+     * Return a map of the subgrid geometry objects on this mesh (for
+     * convenience), at the given time.
      */
     pub fn grid_blocks_geometry(&self, time: f64) -> HashMap<BlockIndex, GridGeometry> {
+        let a = self.expansion_factor(time);
         self.grid_blocks(time)
             .iter()
-            .map(|(&index, grid)| (index, grid.geometry()))
+            .map(|(&index, grid)| (index, if self.is_homologous() { grid.geometry().scaled(a) } else { grid.geometry() }))
             .collect()
     }
 }
@@ -0,0 +1,131 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-6;
+
+
+
+
+/**
+ * Cold relativistic shell decelerating in an external medium with density
+ * rho = rho_0 (r / r_0)^-k, where k = 0 is a uniform (ISM) medium and k = 2
+ * is a stellar wind. The shell is launched from the origin at t = 0 and
+ * coasts with a uniform four-velocity until it sweeps up the medium. This is
+ * meant for afterglow-phase runs out to large radii, where the outer
+ * excision surface can be moved with the shell.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Afterglow {
+
+    /// Isotropic kinetic energy (Gamma - 1) M c^2 of the shell (erg)
+    pub shell_energy: f64,
+
+    /// Four-velocity of the shell
+    pub shell_gamma_beta: f64,
+
+    /// Radial thickness of the shell (cm)
+    pub shell_thickness: f64,
+
+    /// External medium density rho_0 (g/cm^3) at the reference radius
+    pub external_medium_density: f64,
+
+    /// Reference radius r_0 (cm) of the external medium density
+    pub external_medium_radius: f64,
+
+    /// Index k of the external medium density profile
+    pub external_medium_index: f64,
+}
+
+
+
+
+// ============================================================================
+impl Afterglow {
+
+    /**
+     * Dimensionless shell velocity: v / c
+     */
+    pub fn shell_beta(&self) -> f64 {
+        self.shell_gamma_beta / (1.0 + self.shell_gamma_beta.powi(2)).sqrt()
+    }
+
+    /**
+     * Rest mass of the shell (g)
+     */
+    pub fn shell_mass(&self) -> f64 {
+        let gamma = (1.0 + self.shell_gamma_beta.powi(2)).sqrt();
+        self.shell_energy / ((gamma - 1.0) * LIGHT_SPEED * LIGHT_SPEED)
+    }
+
+    /**
+     * Return the radial extent (in cm) of the shell at time t.
+     */
+    pub fn shell_extent(&self, t: f64) -> std::ops::Range<f64> {
+        let r_outer = self.shell_beta() * LIGHT_SPEED * t;
+        (r_outer - self.shell_thickness)..r_outer
+    }
+
+    /**
+     * The external medium density at radius r
+     */
+    pub fn external_density(&self, r: f64) -> f64 {
+        self.external_medium_density * (r / self.external_medium_radius).powf(-self.external_medium_index)
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for Afterglow {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.shell_energy <= 0.0 || self.shell_gamma_beta <= 0.0 || self.shell_thickness <= 0.0 {
+            anyhow::bail!("shell_energy, shell_gamma_beta, and shell_thickness must be positive")
+        }
+        if self.external_medium_density <= 0.0 || self.external_medium_radius <= 0.0 {
+            anyhow::bail!("external_medium_density and external_medium_radius must be positive")
+        }
+        if self.external_medium_index < 0.0 || self.external_medium_index >= 3.0 {
+            anyhow::bail!("external_medium_index must be in [0, 3), e.g. 0 (ISM) or 2 (wind)")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, _q) = coordinate;
+
+        if self.shell_extent(t).contains(&r) {
+            let u = self.shell_gamma_beta;
+            let gamma = (1.0 + u * u).sqrt();
+            let d = self.shell_mass() / (4.0 * PI * r * r * self.shell_thickness) / gamma;
+
+            AnyPrimitive {
+                velocity_r: u,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        } else {
+            let d = self.external_density(r);
+
+            AnyPrimitive {
+                velocity_r: 0.0,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        if self.shell_extent(t).contains(&coordinate.0) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::f64::consts::PI;
+use crate::physics::LIGHT_SPEED;
+
+
+
+
+/**
+ * Kilonova debris cloud and surrounding relativistic envelope, which is the
+ * ambient medium of the jet_in_cloud, two_component_jet, structured_jet, and
+ * precessing_jet models. The cloud is ejected at a uniform rate until the
+ * engine turns on, and moves with the slowest envelope shell. The envelope
+ * expands homologously, with u(m) ~ m^-psi. The models keep these
+ * parameters as their own fields, and build this from them.
+ */
+#[derive(Clone, Copy)]
+pub struct Cloud {
+
+    /// Mass of the merger ejecta cloud
+    pub cloud_mass: f64,
+
+    /// Time following the cloud onset when the jet begins
+    pub engine_delay: f64,
+
+    /// Mass coordinate of the u=1 shell
+    pub envelop_m1: f64,
+
+    /// Beta (v/c) of the slowest envelope shell
+    pub envelop_slowest_beta: f64,
+
+    /// Beta (v/c) of the outer shell
+    pub envelop_fastest_beta: f64,
+
+    /// Index psi in u(m) ~ m^-psi
+    pub envelop_psi: f64,
+}
+
+
+
+
+/**
+ * Zones of the ambient medium, which is the setup in the absence of the jet
+ */
+#[derive(Clone, Copy)]
+pub enum AmbientZone {
+    Envelope,
+    Cloud,
+}
+
+
+
+
+// ============================================================================
+impl Cloud {
+
+    /**
+     * Four-velocity gamma-beta of the slowest envelope shell
+     */
+    pub fn envelop_slowest_u(&self) -> f64 {
+        let b = self.envelop_slowest_beta;
+        b / (1.0 - b * b).sqrt()
+    }
+
+    /**
+     * Determine the zone of the ambient medium for a given radius and time.
+     *
+     * * `r` - Radius
+     * * `t` - Time
+     */
+    pub fn zone(&self, r: f64, t: f64) -> AmbientZone {
+        if r > self.envelop_slowest_beta * LIGHT_SPEED * t {
+            AmbientZone::Envelope
+        } else {
+            AmbientZone::Cloud
+        }
+    }
+
+    /**
+     * Return the passive scalar concentration in the given zone.
+     */
+    pub fn scalar_in(&self, zone: AmbientZone) -> f64 {
+        match zone {
+            AmbientZone::Cloud    => 1e+0,
+            AmbientZone::Envelope => 1e-2,
+        }
+    }
+
+    /**
+     * Return the radial four-velocity (gamma-beta).
+     *
+     * * `r` - The radius
+     * * `t` - The time
+     */
+    pub fn gamma_beta(&self, r: f64, t: f64) -> f64 {
+        self.gamma_beta_in(self.zone(r, t), r, t)
+    }
+
+    /**
+     * Return the mass rate per solid angle.
+     *
+     * * `r` - The radius
+     * * `t` - The time
+     */
+    pub fn mass_rate_per_steradian(&self, r: f64, t: f64) -> f64 {
+        self.mass_rate_per_steradian_in(self.zone(r, t), r, t)
+    }
+
+    /**
+     * Return the radial four-velocity (gamma-beta) in the given zone.
+     *
+     * * `r` - The radius
+     * * `t` - The time
+     */
+    pub fn gamma_beta_in(&self, zone: AmbientZone, r: f64, t: f64) -> f64 {
+        match zone {
+            AmbientZone::Cloud => {
+                self.envelop_slowest_u()
+            }
+            AmbientZone::Envelope => {
+                let b = f64::min(r / t / LIGHT_SPEED, self.envelop_fastest_beta);
+                b / f64::sqrt(1.0 - b * b)
+            }
+        }
+    }
+
+    /**
+     * Return the mass rate per solid angle in the given zone.
+     *
+     * * `r` - The radius
+     * * `t` - The time
+     */
+    pub fn mass_rate_per_steradian_in(&self, zone: AmbientZone, r: f64, t: f64) -> f64 {
+        match zone {
+            AmbientZone::Cloud => {
+                self.cloud_mass / (4.0 * PI * self.engine_delay)
+            }
+            AmbientZone::Envelope => {
+                let s = f64::min(r / t / LIGHT_SPEED, self.envelop_fastest_beta);
+                let f = f64::powf(s, -1.0 / self.envelop_psi) * f64::powf(1.0 - s * s, 0.5 / self.envelop_psi - 1.0);
+                self.envelop_m1 / (4.0 * PI * self.envelop_psi * t) * f
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Serialize, Deserialize};
+use crate::app::AnyModel;
+use crate::physics::{AnyConserved, AnyPrimitive};
+use crate::reductions::Reductions;
+use crate::traits::InitialModel;
+
+
+
+
+/**
+ * A superposition of models, e.g. kilonova ejecta, a magnetar wind, and an
+ * ambient medium. At each point, the mass densities and gas pressures of the
+ * components are summed, and the velocity is the mass-weighted average of
+ * the component velocities, which conserves momentum in the Newtonian case
+ * (for relativistic hydro, gamma-beta is averaged). The scalar concentration
+ * is the mass-weighted average of the component scalars, so each component
+ * tags its own material. Source terms and scalar injection rates are summed.
+ * In a configuration file, the model is a list of component models.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Composite {
+    pub components: Vec<AnyModel>,
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for Composite {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.components.is_empty() {
+            anyhow::bail!("a composite model must have at least one component")
+        }
+        for component in &self.components {
+            component.validate()?
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), time: f64) -> AnyPrimitive {
+        let mut result = AnyPrimitive {
+            velocity_r: 0.0,
+            velocity_q: 0.0,
+            mass_density: 0.0,
+            gas_pressure: 0.0,
+        };
+
+        for component in &self.components {
+            let p = component.primitive_at(coordinate, time);
+            result.velocity_r += p.mass_density * p.velocity_r;
+            result.velocity_q += p.mass_density * p.velocity_q;
+            result.mass_density += p.mass_density;
+            result.gas_pressure += p.gas_pressure;
+        }
+        result.velocity_r /= result.mass_density;
+        result.velocity_q /= result.mass_density;
+        result
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        let (scalar_mass, mass) = self.components
+            .iter()
+            .map(|component| {
+                let d = component.primitive_at(coordinate, time).mass_density;
+                (d * component.scalar_at(coordinate, time), d)
+            })
+            .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+
+        scalar_mass / mass
+    }
+
+    fn scalar_injection_at(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        self.components
+            .iter()
+            .map(|component| component.scalar_injection_at(coordinate, time))
+            .sum()
+    }
+
+    fn conserved_source_at(&self, coordinate: (f64, f64), time: f64) -> AnyConserved {
+        let mut result = AnyConserved::default();
+
+        for component in &self.components {
+            let s = component.conserved_source_at(coordinate, time);
+            result.mass_density += s.mass_density;
+            result.momentum_r += s.momentum_r;
+            result.momentum_q += s.momentum_q;
+            result.energy_density += s.energy_density;
+        }
+        result
+    }
+
+    fn regulate(&mut self, measured: &Reductions) {
+        for component in &mut self.components {
+            component.regulate(measured)
+        }
+    }
+
+    fn needs_regulation(&self) -> bool {
+        self.components.iter().any(|component| component.needs_regulation())
+    }
+}
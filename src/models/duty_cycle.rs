@@ -0,0 +1,70 @@
+use serde::{Serialize, Deserialize};
+
+
+
+
+/**
+ * An intermittent engine, which fires a number of episodes of equal length,
+ * separated by quiet periods of equal length. Each episode launches a shell
+ * of jet material; faster shells catching up with slower material produce
+ * internal shocks.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DutyCycle {
+
+    /// Duration of each episode
+    pub on_time: f64,
+
+    /// Duration of the quiet period between episodes
+    pub off_time: f64,
+
+    /// Number of episodes
+    pub episodes: usize,
+}
+
+
+
+
+// ============================================================================
+impl DutyCycle {
+
+    /**
+     * Return an error if the duty cycle parameters are not sensible.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.on_time <= 0.0 || self.off_time < 0.0 {
+            anyhow::bail!("duty_cycle on_time must be positive, and off_time non-negative")
+        }
+        if self.episodes == 0 {
+            anyhow::bail!("duty_cycle must have at least one episode")
+        }
+        Ok(())
+    }
+
+    /**
+     * Determine whether the engine is firing at the given time since it
+     * started.
+     *
+     * * `t` - Time since the start of the first episode
+     */
+    pub fn is_on(&self, t: f64) -> bool {
+        let period = self.on_time + self.off_time;
+        let episode = (t / period).floor();
+        t > 0.0 && episode < self.episodes as f64 && t - episode * period < self.on_time
+    }
+
+    /**
+     * The total time the engine is firing, summed over the episodes.
+     */
+    pub fn total_on_time(&self) -> f64 {
+        self.on_time * self.episodes as f64
+    }
+
+    /**
+     * The time from the start of the first episode to the end of the last.
+     */
+    pub fn duration(&self) -> f64 {
+        (self.on_time + self.off_time) * self.episodes as f64 - self.off_time
+    }
+}
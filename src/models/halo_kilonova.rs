@@ -1,4 +1,6 @@
+use std::sync::{Arc, Mutex};
 use crate::galmod::GalacticModel;
+use crate::lookup_table_v2::LookupTable2d;
 use crate::physics::{AnyPrimitive, LIGHT_SPEED};
 use crate::traits::InitialModel;
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,24 @@ pub struct HaloKilonova {
     pub shell_mass: f64,
     pub radial_distance: f64,
 
+    /// Angle between the polar axis of the explosion and the galactic
+    /// vertical, tilted towards increasing galactocentric radius R.
+    /// Defaults to zero.
+    #[serde(default)]
+    pub tilt: f64,
+
+    /// Optional path to an ASCII table of the external medium on an (R, z)
+    /// grid, with the columns (R [cm], z [cm], mass density [g / cm^3], gas
+    /// pressure). The rows must cover every point of a rectangular grid,
+    /// ordered by R and then by z. Points outside the table take the values
+    /// at its nearest edge. If omitted, the external medium is the vertical
+    /// profile of the galactic model at the given radial distance.
+    #[serde(default)]
+    pub background_table: Option<String>,
+
+    #[serde(skip)]
+    pub lookup_table: Arc<Mutex<Option<Arc<LookupTable2d<4>>>>>,
+
     // this options is deprecated, it's implied by the galactic model
     #[serde(default, skip)]
     pub external_medium_density: f64,
@@ -40,6 +60,24 @@ impl HaloKilonova {
     fn shell_duration(&self) -> f64 {
         self.shell_thickness / self.shell_velocity()
     }
+
+    /**
+     * Return the galactocentric coordinates (R, z) of the point at radius r
+     * and polar angle q from the explosion site.
+     */
+    fn galactic_coordinates(&self, r: f64, q: f64) -> (f64, f64) {
+        let big_r = (self.radial_distance + r * (q + self.tilt).sin()).abs();
+        let z = self.altitude + r * (q + self.tilt).cos();
+        (big_r, z)
+    }
+
+    fn require_lookup_table(&self, filename: &str) -> Arc<LookupTable2d<4>> {
+        self.lookup_table
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(LookupTable2d::from_ascii_file(filename).unwrap()))
+            .clone()
+    }
 }
 
 // ============================================================================
@@ -53,6 +91,9 @@ impl InitialModel for HaloKilonova {
             increasing the shell mass.", self.shell_velocity() / LIGHT_SPEED}
         // } else if rmax < explosion_alititude {
         //     anyhow::bail!{"domain would intersect the galactic midplane!"}
+        } else if let Some(background_table) = &self.background_table {
+            LookupTable2d::<4>::from_ascii_file(background_table)?;
+            Ok(())
         } else {
             Ok(())
         }
@@ -60,7 +101,7 @@ impl InitialModel for HaloKilonova {
 
     fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
         let (r, q) = coordinate;
-        let z = r * q.cos() + self.altitude;
+        let (big_r, z) = self.galactic_coordinates(r, q);
         let p0 = 1e-3; // PRESSURE AT BASE OF ATMOSPHERE -- SET THIS APPROPRIATELY
 
         if self.shell_extent(t).contains(&r) {
@@ -75,6 +116,15 @@ impl InitialModel for HaloKilonova {
                 mass_density: d,
                 gas_pressure: p,
             }
+        } else if let Some(background_table) = &self.background_table {
+            let sample = self.require_lookup_table(background_table).sample(big_r, z);
+
+            AnyPrimitive {
+                velocity_r: 0.0,
+                velocity_q: 0.0,
+                mass_density: sample[2],
+                gas_pressure: sample[3],
+            }
         } else if z > 0.0 {
             let model = GalacticModel {
                 g: 6.67e-8,
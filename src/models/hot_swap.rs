@@ -0,0 +1,85 @@
+use serde::{Serialize, Deserialize};
+use crate::app::AnyModel;
+use crate::physics::{AnyConserved, AnyPrimitive};
+use crate::reductions::Reductions;
+use crate::traits::InitialModel;
+
+
+
+
+/**
+ * A pair of models, with the first used before the switch time and the
+ * second one afterwards. The initial data is generated by whichever model is
+ * active at the start time, and the boundary data and source terms are taken
+ * from the model which is active at the current time. This can be used to
+ * stage physical scenarios, e.g. an engine model followed by a passive
+ * ambient medium.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HotSwap {
+
+    /// Model used before the switch time
+    pub before: Box<AnyModel>,
+
+    /// Model used at and after the switch time
+    pub after: Box<AnyModel>,
+
+    /// Time at which the models are swapped
+    pub switch_time: f64,
+}
+
+
+
+
+// ============================================================================
+impl HotSwap {
+    fn active(&self, time: f64) -> &AnyModel {
+        if time < self.switch_time {
+            &self.before
+        } else {
+            &self.after
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for HotSwap {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.before.validate()?;
+        self.after.validate()?;
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), time: f64) -> AnyPrimitive {
+        self.active(time).primitive_at(coordinate, time)
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        self.active(time).scalar_at(coordinate, time)
+    }
+
+    fn scalar_injection_at(&self, coordinate: (f64, f64), time: f64) -> f64 {
+        self.active(time).scalar_injection_at(coordinate, time)
+    }
+
+    fn conserved_source_at(&self, coordinate: (f64, f64), time: f64) -> AnyConserved {
+        self.active(time).conserved_source_at(coordinate, time)
+    }
+
+    fn regulate(&mut self, measured: &Reductions) {
+        if measured.time < self.switch_time {
+            self.before.regulate(measured)
+        } else {
+            self.after.regulate(measured)
+        }
+    }
+
+    fn needs_regulation(&self) -> bool {
+        self.before.needs_regulation() || self.after.needs_regulation()
+    }
+}
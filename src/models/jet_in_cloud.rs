@@ -1,6 +1,8 @@
 use std::f64::consts::PI;
 use serde::{Serialize, Deserialize};
-use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::models::{AmbientZone, Cloud, DutyCycle, LuminosityTable, Nozzle, Perturbation, SouthernEngine};
+use crate::physics::{AnyConserved, AnyPrimitive, LIGHT_SPEED};
+use crate::reductions::Reductions;
 use crate::traits::InitialModel;
 
 static NOMINAL_LAUNCH_RADIUS: f64 = 1e8;
@@ -46,6 +48,62 @@ pub struct JetInCloud {
 
     /// Index psi in u(m) ~ m^-psi
     pub envelop_psi: f64,
+
+    /// Scalar mass injected per unit volume per unit time in the jet zone,
+    /// inside the scalar injection radius. Defaults to zero, in which case
+    /// the jet scalar only enters through the inner boundary.
+    #[serde(default)]
+    pub scalar_injection_rate: f64,
+
+    /// Radius inside which the jet scalar is injected
+    #[serde(default)]
+    pub scalar_injection_radius: f64,
+
+    /// Optional engine which injects the jet through volumetric source
+    /// terms. If given, the model does not impose the jet zone in the
+    /// initial and boundary data, and the engine parameters above are
+    /// unused.
+    #[serde(default)]
+    pub nozzle: Option<Nozzle>,
+
+    /// Optional table of the engine luminosity and Lorentz factor versus
+    /// time (see [`LuminosityTable`]). If given, the jet zone contains the
+    /// material launched while the table luminosity is positive, and the
+    /// engine duration, strength, and four-velocity are unused.
+    #[serde(default)]
+    pub luminosity_table: Option<LuminosityTable>,
+
+    /// Optional duty cycle, which makes the engine fire in episodes. If
+    /// given, the engine duration is unused, and the engine energy is
+    /// divided evenly among the episodes.
+    #[serde(default)]
+    pub duty_cycle: Option<DutyCycle>,
+
+    /// Optional engine parameters for the southern hemisphere. If given,
+    /// the jet launched into theta > pi/2 differs from the northern one in
+    /// the parameters it lists. The cloud is the same in both hemispheres.
+    #[serde(default)]
+    pub southern_engine: Option<SouthernEngine>,
+
+    /// Optional random density perturbations of the ambient medium (every
+    /// zone but the jet). Their radial coordinate is the velocity r / t, so
+    /// they expand with the cloud and envelope. The pressure is not
+    /// perturbed.
+    #[serde(default)]
+    pub perturbation: Option<Perturbation>,
+
+    /// Time over which the engine ramps on and off, at the start and end of
+    /// each episode. The jet material launched during the ramp is blended
+    /// smoothly with the ambient medium. Defaults to zero, in which case
+    /// the engine switches on and off sharply.
+    #[serde(default)]
+    pub engine_taper_time: f64,
+
+    /// Angle, inside the engine opening angle, over which the jet blends
+    /// smoothly into the ambient medium. Defaults to zero, in which case the
+    /// jet has a sharp edge.
+    #[serde(default)]
+    pub engine_taper_angle: f64,
 }
 
 
@@ -55,8 +113,7 @@ pub struct JetInCloud {
  * Different space-time zones in the setup
  */
 pub enum Zone {
-    Envelope,
-    Cloud,
+    Ambient(AmbientZone),
     Jet,
 }
 
@@ -67,6 +124,33 @@ pub enum Zone {
 impl InitialModel for JetInCloud {
 
     fn validate(&self) -> anyhow::Result<()> {
+        if self.scalar_injection_rate < 0.0 {
+            anyhow::bail!("scalar_injection_rate must be non-negative")
+        }
+        if let Some(nozzle) = &self.nozzle {
+            nozzle.validate()?
+        }
+        if let Some(luminosity_table) = &self.luminosity_table {
+            luminosity_table.validate()?
+        }
+        if let Some(duty_cycle) = &self.duty_cycle {
+            if self.luminosity_table.is_some() {
+                anyhow::bail!("duty_cycle and luminosity_table cannot both be given")
+            }
+            duty_cycle.validate()?
+        }
+        if let Some(southern_engine) = &self.southern_engine {
+            southern_engine.validate()?
+        }
+        if let Some(perturbation) = &self.perturbation {
+            perturbation.validate()?
+        }
+        if self.engine_taper_time < 0.0 || self.engine_taper_angle < 0.0 {
+            anyhow::bail!("engine_taper_time and engine_taper_angle must be non-negative")
+        }
+        if self.engine_taper_time > 0.0 && self.luminosity_table.is_some() {
+            anyhow::bail!("engine_taper_time cannot be used with a luminosity_table")
+        }
         self.print(&mut std::io::stdout());
         Ok(())
     }
@@ -77,6 +161,7 @@ impl InitialModel for JetInCloud {
         let u = self.gamma_beta(r, q, t);
         let d = f / (r * r * u) / LIGHT_SPEED;
         let p = d * UNIFORM_TEMPERATURE;
+        let d = d * self.density_factor(r, q, t);
 
         AnyPrimitive{
             velocity_r: u,
@@ -90,11 +175,36 @@ impl InitialModel for JetInCloud {
         let (r, q) = coordinate;
 
         match self.zone(r, q, t) {
-            Zone::Cloud       => 1e+0,
-            Zone::Jet         => 1e+2,
-            Zone::Envelope    => 1e-2,
+            Zone::Ambient(zone) => self.cloud().scalar_in(zone),
+            Zone::Jet           => 1e+2,
         }
     }
+
+    fn scalar_injection_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, q) = coordinate;
+
+        match self.zone(r, q, t) {
+            Zone::Jet if r < self.scalar_injection_radius => self.scalar_injection_rate,
+            _ => 0.0,
+        }
+    }
+
+    fn conserved_source_at(&self, coordinate: (f64, f64), t: f64) -> AnyConserved {
+        match &self.nozzle {
+            Some(nozzle) => nozzle.source_at(coordinate, t),
+            None => AnyConserved::default(),
+        }
+    }
+
+    fn regulate(&mut self, measured: &Reductions) {
+        if let Some(nozzle) = &mut self.nozzle {
+            nozzle.regulate(measured)
+        }
+    }
+
+    fn needs_regulation(&self) -> bool {
+        self.nozzle.as_ref().map_or(false, Nozzle::has_feedback)
+    }
 }
 
 
@@ -146,15 +256,25 @@ impl JetInCloud
      * Time when the jet turns off
      */
     pub fn get_t4(&self) -> f64 {
-        self.get_t2() + self.engine_duration
+        match &self.duty_cycle {
+            Some(duty_cycle) => self.get_t2() + duty_cycle.duration(),
+            None => self.get_t2() + self.engine_duration,
+        }
     }
 
     /**
-     * Four-velocity gamma-beta of the slowest envelope shell
+     * The debris cloud and envelope which the jet propagates through, and
+     * which are the ambient medium blended with the jet at its tapered edges
      */
-    pub fn envelop_slowest_u(&self) -> f64 {
-        let b = self.envelop_slowest_beta;
-        b / (1.0 - b * b).sqrt()
+    pub fn cloud(&self) -> Cloud {
+        Cloud{
+            cloud_mass: self.cloud_mass,
+            engine_delay: self.engine_delay,
+            envelop_m1: self.envelop_m1,
+            envelop_slowest_beta: self.envelop_slowest_beta,
+            envelop_fastest_beta: self.envelop_fastest_beta,
+            envelop_psi: self.envelop_psi,
+        }
     }
 
     /**
@@ -165,36 +285,130 @@ impl JetInCloud
     }
 
     /**
-     * Determine if a polar angle is within theta_jet of either pole.
+     * Return the southern engine parameters if the polar angle is in the
+     * southern hemisphere.
+     */
+    fn south(&self, q: f64) -> Option<&SouthernEngine> {
+        self.southern_engine.as_ref().filter(|_| q > 0.5 * PI)
+    }
+
+    /**
+     * Time when the jet turns on in the hemisphere of the polar angle q
+     */
+    pub fn engine_delay_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_delay).unwrap_or(self.engine_delay)
+    }
+
+    /**
+     * Duration of the engine in the hemisphere of the polar angle q
+     */
+    pub fn engine_duration_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_duration).unwrap_or(self.engine_duration)
+    }
+
+    /**
+     * Engine four-velocity in the hemisphere of the polar angle q
+     */
+    pub fn engine_u_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_u).unwrap_or(self.engine_u)
+    }
+
+    /**
+     * Engine opening angle in the hemisphere of the polar angle q
+     */
+    pub fn engine_theta_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_theta).unwrap_or(self.engine_theta)
+    }
+
+    /**
+     * Determine if a polar angle is within theta_jet of either pole. The
+     * opening angle is that of the engine in the same hemisphere.
      *
      * * `q` - The polar angle theta
      */
     pub fn in_nozzle(&self, q: f64) -> bool {
-        q < self.engine_theta || q > PI - self.engine_theta
+        let engine_theta = self.engine_theta_at(q);
+        q < engine_theta || q > PI - engine_theta
     }
 
     /**
-     * Determine the zone of the ambient medium for a given radius and time.
+     * Determine the zone for a given radius, polar angle, and time.
      *
      * * `r` - Radius
      * * `q` - Polar angle
      * * `t` - Time
      */
     pub fn zone(&self, r: f64, q: f64, t: f64) -> Zone {
-        let v_min = self.envelop_slowest_beta * LIGHT_SPEED;
-        let v_jet = self.engine_beta() * LIGHT_SPEED;
+        let engine_u = self.engine_u_at(q);
+        let engine_delay = self.engine_delay_at(q);
+        let v_jet = engine_u / (1.0 + engine_u.powi(2)).sqrt() * LIGHT_SPEED;
+
+        let r_jet_head = v_jet * (t - engine_delay);
+        let r_jet_tail = v_jet * (t - engine_delay - self.engine_duration_at(q));
 
-        let r_cloud_envelop_interface = v_min * t;
-        let r_jet_head = v_jet * (t - self.engine_delay);
-        let r_jet_tail = v_jet * (t - self.engine_delay - self.engine_duration);
+        let in_jet = match (&self.luminosity_table, &self.duty_cycle) {
+            (Some(table), _) => table.engine_at(r, t).is_some(),
+            (None, Some(duty_cycle)) => duty_cycle.is_on(t - engine_delay - r / v_jet),
+            (None, None) => r < r_jet_head && r > r_jet_tail,
+        };
 
-        if self.in_nozzle(q) && r < r_jet_head  && r > r_jet_tail {
+        if self.nozzle.is_none() && self.in_nozzle(q) && in_jet {
             Zone::Jet
-        } else if r > r_cloud_envelop_interface {
-            Zone::Envelope
         } else {
-            Zone::Cloud
+            Zone::Ambient(self.cloud().zone(r, t))
+        }
+    }
+
+    /**
+     * Return the weight, between zero and one, of the jet material in the
+     * jet zone, where the remainder is the ambient medium. The weight is
+     * less than one within the taper time of the engine switching on or off
+     * when the material was launched, or within the taper angle of the jet
+     * edge.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn jet_weight(&self, r: f64, q: f64, t: f64) -> f64 {
+        let mut w = 1.0;
+
+        if self.engine_taper_time > 0.0 {
+            let engine_u = self.engine_u_at(q);
+            let v_jet = engine_u / (1.0 + engine_u.powi(2)).sqrt() * LIGHT_SPEED;
+            let s = t - self.engine_delay_at(q) - r / v_jet;
+
+            let (s, length) = match &self.duty_cycle {
+                Some(duty_cycle) => {
+                    let period = duty_cycle.on_time + duty_cycle.off_time;
+                    (s - (s / period).floor() * period, duty_cycle.on_time)
+                }
+                None => (s, self.engine_duration_at(q)),
+            };
+            w *= taper(s / self.engine_taper_time) * taper((length - s) / self.engine_taper_time);
+        }
+        if self.engine_taper_angle > 0.0 {
+            let angle_from_pole = f64::min(q, PI - q);
+            w *= taper((self.engine_theta_at(q) - angle_from_pole) / self.engine_taper_angle);
         }
+        w
+    }
+
+    /**
+     * Return the factor by which the density is perturbed, which is one in
+     * the jet zone, or if there are no perturbations.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn density_factor(&self, r: f64, q: f64, t: f64) -> f64 {
+        if let Some(perturbation) = &self.perturbation {
+            if !matches!(self.zone(r, q, t), Zone::Jet) {
+                return perturbation.density_factor(r / t, q)
+            }
+        }
+        1.0
     }
 
     /**
@@ -206,16 +420,21 @@ impl JetInCloud
      */
     pub fn gamma_beta(&self, r: f64, q: f64, t: f64) -> f64 {
         match self.zone(r, q, t) {
-            Zone::Cloud => {
-                self.envelop_slowest_u()
-            }
-            Zone::Envelope => {
-                let b = f64::min(r / t / LIGHT_SPEED, self.envelop_fastest_beta);
-                let u = b / f64::sqrt(1.0 - b * b);
-                u
+            Zone::Ambient(zone) => {
+                self.cloud().gamma_beta_in(zone, r, t)
             }
             Zone::Jet => {
-                self.engine_u
+                let u = match &self.luminosity_table {
+                    Some(table) => table.engine_at(r, t).unwrap().1,
+                    None => self.engine_u_at(q),
+                };
+                let w = self.jet_weight(r, q, t);
+
+                if w < 1.0 {
+                    w * u + (1.0 - w) * self.cloud().gamma_beta(r, t)
+                } else {
+                    u
+                }
             }
         }
     }
@@ -228,31 +447,53 @@ impl JetInCloud
      * * `t` - The time
      */
     pub fn mass_rate_per_steradian(&self, r: f64, q: f64, t: f64) -> f64 {
-
         match self.zone(r, q, t) {
-            Zone::Cloud => {
-                self.cloud_mass_rate_per_steradian()
-            }
-            Zone::Envelope => {
-                let s = f64::min(r / t / LIGHT_SPEED, self.envelop_fastest_beta);
-                let f = f64::powf(s, -1.0 / self.envelop_psi) * f64::powf(1.0 - s * s, 0.5 / self.envelop_psi - 1.0);
-                self.envelop_m1 / (4.0 * PI * self.envelop_psi * t) * f
+            Zone::Ambient(zone) => {
+                self.cloud().mass_rate_per_steradian_in(zone, r, t)
             }
             Zone::Jet => {
-                self.jet_mass_rate_per_steradian()
+                let f = match &self.luminosity_table {
+                    Some(table) => {
+                        let (l, u) = table.engine_at(r, t).unwrap();
+                        l / (4.0 * PI * LIGHT_SPEED * LIGHT_SPEED) / f64::sqrt(1.0 + u * u)
+                    }
+                    None => self.jet_mass_rate_per_steradian(q),
+                };
+                let w = self.jet_weight(r, q, t);
+
+                if w < 1.0 {
+                    w * f + (1.0 - w) * self.cloud().mass_rate_per_steradian(r, t)
+                } else {
+                    f
+                }
             }
         }
     }
 
-    // fn taper(x: f64)
-    fn jet_mass_rate_per_steradian(&self) -> f64 {
-        let engine_gamma = f64::sqrt(1.0 + self.engine_u * self.engine_u);
-        let e = self.engine_strength * self.cloud_mass;
-        let l = e / (4.0 * PI * self.engine_duration);
+    fn jet_mass_rate_per_steradian(&self, q: f64) -> f64 {
+        let engine_u = self.engine_u_at(q);
+        let engine_gamma = f64::sqrt(1.0 + engine_u * engine_u);
+        let e = match self.south(q).and_then(|s| s.energy_ratio) {
+            Some(energy_ratio) => self.engine_strength * self.cloud_mass * energy_ratio,
+            None => self.engine_strength * self.cloud_mass,
+        };
+        let l = match &self.duty_cycle {
+            Some(duty_cycle) => e / (4.0 * PI * duty_cycle.total_on_time()),
+            None => e / (4.0 * PI * self.engine_duration_at(q)),
+        };
         l / engine_gamma
     }
+}
 
-    fn cloud_mass_rate_per_steradian(&self) -> f64 {
-        self.cloud_mass / (4.0 * PI * self.engine_delay)
-    }
+
+
+
+// ============================================================================
+/**
+ * A smooth step from zero at x <= 0 to one at x >= 1, with zero slope at
+ * both ends.
+ */
+fn taper(x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
 }
@@ -1,6 +1,8 @@
 use std::f64::consts::PI;
 use serde::{Serialize, Deserialize};
-use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::models::{DutyCycle, LuminosityTable, Nozzle, Perturbation, SouthernEngine};
+use crate::physics::{AnyConserved, AnyPrimitive, LIGHT_SPEED};
+use crate::reductions::Reductions;
 use crate::traits::InitialModel;
 
 
@@ -8,22 +10,71 @@ use crate::traits::InitialModel;
 
 static UNIFORM_TEMPERATURE: f64 = 1e-10;
 
-// Constants as given in Duffell & MacDayen(2015)
-// source: https://arxiv.org/pdf/1407.8250.pdf
-static R0:                  f64 = 7e10;
-static M0:                  f64 = 2e33;
-static RHO_C:               f64 = 3e7 * M0 / (1.33 * PI * R0 * R0 * R0);
-static R1:                  f64 = 0.0017 * R0;
-static R2:                  f64 = 0.0125 * R0;
-static R3:                  f64 = 0.65   * R0;
-static K1:                  f64 = 3.24;
-static K2:                  f64 = 2.57;
-static N:                   f64 = 16.7;
-static RHO_WIND:            f64 = 1e-9 * M0 / (1.33 * PI * R0 * R0 * R0);
-// static RHO_ENV:             f64 = 1e-7 * M0 / (1.33 * PI * R0 * R0 * R0);
-static R_NOZZ:              f64 = 0.01 * R0; 
-static R_ENV:               f64 = 1.2  * R0;
-static ALPHA:               f64 = 2.5;
+
+
+
+/**
+ * Density profile of the progenitor star, following Duffell & MacFadyen
+ * (2015), source: https://arxiv.org/pdf/1407.8250.pdf. The core density is
+ *
+ * rho_c (1 - r / r3)^n / (1 + (r / r1)^k1 / (1 + (r / r2)^k2))
+ *
+ * inside r3. Radii are given in units of the stellar radius scale R0. Each
+ * parameter defaults to the value in the paper.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StellarProfile {
+
+    /// Radius scale R0 of the star (cm)
+    #[serde(default = "StellarProfile::default_radius")]
+    pub radius: f64,
+
+    /// Central density rho_c (g/cm^3)
+    #[serde(default = "StellarProfile::default_central_density")]
+    pub central_density: f64,
+
+    /// Inner break radius r1 / R0
+    #[serde(default = "StellarProfile::default_r1")]
+    pub r1: f64,
+
+    /// Outer break radius r2 / R0
+    #[serde(default = "StellarProfile::default_r2")]
+    pub r2: f64,
+
+    /// Radius r3 / R0 of the edge of the stellar core
+    #[serde(default = "StellarProfile::default_r3")]
+    pub r3: f64,
+
+    /// Index k1 of the inner profile
+    #[serde(default = "StellarProfile::default_k1")]
+    pub k1: f64,
+
+    /// Index k2 of the outer profile
+    #[serde(default = "StellarProfile::default_k2")]
+    pub k2: f64,
+
+    /// Index n of the cutoff at the edge of the core
+    #[serde(default = "StellarProfile::default_n")]
+    pub n: f64,
+
+    /// Index alpha of the envelope density rho ~ r^-alpha
+    #[serde(default = "StellarProfile::default_envelope_index")]
+    pub envelope_index: f64,
+
+    /// Density (g/cm^3) of the r^-2 wind outside the envelope, at the wind
+    /// reference radius
+    #[serde(default = "StellarProfile::default_wind_density")]
+    pub wind_density: f64,
+
+    /// Wind reference radius / R0
+    #[serde(default = "StellarProfile::default_wind_radius")]
+    pub wind_radius: f64,
+
+    /// Radius / R0 of the region where the jet is launched
+    #[serde(default = "StellarProfile::default_nozzle_radius")]
+    pub nozzle_radius: f64,
+}
 
 
 
@@ -36,7 +87,8 @@ static ALPHA:               f64 = 2.5;
 #[serde(deny_unknown_fields)]
 pub struct JetInStar {
 
-    /// Mass of the star
+    /// Mass of the star. This is not used; the stellar density is set by
+    /// the stellar profile.
     pub star_mass: f64,
 
     /// Duration of the engine
@@ -59,6 +111,54 @@ pub struct JetInStar {
 
     /// Hydrogen Volume Filling Factor
     pub volume_factor: f64,
+
+    /// Scalar mass injected per unit volume per unit time in the jet zone,
+    /// inside the scalar injection radius. Defaults to zero, in which case
+    /// the jet scalar only enters through the inner boundary.
+    #[serde(default)]
+    pub scalar_injection_rate: f64,
+
+    /// Radius inside which the jet scalar is injected
+    #[serde(default)]
+    pub scalar_injection_radius: f64,
+
+    /// Optional engine which injects the jet through volumetric source
+    /// terms. If given, the model does not impose the jet zone in the
+    /// initial and boundary data, and the engine parameters above are
+    /// unused.
+    #[serde(default)]
+    pub nozzle: Option<Nozzle>,
+
+    /// Optional table of the engine luminosity and Lorentz factor versus
+    /// time (see [`LuminosityTable`]). If given, the jet zone contains the
+    /// material launched while the table luminosity is positive, and the
+    /// engine duration, energy, and four-velocity are unused.
+    #[serde(default)]
+    pub luminosity_table: Option<LuminosityTable>,
+
+    /// Optional duty cycle, which makes the engine fire in episodes. If
+    /// given, the engine duration is unused, and the engine energy is
+    /// divided evenly among the episodes.
+    #[serde(default)]
+    pub duty_cycle: Option<DutyCycle>,
+
+    /// Optional engine parameters for the southern hemisphere. If given,
+    /// the jet launched into theta > pi/2 differs from the northern one in
+    /// the parameters it lists. The northern jet starts at t = 0, and the
+    /// southern jet after the southern engine_delay, if it is given.
+    #[serde(default)]
+    pub southern_engine: Option<SouthernEngine>,
+
+    /// Optional random density perturbations of the ambient medium (every
+    /// zone but the jet). Their radial coordinate is the radius. The
+    /// pressure is not perturbed.
+    #[serde(default)]
+    pub perturbation: Option<Perturbation>,
+
+    /// Density profile of the star. Defaults to the profile of Duffell &
+    /// MacFadyen (2015).
+    #[serde(default)]
+    pub star: StellarProfile,
 }
 
 
@@ -77,11 +177,113 @@ pub enum Zone {
 
 
 
+// ============================================================================
+impl Default for StellarProfile {
+    fn default() -> Self {
+        Self {
+            radius:          Self::default_radius(),
+            central_density: Self::default_central_density(),
+            r1:              Self::default_r1(),
+            r2:              Self::default_r2(),
+            r3:              Self::default_r3(),
+            k1:              Self::default_k1(),
+            k2:              Self::default_k2(),
+            n:               Self::default_n(),
+            envelope_index:  Self::default_envelope_index(),
+            wind_density:    Self::default_wind_density(),
+            wind_radius:     Self::default_wind_radius(),
+            nozzle_radius:   Self::default_nozzle_radius(),
+        }
+    }
+}
+
+impl StellarProfile {
+    const SOLAR_MASS: f64 = 2e33;
+
+    fn default_radius()          -> f64 { 7e10 }
+    fn default_central_density() -> f64 { 3e7 * Self::SOLAR_MASS / (1.33 * PI * Self::default_radius() * Self::default_radius() * Self::default_radius()) }
+    fn default_r1()              -> f64 { 0.0017 }
+    fn default_r2()              -> f64 { 0.0125 }
+    fn default_r3()              -> f64 { 0.65 }
+    fn default_k1()              -> f64 { 3.24 }
+    fn default_k2()              -> f64 { 2.57 }
+    fn default_n()               -> f64 { 16.7 }
+    fn default_envelope_index()  -> f64 { 2.5 }
+    fn default_wind_density()    -> f64 { 1e-9 * Self::SOLAR_MASS / (1.33 * PI * Self::default_radius() * Self::default_radius() * Self::default_radius()) }
+    fn default_wind_radius()     -> f64 { 1.2 }
+    fn default_nozzle_radius()   -> f64 { 0.01 }
+
+    /**
+     * Return an error if the profile parameters are not sensible.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.radius <= 0.0 || self.central_density <= 0.0 || self.wind_density < 0.0 {
+            anyhow::bail!("the star radius and central_density must be positive, and wind_density non-negative")
+        }
+        if [self.r1, self.r2, self.r3, self.wind_radius, self.nozzle_radius].iter().any(|&r| r <= 0.0) {
+            anyhow::bail!("the stellar profile radii must be positive")
+        }
+        Ok(())
+    }
+
+    /**
+     * The radius (cm) of the edge of the stellar core
+     */
+    pub fn core_radius(&self) -> f64 {
+        self.r3 * self.radius
+    }
+
+    /**
+     * The density (g/cm^3) of the stellar core at radius r
+     */
+    pub fn core_density(&self, r: f64) -> f64 {
+        let r1 = self.r1 * self.radius;
+        let r2 = self.r2 * self.radius;
+        let num = self.central_density * (1.0 - r / self.core_radius()).powf(self.n);
+        let denom = 1.0 + (r / r1).powf(self.k1) / (1.0 + (r / r2).powf(self.k2));
+        num / denom
+    }
+
+    /**
+     * The density (g/cm^3) of the wind at radius r
+     */
+    pub fn wind_density_at(&self, r: f64) -> f64 {
+        self.wind_density * (r / (self.wind_radius * self.radius)).powf(-2.0)
+    }
+}
+
+
+
+
 // ============================================================================
 impl InitialModel for JetInStar {
 
     fn validate(&self) -> anyhow::Result<()> {
-        Ok(())
+        if self.scalar_injection_rate < 0.0 {
+            anyhow::bail!("scalar_injection_rate must be non-negative")
+        }
+        if let Some(nozzle) = &self.nozzle {
+            nozzle.validate()?
+        }
+        if let Some(luminosity_table) = &self.luminosity_table {
+            luminosity_table.validate()?
+        }
+        if let Some(duty_cycle) = &self.duty_cycle {
+            if self.luminosity_table.is_some() {
+                anyhow::bail!("duty_cycle and luminosity_table cannot both be given")
+            }
+            duty_cycle.validate()?
+        }
+        if let Some(southern_engine) = &self.southern_engine {
+            southern_engine.validate()?
+        }
+        if let Some(perturbation) = &self.perturbation {
+            perturbation.validate()?
+        }
+        if self.envelope_radius <= self.star.core_radius() || self.envelope_mass < 0.0 || self.volume_factor <= 0.0 {
+            anyhow::bail!("envelope_radius must exceed the stellar core radius, envelope_mass must be non-negative, and volume_factor positive")
+        }
+        self.star.validate()
     }
 
     fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
@@ -89,6 +291,7 @@ impl InitialModel for JetInStar {
         let d = self.mass_density(r, q, t);
         let u = self.gamma_beta(r, q, t);
         let p = d * UNIFORM_TEMPERATURE;
+        let d = d * self.density_factor(r, q, t);
 
         AnyPrimitive {
             velocity_r: u,
@@ -105,10 +308,36 @@ impl InitialModel for JetInStar {
         match zone {
             Zone::Core     => 1e+0,
             Zone::Jet      => 1e+2,
-            Zone::Envelope => 1e-2 * (r / R3).powf(-2.0),
-            Zone::Wind     => 1e-5 * (r / R_ENV).powf(-2.0),
+            Zone::Envelope => 1e-2 * (r / self.star.core_radius()).powf(-2.0),
+            Zone::Wind     => 1e-5 * (r / (self.star.wind_radius * self.star.radius)).powf(-2.0),
+        }
+    }
+
+    fn scalar_injection_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, q) = coordinate;
+
+        match self.zone(r, q, t) {
+            Zone::Jet if r < self.scalar_injection_radius && self.engine_is_on(q, t) => self.scalar_injection_rate,
+            _ => 0.0,
         }
     }
+
+    fn conserved_source_at(&self, coordinate: (f64, f64), t: f64) -> AnyConserved {
+        match &self.nozzle {
+            Some(nozzle) => nozzle.source_at(coordinate, t),
+            None => AnyConserved::default(),
+        }
+    }
+
+    fn regulate(&mut self, measured: &Reductions) {
+        if let Some(nozzle) = &mut self.nozzle {
+            nozzle.regulate(measured)
+        }
+    }
+
+    fn needs_regulation(&self) -> bool {
+        self.nozzle.as_ref().map_or(false, Nozzle::has_feedback)
+    }
 }
 
 
@@ -121,24 +350,22 @@ impl JetInStar
      * The comoving mass density in g/cc
      */
     fn mass_density(&self, r: f64, q: f64, t: f64) -> f64{
-        let zone      = self.zone(r, q, t);
-        let num       = RHO_C * ((1.0 - r / R3)).powf(N);
-        let denom     = 1.0 + (r / R1).powf(K1) / (1.0 + (r / R2).powf(K2));
-        let core_zone = num / denom;
-        let rho_env   = self.envelope_mass / (4.0 * PI * self.envelope_radius.powi(2) * (self.envelope_radius - R3) * self.volume_factor);
+        let r3      = self.star.core_radius();
+        let rho_env = self.envelope_mass / (4.0 * PI * self.envelope_radius.powi(2) * (self.envelope_radius - r3) * self.volume_factor);
 
-        match zone {
+        match self.zone(r, q, t) {
             Zone::Core => {
-                core_zone + rho_env * (r/R3).powf(-2.0)
+                self.star.core_density(r) + rho_env * (r / r3).powf(-2.0)
             }
             Zone::Envelope => {
-                rho_env *(r/R3).powf(-ALPHA)
+                rho_env * (r / r3).powf(-self.star.envelope_index)
             }
             Zone::Jet => {
-                self.jet_mass_rate_per_steradian(r, q) / (r * r * self.engine_u * LIGHT_SPEED)
+                let u = self.engine_u_at(r, q, t);
+                self.jet_mass_rate_per_steradian(r, q, t) / (r * r * u * LIGHT_SPEED)
             }
             Zone::Wind => {
-                RHO_WIND * (r/R_ENV).powf(-2.0)
+                self.star.wind_density_at(r)
             }
         }
     }
@@ -151,12 +378,43 @@ impl JetInStar
     }
 
     /**
-     * Determine if a polar angle is within theta_jet of either pole.
+     * Return the southern engine parameters if the polar angle is in the
+     * southern hemisphere.
+     */
+    fn south(&self, q: f64) -> Option<&SouthernEngine> {
+        self.southern_engine.as_ref().filter(|_| q > 0.5 * PI)
+    }
+
+    /**
+     * Time when the jet turns on in the hemisphere of the polar angle q
+     */
+    pub fn engine_delay_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_delay).unwrap_or(0.0)
+    }
+
+    /**
+     * Duration of the engine in the hemisphere of the polar angle q
+     */
+    pub fn engine_duration_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_duration).unwrap_or(self.engine_duration)
+    }
+
+    /**
+     * Engine opening angle in the hemisphere of the polar angle q
+     */
+    pub fn engine_theta_at(&self, q: f64) -> f64 {
+        self.south(q).and_then(|s| s.engine_theta).unwrap_or(self.engine_theta)
+    }
+
+    /**
+     * Determine if a polar angle is within theta_jet of either pole. The
+     * opening angle is that of the engine in the same hemisphere.
      *
      * * `q` - The polar angle theta
      */
     pub fn in_nozzle(&self, q: f64) -> bool {
-        q < self.engine_theta || q > PI - self.engine_theta
+        let engine_theta = self.engine_theta_at(q);
+        q < engine_theta || q > PI - engine_theta
     }
 
     /**
@@ -177,20 +435,45 @@ impl JetInStar
      * * `t` - Time
      */
     pub fn zone(&self, r: f64, q: f64, t: f64) -> Zone {
-        let v_jet = self.engine_beta() * LIGHT_SPEED;
-        let r_jet_head = v_jet * t;
-
-        if self.in_nozzle(q) && r < r_jet_head {
+        let engine_u = self.south(q).and_then(|s| s.engine_u).unwrap_or(self.engine_u);
+        let engine_delay = self.engine_delay_at(q);
+        let v_jet = engine_u / (1.0 + engine_u.powi(2)).sqrt() * LIGHT_SPEED;
+        let r_jet_head = v_jet * (t - engine_delay);
+
+        let in_jet = match (&self.luminosity_table, &self.duty_cycle) {
+            (Some(table), _) => table.engine_at(r, t).is_some(),
+            (None, Some(duty_cycle)) => duty_cycle.is_on(t - engine_delay - r / v_jet),
+            (None, None) => r < r_jet_head,
+        };
+
+        if self.nozzle.is_none() && self.in_nozzle(q) && in_jet {
             Zone::Jet
-        } else if r < R3 {
+        } else if r < self.star.core_radius() {
             Zone::Core
-        } else if R3 < r && r < self.envelope_radius {
-            Zone:: Envelope
+        } else if r < self.envelope_radius {
+            Zone::Envelope
         } else {
             Zone::Wind
         }
     }
 
+    /**
+     * Return the factor by which the density is perturbed, which is one in
+     * the jet zone, or if there are no perturbations.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn density_factor(&self, r: f64, q: f64, t: f64) -> f64 {
+        if let Some(perturbation) = &self.perturbation {
+            if !matches!(self.zone(r, q, t), Zone::Jet) {
+                return perturbation.density_factor(r, q)
+            }
+        }
+        1.0
+    }
+
     /**
      * Return the radial four-velocity (gamma-beta).
      *
@@ -200,7 +483,7 @@ impl JetInStar
      */
     pub fn gamma_beta(&self, r: f64, q: f64, t: f64) -> f64 {
         match self.zone(r, q, t) {
-            Zone::Jet => self.engine_u,
+            Zone::Jet => self.engine_u_at(r, q, t),
             _ => 0.0
 
         }
@@ -215,23 +498,64 @@ impl JetInStar
      */
     pub fn nozzle_function(&self, r: f64, q: f64) -> f64 {
         // Normalize the Nozzle Radius
-        let r0 = R_NOZZ/R0;
-        let q2 = self.engine_theta.powi(2);
+        let r_nozz = self.star.nozzle_radius * self.star.radius;
+        let r0 = r_nozz / self.star.radius;
+        let q2 = self.engine_theta_at(q).powi(2);
 
         // Nozzle Function Normalization Factor
         // N0 = 4 * PI * r0^3 * exp(-2/theta0^2) * theta0^2
         let n_0 =  4.0 * PI * r0 * r0 * r0 * (1.0 - (-2.0 / q2).exp()) * q2;
 
         // Nozzle Function: g = (r/r0) * exp(-(r/r0)^2) * exp[(cos^2(q) - 1)/theta0^2] / N0
-        let g = (r / R_NOZZ) * f64::exp(-(r / R_NOZZ).powf(2.0) / 2.0) * f64::exp((q.cos().powf(2.0) - 1.0) / q2);
+        let g = (r / r_nozz) * f64::exp(-(r / r_nozz).powf(2.0) / 2.0) * f64::exp((q.cos().powf(2.0) - 1.0) / q2);
 
         g / n_0
     }
 
-    fn jet_mass_rate_per_steradian(&self, r: f64, q: f64) -> f64 {
-        let engine_gamma = f64::sqrt(1.0 + self.engine_u * self.engine_u);
-        let e = self.engine_energy;
-        let l = self.nozzle_function(r, q) * e / (4.0 * PI * self.engine_duration);
+    /**
+     * Determine whether the engine in the hemisphere of the polar angle q is
+     * firing at time t. The engine is considered on whenever there is a
+     * luminosity table, since the jet zone then only contains material
+     * launched while it was on.
+     */
+    fn engine_is_on(&self, q: f64, t: f64) -> bool {
+        let t = t - self.engine_delay_at(q);
+
+        match (&self.luminosity_table, &self.duty_cycle) {
+            (Some(_), _) => true,
+            (None, Some(duty_cycle)) => duty_cycle.is_on(t),
+            (None, None) => t < self.engine_duration_at(q),
+        }
+    }
+
+    /**
+     * Return the engine four-velocity of the jet material at radius r,
+     * polar angle q, and time t, which is constant in each hemisphere
+     * unless there is a luminosity table.
+     */
+    fn engine_u_at(&self, r: f64, q: f64, t: f64) -> f64 {
+        let engine_u = self.south(q).and_then(|s| s.engine_u).unwrap_or(self.engine_u);
+
+        match &self.luminosity_table {
+            Some(table) => table.engine_at(r, t).map_or(engine_u, |(_, u)| u),
+            None => engine_u,
+        }
+    }
+
+    fn jet_mass_rate_per_steradian(&self, r: f64, q: f64, t: f64) -> f64 {
+        let engine_u = self.engine_u_at(r, q, t);
+        let engine_gamma = f64::sqrt(1.0 + engine_u * engine_u);
+        let engine_energy = match self.south(q).and_then(|s| s.energy_ratio) {
+            Some(energy_ratio) => self.engine_energy * energy_ratio,
+            None => self.engine_energy,
+        };
+        let l = match &self.luminosity_table {
+            Some(table) => self.nozzle_function(r, q) * table.engine_at(r, t).map_or(0.0, |(l, _)| l) / (4.0 * PI),
+            None => match &self.duty_cycle {
+                Some(duty_cycle) => self.nozzle_function(r, q) * engine_energy / (4.0 * PI * duty_cycle.total_on_time()),
+                None => self.nozzle_function(r, q) * engine_energy / (4.0 * PI * self.engine_duration_at(q)),
+            }
+        };
         l / (engine_gamma * LIGHT_SPEED * LIGHT_SPEED)
     }
 }
@@ -9,7 +9,9 @@ const UNIFORM_TEMPERATURE: f64 = 1e-3;
 
 
 /**
- * Explosion in a horizontally stratified external medium
+ * Explosion in a horizontally stratified external medium. The shell is
+ * assumed to be sub-relativistic; see the relativistic_bomb model for faster
+ * ejecta.
  */
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
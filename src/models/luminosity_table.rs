@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use crate::lookup_table_v2::LookupTable;
+use crate::physics::LIGHT_SPEED;
+
+
+
+
+/**
+ * A time-dependent engine read from an ASCII table with the columns (time
+ * [s], isotropic-equivalent luminosity [erg / s], Lorentz factor). The
+ * luminosity and Lorentz factor are linearly interpolated in time, and the
+ * engine is off outside the time range of the table. In a configuration
+ * file, the table is given by its file name. The file is read when the
+ * engine is first sampled.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct LuminosityTable {
+    filename: String,
+    lookup_table: Arc<Mutex<Option<Arc<LookupTable<3>>>>>,
+}
+
+
+
+
+// ============================================================================
+impl From<String> for LuminosityTable {
+    fn from(filename: String) -> Self {
+        Self { filename, lookup_table: Arc::new(Mutex::new(None)) }
+    }
+}
+
+impl From<LuminosityTable> for String {
+    fn from(table: LuminosityTable) -> Self {
+        table.filename
+    }
+}
+
+
+
+
+// ============================================================================
+impl LuminosityTable {
+
+    /**
+     * Return an error if the table file could not be read.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        LookupTable::<3>::from_ascii_file(&self.filename)?;
+        Ok(())
+    }
+
+    /**
+     * Return the lookup table, reading it from the file if this is the
+     * first time it is needed. Panics if the file could not be read, which
+     * is checked in advance by [`LuminosityTable::validate`].
+     */
+    fn require_lookup_table(&self) -> Arc<LookupTable<3>> {
+        self.lookup_table
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(LookupTable::from_ascii_file(&self.filename).unwrap()))
+            .clone()
+    }
+
+    /**
+     * Return the luminosity (erg / s) and four-velocity of the jet material
+     * at radius r and time t, or None if that material was launched while
+     * the engine was off. The launch time is found by tracing the material
+     * back to the origin, at the velocity the engine had when material
+     * moving at the speed of light would have been launched. This is exact
+     * for a constant Lorentz factor, and near the origin.
+     *
+     * * `r` - The radius
+     * * `t` - The time
+     */
+    pub fn engine_at(&self, r: f64, t: f64) -> Option<(f64, f64)> {
+        let table = self.require_lookup_table();
+        let (t0, t1) = table.domain();
+        let sample = |t: f64| if t0 < t && t <= t1 { Some(table.sample(t)) } else { None };
+
+        let [_, _, gamma] = sample(t - r / LIGHT_SPEED)?;
+        let beta = (1.0 - gamma.powi(-2)).max(0.0).sqrt();
+        let [_, luminosity, gamma] = sample(t - r / (beta * LIGHT_SPEED))?;
+
+        if luminosity > 0.0 && gamma > 1.0 {
+            Some((luminosity, (gamma * gamma - 1.0).sqrt()))
+        } else {
+            None
+        }
+    }
+}
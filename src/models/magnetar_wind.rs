@@ -0,0 +1,204 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * Hot, fast wind from a spinning-down magnetar, driven into the homologously
+ * expanding merger ejecta. The wind luminosity follows the magnetic dipole
+ * spin-down law L(t) = L0 / (1 + t / t_sd)^2, where t is the time since the
+ * wind turned on. The magnetization is modeled by loading the wind with
+ * thermal energy: the specific enthalpy of the wind is h = 1 + sigma, which
+ * is the same as a cold wind with magnetization sigma, in the energy
+ * budget.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MagnetarWind {
+
+    /// Initial spin-down luminosity L0 (erg/s, isotropic)
+    pub spin_down_luminosity: f64,
+
+    /// Spin-down time t_sd (s)
+    pub spin_down_time: f64,
+
+    /// Time when the wind turns on (s)
+    #[serde(default)]
+    pub engine_delay: f64,
+
+    /// Four-velocity of the wind
+    pub wind_gamma_beta: f64,
+
+    /// Magnetization proxy sigma: the ratio of the wind's thermal enthalpy
+    /// to its rest-mass energy
+    pub wind_magnetization: f64,
+
+    /// Mass of the merger ejecta (g)
+    pub ejecta_mass: f64,
+
+    /// Beta (v/c) of the outer edge of the ejecta
+    pub ejecta_beta: f64,
+
+    /// Density (g/cm^3) of the medium at rest outside the ejecta
+    pub external_medium_density: f64,
+}
+
+
+
+
+/**
+ * Different space-time zones in the setup
+ */
+pub enum Zone {
+    Wind,
+    Ejecta,
+    External,
+}
+
+
+
+
+// ============================================================================
+impl MagnetarWind {
+
+    /**
+     * The wind luminosity at time t. It is zero before the wind turns on.
+     */
+    pub fn luminosity_at(&self, t: f64) -> f64 {
+        if t < self.engine_delay {
+            0.0
+        } else {
+            let x = 1.0 + (t - self.engine_delay) / self.spin_down_time;
+            self.spin_down_luminosity / (x * x)
+        }
+    }
+
+    /**
+     * The rotational energy L0 t_sd extracted by the wind over its lifetime.
+     */
+    pub fn total_energy(&self) -> f64 {
+        self.spin_down_luminosity * self.spin_down_time
+    }
+
+    /**
+     * Dimensionless wind velocity: v_wind / c
+     */
+    pub fn wind_beta(&self) -> f64 {
+        self.wind_gamma_beta / (1.0 + self.wind_gamma_beta.powi(2)).sqrt()
+    }
+
+    /**
+     * Determine the zone for a given radius and time.
+     *
+     * * `r` - Radius
+     * * `t` - Time
+     */
+    pub fn zone(&self, r: f64, t: f64) -> Zone {
+        let r_wind_head = self.wind_beta() * LIGHT_SPEED * (t - self.engine_delay);
+        let r_ejecta_edge = self.ejecta_beta * LIGHT_SPEED * t;
+
+        if r < r_wind_head {
+            Zone::Wind
+        } else if r < r_ejecta_edge {
+            Zone::Ejecta
+        } else {
+            Zone::External
+        }
+    }
+
+    /**
+     * Return the primitive state of the wind at radius r and time t. The
+     * wind material at r was launched at the retarded time t - r / v_wind,
+     * and carries the luminosity from then: L = 4 pi r^2 rho h Gamma u c^3.
+     */
+    fn wind_primitive(&self, r: f64, t: f64) -> AnyPrimitive {
+        let u = self.wind_gamma_beta;
+        let h = 1.0 + self.wind_magnetization;
+        let gamma = (1.0 + u * u).sqrt();
+        let l = self.luminosity_at(t - r / (self.wind_beta() * LIGHT_SPEED));
+        let d = l / (4.0 * PI * r * r * h * gamma * u * LIGHT_SPEED.powi(3));
+
+        AnyPrimitive{
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: 0.25 * (h - 1.0) * d,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for MagnetarWind {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.spin_down_luminosity <= 0.0 {
+            anyhow::bail!("spin_down_luminosity must be positive")
+        }
+        if self.spin_down_time <= 0.0 {
+            anyhow::bail!("spin_down_time must be positive")
+        }
+        if self.wind_gamma_beta <= 0.0 {
+            anyhow::bail!("wind_gamma_beta must be positive")
+        }
+        if self.wind_magnetization < 0.0 {
+            anyhow::bail!("wind_magnetization must be non-negative")
+        }
+        if self.ejecta_beta <= 0.0 || self.ejecta_beta >= 1.0 {
+            anyhow::bail!("ejecta_beta must be in (0, 1)")
+        }
+        if self.ejecta_mass <= 0.0 || self.external_medium_density <= 0.0 {
+            anyhow::bail!("ejecta_mass and external_medium_density must be positive")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, _q) = coordinate;
+
+        match self.zone(r, t) {
+            Zone::Wind => {
+                self.wind_primitive(r, t)
+            }
+            Zone::Ejecta => {
+                let r_edge = self.ejecta_beta * LIGHT_SPEED * t;
+                let d = self.ejecta_mass / (4.0 / 3.0 * PI * r_edge.powi(3));
+                let b = r / t / LIGHT_SPEED;
+
+                AnyPrimitive{
+                    velocity_r: b / (1.0 - b * b).sqrt(),
+                    velocity_q: 0.0,
+                    mass_density: d,
+                    gas_pressure: d * UNIFORM_TEMPERATURE,
+                }
+            }
+            Zone::External => {
+                let d = self.external_medium_density;
+
+                AnyPrimitive{
+                    velocity_r: 0.0,
+                    velocity_q: 0.0,
+                    mass_density: d,
+                    gas_pressure: d * UNIFORM_TEMPERATURE,
+                }
+            }
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, _q) = coordinate;
+
+        match self.zone(r, t) {
+            Zone::Ejecta      => 1e+0,
+            Zone::Wind        => 1e+2,
+            Zone::External    => 1e-2,
+        }
+    }
+}
@@ -3,9 +3,47 @@ mod halo_kilonova;
 mod jet_in_star;
 mod wind_shock;
 mod kinetic_bomb;
+mod hot_swap;
+mod nozzle;
+mod luminosity_table;
+mod duty_cycle;
+mod southern_engine;
+mod perturbation;
+mod scripted;
+mod table_model;
+mod structured_jet;
+mod magnetar_wind;
+mod two_component_jet;
+mod precessing_jet;
+mod composite;
+mod two_component_kilonova;
+mod supernova_ejecta;
+mod afterglow;
+mod pulsar_wind_nebula;
+mod relativistic_bomb;
+mod cloud;
 
 pub use jet_in_cloud::JetInCloud;
 pub use halo_kilonova::HaloKilonova;
 pub use jet_in_star::JetInStar;
-pub use wind_shock::WindShock;
+pub use wind_shock::{WindShock, Flare};
 pub use kinetic_bomb::KineticBomb;
+pub use hot_swap::HotSwap;
+pub use nozzle::{Nozzle, AccretionFeedback};
+pub use luminosity_table::LuminosityTable;
+pub use duty_cycle::DutyCycle;
+pub use southern_engine::SouthernEngine;
+pub use perturbation::Perturbation;
+pub use scripted::Scripted;
+pub use table_model::TableModel;
+pub use structured_jet::{StructuredJet, AngularProfile};
+pub use magnetar_wind::MagnetarWind;
+pub use two_component_jet::{TwoComponentJet, JetComponent};
+pub use precessing_jet::PrecessingJet;
+pub use composite::Composite;
+pub use two_component_kilonova::{TwoComponentKilonova, EjectaComponent};
+pub use supernova_ejecta::SupernovaEjecta;
+pub use afterglow::Afterglow;
+pub use pulsar_wind_nebula::PulsarWindNebula;
+pub use relativistic_bomb::RelativisticBomb;
+pub use cloud::{Cloud, AmbientZone};
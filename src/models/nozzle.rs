@@ -0,0 +1,195 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyConserved, LIGHT_SPEED};
+use crate::reductions::Reductions;
+
+
+
+
+/**
+ * An engine which deposits mass, momentum, and energy as volumetric source
+ * terms in a small region near the origin, following Duffell & MacFadyen
+ * (2015), source: https://arxiv.org/pdf/1407.8250.pdf. The injected material
+ * is cold, with a radial four-velocity equal to `four_velocity`.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Nozzle {
+
+    /// Characteristic radius r0 of the source region
+    pub radius: f64,
+
+    /// Characteristic opening angle theta0 of the source region
+    pub opening_angle: f64,
+
+    /// Four-velocity of the injected material
+    pub four_velocity: f64,
+
+    /// Total engine luminosity (erg/s, both poles) as a list of (time,
+    /// luminosity) pairs. The luminosity is linearly interpolated between
+    /// these, and is zero outside the time range they span.
+    pub luminosity: Vec<(f64, f64)>,
+
+    /// Optional feedback, which adds to the luminosity in proportion to the
+    /// measured accretion rate through the inner excision surface
+    #[serde(default)]
+    pub feedback: Option<AccretionFeedback>,
+}
+
+
+
+
+/**
+ * Feedback which regulates the engine luminosity by the rate at which mass is
+ * removed by the inner excision surface. The measured accretion rate is
+ * updated by the code, and is saved in checkpoints along with the rest of the
+ * model configuration.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccretionFeedback {
+
+    /// Fraction of the accreted rest-mass energy (per unit time) which is
+    /// added to the engine luminosity
+    pub efficiency: f64,
+
+    /// Time scale over which the measured accretion rate is smoothed
+    pub averaging_time: f64,
+
+    /// The smoothed accretion rate (g/s) measured so far
+    #[serde(default)]
+    pub accretion_rate: f64,
+
+    /// The time and the total excised mass at the last measurement
+    #[serde(default)]
+    pub last_measurement: Option<(f64, f64)>,
+}
+
+
+
+
+// ============================================================================
+impl Nozzle {
+
+    /**
+     * Return an error if the nozzle parameters are not sensible.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.radius <= 0.0 {
+            anyhow::bail!("nozzle radius must be positive")
+        }
+        if self.opening_angle <= 0.0 || self.opening_angle > 0.5 * PI {
+            anyhow::bail!("nozzle opening_angle must be in (0, pi/2]")
+        }
+        if self.four_velocity <= 0.0 {
+            anyhow::bail!("nozzle four_velocity must be positive")
+        }
+        if self.luminosity.windows(2).any(|w| w[1].0 <= w[0].0) {
+            anyhow::bail!("nozzle luminosity times must be increasing")
+        }
+        if self.luminosity.iter().any(|&(_, l)| l < 0.0) {
+            anyhow::bail!("nozzle luminosity must be non-negative")
+        }
+        if let Some(feedback) = &self.feedback {
+            if feedback.efficiency < 0.0 || feedback.averaging_time <= 0.0 {
+                anyhow::bail!("feedback efficiency must be non-negative, and averaging_time positive")
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The engine luminosity at time t: the interpolated luminosity history,
+     * plus the feedback luminosity if there is any.
+     */
+    pub fn luminosity_at(&self, t: f64) -> f64 {
+        let feedback = self.feedback
+            .as_ref()
+            .map_or(0.0, |f| f.efficiency * f.accretion_rate * LIGHT_SPEED * LIGHT_SPEED);
+
+        for w in self.luminosity.windows(2) {
+            let ((t0, l0), (t1, l1)) = (w[0], w[1]);
+            if t0 <= t && t <= t1 {
+                return l0 + (l1 - l0) * (t - t0) / (t1 - t0) + feedback
+            }
+        }
+        feedback
+    }
+
+    /**
+     * Return true if the nozzle has accretion feedback, which is updated
+     * from measured quantities.
+     */
+    pub fn has_feedback(&self) -> bool {
+        self.feedback.is_some()
+    }
+
+    /**
+     * Update the measured accretion rate, if there is feedback.
+     */
+    pub fn regulate(&mut self, measured: &Reductions) {
+        if let Some(feedback) = &mut self.feedback {
+            feedback.measure(measured.time, measured.excised_mass)
+        }
+    }
+
+    /**
+     * The nozzle profile g(r, theta), which is normalized (to within
+     * exp(-2 / theta0^2)) to have unit volume integral.
+     */
+    pub fn profile(&self, r: f64, q: f64) -> f64 {
+        let r0 = self.radius;
+        let q2 = self.opening_angle.powi(2);
+        let n0 = 4.0 * PI * r0.powi(3) * (1.0 - (-2.0 / q2).exp()) * q2;
+        let y = r / r0;
+        y * (-0.5 * y * y).exp() * ((q.cos().powi(2) - 1.0) / q2).exp() / n0
+    }
+
+    /**
+     * Return the rates of mass, momentum, and energy (excluding rest mass)
+     * deposition per unit volume, in cgs units, at the given r-theta
+     * coordinate and time. The momentum is radial.
+     */
+    pub fn source_at(&self, coordinate: (f64, f64), t: f64) -> AnyConserved {
+        let (r, q) = coordinate;
+        let l = self.luminosity_at(t) * self.profile(r, q);
+
+        if l == 0.0 {
+            return AnyConserved::default()
+        }
+        let u = self.four_velocity;
+        let gamma = (1.0 + u * u).sqrt();
+        let m = l / (gamma * LIGHT_SPEED * LIGHT_SPEED);
+
+        AnyConserved {
+            mass_density: m,
+            momentum_r: m * u * LIGHT_SPEED,
+            momentum_q: 0.0,
+            energy_density: l - m * LIGHT_SPEED * LIGHT_SPEED,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl AccretionFeedback {
+
+    /**
+     * Update the smoothed accretion rate from the total excised mass at the
+     * given time. The rate over the interval since the last measurement is
+     * blended in with a weight which approaches one for intervals longer
+     * than the averaging time.
+     */
+    pub fn measure(&mut self, time: f64, excised_mass: f64) {
+        if let Some((t0, m0)) = self.last_measurement {
+            if time > t0 {
+                let rate = (excised_mass - m0) / (time - t0);
+                let weight = 1.0 - (-(time - t0) / self.averaging_time).exp();
+                self.accretion_rate += (rate - self.accretion_rate) * weight;
+            }
+        }
+        self.last_measurement = Some((time, excised_mass));
+    }
+}
@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+
+
+
+
+/**
+ * A random, multiplicative perturbation of the density of an ambient
+ * medium. The perturbation is a deterministic function of position and the
+ * seed, so the same seed gives the same medium in the initial data and in
+ * every run. The radial coordinate it is given may be the radius, or for
+ * homologously expanding media, the velocity r / t, in which case the clumps
+ * expand with the medium.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum Perturbation {
+
+    /// Log-normal clumps: the log of the density factor is a random field
+    /// with standard deviation `amplitude`, correlated over `radial_size`
+    /// in the log of the radial coordinate, and `polar_size` in theta
+    Clumps {
+        seed: u64,
+        amplitude: f64,
+        radial_size: f64,
+        polar_size: f64,
+    },
+
+    /// A sum of axisymmetric spherical harmonics (Legendre polynomials) of
+    /// degree 1 through `max_degree`, with random coefficients, giving the
+    /// log of the density factor a standard deviation of about `amplitude`
+    SphericalHarmonics {
+        seed: u64,
+        amplitude: f64,
+        max_degree: usize,
+    },
+}
+
+
+
+
+// ============================================================================
+/**
+ * Mix the bits of a 64-bit integer (the SplitMix64 finalizer).
+ */
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/**
+ * Return a standard normal deviate determined by the seed and a pair of
+ * lattice indexes, using the Box-Muller transform.
+ */
+fn gaussian(seed: u64, i: i64, j: i64) -> f64 {
+    let h = mix(mix(mix(seed) ^ i as u64) ^ j as u64);
+    let u1 = ((mix(h) >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    let u2 = ((mix(h ^ 1) >> 11) as f64) / (1u64 << 53) as f64;
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+
+
+
+// ============================================================================
+impl Perturbation {
+
+    /**
+     * Return an error if the perturbation parameters are not sensible.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Perturbation::Clumps { amplitude, radial_size, polar_size, .. } => {
+                if *amplitude < 0.0 || *radial_size <= 0.0 || *polar_size <= 0.0 {
+                    anyhow::bail!("clumps must have non-negative amplitude, and positive radial and polar sizes")
+                }
+            }
+            Perturbation::SphericalHarmonics { amplitude, max_degree, .. } => {
+                if *amplitude < 0.0 || *max_degree == 0 {
+                    anyhow::bail!("spherical harmonics must have non-negative amplitude, and max_degree of at least 1")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Return the factor by which the density is multiplied at the given
+     * radial coordinate and polar angle.
+     *
+     * * `x` - The radial coordinate (radius, or velocity)
+     * * `q` - The polar angle theta
+     */
+    pub fn density_factor(&self, x: f64, q: f64) -> f64 {
+        match self {
+            Perturbation::Clumps { seed, amplitude, radial_size, polar_size } => {
+                let a = x.ln() / radial_size;
+                let b = q / polar_size;
+                let (i, j) = (a.floor(), b.floor());
+                let (fa, fb) = (a - i, b - j);
+                let (i, j) = (i as i64, j as i64);
+                let g = gaussian(*seed, i, j)         * (1.0 - fa) * (1.0 - fb)
+                      + gaussian(*seed, i + 1, j)     * fa * (1.0 - fb)
+                      + gaussian(*seed, i, j + 1)     * (1.0 - fa) * fb
+                      + gaussian(*seed, i + 1, j + 1) * fa * fb;
+                (amplitude * g - 0.5 * amplitude * amplitude).exp()
+            }
+            Perturbation::SphericalHarmonics { seed, amplitude, max_degree } => {
+                let mu = q.cos();
+                let (mut p0, mut p1) = (1.0, mu);
+                let mut s = 0.0;
+
+                for l in 1..=*max_degree {
+                    let norm = (2.0 * l as f64 + 1.0).sqrt();
+                    s += gaussian(*seed, l as i64, 0) * norm * p1;
+                    let p2 = ((2 * l + 1) as f64 * mu * p1 - l as f64 * p0) / (l + 1) as f64;
+                    p0 = p1;
+                    p1 = p2;
+                }
+                (amplitude * s / (*max_degree as f64).sqrt()).exp()
+            }
+        }
+    }
+}
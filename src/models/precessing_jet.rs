@@ -0,0 +1,235 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::models::{AmbientZone, Cloud};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * Jet with a precessing axis, propagating through a kilonova debris cloud
+ * and surrounding relativistic envelope. The cloud and envelope are the
+ * shared [`Cloud`]. The jet axis is tilted from the polar axis by the
+ * precession angle, and circles it with the precession period.
+ *
+ * The mesh is axisymmetric, so the azimuthal motion of the jet axis is not
+ * represented. Instead, the jet axis is projected onto the r-theta plane,
+ * where it swings from one side of the polar axis to the other, and the jet
+ * at each time is the nozzle of half-angle engine_theta around the
+ * projected axis. Its footprint on the mesh is a cone whose apparent
+ * opening angle and direction vary over the precession period. The
+ * counter-jet is the mirror image through the equator.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrecessingJet {
+
+    /// Mass of the merger ejecta cloud
+    pub cloud_mass: f64,
+
+    /// Time following the cloud onset when the jet begins
+    pub engine_delay: f64,
+
+    /// Duration of the engine
+    pub engine_duration: f64,
+
+    /// E / M c^2: M = cloud mass, E = isotropic-equivalent jet energy
+    pub engine_strength: f64,
+
+    /// Engine opening angle
+    pub engine_theta: f64,
+
+    /// Engine four-velocity
+    pub engine_u: f64,
+
+    /// Period of the precession of the jet axis
+    pub precession_period: f64,
+
+    /// Angle between the jet axis and the polar axis
+    pub precession_angle: f64,
+
+    /// Mass coordinate of the u=1 shell
+    pub envelop_m1: f64,
+
+    /// Beta (v/c) of the slowest envelope shell
+    pub envelop_slowest_beta: f64,
+
+    /// Beta (v/c) of the outer shell
+    pub envelop_fastest_beta: f64,
+
+    /// Index psi in u(m) ~ m^-psi
+    pub envelop_psi: f64,
+}
+
+
+
+
+/**
+ * Different space-time zones in the setup
+ */
+pub enum Zone {
+    Ambient(AmbientZone),
+    Jet,
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for PrecessingJet {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.engine_theta <= 0.0 {
+            anyhow::bail!("engine_theta must be positive")
+        }
+        if self.precession_period <= 0.0 {
+            anyhow::bail!("precession_period must be positive")
+        }
+        if self.precession_angle < 0.0 || self.precession_angle + self.engine_theta > 0.5 * PI {
+            anyhow::bail!("precession_angle must be non-negative, and the jet must not cross the equator")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, q) = coordinate;
+        let f = self.mass_rate_per_steradian(r, q, t);
+        let u = self.gamma_beta(r, q, t);
+        let d = f / (r * r * u) / LIGHT_SPEED;
+        let p = d * UNIFORM_TEMPERATURE;
+
+        AnyPrimitive{
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: p,
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, q) = coordinate;
+
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => self.cloud().scalar_in(zone),
+            Zone::Jet           => 1e+2,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl PrecessingJet {
+
+    /**
+     * Dimensionless jet velocity: v_jet / c
+     */
+    pub fn engine_beta(&self) -> f64 {
+        self.engine_u / (1.0 + self.engine_u.powi(2)).sqrt()
+    }
+
+    /**
+     * The debris cloud and envelope which the jet propagates through
+     */
+    pub fn cloud(&self) -> Cloud {
+        Cloud{
+            cloud_mass: self.cloud_mass,
+            engine_delay: self.engine_delay,
+            envelop_m1: self.envelop_m1,
+            envelop_slowest_beta: self.envelop_slowest_beta,
+            envelop_fastest_beta: self.envelop_fastest_beta,
+            envelop_psi: self.envelop_psi,
+        }
+    }
+
+    /**
+     * Return the angle between the polar axis and the jet axis projected
+     * onto the r-theta plane, at the given time. The sign tells which side
+     * of the polar axis the projected axis is on.
+     *
+     * * `t` - Time
+     */
+    pub fn projected_axis(&self, t: f64) -> f64 {
+        let phase = 2.0 * PI * (t - self.engine_delay) / self.precession_period;
+        self.precession_angle * phase.cos()
+    }
+
+    /**
+     * Determine if a polar angle is within the nozzle around the projected
+     * jet axis (or its mirror image through the equator) at the given time.
+     * The mesh stands for every meridional half-plane, including those on
+     * both sides of the polar axis, so the nozzle is centered on the
+     * absolute value of the projected axis angle.
+     *
+     * * `q` - The polar angle theta
+     * * `t` - Time
+     */
+    pub fn in_nozzle(&self, q: f64, t: f64) -> bool {
+        let theta = f64::min(q, PI - q);
+        (theta - self.projected_axis(t).abs()).abs() < self.engine_theta
+    }
+
+    /**
+     * Determine the zone for a given radius, polar angle, and time. Jet
+     * material at radius r was launched at the time t - r / v_jet, from the
+     * nozzle at that time.
+     *
+     * * `r` - Radius
+     * * `q` - Polar angle
+     * * `t` - Time
+     */
+    pub fn zone(&self, r: f64, q: f64, t: f64) -> Zone {
+        let v_jet = self.engine_beta() * LIGHT_SPEED;
+        let r_jet_head = v_jet * (t - self.engine_delay);
+        let r_jet_tail = v_jet * (t - self.engine_delay - self.engine_duration);
+
+        if r < r_jet_head && r > r_jet_tail && self.in_nozzle(q, t - r / v_jet) {
+            Zone::Jet
+        } else {
+            Zone::Ambient(self.cloud().zone(r, t))
+        }
+    }
+
+    /**
+     * Return the radial four-velocity (gamma-beta).
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn gamma_beta(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().gamma_beta_in(zone, r, t)
+            }
+            Zone::Jet => {
+                self.engine_u
+            }
+        }
+    }
+
+    /**
+     * Return the mass rate per solid angle.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn mass_rate_per_steradian(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().mass_rate_per_steradian_in(zone, r, t)
+            }
+            Zone::Jet => {
+                let engine_gamma = f64::sqrt(1.0 + self.engine_u * self.engine_u);
+                let e = self.engine_strength * self.cloud_mass;
+                let l = e / (4.0 * PI * self.engine_duration);
+                l / engine_gamma
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::models::SupernovaEjecta;
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+
+
+
+/**
+ * Pulsar wind nebula: a relativistic, hot, low-density wind, driven by the
+ * spin-down of a pulsar born at t = 0, inflating a bubble inside expanding
+ * supernova ejecta. The spin-down luminosity is
+ *
+ * L(t) = L0 (1 + t / t_sd)^(-(n + 1) / (n - 1)),
+ *
+ * where n is the braking index (n = 3 for magnetic dipole spin-down). The
+ * wind enters through the inner boundary. Initially, it fills the ejecta
+ * moving slower than the nebula velocity, and the rest of the ejecta is
+ * given by the supernova_ejecta model.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PulsarWindNebula {
+
+    /// Initial spin-down luminosity L0 (erg/s)
+    pub spin_down_luminosity: f64,
+
+    /// Spin-down time t_sd (s)
+    pub spin_down_time: f64,
+
+    /// Braking index n of the pulsar
+    pub braking_index: f64,
+
+    /// Four-velocity of the wind
+    pub wind_gamma_beta: f64,
+
+    /// Specific enthalpy h (in units of c^2) of the wind, which is large for
+    /// a hot wind
+    pub wind_enthalpy: f64,
+
+    /// Beta (v/c) of the homologous ejecta shell bounding the initial
+    /// nebula
+    pub nebula_beta: f64,
+
+    /// The supernova ejecta the nebula expands into
+    pub ejecta: SupernovaEjecta,
+}
+
+
+
+
+// ============================================================================
+impl PulsarWindNebula {
+
+    /**
+     * The pulsar spin-down luminosity at time t
+     */
+    pub fn luminosity_at(&self, t: f64) -> f64 {
+        let n = self.braking_index;
+        let x = 1.0 + t.max(0.0) / self.spin_down_time;
+        self.spin_down_luminosity * x.powf(-(n + 1.0) / (n - 1.0))
+    }
+
+    /**
+     * Dimensionless wind velocity: v_wind / c
+     */
+    pub fn wind_beta(&self) -> f64 {
+        self.wind_gamma_beta / (1.0 + self.wind_gamma_beta.powi(2)).sqrt()
+    }
+
+    /**
+     * Determine whether the radius is inside the nebula at time t.
+     */
+    pub fn in_nebula(&self, r: f64, t: f64) -> bool {
+        r < self.nebula_beta * LIGHT_SPEED * t
+    }
+
+    /**
+     * Return the primitive state of the wind at radius r and time t. The
+     * wind material at r was launched at the retarded time t - r / v_wind,
+     * and carries the luminosity from then: L = 4 pi r^2 rho h Gamma u c^3.
+     */
+    fn wind_primitive(&self, r: f64, t: f64) -> AnyPrimitive {
+        let u = self.wind_gamma_beta;
+        let h = self.wind_enthalpy;
+        let gamma = (1.0 + u * u).sqrt();
+        let l = self.luminosity_at(t - r / (self.wind_beta() * LIGHT_SPEED));
+        let d = l / (4.0 * PI * r * r * h * gamma * u * LIGHT_SPEED.powi(3));
+
+        AnyPrimitive{
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: 0.25 * (h - 1.0) * d,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for PulsarWindNebula {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.spin_down_luminosity <= 0.0 || self.spin_down_time <= 0.0 {
+            anyhow::bail!("spin_down_luminosity and spin_down_time must be positive")
+        }
+        if self.braking_index <= 1.0 {
+            anyhow::bail!("braking_index must be greater than 1")
+        }
+        if self.wind_gamma_beta <= 0.0 {
+            anyhow::bail!("wind_gamma_beta must be positive")
+        }
+        if self.wind_enthalpy < 1.0 {
+            anyhow::bail!("wind_enthalpy must be at least 1")
+        }
+        if self.nebula_beta <= 0.0 || self.nebula_beta >= self.wind_beta() {
+            anyhow::bail!("nebula_beta must be positive, and less than the wind velocity")
+        }
+        self.ejecta.validate()
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        if self.in_nebula(coordinate.0, t) {
+            self.wind_primitive(coordinate.0, t)
+        } else {
+            self.ejecta.primitive_at(coordinate, t)
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        if self.in_nebula(coordinate.0, t) {
+            1e+2
+        } else {
+            self.ejecta.scalar_at(coordinate, t)
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+const UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * Relativistic explosion in a uniform external medium. Unlike the
+ * kinetic_bomb model, the ejecta may be moving at any speed: the explosion
+ * takes place at the origin at t = 0, and the ejecta mass is distributed
+ * over four-velocities u = gamma-beta as
+ *
+ * dM / d ln(u) ~ u^-k
+ *
+ * between a minimum and maximum four-velocity. Each shell coasts at its
+ * four-velocity, so the ejecta is homologous, with the shell u found at
+ * r = beta c t. The model is singular at t = 0, so the start time must be
+ * positive.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelativisticBomb {
+
+    /// Total mass of the ejecta (g)
+    pub ejecta_mass: f64,
+
+    /// Four-velocity of the slowest ejecta
+    pub min_gamma_beta: f64,
+
+    /// Four-velocity of the fastest ejecta
+    pub max_gamma_beta: f64,
+
+    /// Index k of the mass distribution dM / d ln(u) ~ u^-k
+    pub gamma_beta_index: f64,
+
+    /// Density (g/cm^3) of the medium outside the ejecta
+    pub external_medium_density: f64,
+}
+
+
+
+
+// ============================================================================
+impl RelativisticBomb {
+
+    /**
+     * The ejecta mass per unit ln(u) at four-velocity u, if u is within the
+     * ejecta, and zero otherwise.
+     */
+    pub fn mass_per_log_gamma_beta(&self, u: f64) -> f64 {
+        if u < self.min_gamma_beta || u > self.max_gamma_beta {
+            return 0.0
+        }
+        let k = self.gamma_beta_index;
+        let integral = if k.abs() < 1e-12 {
+            (self.max_gamma_beta / self.min_gamma_beta).ln()
+        } else {
+            (self.min_gamma_beta.powf(-k) - self.max_gamma_beta.powf(-k)) / k
+        };
+        self.ejecta_mass / integral * u.powf(-k)
+    }
+
+    /**
+     * The kinetic energy (Gamma - 1) M c^2 of the ejecta (erg), computed
+     * numerically from the mass distribution.
+     */
+    pub fn kinetic_energy(&self) -> f64 {
+        let n = 1000;
+        let a = self.min_gamma_beta.ln();
+        let b = self.max_gamma_beta.ln();
+        let dx = (b - a) / n as f64;
+
+        (0..n).map(|i| {
+            let u = (a + (i as f64 + 0.5) * dx).exp();
+            self.mass_per_log_gamma_beta(u) * ((1.0 + u * u).sqrt() - 1.0) * dx
+        }).sum::<f64>() * LIGHT_SPEED * LIGHT_SPEED
+    }
+
+    /**
+     * Return the four-velocity and comoving mass density of the ejecta at
+     * radius r and time t, or None outside the ejecta. The lab-frame mass in
+     * a shell is dM = 4 pi r^2 rho Gamma dr, where dr = c t d(beta) and
+     * d(beta) = d(u) / Gamma^3.
+     */
+    pub fn ejecta_state(&self, r: f64, t: f64) -> Option<(f64, f64)> {
+        let b = r / t / LIGHT_SPEED;
+
+        if b >= 1.0 {
+            return None
+        }
+        let u = b / (1.0 - b * b).sqrt();
+        let dm_du = self.mass_per_log_gamma_beta(u) / u;
+
+        if dm_du > 0.0 {
+            let gamma = (1.0 + u * u).sqrt();
+            let d = dm_du * gamma.powi(2) / (4.0 * PI * r * r * LIGHT_SPEED * t);
+            Some((u, d))
+        } else {
+            None
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for RelativisticBomb {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.ejecta_mass <= 0.0 {
+            anyhow::bail!("ejecta_mass must be positive")
+        }
+        if self.min_gamma_beta <= 0.0 || self.max_gamma_beta <= self.min_gamma_beta {
+            anyhow::bail!("the ejecta must have 0 < min_gamma_beta < max_gamma_beta")
+        }
+        if self.external_medium_density <= 0.0 {
+            anyhow::bail!("external_medium_density must be positive")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, _q) = coordinate;
+
+        match self.ejecta_state(r, t) {
+            Some((u, d)) => AnyPrimitive {
+                velocity_r: u,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            },
+            None => {
+                let d = self.external_medium_density;
+
+                AnyPrimitive {
+                    velocity_r: 0.0,
+                    velocity_q: 0.0,
+                    mass_density: d,
+                    gas_pressure: d * UNIFORM_TEMPERATURE,
+                }
+            }
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        if self.ejecta_state(coordinate.0, t).is_some() {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
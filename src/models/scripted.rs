@@ -0,0 +1,61 @@
+use serde::{Serialize, Deserialize};
+use crate::expression::Formula;
+use crate::physics::AnyPrimitive;
+use crate::traits::InitialModel;
+
+
+
+
+/**
+ * A model whose primitive fields are given by user formulas of r, q
+ * (theta), and t, written directly in the configuration. The formulas are
+ * parsed once, when the configuration is loaded.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scripted {
+
+    /// Mass density (comoving for relativistic hydro)
+    pub mass_density: Formula,
+
+    /// Gas pressure
+    pub gas_pressure: Formula,
+
+    /// Radial velocity (radial gamma-beta for relativistic hydro). Defaults
+    /// to zero.
+    #[serde(default)]
+    pub velocity_r: Formula,
+
+    /// Polar velocity (polar gamma-beta for relativistic hydro). Defaults to
+    /// zero.
+    #[serde(default)]
+    pub velocity_q: Formula,
+
+    /// Scalar concentration. Defaults to zero.
+    #[serde(default)]
+    pub scalar: Formula,
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for Scripted {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        AnyPrimitive {
+            velocity_r:   self.velocity_r.evaluate(coordinate, t),
+            velocity_q:   self.velocity_q.evaluate(coordinate, t),
+            mass_density: self.mass_density.evaluate(coordinate, t),
+            gas_pressure: self.gas_pressure.evaluate(coordinate, t),
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        self.scalar.evaluate(coordinate, t)
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Serialize, Deserialize};
+
+
+
+
+/**
+ * Engine parameters for the southern hemisphere (theta > pi/2) of a jet
+ * model, which otherwise launches the same jet into both hemispheres. Any
+ * parameter which is not given takes the value of the northern engine.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SouthernEngine {
+
+    /// Time when the southern jet begins
+    #[serde(default)]
+    pub engine_delay: Option<f64>,
+
+    /// Duration of the southern engine
+    #[serde(default)]
+    pub engine_duration: Option<f64>,
+
+    /// Opening angle of the southern jet
+    #[serde(default)]
+    pub engine_theta: Option<f64>,
+
+    /// Four-velocity of the southern jet
+    #[serde(default)]
+    pub engine_u: Option<f64>,
+
+    /// Ratio of the southern to the northern jet energy
+    #[serde(default)]
+    pub energy_ratio: Option<f64>,
+}
+
+
+
+
+// ============================================================================
+impl SouthernEngine {
+
+    /**
+     * Return an error if any of the given parameters are not sensible.
+     */
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.engine_duration.map_or(false, |x| x <= 0.0) {
+            anyhow::bail!("southern engine_duration must be positive")
+        }
+        if self.engine_theta.map_or(false, |x| x <= 0.0) {
+            anyhow::bail!("southern engine_theta must be positive")
+        }
+        if self.engine_u.map_or(false, |x| x <= 0.0) {
+            anyhow::bail!("southern engine_u must be positive")
+        }
+        if self.energy_ratio.map_or(false, |x| x <= 0.0) {
+            anyhow::bail!("southern energy_ratio must be positive")
+        }
+        Ok(())
+    }
+}
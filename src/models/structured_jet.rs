@@ -0,0 +1,310 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::models::{AmbientZone, Cloud, DutyCycle};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * Shape of a jet's angular structure, as a function of the angle from the
+ * jet axis. Each profile is one on the axis.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum AngularProfile {
+
+    /// One inside the core angle, and zero outside it
+    TopHat,
+
+    /// exp(-theta^2 / 2 theta_c^2)
+    Gaussian,
+
+    /// (1 + theta^2 / theta_c^2)^(-index / 2)
+    PowerLaw { index: f64 },
+}
+
+
+
+
+/**
+ * Structured jet propagating through a kilonova debris cloud and surrounding
+ * relativistic envelope. The cloud and envelope are the shared [`Cloud`],
+ * as in the jet_in_cloud model, but the jet energy and Lorentz factor vary
+ * with angle from the axis according to configurable profiles, rather than
+ * being uniform inside a sharp-edged nozzle.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredJet {
+
+    /// Mass of the merger ejecta cloud
+    pub cloud_mass: f64,
+
+    /// Time following the cloud onset when the jet begins
+    pub engine_delay: f64,
+
+    /// Duration of the engine
+    pub engine_duration: f64,
+
+    /// E / M c^2: M = cloud mass, E = isotropic-equivalent jet energy on
+    /// the jet axis
+    pub engine_strength: f64,
+
+    /// Core angle theta_c of the angular profiles
+    pub engine_theta: f64,
+
+    /// Angle from the axis beyond which the jet is truncated
+    pub engine_theta_max: f64,
+
+    /// Engine four-velocity on the jet axis
+    pub engine_u: f64,
+
+    /// Profile f(theta) of the isotropic-equivalent energy: E(theta) = E f(theta)
+    pub energy_profile: AngularProfile,
+
+    /// Profile g(theta) of the Lorentz factor: Gamma(theta) = 1 + (Gamma - 1) g(theta)
+    pub lorentz_factor_profile: AngularProfile,
+
+    /// Mass coordinate of the u=1 shell
+    pub envelop_m1: f64,
+
+    /// Beta (v/c) of the slowest envelope shell
+    pub envelop_slowest_beta: f64,
+
+    /// Beta (v/c) of the outer shell
+    pub envelop_fastest_beta: f64,
+
+    /// Index psi in u(m) ~ m^-psi
+    pub envelop_psi: f64,
+
+    /// Optional duty cycle, which makes the engine fire in episodes. If
+    /// given, the engine duration is unused, and the engine energy is
+    /// divided evenly among the episodes.
+    #[serde(default)]
+    pub duty_cycle: Option<DutyCycle>,
+}
+
+
+
+
+/**
+ * Different space-time zones in the setup
+ */
+pub enum Zone {
+    Ambient(AmbientZone),
+    Jet,
+}
+
+
+
+
+// ============================================================================
+impl AngularProfile {
+
+    /**
+     * Evaluate the profile at the given angle from the jet axis.
+     *
+     * * `theta`   - Angle from the jet axis
+     * * `theta_c` - Core angle
+     */
+    pub fn at(&self, theta: f64, theta_c: f64) -> f64 {
+        let x = theta / theta_c;
+
+        match self {
+            AngularProfile::TopHat => if x < 1.0 { 1.0 } else { 0.0 },
+            AngularProfile::Gaussian => (-0.5 * x * x).exp(),
+            AngularProfile::PowerLaw { index } => (1.0 + x * x).powf(-0.5 * index),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for StructuredJet {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.engine_theta <= 0.0 {
+            anyhow::bail!("engine_theta must be positive")
+        }
+        if self.engine_theta_max <= 0.0 || self.engine_theta_max > 0.5 * PI {
+            anyhow::bail!("engine_theta_max must be in (0, pi/2]")
+        }
+        if self.engine_u <= 0.0 {
+            anyhow::bail!("engine_u must be positive")
+        }
+        for profile in &[&self.energy_profile, &self.lorentz_factor_profile] {
+            if let AngularProfile::PowerLaw { index } = profile {
+                if *index <= 0.0 {
+                    anyhow::bail!("power_law profile index must be positive")
+                }
+            }
+        }
+        if let Some(duty_cycle) = &self.duty_cycle {
+            duty_cycle.validate()?
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, q) = coordinate;
+        let f = self.mass_rate_per_steradian(r, q, t);
+        let u = self.gamma_beta(r, q, t);
+        let d = f / (r * r * u) / LIGHT_SPEED;
+        let p = d * UNIFORM_TEMPERATURE;
+
+        AnyPrimitive{
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: p,
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, q) = coordinate;
+
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => self.cloud().scalar_in(zone),
+            Zone::Jet           => 1e+2,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl StructuredJet {
+
+    /**
+     * Return the angle from the nearest jet axis (either pole).
+     *
+     * * `q` - The polar angle theta
+     */
+    pub fn angle_from_axis(&self, q: f64) -> f64 {
+        f64::min(q, PI - q)
+    }
+
+    /**
+     * Return the engine four-velocity at the given polar angle.
+     *
+     * * `q` - The polar angle theta
+     */
+    pub fn engine_u_at(&self, q: f64) -> f64 {
+        let g = self.lorentz_factor_profile.at(self.angle_from_axis(q), self.engine_theta);
+        let gamma = 1.0 + (f64::sqrt(1.0 + self.engine_u.powi(2)) - 1.0) * g;
+        f64::sqrt(gamma * gamma - 1.0)
+    }
+
+    /**
+     * Return the isotropic-equivalent engine energy (in units of mass) at
+     * the given polar angle.
+     *
+     * * `q` - The polar angle theta
+     */
+    pub fn engine_energy_at(&self, q: f64) -> f64 {
+        let f = self.energy_profile.at(self.angle_from_axis(q), self.engine_theta);
+        self.engine_strength * self.cloud_mass * f
+    }
+
+    /**
+     * The total time the engine is firing
+     */
+    pub fn engine_on_time(&self) -> f64 {
+        match &self.duty_cycle {
+            Some(duty_cycle) => duty_cycle.total_on_time(),
+            None => self.engine_duration,
+        }
+    }
+
+    /**
+     * The debris cloud and envelope which the jet propagates through
+     */
+    pub fn cloud(&self) -> Cloud {
+        Cloud{
+            cloud_mass: self.cloud_mass,
+            engine_delay: self.engine_delay,
+            envelop_m1: self.envelop_m1,
+            envelop_slowest_beta: self.envelop_slowest_beta,
+            envelop_fastest_beta: self.envelop_fastest_beta,
+            envelop_psi: self.envelop_psi,
+        }
+    }
+
+    /**
+     * Determine the zone for a given radius, polar angle, and time. The jet
+     * occupies angles within engine_theta_max of either pole where both the
+     * energy and the four-velocity profiles are non-zero, and the jet head
+     * and tail move at the local engine velocity.
+     *
+     * * `r` - Radius
+     * * `q` - Polar angle
+     * * `t` - Time
+     */
+    pub fn zone(&self, r: f64, q: f64, t: f64) -> Zone {
+        let u_jet = self.engine_u_at(q);
+        let v_jet = u_jet / f64::sqrt(1.0 + u_jet * u_jet) * LIGHT_SPEED;
+
+        let r_jet_head = v_jet * (t - self.engine_delay);
+        let r_jet_tail = v_jet * (t - self.engine_delay - self.engine_duration);
+
+        let in_jet = self.angle_from_axis(q) < self.engine_theta_max
+            && u_jet > 0.0
+            && self.engine_energy_at(q) > 0.0;
+
+        let launched = match &self.duty_cycle {
+            Some(duty_cycle) => duty_cycle.is_on(t - self.engine_delay - r / v_jet),
+            None => r < r_jet_head && r > r_jet_tail,
+        };
+
+        if in_jet && launched {
+            Zone::Jet
+        } else {
+            Zone::Ambient(self.cloud().zone(r, t))
+        }
+    }
+
+    /**
+     * Return the radial four-velocity (gamma-beta).
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn gamma_beta(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().gamma_beta_in(zone, r, t)
+            }
+            Zone::Jet => {
+                self.engine_u_at(q)
+            }
+        }
+    }
+
+    /**
+     * Return the mass rate per solid angle.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn mass_rate_per_steradian(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().mass_rate_per_steradian_in(zone, r, t)
+            }
+            Zone::Jet => {
+                let u = self.engine_u_at(q);
+                let l = self.engine_energy_at(q) / (4.0 * PI * self.engine_on_time());
+                l / f64::sqrt(1.0 + u * u)
+            }
+        }
+    }
+}
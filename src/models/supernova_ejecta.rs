@@ -0,0 +1,140 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-6;
+
+
+
+
+/**
+ * Homologously expanding supernova ejecta, with the broken power-law density
+ * profile of Chevalier & Soker (1989) and Matzner & McKee (1999):
+ *
+ * rho(v, t) = A t^-3 (v / v_t)^-delta for v < v_t, and
+ * rho(v, t) = A t^-3 (v / v_t)^-n     for v > v_t,
+ *
+ * where the transition velocity v_t and the coefficient A are fixed by the
+ * ejecta mass and kinetic energy. The steep outer profile is truncated at a
+ * maximum velocity, outside of which is an external medium at rest. The
+ * ejecta kinetic energy is computed with Newtonian expressions.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SupernovaEjecta {
+
+    /// Mass of the ejecta (g)
+    pub ejecta_mass: f64,
+
+    /// Kinetic energy of the ejecta (erg)
+    pub kinetic_energy: f64,
+
+    /// Index delta of the inner (flat) density profile; must be < 3
+    pub inner_index: f64,
+
+    /// Index n of the outer (steep) density profile; must be > 5
+    pub outer_index: f64,
+
+    /// Beta (v/c) at which the outer profile is truncated
+    pub max_beta: f64,
+
+    /// Density (g/cm^3) of the medium outside the ejecta
+    pub external_medium_density: f64,
+}
+
+
+
+
+// ============================================================================
+impl SupernovaEjecta {
+
+    /**
+     * The velocity (cm/s) of the break between the inner and outer profiles
+     */
+    pub fn transition_velocity(&self) -> f64 {
+        let (d, n) = (self.inner_index, self.outer_index);
+        let e = self.kinetic_energy;
+        let m = self.ejecta_mass;
+        (2.0 * (5.0 - d) * (n - 5.0) * e / ((3.0 - d) * (n - 3.0) * m)).sqrt()
+    }
+
+    /**
+     * The ejecta mass density at radius r and time t, or zero outside the
+     * ejecta.
+     */
+    pub fn ejecta_density(&self, r: f64, t: f64) -> f64 {
+        let (d, n) = (self.inner_index, self.outer_index);
+        let vt = self.transition_velocity();
+        let v = r / t;
+
+        if v > self.max_beta * LIGHT_SPEED {
+            return 0.0
+        }
+        let a = (n - 3.0) * (3.0 - d) * self.ejecta_mass / (4.0 * PI * (n - d) * vt.powi(3));
+        let index = if v < vt { d } else { n };
+        a / t.powi(3) * (v / vt).powf(-index)
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for SupernovaEjecta {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.ejecta_mass <= 0.0 || self.kinetic_energy <= 0.0 {
+            anyhow::bail!("ejecta_mass and kinetic_energy must be positive")
+        }
+        if self.inner_index >= 3.0 || self.outer_index <= 5.0 {
+            anyhow::bail!("the density indexes must satisfy inner_index < 3 and outer_index > 5")
+        }
+        if self.max_beta <= 0.0 || self.max_beta >= 1.0 {
+            anyhow::bail!("max_beta must be in (0, 1)")
+        }
+        if self.max_beta * LIGHT_SPEED <= self.transition_velocity() {
+            anyhow::bail!{"
+             The maximum ejecta velocity (v/c = {}) is below the transition
+             velocity (v/c = {}). Consider increasing max_beta.",
+             self.max_beta, self.transition_velocity() / LIGHT_SPEED}
+        }
+        if self.external_medium_density <= 0.0 {
+            anyhow::bail!("external_medium_density must be positive")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, _q) = coordinate;
+        let d = self.ejecta_density(r, t);
+
+        if d > 0.0 {
+            let b = r / t / LIGHT_SPEED;
+
+            AnyPrimitive {
+                velocity_r: b / (1.0 - b * b).sqrt(),
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        } else {
+            let d = self.external_medium_density;
+
+            AnyPrimitive {
+                velocity_r: 0.0,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        if self.ejecta_density(coordinate.0, t) > 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
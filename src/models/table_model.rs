@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use crate::lookup_table_v2::LookupTable2d;
+use crate::physics::AnyPrimitive;
+use crate::traits::InitialModel;
+
+
+
+
+/**
+ * Initial data sampled from a table of primitives on an (r, theta) grid,
+ * such as the output of another code. The table is read when the model is
+ * first sampled, and is bilinearly interpolated. Points outside the table
+ * take the values at its nearest edge.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableModel {
+
+    /// Path to an ASCII table with the columns (r [cm], theta, mass density
+    /// [g / cm^3], gas pressure, radial velocity, polar velocity, scalar
+    /// concentration). Velocities and pressure are in the same units as the
+    /// model outputs, e.g. gamma-beta for relativistic hydro. The rows
+    /// must cover every point of a rectangular grid, ordered by radius and
+    /// then by theta.
+    pub filename: String,
+
+    #[serde(skip)]
+    pub lookup_table: Arc<Mutex<Option<Arc<LookupTable2d<7>>>>>,
+}
+
+
+
+
+// ============================================================================
+impl TableModel {
+
+    /**
+     * Return the lookup table, reading it from the file if this is the
+     * first time it is needed. Panics if the file could not be read, which
+     * is checked in advance by [`InitialModel::validate`].
+     */
+    fn require_lookup_table(&self) -> Arc<LookupTable2d<7>> {
+        self.lookup_table
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(LookupTable2d::from_ascii_file(&self.filename).unwrap()))
+            .clone()
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for TableModel {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        LookupTable2d::<7>::from_ascii_file(&self.filename)?;
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), _t: f64) -> AnyPrimitive {
+        let sample = self.require_lookup_table().sample(coordinate.0, coordinate.1);
+
+        AnyPrimitive {
+            velocity_r: sample[4],
+            velocity_q: sample[5],
+            mass_density: sample[2],
+            gas_pressure: sample[3],
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), _t: f64) -> f64 {
+        self.require_lookup_table().sample(coordinate.0, coordinate.1)[6]
+    }
+}
@@ -0,0 +1,263 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::models::{AmbientZone, Cloud};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * One component of a two-component jet, which is uniform inside its opening
+ * angle
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JetComponent {
+
+    /// Opening angle, measured from the jet axis
+    pub opening_angle: f64,
+
+    /// Lorentz factor of the launched material
+    pub lorentz_factor: f64,
+
+    /// Isotropic-equivalent luminosity (erg/s)
+    pub luminosity: f64,
+}
+
+
+
+
+/**
+ * Two-component jet propagating through a kilonova debris cloud and
+ * surrounding relativistic envelope: a fast, narrow core, surrounded by a
+ * slower, wider sheath. The cloud and envelope are the shared [`Cloud`].
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TwoComponentJet {
+
+    /// Mass of the merger ejecta cloud
+    pub cloud_mass: f64,
+
+    /// Time following the cloud onset when the jet begins
+    pub engine_delay: f64,
+
+    /// Duration of the engine
+    pub engine_duration: f64,
+
+    /// The narrow, fast component of the jet
+    pub core: JetComponent,
+
+    /// The wide, slow component of the jet, outside the core
+    pub sheath: JetComponent,
+
+    /// Mass coordinate of the u=1 shell
+    pub envelop_m1: f64,
+
+    /// Beta (v/c) of the slowest envelope shell
+    pub envelop_slowest_beta: f64,
+
+    /// Beta (v/c) of the outer shell
+    pub envelop_fastest_beta: f64,
+
+    /// Index psi in u(m) ~ m^-psi
+    pub envelop_psi: f64,
+}
+
+
+
+
+/**
+ * Different space-time zones in the setup
+ */
+pub enum Zone {
+    Ambient(AmbientZone),
+    Core,
+    Sheath,
+}
+
+
+
+
+// ============================================================================
+impl JetComponent {
+
+    /**
+     * Four-velocity gamma-beta of the launched material
+     */
+    pub fn gamma_beta(&self) -> f64 {
+        (self.lorentz_factor.powi(2) - 1.0).sqrt()
+    }
+
+    /**
+     * Dimensionless velocity of the launched material: v / c
+     */
+    pub fn beta(&self) -> f64 {
+        self.gamma_beta() / self.lorentz_factor
+    }
+
+    /**
+     * The mass rate per solid angle, in g/s/sr
+     */
+    pub fn mass_rate_per_steradian(&self) -> f64 {
+        self.luminosity / (4.0 * PI * LIGHT_SPEED * LIGHT_SPEED) / self.lorentz_factor
+    }
+
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        if self.lorentz_factor <= 1.0 {
+            anyhow::bail!("the {} lorentz_factor must be greater than 1", name)
+        }
+        if self.luminosity <= 0.0 {
+            anyhow::bail!("the {} luminosity must be positive", name)
+        }
+        Ok(())
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for TwoComponentJet {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.core.validate("core")?;
+        self.sheath.validate("sheath")?;
+
+        if self.core.opening_angle <= 0.0 {
+            anyhow::bail!("the core opening_angle must be positive")
+        }
+        if self.sheath.opening_angle <= self.core.opening_angle || self.sheath.opening_angle > 0.5 * PI {
+            anyhow::bail!("the sheath opening_angle must be in (core opening_angle, pi/2]")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (r, q) = coordinate;
+        let f = self.mass_rate_per_steradian(r, q, t);
+        let u = self.gamma_beta(r, q, t);
+        let d = f / (r * r * u) / LIGHT_SPEED;
+        let p = d * UNIFORM_TEMPERATURE;
+
+        AnyPrimitive{
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: p,
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (r, q) = coordinate;
+
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => self.cloud().scalar_in(zone),
+            Zone::Core          => 1e+2,
+            Zone::Sheath        => 1e+1,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl TwoComponentJet {
+
+    /**
+     * Return the jet component launched at the given polar angle, if any.
+     *
+     * * `q` - The polar angle theta
+     */
+    pub fn component(&self, q: f64) -> Option<(Zone, &JetComponent)> {
+        let theta = f64::min(q, PI - q);
+
+        if theta < self.core.opening_angle {
+            Some((Zone::Core, &self.core))
+        } else if theta < self.sheath.opening_angle {
+            Some((Zone::Sheath, &self.sheath))
+        } else {
+            None
+        }
+    }
+
+    /**
+     * The debris cloud and envelope which the jet propagates through
+     */
+    pub fn cloud(&self) -> Cloud {
+        Cloud{
+            cloud_mass: self.cloud_mass,
+            engine_delay: self.engine_delay,
+            envelop_m1: self.envelop_m1,
+            envelop_slowest_beta: self.envelop_slowest_beta,
+            envelop_fastest_beta: self.envelop_fastest_beta,
+            envelop_psi: self.envelop_psi,
+        }
+    }
+
+    /**
+     * Determine the zone for a given radius, polar angle, and time.
+     *
+     * * `r` - Radius
+     * * `q` - Polar angle
+     * * `t` - Time
+     */
+    pub fn zone(&self, r: f64, q: f64, t: f64) -> Zone {
+        if let Some((zone, component)) = self.component(q) {
+            let v_jet = component.beta() * LIGHT_SPEED;
+            let r_jet_head = v_jet * (t - self.engine_delay);
+            let r_jet_tail = v_jet * (t - self.engine_delay - self.engine_duration);
+
+            if r < r_jet_head && r > r_jet_tail {
+                return zone
+            }
+        }
+        Zone::Ambient(self.cloud().zone(r, t))
+    }
+
+    /**
+     * Return the radial four-velocity (gamma-beta).
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn gamma_beta(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().gamma_beta_in(zone, r, t)
+            }
+            Zone::Core => {
+                self.core.gamma_beta()
+            }
+            Zone::Sheath => {
+                self.sheath.gamma_beta()
+            }
+        }
+    }
+
+    /**
+     * Return the mass rate per solid angle.
+     *
+     * * `r` - The radius
+     * * `q` - The polar angle theta
+     * * `t` - The time
+     */
+    pub fn mass_rate_per_steradian(&self, r: f64, q: f64, t: f64) -> f64 {
+        match self.zone(r, q, t) {
+            Zone::Ambient(zone) => {
+                self.cloud().mass_rate_per_steradian_in(zone, r, t)
+            }
+            Zone::Core => {
+                self.core.mass_rate_per_steradian()
+            }
+            Zone::Sheath => {
+                self.sheath.mass_rate_per_steradian()
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::physics::{AnyPrimitive, LIGHT_SPEED};
+use crate::traits::InitialModel;
+
+static UNIFORM_TEMPERATURE: f64 = 1e-3;
+
+
+
+
+/**
+ * One component of the kilonova ejecta: homologously expanding material
+ * with lab-frame density D(v, t) ~ (v / c)^-n t^-3 between a minimum and
+ * maximum velocity
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EjectaComponent {
+
+    /// Total mass of the component (g)
+    pub mass: f64,
+
+    /// Beta (v/c) of the slowest material
+    pub min_beta: f64,
+
+    /// Beta (v/c) of the fastest material
+    pub max_beta: f64,
+
+    /// Index n of the density profile D ~ (v / c)^-n
+    pub density_index: f64,
+
+    /// Angular extent of the component, measured from the equator for the
+    /// red component, and from the poles for the blue component
+    pub opening_angle: f64,
+
+    /// Scalar concentration which tags the component's material
+    pub scalar: f64,
+}
+
+
+
+
+/**
+ * Kilonova ejecta made of two components: a slow, lanthanide-rich (red)
+ * component concentrated around the equator, such as the tidal tail ejecta,
+ * and a fast, lanthanide-poor (blue) component around the poles, such as the
+ * shocked or disk wind ejecta. Each component expands homologously, and the
+ * densities add where they overlap. Outside the ejecta is an external medium
+ * at rest.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TwoComponentKilonova {
+
+    /// The slow, equatorial component
+    pub red: EjectaComponent,
+
+    /// The fast, polar component
+    pub blue: EjectaComponent,
+
+    /// Density (g/cm^3) of the medium outside the ejecta
+    pub external_medium_density: f64,
+}
+
+
+
+
+// ============================================================================
+impl EjectaComponent {
+
+    /**
+     * Return the lab-frame mass density of the component at velocity beta
+     * and time t, if beta is within its velocity range, and zero otherwise.
+     * The solid angle the component fills is given, in order to normalize
+     * the density to the component mass.
+     */
+    fn lab_frame_density(&self, beta: f64, t: f64, solid_angle: f64) -> f64 {
+        if beta < self.min_beta || beta > self.max_beta {
+            return 0.0
+        }
+        let k = 3.0 - self.density_index;
+        let integral = if k.abs() < 1e-12 {
+            (self.max_beta / self.min_beta).ln()
+        } else {
+            (self.max_beta.powf(k) - self.min_beta.powf(k)) / k
+        };
+        let vt = LIGHT_SPEED * t;
+        self.mass / (solid_angle * integral * vt * vt * vt) * beta.powf(-self.density_index)
+    }
+
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        if self.mass <= 0.0 {
+            anyhow::bail!("the {} component mass must be positive", name)
+        }
+        if self.min_beta <= 0.0 || self.max_beta <= self.min_beta || self.max_beta >= 1.0 {
+            anyhow::bail!("the {} component must have 0 < min_beta < max_beta < 1", name)
+        }
+        if self.opening_angle <= 0.0 || self.opening_angle > 0.5 * PI {
+            anyhow::bail!("the {} component opening_angle must be in (0, pi/2]", name)
+        }
+        Ok(())
+    }
+}
+
+
+
+
+// ============================================================================
+impl TwoComponentKilonova {
+
+    /**
+     * Return the lab-frame mass densities of the red and blue components at
+     * the given coordinate and time.
+     */
+    pub fn component_densities(&self, coordinate: (f64, f64), t: f64) -> (f64, f64) {
+        let (r, q) = coordinate;
+        let beta = r / t / LIGHT_SPEED;
+
+        let red = if (q - 0.5 * PI).abs() < self.red.opening_angle {
+            let solid_angle = 4.0 * PI * self.red.opening_angle.sin();
+            self.red.lab_frame_density(beta, t, solid_angle)
+        } else {
+            0.0
+        };
+        let blue = if f64::min(q, PI - q) < self.blue.opening_angle {
+            let solid_angle = 4.0 * PI * (1.0 - self.blue.opening_angle.cos());
+            self.blue.lab_frame_density(beta, t, solid_angle)
+        } else {
+            0.0
+        };
+        (red, blue)
+    }
+}
+
+
+
+
+// ============================================================================
+impl InitialModel for TwoComponentKilonova {
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.red.validate("red")?;
+        self.blue.validate("blue")?;
+
+        if self.external_medium_density <= 0.0 {
+            anyhow::bail!("external_medium_density must be positive")
+        }
+        Ok(())
+    }
+
+    fn primitive_at(&self, coordinate: (f64, f64), t: f64) -> AnyPrimitive {
+        let (red, blue) = self.component_densities(coordinate, t);
+
+        if red + blue > 0.0 {
+            let b = coordinate.0 / t / LIGHT_SPEED;
+            let u = b / (1.0 - b * b).sqrt();
+            let d = (red + blue) / (1.0 + u * u).sqrt();
+
+            AnyPrimitive {
+                velocity_r: u,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        } else {
+            let d = self.external_medium_density;
+
+            AnyPrimitive {
+                velocity_r: 0.0,
+                velocity_q: 0.0,
+                mass_density: d,
+                gas_pressure: d * UNIFORM_TEMPERATURE,
+            }
+        }
+    }
+
+    fn scalar_at(&self, coordinate: (f64, f64), t: f64) -> f64 {
+        let (red, blue) = self.component_densities(coordinate, t);
+
+        if red + blue > 0.0 {
+            (red * self.red.scalar + blue * self.blue.scalar) / (red + blue)
+        } else {
+            0.0
+        }
+    }
+}
@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 
 static UNIFORM_TEMPERATURE: f64 = 1e-6;
+static ADIABATIC_INDEX: f64 = 4.0 / 3.0;
 
 /// Jet propagating through a kilonova debris cloud and surrounding
 /// relativistic envelop
@@ -30,34 +31,60 @@ pub struct WindShock {
     /// Four velocity of wind after shock
     pub post_shock_gamma_beta: f64,
 
-    /// Rate of outflow of the flare
-    #[serde(default)]
-    pub flare_outflow_rate: f64,
-
-    /// Four velocity of flare
-    #[serde(default)]
-    pub flare_gamma_beta: f64,
-
-    /// Flare time
+    /// Flares launched through the inner boundary, on top of the wind
     #[serde(default)]
-    pub flare_time: f64,
-
-    /// Flare duration
-    #[serde(default)]
-    pub flare_duration: f64,
+    pub flares: Vec<Flare>,
 
     /// Initial data table. This field is optional. If it's given a value, it
     /// must be the relative path to an ASCII table of initial data for a
     /// wind. The table columns are expected to be (radius [cm], gamma-beta,
     /// mass density [g / cm^3], specific enthalpy [cm^2 / s^2]). If given,
-    /// the above parameters are ignored, except for the ones starting with
-    /// `flare`.
+    /// the above parameters are ignored, except for the flares. If omitted,
+    /// the wind is evaluated from the above parameters.
     pub initial_data_table: Option<String>,
 
     #[serde(skip)]
     pub lookup_table: Arc<Mutex<Option<LookupTable<4>>>>,
 }
 
+/// A flare in the wind: an outflow which starts at a given time, and whose
+/// density tapers linearly to zero over the flare duration
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Flare {
+    /// Time when the flare starts
+    pub start_time: f64,
+
+    /// Flare duration
+    pub duration: f64,
+
+    /// Rate of outflow of the flare
+    pub outflow_rate: f64,
+
+    /// Four velocity of the flare
+    pub gamma_beta: f64,
+}
+
+impl Flare {
+    fn is_active(&self, t: f64) -> bool {
+        t >= self.start_time && t < self.start_time + self.duration
+    }
+
+    fn primitive_at(&self, r: f64, t: f64) -> AnyPrimitive {
+        let u = self.gamma_beta;
+        let n = self.outflow_rate / (4.0 * PI * r * r * u * LIGHT_SPEED);
+        let rho = n * (self.start_time + self.duration - t) / self.duration;
+        let p = rho * UNIFORM_TEMPERATURE;
+
+        AnyPrimitive {
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: rho,
+            gas_pressure: p,
+        }
+    }
+}
+
 impl WindShock {
     fn require_lookup_table(&self) {
         let mut self_table = self.lookup_table.as_ref().lock().unwrap();
@@ -68,15 +95,86 @@ impl WindShock {
             *self_table = Some(table);
         }
     }
+
+    /// Comoving mass density (g/cm^3) of the steady wind at radius r, if it
+    /// has four-velocity u
+    fn wind_density(&self, r: f64, u: f64) -> f64 {
+        self.wind_mass_outflow_rate / (4.0 * PI * r * r * u * LIGHT_SPEED)
+    }
+
+    /// Evaluate the steady wind without a table. Inside the shock location
+    /// is the free wind, with constant four-velocity and pressure. The
+    /// post-shock four-velocity and pressure are imposed just outside the
+    /// shock, with the density following from continuity of the mass flux.
+    /// Beyond that, the subsonic wind conserves the mass flux, the entropy
+    /// p / rho^Gamma, and the Bernoulli constant h gamma, and the
+    /// four-velocity is found at each radius on the subsonic branch. Fails
+    /// if the four-velocity is not within the bracket searched, which can
+    /// happen for a cold post-shock flow far beyond the shock.
+    fn analytic_wind_primitive(&self, r: f64) -> anyhow::Result<AnyPrimitive> {
+        if r < self.shock_location {
+            let u = self.wind_gamma_beta;
+            return Ok(AnyPrimitive {
+                velocity_r: u,
+                velocity_q: 0.0,
+                mass_density: self.wind_density(r, u),
+                gas_pressure: self.wind_pressure,
+            })
+        }
+        let g = ADIABATIC_INDEX;
+        let u2 = self.post_shock_gamma_beta;
+        let d2 = self.wind_density(self.shock_location, u2);
+        let entropy = self.post_shock_pressure / d2.powf(g);
+        let bernoulli_at = |r: f64, u: f64| {
+            let d = self.wind_density(r, u);
+            let h = 1.0 + g / (g - 1.0) * entropy * d.powf(g - 1.0);
+            h * (1.0 + u * u).sqrt()
+        };
+        let bernoulli = bernoulli_at(self.shock_location, u2);
+
+        // The Bernoulli function diverges as u -> 0, and decreases with u
+        // until the sonic point. At r beyond the shock, it is below the
+        // Bernoulli constant at u2, so the subsonic root is in (0, u2]. It
+        // is searched for in [u2 * 1e-12, u2], by bisection in log(u).
+        let (mut lo, mut hi) = ((u2 * 1e-12).ln(), u2.ln());
+
+        if bernoulli_at(r, lo.exp()) < bernoulli || bernoulli_at(r, hi.exp()) > bernoulli {
+            anyhow::bail!("the analytic wind has no subsonic solution with four-velocity in [{:.3e}, {:.3e}] at r = {:.3e}", lo.exp(), hi.exp(), r)
+        }
+
+        for _ in 0..100 {
+            let mid = 0.5 * (lo + hi);
+            if bernoulli_at(r, mid.exp()) > bernoulli {
+                lo = mid
+            } else {
+                hi = mid
+            }
+        }
+        let u = (0.5 * (lo + hi)).exp();
+        let d = self.wind_density(r, u);
+
+        Ok(AnyPrimitive {
+            velocity_r: u,
+            velocity_q: 0.0,
+            mass_density: d,
+            gas_pressure: entropy * d.powf(g),
+        })
+    }
 }
 
 // ============================================================================
 impl InitialModel for WindShock {
     fn validate(&self) -> anyhow::Result<()> {
-        if self.wind_gamma_beta < 0.0 {
+        if self.flares.iter().any(|flare| flare.duration <= 0.0 || flare.outflow_rate < 0.0 || flare.gamma_beta <= 0.0) {
+            anyhow::bail!("each flare must have positive duration and four-velocity, and non-negative outflow rate")
+        } else if self.wind_gamma_beta < 0.0 {
             anyhow::bail!("the wind four-velocity must be positive")
         } else if let Some(initial_data_table) = &self.initial_data_table {
             LookupTable::<4>::from_ascii_file(initial_data_table)?;
+        } else if self.wind_mass_outflow_rate <= 0.0 || self.wind_gamma_beta == 0.0 || self.post_shock_gamma_beta <= 0.0 {
+            anyhow::bail!("without an initial data table, the wind outflow rate and four-velocities must be positive")
+        } else if self.wind_pressure < 0.0 || self.post_shock_pressure <= 0.0 || self.shock_location <= 0.0 {
+            anyhow::bail!("without an initial data table, the post-shock pressure and shock location must be positive")
         }
         Ok(())
     }
@@ -87,45 +185,8 @@ impl InitialModel for WindShock {
         // rho: comoving rest-mass density
         // Mdot = 4 pi r^2 rho u c
 
-        if t >= self.flare_time && t < self.flare_time + self.flare_duration {
-            let r = coordinate.0;
-            let u = self.flare_gamma_beta;
-            let n = self.flare_outflow_rate / (4.0 * PI * r * r * u * LIGHT_SPEED);
-            let rho = n * (self.flare_time + self.flare_duration - t) / self.flare_duration;
-            let p = rho * UNIFORM_TEMPERATURE;
-
-            AnyPrimitive {
-                velocity_r: u,
-                velocity_q: 0.0,
-                mass_density: rho,
-                gas_pressure: p,
-            }
-        } else if t >= self.flare_time + 3.0 && t < self.flare_time + 3.0 + self.flare_duration {
-            let r = coordinate.0;
-            let u = self.flare_gamma_beta;
-            let n = self.flare_outflow_rate / (4.0 * PI * r * r * u * LIGHT_SPEED);
-            let rho = n * (self.flare_time + 3.0 + self.flare_duration - t) / self.flare_duration;
-            let p = rho * UNIFORM_TEMPERATURE;
-
-            AnyPrimitive {
-                velocity_r: u,
-                velocity_q: 0.0,
-                mass_density: rho,
-                gas_pressure: p,
-            }
-        } else if t >= self.flare_time + 6.0 && t < self.flare_time + 6.0 + self.flare_duration {
-            let r = coordinate.0;
-            let u = self.flare_gamma_beta;
-            let n = self.flare_outflow_rate / (4.0 * PI * r * r * u * LIGHT_SPEED);
-            let rho = n * (self.flare_time + 6.0 + self.flare_duration - t) / self.flare_duration;
-            let p = rho * UNIFORM_TEMPERATURE;
-
-            AnyPrimitive {
-                velocity_r: u,
-                velocity_q: 0.0,
-                mass_density: rho,
-                gas_pressure: p,
-            }
+        if let Some(flare) = self.flares.iter().find(|flare| flare.is_active(t)) {
+            flare.primitive_at(coordinate.0, t)
         } else if self.initial_data_table.is_some() {
             self.require_lookup_table();
             let table_borrow = self.lookup_table.as_ref().lock().unwrap();
@@ -144,7 +205,7 @@ impl InitialModel for WindShock {
                 gas_pressure: p,
             }
         } else {
-            todo!("restore evaluation of wind profile which does not rely on a table")
+            self.analytic_wind_primitive(coordinate.0).unwrap_or_else(|e| panic!("{}", e))
         }
     }
 
@@ -152,3 +213,51 @@ impl InitialModel for WindShock {
         0.0
     }
 }
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wind() -> WindShock {
+        WindShock {
+            wind_mass_outflow_rate: 4.0 * PI * 1e20 * 0.1 * LIGHT_SPEED,
+            wind_gamma_beta: 1.0,
+            wind_pressure: 1e-3,
+            post_shock_pressure: 0.1,
+            shock_location: 1e10,
+            post_shock_gamma_beta: 0.1,
+            flares: Vec::new(),
+            initial_data_table: None,
+            lookup_table: Default::default(),
+        }
+    }
+
+    #[test]
+    fn analytic_wind_conserves_mass_flux_entropy_and_bernoulli_constant() {
+        let wind = wind();
+        let g = ADIABATIC_INDEX;
+        let (r2, u2) = (wind.shock_location, wind.post_shock_gamma_beta);
+        let d2 = wind.wind_density(r2, u2);
+        let bernoulli = |p: &AnyPrimitive| (1.0 + g / (g - 1.0) * p.gas_pressure / p.mass_density) * (1.0 + p.velocity_r.powi(2)).sqrt();
+        let p2 = wind.analytic_wind_primitive(r2).unwrap();
+
+        for &r in &[1.5e10, 3e10, 1e11] {
+            let p = wind.analytic_wind_primitive(r).unwrap();
+            let mdot = 4.0 * PI * r * r * p.mass_density * p.velocity_r * LIGHT_SPEED;
+            assert!(p.velocity_r < u2);
+            assert!(f64::abs(mdot / wind.wind_mass_outflow_rate - 1.0) < 1e-10);
+            assert!(f64::abs(p.gas_pressure / p.mass_density.powf(g) / (wind.post_shock_pressure / d2.powf(g)) - 1.0) < 1e-10);
+            assert!(f64::abs(bernoulli(&p) / bernoulli(&p2) - 1.0) < 1e-10);
+        }
+    }
+
+    #[test]
+    fn analytic_wind_fails_if_the_four_velocity_is_not_bracketed() {
+        let wind = WindShock{post_shock_pressure: 1e-20, ..wind()};
+        assert!(wind.analytic_wind_primitive(2e10).is_err());
+    }
+}
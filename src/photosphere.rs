@@ -0,0 +1,216 @@
+use std::f64::consts::PI;
+use std::io::Write;
+use crate::app::AnyHydro;
+use crate::mesh::CoordinateSystem;
+use crate::physics::LIGHT_SPEED;
+use crate::products::Products;
+
+/// The Stefan-Boltzmann constant (erg / cm^2 / s / K^4)
+pub static STEFAN_BOLTZMANN: f64 = 5.6704e-5;
+
+/// The optical depth of the photosphere
+pub static PHOTOSPHERE_OPTICAL_DEPTH: f64 = 2.0 / 3.0;
+
+
+
+
+/**
+ * A grey opacity, which may depend on the electron fraction: material with
+ * Ye below a threshold is assumed to be rich in lanthanides, and so to have
+ * a higher opacity. The electron fraction is the passive scalar
+ * concentration, in models which inject it as such.
+ */
+#[derive(Clone, Copy)]
+pub struct Opacity {
+
+    /// Opacity of the material (cm^2 / g)
+    pub kappa: f64,
+
+    /// Opacity of the material with Ye below `ye_threshold` (cm^2 / g). If
+    /// None, the opacity does not depend on Ye.
+    pub lanthanide_kappa: Option<f64>,
+
+    /// The electron fraction below which `lanthanide_kappa` applies
+    pub ye_threshold: f64,
+}
+
+
+
+
+/**
+ * The photosphere along a radial ray, at the polar angle of a column of
+ * zones
+ */
+pub struct PhotosphereRay {
+
+    /// Polar angle of the ray
+    pub theta: f64,
+
+    /// Radius (cm) at which the optical depth, integrated inwards from the
+    /// outer boundary, reaches [`PHOTOSPHERE_OPTICAL_DEPTH`]. This is NaN
+    /// if the whole ray is optically thin.
+    pub radius: f64,
+
+    /// Temperature (K) of the zone containing the photosphere, or NaN if
+    /// the whole ray is optically thin
+    pub temperature: f64,
+
+    /// Optical depth of the whole ray
+    pub optical_depth: f64,
+
+    /// Isotropic-equivalent luminosity 4 pi r^2 sigma T^4 (erg / s), or zero
+    /// if the whole ray is optically thin
+    pub isotropic_luminosity: f64,
+}
+
+
+
+
+/**
+ * The photosphere and bolometric luminosity of a products file
+ */
+pub struct Photosphere {
+
+    /// The time (s) of the products
+    pub time: f64,
+
+    /// The photosphere along each column of zones
+    pub rays: Vec<PhotosphereRay>,
+
+    /// The bolometric luminosity (erg / s): the blackbody flux from the
+    /// photosphere of each ray, integrated over the solid angle it
+    /// subtends. Only the polar extent of the mesh is included.
+    pub luminosity: f64,
+}
+
+
+
+
+// ============================================================================
+impl Opacity {
+
+    /**
+     * Return the opacity (cm^2 / g) of material with the given electron
+     * fraction.
+     */
+    pub fn at(&self, electron_fraction: f64) -> f64 {
+        match self.lanthanide_kappa {
+            Some(kappa) if electron_fraction < self.ye_threshold => kappa,
+            _ => self.kappa,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Find the photosphere along each column of zones of the given products,
+ * by integrating the optical depth inwards from the outer boundary. In
+ * relativistic runs, the optical depth along the ray to a distant observer
+ * is dtau = kappa rho Gamma (1 - beta_r) dr. The temperature is that of
+ * radiation in equilibrium with the gas pressure, p = a T^4 / 3, which is
+ * appropriate in radiation-dominated ejecta. Only spherical meshes are
+ * supported, and the blocks must tile a rectangular grid (see
+ * [`Products::stitched`]).
+ */
+pub fn photosphere(products: &Products, opacity: &Opacity) -> anyhow::Result<Photosphere> {
+    if products.config.mesh.geometry != CoordinateSystem::Spherical {
+        anyhow::bail!("the photosphere can only be found on spherical meshes")
+    }
+    let relativistic = matches!(products.config.hydro, AnyHydro::Relativistic(_));
+    let pressure_unit = if relativistic { LIGHT_SPEED * LIGHT_SPEED } else { 1.0 };
+    let radiation_constant = 4.0 * STEFAN_BOLTZMANN / LIGHT_SPEED;
+
+    let stitched = products.stitched()?;
+    let rv = &stitched.radial_vertices;
+    let qv = &stitched.polar_vertices;
+    let (nr, nq) = stitched.primitive.dim();
+
+    let mut rays = Vec::with_capacity(nq);
+    let mut luminosity = 0.0;
+
+    for j in 0..nq {
+        let mut tau = 0.0;
+        let mut photosphere = None;
+
+        for i in (0..nr).rev() {
+            let p = &stitched.primitive[(i, j)];
+            let dr = rv[i + 1] - rv[i];
+            let doppler = if relativistic {
+                let u0 = (1.0 + p.velocity_r.powi(2) + p.velocity_q.powi(2)).sqrt();
+                u0 - p.velocity_r
+            } else {
+                1.0
+            };
+            let dtau = opacity.at(stitched.scalar[(i, j)]) * p.mass_density * doppler * dr;
+
+            if photosphere.is_none() && tau + dtau >= PHOTOSPHERE_OPTICAL_DEPTH {
+                let radius = rv[i + 1] - (PHOTOSPHERE_OPTICAL_DEPTH - tau) / dtau * dr;
+                let temperature = (3.0 * p.gas_pressure * pressure_unit / radiation_constant).powf(0.25);
+                photosphere = Some((radius, temperature));
+            }
+            tau += dtau;
+        }
+
+        let theta = 0.5 * (qv[j] + qv[j + 1]);
+        let solid_angle = 2.0 * PI * (qv[j].cos() - qv[j + 1].cos());
+
+        rays.push(match photosphere {
+            Some((radius, temperature)) => {
+                let flux = STEFAN_BOLTZMANN * temperature.powi(4);
+                luminosity += flux * radius * radius * solid_angle;
+                PhotosphereRay{
+                    theta,
+                    radius,
+                    temperature,
+                    optical_depth: tau,
+                    isotropic_luminosity: 4.0 * PI * radius * radius * flux,
+                }
+            }
+            None => PhotosphereRay{
+                theta,
+                radius: f64::NAN,
+                temperature: f64::NAN,
+                optical_depth: tau,
+                isotropic_luminosity: 0.0,
+            },
+        });
+    }
+    Ok(Photosphere{time: products.time, rays, luminosity})
+}
+
+/**
+ * Write a bolometric light curve as comma-separated values, with a header
+ * line.
+ */
+pub fn write_light_curve<W: Write>(writer: &mut W, photospheres: &[Photosphere]) -> std::io::Result<()> {
+    writeln!(writer, "time,luminosity")?;
+
+    for photosphere in photospheres {
+        writeln!(writer, "{:.12e},{:.12e}", photosphere.time, photosphere.luminosity)?;
+    }
+    Ok(())
+}
+
+/**
+ * Write the photosphere along each ray, for each of the given times, as
+ * comma-separated values with a header line.
+ */
+pub fn write_rays<W: Write>(writer: &mut W, photospheres: &[Photosphere]) -> std::io::Result<()> {
+    writeln!(writer, "time,theta,radius,temperature,optical_depth,isotropic_luminosity")?;
+
+    for photosphere in photospheres {
+        for ray in &photosphere.rays {
+            writeln!(writer, "{:.12e},{:.12e},{:.12e},{:.12e},{:.12e},{:.12e}",
+                photosphere.time,
+                ray.theta,
+                ray.radius,
+                ray.temperature,
+                ray.optical_depth,
+                ray.isotropic_luminosity)?;
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,11 @@
 mod relativistic_hydro;
 mod newtonian_hydro;
+pub mod taub_mathews;
 
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use godunov_core::runge_kutta::RungeKuttaOrder;
+use crate::traits::{Conserved, Hydrodynamics};
 pub use relativistic_hydro::RelativisticHydro;
 pub use newtonian_hydro::NewtonianHydro;
 pub static LIGHT_SPEED: f64 = 3e10;
@@ -33,10 +37,32 @@ pub enum RiemannSolver {
 
 
 /**
- * Primitive variable state that is agnostic to the hydrodynamics system
+ * Enum for the equation of state used by the relativistic hydrodynamics
  */
-#[derive(Clone, Serialize, Deserialize)]
-#[serde(from = "[f64; 4]", into = "[f64; 4]")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EquationOfState {
+    GammaLaw,
+    TaubMathews,
+}
+
+impl Default for EquationOfState {
+    fn default() -> Self {
+        Self::GammaLaw
+    }
+}
+
+
+
+
+/**
+ * Primitive variable state that is agnostic to the hydrodynamics system. It
+ * is serialized as an array of four numbers, and can also be deserialized
+ * from the map keyed by field name which was written by older versions of
+ * the code (as `AgnosticPrimitive`).
+ */
+#[derive(Clone, Serialize)]
+#[serde(into = "[f64; 4]")]
 pub struct AnyPrimitive {
 
     /// Radial velocity (radial gamma-beta for relativistic hydro)
@@ -55,6 +81,30 @@ pub struct AnyPrimitive {
 
 
 
+/**
+ * Rates of change of the conserved quantities per unit volume, in cgs units,
+ * agnostic to the hydrodynamics system. The energy density excludes the rest
+ * mass energy.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AnyConserved {
+
+    /// Mass density
+    pub mass_density: f64,
+
+    /// Radial momentum density
+    pub momentum_r: f64,
+
+    /// Polar momentum density
+    pub momentum_q: f64,
+
+    /// Energy density
+    pub energy_density: f64,
+}
+
+
+
+
 // ============================================================================
 impl Into<[f64; 4]> for AnyPrimitive {
     fn into(self) -> [f64; 4] {
@@ -73,6 +123,57 @@ impl From<[f64; 4]> for AnyPrimitive {
     }
 }
 
+impl<'de> Deserialize<'de> for AnyPrimitive {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AnyPrimitiveVisitor)
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Deserializes an [`AnyPrimitive`] from either an array of four numbers, or
+ * a legacy `AgnosticPrimitive` map
+ */
+struct AnyPrimitiveVisitor;
+
+impl<'de> Visitor<'de> for AnyPrimitiveVisitor {
+    type Value = AnyPrimitive;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of four numbers, or a map of primitive fields")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<AnyPrimitive, A::Error> {
+        let mut d = [0.0; 4];
+
+        for (n, x) in d.iter_mut().enumerate() {
+            *x = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(n, &self))?;
+        }
+        Ok(d.into())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<AnyPrimitive, A::Error> {
+        let names = ["velocity_r", "velocity_q", "mass_density", "gas_pressure"];
+        let mut d = [None; 4];
+
+        while let Some(key) = map.next_key::<String>()? {
+            match names.iter().position(|&name| name == key) {
+                Some(n) => d[n] = Some(map.next_value()?),
+                None => { map.next_value::<de::IgnoredAny>()?; }
+            }
+        }
+        let mut result = [0.0; 4];
+
+        for (x, (value, &name)) in result.iter_mut().zip(d.iter().zip(&names)) {
+            *x = value.ok_or_else(|| de::Error::missing_field(name))?;
+        }
+        Ok(result.into())
+    }
+}
+
 
 
 
@@ -91,7 +192,10 @@ pub enum HydroErrorType {
     NegativeEnergyDensity(f64),
 
     #[error("The root finder failed to converge \n {0:?}")]
-    RootFinderFailed(hydro_srhd::srhd_2d::Conserved)
+    RootFinderFailed(hydro_srhd::srhd_2d::Conserved),
+
+    #[error("The time step exceeds the signal crossing time: dt / crossing time = {0:.3}")]
+    CflViolation(f64),
 }
 
 impl HydroErrorType {
@@ -127,4 +231,100 @@ impl HydroError {
             position: self.position,
         }
     }
+
+    /**
+     * The (r, theta) position of the zone where the error occurred.
+     */
+    pub fn position(&self) -> (f64, f64) {
+        self.position
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the Godunov flux of (mass, radial momentum, polar momentum,
+ * energy) through a radial interface between two primitive states, in the
+ * code units of the hydrodynamics system. The solver is `euler_hlle` for
+ * Newtonian hydrodynamics, or `srhd_hlle` or `srhd_hllc` for relativistic
+ * hydrodynamics, with a gamma-law equation of state in either case. This
+ * is for testing and teaching; the scheme calls
+ * [`Hydrodynamics::intercell_flux`] on the configured hydrodynamics.
+ */
+pub fn riemann_flux(solver: &str, gamma_law_index: f64, pl: &AnyPrimitive, pr: &AnyPrimitive) -> anyhow::Result<[f64; 4]> {
+    fn flux<H: Hydrodynamics>(hydro: &H, pl: &AnyPrimitive, pr: &AnyPrimitive) -> [f64; 4] {
+        let (f, _) = hydro.intercell_flux(hydro.interpret(pl), hydro.interpret(pr), 0.0, 0.0, Direction::Radial);
+        [f.lab_frame_mass(), f.radial_momentum(), f.polar_momentum(), f.energy()]
+    }
+    let riemann_solver = match solver {
+        "euler_hlle" => {
+            return Ok(flux(&NewtonianHydro{
+                gamma_law_index,
+                plm_theta: 1.5,
+                cfl_number: 0.1,
+                runge_kutta_order: RungeKuttaOrder::RK1,
+            }, pl, pr))
+        }
+        "srhd_hlle" => RiemannSolver::HLLE,
+        "srhd_hllc" => RiemannSolver::HLLC,
+        _ => anyhow::bail!("unknown Riemann solver '{}' (choose from euler_hlle, srhd_hlle, srhd_hllc)", solver),
+    };
+    Ok(flux(&RelativisticHydro{
+        gamma_law_index,
+        equation_of_state: EquationOfState::GammaLaw,
+        plm_theta: 1.5,
+        cfl_number: 0.1,
+        runge_kutta_order: RungeKuttaOrder::RK1,
+        riemann_solver,
+        adaptive_time_step: false,
+    }, pl, pr))
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct AgnosticPrimitive {
+        velocity_r: f64,
+        velocity_q: f64,
+        mass_density: f64,
+        gas_pressure: f64,
+    }
+
+    fn to_cbor<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn any_primitive_reads_the_array_layout() {
+        let bytes = to_cbor(&AnyPrimitive{velocity_r: 1.0, velocity_q: 2.0, mass_density: 3.0, gas_pressure: 4.0});
+        let p: AnyPrimitive = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!((p.velocity_r, p.velocity_q, p.mass_density, p.gas_pressure), (1.0, 2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn any_primitive_reads_the_legacy_map_layout() {
+        let bytes = to_cbor(&AgnosticPrimitive{velocity_r: 1.0, velocity_q: 2.0, mass_density: 3.0, gas_pressure: 4.0});
+        let p: AnyPrimitive = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!((p.velocity_r, p.velocity_q, p.mass_density, p.gas_pressure), (1.0, 2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn euler_flux_of_uniform_state_is_the_physical_flux() {
+        let p = AnyPrimitive{velocity_r: 0.5, velocity_q: 0.0, mass_density: 1.0, gas_pressure: 1.0};
+        let [mass, momentum_r, momentum_q, energy] = riemann_flux("euler_hlle", 5.0 / 3.0, &p, &p).unwrap();
+        assert!((mass - 0.5).abs() < 1e-12);
+        assert!((momentum_r - 1.25).abs() < 1e-12);
+        assert!(momentum_q.abs() < 1e-12);
+        assert!((energy - 1.3125).abs() < 1e-12);
+    }
 }
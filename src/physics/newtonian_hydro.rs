@@ -1,7 +1,8 @@
 use serde::{Serialize, Deserialize};
 use godunov_core::piecewise_linear;
 use godunov_core::runge_kutta::RungeKuttaOrder;
-use crate::physics::{AnyPrimitive, Direction, HydroErrorType};
+use crate::mesh::CoordinateSystem;
+use crate::physics::{AnyConserved, AnyPrimitive, Direction, HydroErrorType};
 use crate::traits::Hydrodynamics;
 
 
@@ -76,6 +77,14 @@ impl Hydrodynamics for NewtonianHydro {
         p.max_signal_speed(self.gamma_law_index)
     }
 
+    fn radial_velocity(&self, p: &Self::Primitive) -> f64 {
+        p.velocity_1()
+    }
+
+    fn polar_velocity(&self, p: &Self::Primitive) -> f64 {
+        p.velocity_2()
+    }
+
     fn global_signal_speed(&self) -> Option<f64> {
         None
     }
@@ -84,6 +93,10 @@ impl Hydrodynamics for NewtonianHydro {
         hydro_euler::euler_2d::Primitive(a.mass_density, a.velocity_r, a.velocity_q, a.gas_pressure)
     }
 
+    fn interpret_conserved(&self, a: &AnyConserved) -> Self::Conserved {
+        hydro_euler::euler_2d::Conserved(a.mass_density, a.momentum_r, a.momentum_q, a.energy_density)
+    }
+
     fn any(&self, p: &Self::Primitive) -> AnyPrimitive {
         AnyPrimitive {
             velocity_r: p.velocity_1(),
@@ -101,8 +114,29 @@ impl Hydrodynamics for NewtonianHydro {
         hydro_euler::euler_2d::riemann_hlle_scalar(pl, pr, sl, sr, axis, self.gamma_law_index)
     }
 
-    fn geometrical_source_terms(&self, p: Self::Primitive, coordinate: (f64, f64)) -> Self::Conserved {
-        p.spherical_geometry_source_terms(coordinate.0, coordinate.1)
+    fn geometrical_source_terms(
+        &self,
+        p: Self::Primitive,
+        coordinate: (f64, f64),
+        volume: f64,
+        face_area_differences: (f64, f64),
+        coordinates: CoordinateSystem) -> Self::Conserved
+    {
+        let (r, q) = coordinate;
+        let (da_r, da_q) = face_area_differences;
+        let pg = p.gas_pressure();
+
+        // With no azimuthal velocity, the only cylindrical source is the
+        // pressure on the radial faces.
+        if coordinates == CoordinateSystem::Cylindrical {
+            return hydro_euler::euler_2d::Conserved(0.0, pg * da_r, pg * da_q, 0.0)
+        }
+        let pressure_correction = hydro_euler::euler_2d::Conserved(
+            0.0,
+            pg * (da_r - 2.0 * volume / r),
+            pg * (da_q - volume * q.cos() / q.sin() / r),
+            0.0);
+        p.spherical_geometry_source_terms(r, q) * volume + pressure_correction
     }
 
     fn cfl_number(&self) -> f64 {
@@ -121,6 +155,15 @@ impl crate::traits::Conserved for hydro_euler::euler_2d::Conserved {
     fn lab_frame_mass(&self) -> f64 {
         self.mass_density()
     }
+    fn radial_momentum(&self) -> f64 {
+        self.1
+    }
+    fn polar_momentum(&self) -> f64 {
+        self.2
+    }
+    fn energy(&self) -> f64 {
+        self.3
+    }
 }
 
 impl crate::traits::Arithmetic for hydro_euler::euler_2d::Primitive {
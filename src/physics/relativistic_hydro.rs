@@ -1,7 +1,9 @@
 use serde::{Serialize, Deserialize};
 use godunov_core::piecewise_linear;
 use godunov_core::runge_kutta::RungeKuttaOrder;
-use crate::physics::{AnyPrimitive, RiemannSolver, Direction, HydroErrorType, LIGHT_SPEED};
+use crate::mesh::CoordinateSystem;
+use crate::physics::{AnyConserved, AnyPrimitive, EquationOfState, RiemannSolver, Direction, HydroErrorType, LIGHT_SPEED};
+use crate::physics::taub_mathews;
 use crate::traits::Hydrodynamics;
 
 
@@ -17,6 +19,13 @@ pub struct RelativisticHydro {
     /// Index for the gamma-law equation of state
     pub gamma_law_index: f64,
 
+    /// Equation of state: [gamma_law | taub_mathews]. The Taub-Mathews
+    /// approximation to the Synge gas has an effective adiabatic index which
+    /// goes from 5/3 at low temperature to 4/3 at high temperature, and it
+    /// ignores the `gamma_law_index`. If omitted, defaults to gamma_law.
+    #[serde(default)]
+    pub equation_of_state: EquationOfState,
+
     /// Parameter for gradient estimation: [1, 2]
     pub plm_theta: f64,
 
@@ -50,6 +59,9 @@ impl Hydrodynamics for RelativisticHydro {
         if self.cfl_number < 0.0 || self.cfl_number > 0.7 {
             anyhow::bail!("cfl_number must be in the range [0.0, 0.7]")
         }
+        if let (EquationOfState::TaubMathews, RiemannSolver::HLLC) = (&self.equation_of_state, &self.riemann_solver) {
+            anyhow::bail!("the taub_mathews equation of state requires the HLLE Riemann solver")
+        }
         Ok(())
     }
 
@@ -74,6 +86,10 @@ impl Hydrodynamics for RelativisticHydro {
             return Err(HydroErrorType::NegativeEnergyDensity(u.energy_density()))
         }
 
+        if let EquationOfState::TaubMathews = self.equation_of_state {
+            return taub_mathews::try_to_primitive(u)
+        }
+
         let valid_primitive = match u.to_primitive(self.gamma_law_index) {
             hydro_srhd::srhd_2d::RecoveredPrimitive::Success(p) => p,
             hydro_srhd::srhd_2d::RecoveredPrimitive::NegativePressure(p) => {
@@ -92,11 +108,25 @@ impl Hydrodynamics for RelativisticHydro {
     }
 
     fn to_conserved(&self, p: Self::Primitive) -> Self::Conserved {
-        p.to_conserved(self.gamma_law_index)
+        match self.equation_of_state {
+            EquationOfState::GammaLaw    => p.to_conserved(self.gamma_law_index),
+            EquationOfState::TaubMathews => taub_mathews::to_conserved(&p),
+        }
     }
 
     fn max_signal_speed(&self, p: Self::Primitive) -> f64 {
-        p.max_signal_speed(self.gamma_law_index) * LIGHT_SPEED
+        match self.equation_of_state {
+            EquationOfState::GammaLaw    => p.max_signal_speed(self.gamma_law_index) * LIGHT_SPEED,
+            EquationOfState::TaubMathews => taub_mathews::max_signal_speed(&p) * LIGHT_SPEED,
+        }
+    }
+
+    fn radial_velocity(&self, p: &Self::Primitive) -> f64 {
+        p.gamma_beta_1() / p.lorentz_factor_squared().sqrt() * LIGHT_SPEED
+    }
+
+    fn polar_velocity(&self, p: &Self::Primitive) -> f64 {
+        p.gamma_beta_2() / p.lorentz_factor_squared().sqrt() * LIGHT_SPEED
     }
 
     fn global_signal_speed(&self) -> Option<f64> {
@@ -111,6 +141,14 @@ impl Hydrodynamics for RelativisticHydro {
         hydro_srhd::srhd_2d::Primitive(a.mass_density, a.velocity_r, a.velocity_q, a.gas_pressure)
     }
 
+    fn interpret_conserved(&self, a: &AnyConserved) -> Self::Conserved {
+        hydro_srhd::srhd_2d::Conserved(
+            a.mass_density,
+            a.momentum_r / LIGHT_SPEED,
+            a.momentum_q / LIGHT_SPEED,
+            a.energy_density / LIGHT_SPEED / LIGHT_SPEED)
+    }
+
     fn any(&self, p: &Self::Primitive) -> AnyPrimitive {
         AnyPrimitive {
             velocity_r: p.gamma_beta_1(),
@@ -121,6 +159,10 @@ impl Hydrodynamics for RelativisticHydro {
     }
 
     fn intercell_flux(&self, pl: Self::Primitive, pr: Self::Primitive, sl: f64, sr: f64, direction: Direction) -> (Self::Conserved, f64) {
+        if let EquationOfState::TaubMathews = self.equation_of_state {
+            let (f, g) = taub_mathews::riemann_hlle_scalar(pl, pr, sl, sr, direction);
+            return (f * LIGHT_SPEED, g * LIGHT_SPEED)
+        }
         let mode = match self.riemann_solver {
             RiemannSolver::HLLE => hydro_srhd::srhd_2d::RiemannSolverMode::HlleFlux,
             RiemannSolver::HLLC => hydro_srhd::srhd_2d::RiemannSolverMode::HllcFlux,
@@ -133,8 +175,37 @@ impl Hydrodynamics for RelativisticHydro {
         (f * LIGHT_SPEED, g * LIGHT_SPEED)
     }
 
-    fn geometrical_source_terms(&self, p: Self::Primitive, coordinate: (f64, f64)) -> Self::Conserved {
-        p.spherical_geometry_source_terms(coordinate.0, coordinate.1, self.gamma_law_index) * LIGHT_SPEED
+    fn geometrical_source_terms(
+        &self,
+        p: Self::Primitive,
+        coordinate: (f64, f64),
+        volume: f64,
+        face_area_differences: (f64, f64),
+        coordinates: CoordinateSystem) -> Self::Conserved
+    {
+        let (r, q) = coordinate;
+        let (da_r, da_q) = face_area_differences;
+        let pg = p.gas_pressure();
+
+        // With no azimuthal velocity, the only cylindrical source is the
+        // pressure on the radial faces.
+        if coordinates == CoordinateSystem::Cylindrical {
+            return hydro_srhd::srhd_2d::Conserved(0.0, pg * da_r, pg * da_q, 0.0) * LIGHT_SPEED
+        }
+        let point_source = match self.equation_of_state {
+            EquationOfState::GammaLaw => {
+                p.spherical_geometry_source_terms(r, q, self.gamma_law_index)
+            }
+            EquationOfState::TaubMathews => {
+                taub_mathews::spherical_geometry_source_terms(&p, r, q)
+            }
+        };
+        let pressure_correction = hydro_srhd::srhd_2d::Conserved(
+            0.0,
+            pg * (da_r - 2.0 * volume / r),
+            pg * (da_q - volume * q.cos() / q.sin() / r),
+            0.0);
+        (point_source * volume + pressure_correction) * LIGHT_SPEED
     }
 
     fn cfl_number(&self) -> f64 {
@@ -153,6 +224,15 @@ impl crate::traits::Conserved for hydro_srhd::srhd_2d::Conserved {
     fn lab_frame_mass(&self) -> f64 {
         self.lab_frame_density()
     }
+    fn radial_momentum(&self) -> f64 {
+        self.1
+    }
+    fn polar_momentum(&self) -> f64 {
+        self.2
+    }
+    fn energy(&self) -> f64 {
+        self.energy_density()
+    }
 }
 
 impl crate::traits::Arithmetic for hydro_srhd::srhd_2d::Primitive {
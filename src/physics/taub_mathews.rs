@@ -0,0 +1,239 @@
+use hydro_srhd::srhd_2d::{Conserved, Primitive};
+use crate::physics::{Direction, HydroErrorType};
+
+
+
+
+/**
+ * Maximum number of Newton iterations in the conserved-to-primitive
+ * conversion
+ */
+static MAX_ITERATIONS: usize = 64;
+
+/**
+ * Relative tolerance on the gas pressure in the conserved-to-primitive
+ * conversion
+ */
+static PRESSURE_TOLERANCE: f64 = 1e-12;
+
+
+
+
+/**
+ * Specific enthalpy h = 1 + e + p / rho of the Taub-Mathews gas, in units of
+ * c^2, given the dimensionless temperature theta = p / rho.
+ */
+pub fn specific_enthalpy(theta: f64) -> f64 {
+    2.5 * theta + (2.25 * theta * theta + 1.0).sqrt()
+}
+
+/**
+ * Dimensionless temperature theta = p / rho of the Taub-Mathews gas, given
+ * its specific enthalpy h. This is the positive root of 4 theta^2 - 5 h theta
+ * + h^2 - 1 = 0.
+ */
+pub fn temperature(specific_enthalpy: f64) -> f64 {
+    let h = specific_enthalpy;
+    (5.0 * h - (9.0 * h * h + 16.0).sqrt()) / 8.0
+}
+
+/**
+ * Sound speed squared (in units of c^2) of the Taub-Mathews gas, given the
+ * dimensionless temperature theta = p / rho. The effective adiabatic index is
+ * 5/3 as theta -> 0 and 4/3 as theta -> infinity.
+ */
+pub fn sound_speed_squared(theta: f64) -> f64 {
+    let h = specific_enthalpy(theta);
+    theta / (3.0 * h) * (5.0 * h - 8.0 * theta) / (h - theta)
+}
+
+
+
+
+// ============================================================================
+fn normal_gamma_beta(p: &Primitive, direction: &Direction) -> f64 {
+    match direction {
+        Direction::Radial => p.gamma_beta_1(),
+        Direction::Polar  => p.gamma_beta_2(),
+    }
+}
+
+fn flux_vector(p: &Primitive, direction: &Direction) -> Conserved {
+    let u = to_conserved(p);
+    let pg = p.gas_pressure();
+    let vn = normal_gamma_beta(p, direction) / p.lorentz_factor_squared().sqrt();
+
+    match direction {
+        Direction::Radial => Conserved(u.0 * vn, u.1 * vn + pg, u.2 * vn, (u.3 + pg) * vn),
+        Direction::Polar  => Conserved(u.0 * vn, u.1 * vn, u.2 * vn + pg, (u.3 + pg) * vn),
+    }
+}
+
+
+
+
+/**
+ * Convert a primitive state to a conserved one. The energy density excludes
+ * the rest-mass energy.
+ */
+pub fn to_conserved(p: &Primitive) -> Conserved {
+    let w = p.lorentz_factor_squared().sqrt();
+    let d = p.mass_density() * w;
+    let h = specific_enthalpy(p.gas_pressure() / p.mass_density());
+    Conserved(
+        d,
+        d * h * p.gamma_beta_1(),
+        d * h * p.gamma_beta_2(),
+        d * h * w - p.gas_pressure() - d)
+}
+
+/**
+ * Try to recover a primitive state from a conserved one, by a Newton
+ * iteration on the gas pressure. The iteration is started from the pressure
+ * recovered with a 4/3 gamma-law, and is kept above the smallest pressure for
+ * which the velocity is sub-luminal. A recovered pressure which is negative
+ * is floored in the same way as for the gamma-law equation of state.
+ */
+pub fn try_to_primitive(u: Conserved) -> Result<Primitive, HydroErrorType> {
+    let d = u.0;
+    let s = (u.1 * u.1 + u.2 * u.2).sqrt();
+    let tau = u.3;
+
+    let residual = |p: f64| {
+        let e = tau + d + p;
+        let v = s / e;
+        let w = 1.0 / (1.0 - v * v).sqrt();
+        let rho = d / w;
+        rho * temperature(e / (d * w)) - p
+    };
+
+    let p_min = (s - tau - d).max(0.0);
+    let p_tol = PRESSURE_TOLERANCE * (tau + d);
+    let mut p = match u.to_primitive(4.0 / 3.0) {
+        hydro_srhd::srhd_2d::RecoveredPrimitive::Success(p) => p.gas_pressure().max(2.0 * p_min + p_tol),
+        _ => 2.0 * p_min + p_tol,
+    };
+
+    for _ in 0..MAX_ITERATIONS {
+        let dp = 1e-7 * p + p_tol;
+        let f0 = residual(p);
+        let f1 = residual(p + dp);
+        let mut p_next = p - f0 * dp / (f1 - f0);
+
+        if !p_next.is_finite() {
+            break
+        }
+        if p_next <= p_min {
+            p_next = 0.5 * (p + p_min)
+        }
+        if (p_next - p).abs() < PRESSURE_TOLERANCE * p + p_tol {
+            let e = tau + d + p_next;
+            let v = s / e;
+            let w = 1.0 / (1.0 - v * v).sqrt();
+            let rho = d / w;
+            let pg = if p_next < 0.0 { 1e-3 * rho } else { p_next };
+            return Ok(Primitive(rho, w * u.1 / e, w * u.2 / e, pg))
+        }
+        p = p_next;
+    }
+    Err(HydroErrorType::RootFinderFailed(u))
+}
+
+
+
+
+/**
+ * Return the slowest and fastest characteristic speeds (in units of c) in the
+ * given direction.
+ */
+pub fn outer_wavespeeds(p: &Primitive, direction: &Direction) -> (f64, f64) {
+    let a2 = sound_speed_squared(p.gas_pressure() / p.mass_density());
+    let w2 = p.lorentz_factor_squared();
+    let vn = normal_gamma_beta(p, direction) / w2.sqrt();
+    let v2 = 1.0 - 1.0 / w2;
+    let disc = (a2 * (1.0 - v2) * (1.0 - v2 * a2 - vn * vn * (1.0 - a2))).sqrt();
+    let denom = 1.0 - v2 * a2;
+    ((vn * (1.0 - a2) - disc) / denom, (vn * (1.0 - a2) + disc) / denom)
+}
+
+/**
+ * Return the largest characteristic speed (in units of c) in either of the
+ * grid directions.
+ */
+pub fn max_signal_speed(p: &Primitive) -> f64 {
+    let (a, b) = outer_wavespeeds(p, &Direction::Radial);
+    let (c, d) = outer_wavespeeds(p, &Direction::Polar);
+    a.abs().max(b.abs()).max(c.abs()).max(d.abs())
+}
+
+/**
+ * Return the HLLE flux of the conserved quantities and the passive scalar.
+ */
+pub fn riemann_hlle_scalar(pl: Primitive, pr: Primitive, sl: f64, sr: f64, direction: Direction) -> (Conserved, f64) {
+    let ul = to_conserved(&pl);
+    let ur = to_conserved(&pr);
+    let fl = flux_vector(&pl, &direction);
+    let fr = flux_vector(&pr, &direction);
+    let (alm, alp) = outer_wavespeeds(&pl, &direction);
+    let (arm, arp) = outer_wavespeeds(&pr, &direction);
+    let am = alm.min(arm).min(0.0);
+    let ap = alp.max(arp).max(0.0);
+
+    let f = (fl * ap - fr * am + (ur - ul) * (ap * am)) / (ap - am);
+    let g = (fl.0 * sl * ap - fr.0 * sr * am + (ur.0 * sr - ul.0 * sl) * ap * am) / (ap - am);
+    (f, g)
+}
+
+/**
+ * Return the geometrical source terms for spherical polar coordinates, in
+ * the same units as the conserved quantities per unit volume per unit time
+ * (with c = 1).
+ */
+pub fn spherical_geometry_source_terms(p: &Primitive, r: f64, q: f64) -> Conserved {
+    let pg = p.gas_pressure();
+    let hd = p.mass_density() * specific_enthalpy(pg / p.mass_density());
+    let ur = p.gamma_beta_1();
+    let uq = p.gamma_beta_2();
+    let cotq = q.cos() / q.sin();
+    Conserved(
+        0.0,
+        (2.0 * pg + hd * uq * uq) / r,
+        (pg * cotq - hd * ur * uq) / r,
+        0.0)
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature_inverts_specific_enthalpy() {
+        for &theta in &[1e-6, 1e-2, 1.0, 1e2] {
+            assert!((temperature(specific_enthalpy(theta)) - theta).abs() < 1e-10 * theta.max(1.0));
+        }
+    }
+
+    #[test]
+    fn adiabatic_index_has_the_right_limits() {
+        let gamma_eff = |theta: f64| {
+            let a2 = sound_speed_squared(theta);
+            a2 * specific_enthalpy(theta) / theta
+        };
+        assert!((gamma_eff(1e-8) - 5.0 / 3.0).abs() < 1e-6);
+        assert!((gamma_eff(1e+8) - 4.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn primitive_can_be_recovered() {
+        let p = Primitive(1.0, 2.0, -0.5, 0.3);
+        let q = try_to_primitive(to_conserved(&p)).unwrap();
+        assert!((q.mass_density() - p.mass_density()).abs() < 1e-8);
+        assert!((q.gamma_beta_1() - p.gamma_beta_1()).abs() < 1e-8);
+        assert!((q.gamma_beta_2() - p.gamma_beta_2()).abs() < 1e-8);
+        assert!((q.gas_pressure() - p.gas_pressure()).abs() < 1e-8);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Write;
+use ndarray::{Array, Ix2};
+use crate::mesh::{BlockIndex, GridGeometry, Mesh};
+use crate::physics::{AnyPrimitive, HydroError};
+use crate::state::State;
+use crate::traits::{Conserved, Hydrodynamics};
+
+
+
+
+/// The primitive state and scalar concentration at each probe, or None for
+/// probes which are not on the mesh
+pub type Samples = Vec<Option<(AnyPrimitive, f64)>>;
+
+
+
+
+// ============================================================================
+/**
+ * Return the zone indexes bracketing the value x in a list of zone centers,
+ * and the weight of the second one, for linear interpolation. The value is
+ * clamped to the range of zone centers.
+ */
+fn bracket(centers: &[f64], x: f64) -> (usize, usize, f64) {
+    let n = centers.len();
+
+    if n == 1 || x <= centers[0] {
+        return (0, 0, 0.0)
+    }
+    if x >= centers[n - 1] {
+        return (n - 1, n - 1, 0.0)
+    }
+    let i = centers.partition_point(|&c| c <= x) - 1;
+    (i, i + 1, (x - centers[i]) / (centers[i + 1] - centers[i]))
+}
+
+/**
+ * Return the primitive state and scalar concentration at each of the given
+ * (r, theta) points, bilinearly interpolated between the zone centers of
+ * the block which contains it, or None if the point is not on the mesh.
+ * The geometry map must contain every block of the state, at the time of
+ * the state. Only the blocks containing a probe are converted to
+ * primitive, each once. Fails if any of those conversions failed.
+ */
+pub fn sample<H, C>(
+    state: &State<C>,
+    hydro: &H,
+    mesh: &Mesh,
+    geometry: &HashMap<BlockIndex, GridGeometry>,
+    points: &[(f64, f64)]) -> Result<Samples, HydroError>
+where
+    H: Hydrodynamics<Conserved = C>,
+    C: Conserved,
+{
+    let a = mesh.expansion_factor(state.time);
+    let mut blocks: HashMap<BlockIndex, (Array<AnyPrimitive, Ix2>, Array<f64, Ix2>)> = HashMap::new();
+    let mut samples = Vec::with_capacity(points.len());
+
+    for &(r, q) in points {
+        let index = state.solution.keys().find(|&&index| {
+            let extent = mesh.subgrid_extent(index);
+            extent.inner_radius * a <= r && r < extent.outer_radius * a && extent.lower_theta <= q && q <= extent.upper_theta
+        });
+        let index = match index {
+            Some(index) => *index,
+            None => {
+                samples.push(None);
+                continue
+            }
+        };
+        let geometry = &geometry[&index];
+        let (primitive, scalar) = match blocks.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let block = &state.solution[&index];
+                let primitive = block.try_to_primitive(hydro, geometry)?.mapv(|p| hydro.any(&p));
+                let scalar = &block.scalar_mass / &block.conserved.mapv(|u| u.lab_frame_mass());
+                entry.insert((primitive, scalar))
+            }
+        };
+
+        let rc: Vec<_> = geometry.cell_centers.column(0).iter().map(|c| c.0).collect();
+        let qc: Vec<_> = geometry.cell_centers.row(0).iter().map(|c| c.1).collect();
+        let (i0, i1, wr) = bracket(&rc, r);
+        let (j0, j1, wq) = bracket(&qc, q);
+
+        let corners = [
+            ((i0, j0), (1.0 - wr) * (1.0 - wq)),
+            ((i1, j0), wr * (1.0 - wq)),
+            ((i0, j1), (1.0 - wr) * wq),
+            ((i1, j1), wr * wq),
+        ];
+        let zero = AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 0.0, gas_pressure: 0.0};
+        let p = corners.iter().fold(zero, |p, &(zone, w)| {
+            let c = &primitive[zone];
+            AnyPrimitive {
+                velocity_r:   p.velocity_r   + c.velocity_r   * w,
+                velocity_q:   p.velocity_q   + c.velocity_q   * w,
+                mass_density: p.mass_density + c.mass_density * w,
+                gas_pressure: p.gas_pressure + c.gas_pressure * w,
+            }
+        });
+        let s = corners.iter().map(|&(zone, w)| scalar[zone] * w).sum();
+        samples.push(Some((p, s)));
+    }
+    Ok(samples)
+}
+
+/**
+ * Append a line with the time and the sampled values at each probe to the
+ * probe file, writing a header line first if the file is new or empty.
+ * Probes which are not on the mesh are written as NaN.
+ */
+pub fn append(filename: &str, time: f64, samples: &[Option<(AnyPrimitive, f64)>]) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(filename)?;
+
+    if file.metadata()?.len() == 0 {
+        let mut header = vec!["time".to_string()];
+        for n in 0..samples.len() {
+            for field in &["rho", "pre", "ur", "uq", "scalar"] {
+                header.push(format!("{}_{}", field, n));
+            }
+        }
+        writeln!(file, "{}", header.join(" "))?;
+    }
+    let mut line = vec![format!("{:.12e}", time)];
+
+    for sample in samples {
+        let values = match sample {
+            Some((p, s)) => [p.mass_density, p.gas_pressure, p.velocity_r, p.velocity_q, *s],
+            None => [f64::NAN; 5],
+        };
+        line.extend(values.iter().map(|x| format!("{:.12e}", x)));
+    }
+    writeln!(file, "{}", line.join(" "))
+}
@@ -1,16 +1,37 @@
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
-use ndarray::{ArcArray, Ix1, Ix2};
-use crate::app::{self, Configuration, AnyHydro, AnyState};
-use crate::mesh::{BlockIndex, GridGeometry};
-use crate::physics::{AnyPrimitive, HydroError};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use ndarray::{s, Array, ArcArray, ArrayView, Axis, Ix1, Ix2};
+use crate::app::{self, Configuration, AnyHydro, AnyState, ProductsContents};
+use crate::io;
+use crate::mesh::{BlockIndex, CoordinateSystem, GridGeometry};
+use crate::physics::{taub_mathews, AnyPrimitive, EquationOfState, HydroError, LIGHT_SPEED};
 use crate::products;
+use crate::reductions::Reductions;
 use crate::state::{BlockState, State};
+use crate::tracers::Tracer;
 use crate::traits::{Conserved, Hydrodynamics};
 
 
 
 
+/// The smallest fractional pressure difference between a converging zone and
+/// any of its neighbors for that zone to be flagged as shocked
+pub static SHOCK_PRESSURE_JUMP: f64 = 0.5;
+
+thread_local! {
+	/// The blocks kept while products are being read by
+	/// [`Products::read_selected`] on this thread, or None to keep all of
+	/// them. The CBOR reader does not support stateful deserialization, so
+	/// the selection is passed to the block map deserializer this way.
+	static SELECTED_BLOCKS: RefCell<Option<HashSet<BlockIndex>>> = RefCell::new(None);
+}
+
+
+
+
 /**
  * Useful per-block data for post-processing and plotting
  */
@@ -20,6 +41,21 @@ pub struct BlockProducts {
 	pub polar_vertices: ArcArray<f64, Ix1>,
 	pub primitive: ArcArray<AnyPrimitive, Ix2>,
 	pub scalar: ArcArray<f64, Ix2>,	
+
+	/// True in zones identified as shocked by [`shock_flag`]
+	#[serde(default)]
+	pub shock_flag: ArcArray<bool, Ix2>,
+
+	/// The volume-integrated conserved quantities (lab-frame mass, radial
+	/// momentum, polar momentum, energy) in each zone, if requested by
+	/// [`ProductsContents::conserved`]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub conserved: Option<ArcArray<[f64; 4], Ix2>>,
+
+	/// The volume of each zone, if requested by
+	/// [`ProductsContents::cell_volumes`]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cell_volumes: Option<ArcArray<f64, Ix2>>,
 }
 
 
@@ -31,9 +67,394 @@ pub struct BlockProducts {
 #[derive(Serialize, Deserialize)]
 pub struct Products {
 	pub time: f64,
+	#[serde(serialize_with = "crate::io::serialize_sorted", deserialize_with = "deserialize_selected_blocks")]
 	pub blocks: HashMap<BlockIndex, BlockProducts>,
 	pub config: Configuration,
 	pub version: String,
+
+	/// Global totals computed from the solution state
+	#[serde(default)]
+	pub reductions: Reductions,
+
+	/// Options in effect when these products were generated. This is absent
+	/// from products files written by older versions of the code.
+	#[serde(default)]
+	pub provenance: Option<Provenance>,
+
+	/// Lagrangian tracer particles, with their histories up to the time of
+	/// these products. This is absent from products files written by older
+	/// versions of the code.
+	#[serde(default)]
+	pub tracers: Vec<Tracer>,
+}
+
+
+
+
+/**
+ * A record of how a products file was generated, so that archives of
+ * products from different code versions remain interpretable. The
+ * expressions for custom reductions are recorded in the configuration.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Provenance {
+
+	/// Names of the per-zone fields stored in each block
+	pub fields: Vec<String>,
+
+	/// Meaning of the velocity components: `four_velocity` (relativistic
+	/// hydrodynamics) or `cm_per_s` (Newtonian hydrodynamics)
+	pub velocity: String,
+
+	/// Threshold used by the shock detector: [`SHOCK_PRESSURE_JUMP`]
+	pub shock_pressure_jump: f64,
+
+	/// Velocity (in units of c) of the observer frame along the polar axis,
+	/// if the zone data were transformed by [`Products::boosted`]
+	pub boost_beta: Option<f64>,
+
+	/// Time of the simulated products from which these were extrapolated,
+	/// if they were made by [`Products::extrapolated`]
+	#[serde(default)]
+	pub extrapolated_from: Option<f64>,
+
+	/// Number of uniform polar bins per block, if the zone data were
+	/// remapped by [`Products::polar_regridded`]
+	#[serde(default)]
+	pub polar_bins: Option<usize>,
+
+	/// Number of radial bins per column, if the blocks were merged and
+	/// rebinned by [`Products::radial_rebinned`]
+	#[serde(default)]
+	pub radial_bins: Option<usize>,
+
+	/// Factor by which the zones were coarsened in each direction, if the
+	/// zone data were block-averaged by [`Products::downsampled`]
+	#[serde(default)]
+	pub downsample: Option<usize>,
+}
+
+
+
+
+/**
+ * Kinematic and thermodynamic quantities derived from the primitive state of
+ * a zone, using the equation of state of the run (see
+ * [`DerivedFields::from_primitive`]). The specific quantities are in cgs
+ * units (erg / g) for both Newtonian and relativistic hydro, and exclude the
+ * rest mass energy.
+ */
+#[derive(Clone, Copy)]
+pub struct DerivedFields {
+
+	/// The Lorentz factor (1 for Newtonian hydro)
+	pub lorentz_factor: f64,
+
+	/// The specific enthalpy e + p / rho
+	pub specific_enthalpy: f64,
+
+	/// The temperature, as kT / m = p / rho
+	pub temperature: f64,
+
+	/// The specific internal energy e
+	pub specific_internal_energy: f64,
+}
+
+
+
+
+/**
+ * Cumulative distributions of the mass of the material in a products file
+ * over four-velocity, computed by [`Products::mass_distributions`]. Masses
+ * are lab-frame masses in g.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MassDistributions {
+
+	/// Centers of the uniform polar angle bins
+	pub theta: Vec<f64>,
+
+	/// The four-velocities (gamma-beta) at which the mass is measured
+	pub gamma_beta: Vec<f64>,
+
+	/// The velocity (cm/s) corresponding to each four-velocity
+	pub velocity: Vec<f64>,
+
+	/// The mass of the material faster than each four-velocity, in each
+	/// polar angle bin, indexed as [bin][four-velocity]
+	pub mass_above: Vec<Vec<f64>>,
+
+	/// The mass of the material faster than each four-velocity, in all
+	/// polar angle bins
+	pub total_mass_above: Vec<f64>,
+}
+
+
+
+
+/**
+ * The zone data of all the blocks in a products file, concatenated into
+ * global arrays by [`Products::stitched`]. The primitive, scalar, and shock
+ * flag arrays have shape (Nr, Nq), and the vertex arrays have Nr + 1 and
+ * Nq + 1 elements.
+ */
+#[derive(Clone)]
+pub struct StitchedProducts {
+	pub radial_vertices: Array<f64, Ix1>,
+	pub polar_vertices: Array<f64, Ix1>,
+	pub primitive: Array<AnyPrimitive, Ix2>,
+	pub scalar: Array<f64, Ix2>,
+	pub shock_flag: Array<bool, Ix2>,
+}
+
+
+
+
+/**
+ * Distributions of the energy of the material in a products file, computed
+ * by [`Products::energy_distributions`]. Energies are in erg, and exclude
+ * the rest mass energy.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnergyDistributions {
+
+	/// Centers of the uniform polar angle bins
+	pub theta: Vec<f64>,
+
+	/// The isotropic-equivalent energy, E_iso = 4 pi dE / dOmega, of the
+	/// material (kinetic and thermal) in each polar angle bin
+	pub isotropic_energy: Vec<f64>,
+
+	/// The four-velocities (gamma-beta) at which the kinetic energy is
+	/// measured
+	pub gamma_beta: Vec<f64>,
+
+	/// The kinetic energy of the material faster than each four-velocity
+	pub kinetic_energy_above: Vec<f64>,
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return an array which is true in zones where the velocity divergence is
+ * negative, and the gas pressure differs from that of at least one neighboring
+ * zone by more than the fraction [`SHOCK_PRESSURE_JUMP`]. Finite differences
+ * are one-sided at the block edges, so no data from neighboring blocks is
+ * required.
+ */
+pub fn shock_flag(primitive: ArrayView<AnyPrimitive, Ix2>, geometry: &GridGeometry) -> Array<bool, Ix2> {
+	let (nr, nq) = primitive.dim();
+
+	Array::from_shape_fn((nr, nq), |(i, j)| {
+		let (r, q) = geometry.cell_centers[(i, j)];
+		let (il, ir) = (i.saturating_sub(1), (i + 1).min(nr - 1));
+		let (jl, jr) = (j.saturating_sub(1), (j + 1).min(nq - 1));
+
+		let div_r = if ir > il {
+			let (rl, rr) = (geometry.cell_centers[(il, j)].0, geometry.cell_centers[(ir, j)].0);
+			let (vl, vr) = (primitive[(il, j)].velocity_r, primitive[(ir, j)].velocity_r);
+			(rr * rr * vr - rl * rl * vl) / (r * r * (rr - rl))
+		} else {
+			0.0
+		};
+
+		let div_q = if jr > jl {
+			let (ql, qr) = (geometry.cell_centers[(i, jl)].1, geometry.cell_centers[(i, jr)].1);
+			let (vl, vr) = (primitive[(i, jl)].velocity_q, primitive[(i, jr)].velocity_q);
+			(qr.sin() * vr - ql.sin() * vl) / (r * q.sin() * (qr - ql))
+		} else {
+			0.0
+		};
+
+		let p0 = primitive[(i, j)].gas_pressure;
+		let jump = [(il, j), (ir, j), (i, jl), (i, jr)]
+			.iter()
+			.map(|&n| primitive[n].gas_pressure)
+			.map(|p1| (p1 - p0).abs() / p1.min(p0))
+			.fold(0.0, f64::max);
+
+		div_r + div_q < 0.0 && jump > SHOCK_PRESSURE_JUMP
+	})
+}
+
+
+
+
+// ============================================================================
+/**
+ * Deserialize a map of block products, keeping only the blocks selected by
+ * [`Products::read_selected`]. The others are skipped over without
+ * allocating their arrays.
+ */
+fn deserialize_selected_blocks<'de, D>(deserializer: D) -> Result<HashMap<BlockIndex, BlockProducts>, D::Error>
+where
+	D: Deserializer<'de> {
+
+	struct BlocksVisitor;
+
+	impl<'de> Visitor<'de> for BlocksVisitor {
+		type Value = HashMap<BlockIndex, BlockProducts>;
+
+		fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+			write!(formatter, "a map of block products")
+		}
+
+		fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+			let mut blocks = HashMap::new();
+
+			while let Some(index) = map.next_key::<BlockIndex>()? {
+				if SELECTED_BLOCKS.with(|selected| selected.borrow().as_ref().map_or(true, |selected| selected.contains(&index))) {
+					blocks.insert(index, map.next_value()?);
+				} else {
+					map.next_value::<IgnoredAny>()?;
+				}
+			}
+			Ok(blocks)
+		}
+	}
+	deserializer.deserialize_map(BlocksVisitor)
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the primitive state seen by an observer moving along the polar axis
+ * (z) with velocity beta (in units of c), at the polar angle q. If
+ * `relativistic` is true, the velocity components are four-velocities which
+ * are Lorentz-boosted. Otherwise they are velocities in cm/s, which are
+ * Galilean-shifted. The comoving density and gas pressure are unchanged.
+ */
+pub fn boost_primitive(p: &AnyPrimitive, q: f64, beta: f64, relativistic: bool) -> AnyPrimitive {
+	let (sq, cq) = (q.sin(), q.cos());
+	let v_cyl = p.velocity_r * sq + p.velocity_q * cq;
+	let v_z = p.velocity_r * cq - p.velocity_q * sq;
+
+	let v_z = if relativistic {
+		let u0 = (1.0 + p.velocity_r.powi(2) + p.velocity_q.powi(2)).sqrt();
+		let gamma = 1.0 / (1.0 - beta * beta).sqrt();
+		gamma * (v_z - beta * u0)
+	} else {
+		v_z - beta * LIGHT_SPEED
+	};
+
+	AnyPrimitive{
+		velocity_r: v_cyl * sq + v_z * cq,
+		velocity_q: v_cyl * cq - v_z * sq,
+		mass_density: p.mass_density,
+		gas_pressure: p.gas_pressure,
+	}
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the measure of the overlap between the polar intervals a and b: the
+ * solid angle per radian of azimuth, or the length in z for a cylindrical
+ * mesh.
+ */
+fn polar_overlap(a: (f64, f64), b: (f64, f64), cylindrical: bool) -> f64 {
+	let (lo, hi) = (a.0.max(b.0), a.1.min(b.1));
+
+	if hi <= lo {
+		0.0
+	} else if cylindrical {
+		hi - lo
+	} else {
+		lo.cos() - hi.cos()
+	}
+}
+
+
+
+
+/**
+ * Concatenate the vertices of adjacent blocks, keeping only one copy of the
+ * vertex shared by each pair of neighbors.
+ */
+fn stitch_vertices(vertices: &[ArrayView<f64, Ix1>]) -> Array<f64, Ix1> {
+	let last = vertices[vertices.len() - 1];
+	let mut arrays: Vec<_> = vertices.iter().map(|v| v.slice(s![..-1])).collect();
+	arrays.push(last.slice(s![-1..]));
+	ndarray::concatenate(Axis(0), &arrays).unwrap()
+}
+
+
+
+
+/**
+ * Concatenate a 2D grid of block arrays, given as a list of rows of blocks at
+ * increasing radius, each listing its blocks at increasing polar angle.
+ */
+fn stitch_zones<T: Clone>(rows: &[Vec<ArrayView<T, Ix2>>]) -> anyhow::Result<Array<T, Ix2>> {
+	let rows = rows
+		.iter()
+		.map(|row| ndarray::concatenate(Axis(1), row))
+		.collect::<Result<Vec<_>, _>>()?;
+	let views: Vec<_> = rows.iter().map(|row| row.view()).collect();
+	Ok(ndarray::concatenate(Axis(0), &views)?)
+}
+
+
+
+
+/**
+ * Return the sum of the given primitive states, each multiplied by its weight.
+ */
+fn weighted_sum<'a>(terms: impl Iterator<Item = (f64, &'a AnyPrimitive)>) -> AnyPrimitive {
+	terms.fold(AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 0.0, gas_pressure: 0.0}, |a, (w, p)| {
+		AnyPrimitive{
+			velocity_r: a.velocity_r + w * p.velocity_r,
+			velocity_q: a.velocity_q + w * p.velocity_q,
+			mass_density: a.mass_density + w * p.mass_density,
+			gas_pressure: a.gas_pressure + w * p.gas_pressure,
+		}
+	})
+}
+
+
+
+
+// ============================================================================
+impl DerivedFields {
+
+	/**
+	 * Compute the derived fields from a primitive state, with the equation
+	 * of state of the given hydrodynamics system.
+	 */
+	pub fn from_primitive(p: &AnyPrimitive, hydro: &AnyHydro) -> Self {
+		match hydro {
+			AnyHydro::Newtonian(hydro) => {
+				let temperature = p.gas_pressure / p.mass_density;
+				let e = temperature / (hydro.gamma_law_index - 1.0);
+				Self {
+					lorentz_factor: 1.0,
+					specific_enthalpy: e + temperature,
+					temperature,
+					specific_internal_energy: e,
+				}
+			}
+			AnyHydro::Relativistic(hydro) => {
+				let theta = p.gas_pressure / p.mass_density;
+				let h = match hydro.equation_of_state {
+					EquationOfState::GammaLaw    => 1.0 + hydro.gamma_law_index / (hydro.gamma_law_index - 1.0) * theta,
+					EquationOfState::TaubMathews => taub_mathews::specific_enthalpy(theta),
+				};
+				let c2 = LIGHT_SPEED * LIGHT_SPEED;
+				Self {
+					lorentz_factor: (1.0 + p.velocity_r.powi(2) + p.velocity_q.powi(2)).sqrt(),
+					specific_enthalpy: (h - 1.0) * c2,
+					temperature: theta * c2,
+					specific_internal_energy: (h - 1.0 - theta) * c2,
+				}
+			}
+		}
+	}
 }
 
 
@@ -41,7 +462,7 @@ pub struct Products {
 
 // ============================================================================
 impl BlockProducts {
-	pub fn try_from_block_state<H, C>(state: &BlockState<C>, hydro: &H, geometry: &GridGeometry) -> Result::<Self, HydroError>
+	pub fn try_from_block_state<H, C>(state: &BlockState<C>, hydro: &H, geometry: &GridGeometry, contents: &ProductsContents) -> Result::<Self, HydroError>
 	where
 		H: Hydrodynamics<Conserved = C>,
 		C: Conserved {
@@ -56,14 +477,206 @@ impl BlockProducts {
 		let primitive = primitive.map(|p| p.to_shared())
 								 .unwrap()
 								 .mapv(|p| hydro.any(&p));
+		let shock_flag = shock_flag(primitive.view(), geometry);
 
 		Ok(BlockProducts{
 			radial_vertices: geometry.radial_vertices.clone(),
 			polar_vertices: geometry.polar_vertices.clone(),
 			primitive: primitive.to_shared(),
 			scalar: scalar.to_shared(),
+			shock_flag: shock_flag.to_shared(),
+			conserved: if contents.conserved {
+				Some(state.conserved.mapv(|u| [u.lab_frame_mass(), u.radial_momentum(), u.polar_momentum(), u.energy()]).to_shared())
+			} else {
+				None
+			},
+			cell_volumes: if contents.cell_volumes {
+				Some(geometry.cell_volumes.clone())
+			} else {
+				None
+			},
 		})
 	}
+
+	/**
+	 * Return an array of a derived field, selected by the function f, in
+	 * each zone of this block. The derived fields are computed on access
+	 * with the equation of state of the given hydrodynamics system, which
+	 * is that of the products configuration.
+	 */
+	pub fn map_derived<F>(&self, hydro: &AnyHydro, f: F) -> Array<f64, Ix2>
+	where
+		F: Fn(&DerivedFields) -> f64 {
+		self.primitive.map(|p| f(&DerivedFields::from_primitive(p, hydro)))
+	}
+
+	/**
+	 * Return a copy of this block with the primitive data transformed by
+	 * [`boost_primitive`], using the polar angle at the zone centers. The
+	 * conserved quantities, which are those of the lab frame, are dropped.
+	 */
+	pub fn boosted(&self, beta: f64, relativistic: bool) -> Self {
+		let qv = &self.polar_vertices;
+		let primitive = Array::from_shape_fn(self.primitive.dim(), |(i, j)| {
+			boost_primitive(&self.primitive[(i, j)], 0.5 * (qv[j] + qv[j + 1]), beta, relativistic)
+		});
+		Self {
+			primitive: primitive.to_shared(),
+			conserved: None,
+			..self.clone()
+		}
+	}
+
+	/**
+	 * Return a copy of this block remapped onto num_bins uniform polar bins
+	 * spanning its polar extent. Each field in a bin is the average over the
+	 * zones it overlaps, weighted by the overlapping solid angle, so that
+	 * the total mass in each radial shell is unchanged. A bin is flagged as
+	 * shocked if any zone it overlaps is shocked. The conserved quantities
+	 * and cell volumes are dropped.
+	 */
+	pub fn polar_regridded(&self, num_bins: usize, cylindrical: bool) -> Self {
+		let qv = &self.polar_vertices;
+		let nq = qv.len() - 1;
+		let new_qv = Array::linspace(qv[0], qv[nq], num_bins + 1);
+		let weights = Array::from_shape_fn((num_bins, nq), |(k, j)| {
+			let bin = (new_qv[k], new_qv[k + 1]);
+			polar_overlap(bin, (qv[j], qv[j + 1]), cylindrical) / polar_overlap(bin, bin, cylindrical)
+		});
+		let nr = self.primitive.dim().0;
+
+		let primitive = Array::from_shape_fn((nr, num_bins), |(i, k)| {
+			weighted_sum((0..nq).map(|j| (weights[(k, j)], &self.primitive[(i, j)])))
+		});
+		let scalar = Array::from_shape_fn((nr, num_bins), |(i, k)| {
+			(0..nq).map(|j| weights[(k, j)] * self.scalar[(i, j)]).sum()
+		});
+		let shock_flag = Array::from_shape_fn((nr, num_bins), |(i, k)| {
+			(0..nq).any(|j| weights[(k, j)] > 0.0 && self.shock_flag.get((i, j)).copied().unwrap_or(false))
+		});
+
+		Self {
+			radial_vertices: self.radial_vertices.clone(),
+			polar_vertices: new_qv.to_shared(),
+			primitive: primitive.to_shared(),
+			scalar: scalar.to_shared(),
+			shock_flag: shock_flag.to_shared(),
+			conserved: None,
+			cell_volumes: None,
+		}
+	}
+
+	/**
+	 * Return a copy of this block coarsened by the given factor in each
+	 * direction. Each coarse zone covers up to factor x factor zones (fewer
+	 * at the outer edges, if the block size is not a multiple of the
+	 * factor). The primitive fields and scalar are averaged over the zones
+	 * it covers, weighted by their volume, and it is flagged as shocked if
+	 * any of them is shocked. The conserved quantities and cell volumes,
+	 * which are volume-integrated, are summed.
+	 */
+	pub fn downsampled(&self, factor: usize, cylindrical: bool) -> Self {
+		let (rv, qv) = (&self.radial_vertices, &self.polar_vertices);
+		let (nr, nq) = self.primitive.dim();
+		let (mr, mq) = ((nr + factor - 1) / factor, (nq + factor - 1) / factor);
+		let coarse = |v: &ArcArray<f64, Ix1>, n: usize| {
+			Array::from_shape_fn(n + 1, |k| v[(k * factor).min(v.len() - 1)]).to_shared()
+		};
+		let zones = |(i, j): (usize, usize)| {
+			(i * factor..((i + 1) * factor).min(nr)).flat_map(move |a| (j * factor..((j + 1) * factor).min(nq)).map(move |b| (a, b)))
+		};
+		let volume = |(i, j): (usize, usize)| {
+			if cylindrical {
+				(rv[i + 1].powi(2) - rv[i].powi(2)) * (qv[j + 1] - qv[j])
+			} else {
+				(rv[i + 1].powi(3) - rv[i].powi(3)) * (qv[j].cos() - qv[j + 1].cos())
+			}
+		};
+		let weights = Array::from_shape_fn((mr, mq), |index| {
+			let total: f64 = zones(index).map(volume).sum();
+			zones(index).map(|n| (volume(n) / total, n)).collect::<Vec<_>>()
+		});
+
+		let primitive = weights.map(|w| weighted_sum(w.iter().map(|&(w, n)| (w, &self.primitive[n]))));
+		let scalar = weights.map(|w| w.iter().map(|&(w, n)| w * self.scalar[n]).sum::<f64>());
+		let shock_flag = weights.map(|w| w.iter().any(|&(_, n)| self.shock_flag.get(n).copied().unwrap_or(false)));
+		let conserved = self.conserved.as_ref().map(|u| {
+			Array::from_shape_fn((mr, mq), |index| {
+				zones(index).fold([0.0; 4], |a, n| [a[0] + u[n][0], a[1] + u[n][1], a[2] + u[n][2], a[3] + u[n][3]])
+			}).to_shared()
+		});
+		let cell_volumes = self.cell_volumes.as_ref().map(|dv| {
+			Array::from_shape_fn((mr, mq), |index| zones(index).map(|n| dv[n]).sum()).to_shared()
+		});
+
+		Self {
+			radial_vertices: coarse(rv, mr),
+			polar_vertices: coarse(qv, mq),
+			primitive: primitive.to_shared(),
+			scalar: scalar.to_shared(),
+			shock_flag: shock_flag.to_shared(),
+			conserved,
+			cell_volumes,
+		}
+	}
+
+	/**
+	 * Return a copy of this block, freely expanded by the factor a: the
+	 * vertices are scaled by a (including the z vertices of a cylindrical
+	 * mesh), the velocities are unchanged, the density falls as a^-3, and
+	 * the pressure falls adiabatically with the index gamma. The cell
+	 * volumes are scaled by a^3, and the conserved quantities, which are
+	 * not modeled, are dropped.
+	 */
+	pub fn extrapolated(&self, a: f64, gamma: f64, cylindrical: bool) -> Self {
+		let primitive = self.primitive.mapv(|p| AnyPrimitive{
+			mass_density: p.mass_density * a.powi(-3),
+			gas_pressure: p.gas_pressure * a.powf(-3.0 * gamma),
+			..p
+		});
+		Self {
+			radial_vertices: self.radial_vertices.mapv(|r| r * a).to_shared(),
+			polar_vertices: if cylindrical { self.polar_vertices.mapv(|z| z * a).to_shared() } else { self.polar_vertices.clone() },
+			primitive: primitive.to_shared(),
+			conserved: None,
+			cell_volumes: self.cell_volumes.as_ref().map(|dv| dv.mapv(|dv| dv * a * a * a).to_shared()),
+			..self.clone()
+		}
+	}
+}
+
+
+
+
+// ============================================================================
+impl Provenance {
+
+	/**
+	 * Return the provenance of products generated in the lab frame, with the
+	 * given hydrodynamics configuration and optional contents.
+	 */
+	pub fn new(hydro: &AnyHydro, contents: &ProductsContents) -> Self {
+		let velocity = match hydro {
+			AnyHydro::Newtonian(_)    => "cm_per_s",
+			AnyHydro::Relativistic(_) => "four_velocity",
+		};
+		let optional = [("conserved", contents.conserved), ("cell_volumes", contents.cell_volumes)];
+
+		Self{
+			fields: ["mass_density", "gas_pressure", "velocity_r", "velocity_q", "scalar", "shock_flag"]
+				.iter()
+				.chain(optional.iter().filter(|(_, included)| *included).map(|(name, _)| name))
+				.map(|s| s.to_string())
+				.collect(),
+			velocity: velocity.to_string(),
+			shock_pressure_jump: SHOCK_PRESSURE_JUMP,
+			boost_beta: None,
+			extrapolated_from: None,
+			polar_bins: None,
+			radial_bins: None,
+			downsample: None,
+		}
+	}
 }
 
 
@@ -76,20 +689,448 @@ impl Products {
 		H: Hydrodynamics<Conserved = C>,
 		C: Conserved {
 
-		let geometry = config.mesh.grid_blocks_geometry(state.time);
+		let geometry = state.geometry(&config.mesh);
 		let mut blocks = HashMap::new();
 
 		for (index, block_state) in &state.solution {
-			blocks.insert(*index, BlockProducts::try_from_block_state(block_state, hydro, &geometry[index])?);
+			let rv = &geometry[index].radial_vertices;
+
+			if let Some((r0, r1)) = config.control.products_radial_range {
+				if rv[rv.len() - 1] <= r0 || rv[0] >= r1 {
+					continue
+				}
+			}
+			blocks.insert(*index, BlockProducts::try_from_block_state(block_state, hydro, &geometry[index], &config.control.products_contents)?);
 		}
 
+		let reductions = Reductions::from_state(state, &geometry)
+			.try_with_custom(state, hydro, &geometry, &config.control.custom_reductions)?
+			.try_with_opening_angle(state, hydro, &geometry, &config.control.opening_angle)?
+			.try_with_isotropic_energy(state, hydro, &geometry, &config.control.isotropic_energy_cuts)?;
+
 		Ok(Products{
 			time: state.time,
 			blocks: blocks,
 			config: config.clone(),
 			version: app::VERSION_AND_BUILD.to_string(),
+			reductions: reductions,
+			provenance: Some(Provenance::new(&config.hydro, &config.control.products_contents)),
+			tracers: state.tracers.clone(),
+		})
+	}
+
+	/**
+	 * Return a copy of these products with the velocity data transformed to
+	 * the frame of an observer moving along the polar axis with velocity
+	 * beta (in units of c), for comparison with boosted-frame solutions. Only
+	 * the zone data is transformed: the zone coordinates and the time are
+	 * those of the original frame, and the reductions are left unchanged.
+	 */
+	pub fn boosted(&self, beta: f64) -> Self {
+		let relativistic = matches!(self.config.hydro, AnyHydro::Relativistic(_));
+
+		Products{
+			time: self.time,
+			blocks: self.blocks.iter().map(|(&index, block)| (index, block.boosted(beta, relativistic))).collect(),
+			config: self.config.clone(),
+			version: self.version.clone(),
+			reductions: self.reductions.clone(),
+			provenance: self.provenance.clone().map(|provenance| Provenance{
+				boost_beta: Some(beta),
+				..provenance
+			}),
+			tracers: self.tracers.clone(),
+		}
+	}
+
+	/**
+	 * Return synthetic products at a later time, extrapolated from these
+	 * assuming free (homologous) expansion from the origin, for radiative
+	 * post-processing beyond the end of a run. The adiabatic index is that of
+	 * the gamma-law equation of state, or 5/3 for the Taub-Mathews equation
+	 * of state, whose ejecta are cold at late times. The reductions are left
+	 * unchanged.
+	 */
+	pub fn extrapolated(&self, time: f64) -> Self {
+		let a = time / self.time;
+		let cylindrical = self.config.mesh.geometry == CoordinateSystem::Cylindrical;
+		let gamma = match &self.config.hydro {
+			AnyHydro::Newtonian(hydro) => hydro.gamma_law_index,
+			AnyHydro::Relativistic(hydro) => match hydro.equation_of_state {
+				EquationOfState::GammaLaw    => hydro.gamma_law_index,
+				EquationOfState::TaubMathews => 5.0 / 3.0,
+			},
+		};
+
+		Products{
+			time,
+			blocks: self.blocks.iter().map(|(&index, block)| (index, block.extrapolated(a, gamma, cylindrical))).collect(),
+			config: self.config.clone(),
+			version: self.version.clone(),
+			reductions: self.reductions.clone(),
+			provenance: self.provenance.clone().map(|provenance| Provenance{
+				extrapolated_from: Some(self.time),
+				..provenance
+			}),
+			tracers: self.tracers.clone(),
+		}
+	}
+
+	/**
+	 * Return a copy of these products with each block remapped onto num_bins
+	 * uniform polar bins (see [`BlockProducts::polar_regridded`]). On a mesh
+	 * whose blocks all span theta_min to theta_max, the bins are the same in
+	 * every block, so runs with different num_polar_zones can be compared
+	 * zone by zone.
+	 */
+	pub fn polar_regridded(&self, num_bins: usize) -> Self {
+		let cylindrical = self.config.mesh.geometry == CoordinateSystem::Cylindrical;
+
+		Products{
+			time: self.time,
+			blocks: self.blocks.iter().map(|(&index, block)| (index, block.polar_regridded(num_bins, cylindrical))).collect(),
+			config: self.config.clone(),
+			version: self.version.clone(),
+			reductions: self.reductions.clone(),
+			provenance: self.provenance.clone().map(|provenance| Provenance{
+				polar_bins: Some(num_bins),
+				..provenance
+			}),
+			tracers: self.tracers.clone(),
+		}
+	}
+
+	/**
+	 * Return these products with each block coarsened by the given factor in
+	 * each direction (see [`BlockProducts::downsampled`]), for lightweight
+	 * snapshots of high-resolution runs. A factor of 1 leaves the products
+	 * unchanged. The reductions, which were computed at full resolution, are
+	 * left unchanged.
+	 */
+	pub fn downsampled(self, factor: usize) -> Self {
+		if factor <= 1 {
+			return self
+		}
+		let cylindrical = self.config.mesh.geometry == CoordinateSystem::Cylindrical;
+
+		Products{
+			blocks: self.blocks.iter().map(|(&index, block)| (index, block.downsampled(factor, cylindrical))).collect(),
+			provenance: self.provenance.clone().map(|provenance| Provenance{
+				downsample: Some(factor),
+				..provenance
+			}),
+			..self
+		}
+	}
+
+	/**
+	 * Return a copy of these products in which the blocks of each column
+	 * (radial ray) are merged into a single block, at radial index 0, whose
+	 * radial zones are the given bin vertices. Each field in a bin is the
+	 * average over the zones it overlaps, weighted by the overlapping
+	 * volume, so that the mass in each bin is conserved and the block
+	 * structure leaves no trace. Bins which overlap no zones have NaN
+	 * values. The blocks in a column must have the same polar zones, which
+	 * can be ensured by first calling [`Products::polar_regridded`].
+	 */
+	pub fn radial_rebinned(&self, bins: &[f64]) -> anyhow::Result<Self> {
+		if bins.len() < 2 || bins.windows(2).any(|w| w[1] <= w[0]) {
+			anyhow::bail!("radial bins must be at least two increasing vertices")
+		}
+		let n = if self.config.mesh.geometry == CoordinateSystem::Cylindrical { 2 } else { 3 };
+		let mut columns: BTreeMap<usize, Vec<BlockIndex>> = BTreeMap::new();
+
+		for &index in self.blocks.keys() {
+			columns.entry(index.1).or_default().push(index);
+		}
+		let mut blocks = HashMap::new();
+
+		for (j, mut column) in columns {
+			column.sort_unstable();
+			let qv = self.blocks[&column[0]].polar_vertices.clone();
+
+			if column.iter().any(|index| self.blocks[index].polar_vertices != qv) {
+				anyhow::bail!("the blocks in a column have different polar zones (regrid them to uniform polar bins first)")
+			}
+			let zones: Vec<_> = column
+				.iter()
+				.flat_map(|index| {
+					let block = &self.blocks[index];
+					let rv = &block.radial_vertices;
+					(0..rv.len() - 1).map(move |i| (rv[i], rv[i + 1], block, i))
+				})
+				.collect();
+
+			let weights: Vec<Vec<_>> = bins.windows(2).map(|bin| {
+				let overlaps: Vec<_> = zones
+					.iter()
+					.filter_map(|&(r0, r1, block, i)| {
+						let (lo, hi) = (r0.max(bin[0]), r1.min(bin[1]));
+						if hi > lo { Some((hi.powi(n) - lo.powi(n), block, i)) } else { None }
+					})
+					.collect();
+				let total: f64 = overlaps.iter().map(|(w, ..)| w).sum();
+				overlaps.into_iter().map(|(w, block, i)| (w / total, block, i)).collect()
+			}).collect();
+
+			let (nb, nq) = (bins.len() - 1, qv.len() - 1);
+
+			let primitive = Array::from_shape_fn((nb, nq), |(k, j)| {
+				if weights[k].is_empty() {
+					AnyPrimitive{velocity_r: f64::NAN, velocity_q: f64::NAN, mass_density: f64::NAN, gas_pressure: f64::NAN}
+				} else {
+					weighted_sum(weights[k].iter().map(|&(w, block, i)| (w, &block.primitive[(i, j)])))
+				}
+			});
+			let scalar = Array::from_shape_fn((nb, nq), |(k, j)| {
+				if weights[k].is_empty() {
+					f64::NAN
+				} else {
+					weights[k].iter().map(|&(w, block, i)| w * block.scalar[(i, j)]).sum()
+				}
+			});
+			let shock_flag = Array::from_shape_fn((nb, nq), |(k, j)| {
+				weights[k].iter().any(|&(_, block, i)| block.shock_flag.get((i, j)).copied().unwrap_or(false))
+			});
+
+			blocks.insert((0, j), BlockProducts{
+				radial_vertices: Array::from(bins.to_vec()).to_shared(),
+				polar_vertices: qv,
+				primitive: primitive.to_shared(),
+				scalar: scalar.to_shared(),
+				shock_flag: shock_flag.to_shared(),
+				conserved: None,
+				cell_volumes: None,
+			});
+		}
+
+		Ok(Products{
+			time: self.time,
+			blocks,
+			config: self.config.clone(),
+			version: self.version.clone(),
+			reductions: self.reductions.clone(),
+			provenance: self.provenance.clone().map(|provenance| Provenance{
+				radial_bins: Some(bins.len() - 1),
+				..provenance
+			}),
+			tracers: self.tracers.clone(),
+		})
+	}
+
+	/**
+	 * Concatenate the blocks into global arrays of vertices and zone data
+	 * (see [`StitchedProducts`]). The blocks must tile a rectangular grid:
+	 * each radial block index must have a block at each polar block index,
+	 * and the blocks at a given radial (polar) index must have the same
+	 * radial (polar) vertices. That is the case for products of any run,
+	 * including those restricted by `products_radial_range`.
+	 */
+	pub fn stitched(&self) -> anyhow::Result<StitchedProducts> {
+		let is: BTreeSet<_> = self.blocks.keys().map(|index| index.0).collect();
+		let js: BTreeSet<_> = self.blocks.keys().map(|index| index.1).collect();
+
+		if is.is_empty() {
+			anyhow::bail!("the products contain no blocks")
+		}
+		if is.len() * js.len() != self.blocks.len() {
+			anyhow::bail!("the blocks do not tile a rectangular grid")
+		}
+		let (i0, j0) = (*is.iter().next().unwrap(), *js.iter().next().unwrap());
+
+		for (&(i, j), block) in &self.blocks {
+			if block.radial_vertices != self.blocks[&(i, j0)].radial_vertices {
+				anyhow::bail!("the blocks at radial index {} have different radial vertices", i)
+			}
+			if block.polar_vertices != self.blocks[&(i0, j)].polar_vertices {
+				anyhow::bail!("the blocks at polar index {} have different polar vertices", j)
+			}
+		}
+		let rows: Vec<Vec<_>> = is
+			.iter()
+			.map(|&i| js.iter().map(|&j| &self.blocks[&(i, j)]).collect())
+			.collect();
+		let primitive = stitch_zones(&rows.iter().map(|row| row.iter().map(|b| b.primitive.view()).collect()).collect::<Vec<Vec<_>>>())?;
+		let scalar = stitch_zones(&rows.iter().map(|row| row.iter().map(|b| b.scalar.view()).collect()).collect::<Vec<Vec<_>>>())?;
+
+		// Products files written by older versions of the code have no shock
+		// flags, so they are all false.
+		let shock_flag = if rows.iter().flatten().all(|b| b.shock_flag.dim() == b.primitive.dim()) {
+			stitch_zones(&rows.iter().map(|row| row.iter().map(|b| b.shock_flag.view()).collect()).collect::<Vec<Vec<_>>>())?
+		} else {
+			Array::from_elem(primitive.dim(), false)
+		};
+
+		Ok(StitchedProducts{
+			radial_vertices: stitch_vertices(&rows.iter().map(|row| row[0].radial_vertices.view()).collect::<Vec<_>>()),
+			polar_vertices: stitch_vertices(&rows[0].iter().map(|b| b.polar_vertices.view()).collect::<Vec<_>>()),
+			primitive,
+			scalar,
+			shock_flag,
+		})
+	}
+
+	/**
+	 * Compute the isotropic-equivalent energy in num_theta uniform polar
+	 * angle bins, and the kinetic energy of the material faster than each of
+	 * the given four-velocities. The zone energies are integrated over the
+	 * exact zone volumes, and each zone is assigned to the polar bin
+	 * containing its center. In Newtonian runs, the four-velocity is v / c.
+	 * Fails on a cylindrical mesh.
+	 */
+	pub fn energy_distributions(&self, num_theta: usize, gamma_beta: &[f64]) -> anyhow::Result<EnergyDistributions> {
+		if self.config.mesh.geometry == CoordinateSystem::Cylindrical {
+			anyhow::bail!("energy distributions are only available on a spherical mesh")
+		}
+		if num_theta == 0 {
+			anyhow::bail!("energy distributions require at least one polar bin")
+		}
+		let (theta_min, theta_max) = (self.config.mesh.theta_min, self.config.mesh.theta_max);
+		let dtheta = (theta_max - theta_min) / num_theta as f64;
+		let mut energy = vec![0.0; num_theta];
+		let mut kinetic_energy_above = vec![0.0; gamma_beta.len()];
+
+		// Return the total (excluding rest mass) and kinetic energy densities,
+		// in erg / cm^3, and the four-velocity of a zone.
+		let energies = |p: &AnyPrimitive| -> (f64, f64, f64) {
+			let (d, pg) = (p.mass_density, p.gas_pressure);
+
+			match &self.config.hydro {
+				AnyHydro::Newtonian(hydro) => {
+					let v2 = p.velocity_r.powi(2) + p.velocity_q.powi(2);
+					let ek = 0.5 * d * v2;
+					(ek + pg / (hydro.gamma_law_index - 1.0), ek, v2.sqrt() / LIGHT_SPEED)
+				}
+				AnyHydro::Relativistic(_) => {
+					let u2 = p.velocity_r.powi(2) + p.velocity_q.powi(2);
+					let lorentz_factor = (1.0 + u2).sqrt();
+					let c2 = LIGHT_SPEED * LIGHT_SPEED;
+					let h = 1.0 + DerivedFields::from_primitive(p, &self.config.hydro).specific_enthalpy / c2;
+					let et = (d * h * lorentz_factor * lorentz_factor - pg - d * lorentz_factor) * c2;
+					let ek = d * lorentz_factor * (lorentz_factor - 1.0) * c2;
+					(et, ek, u2.sqrt())
+				}
+			}
+		};
+
+		for block in self.blocks.values() {
+			let (rv, qv) = (&block.radial_vertices, &block.polar_vertices);
+
+			for ((i, j), p) in block.primitive.indexed_iter() {
+				let dv = 2.0 * PI / 3.0 * (rv[i + 1].powi(3) - rv[i].powi(3)) * (qv[j].cos() - qv[j + 1].cos());
+				let (et, ek, u) = energies(p);
+				let k = (((0.5 * (qv[j] + qv[j + 1]) - theta_min) / dtheta) as usize).min(num_theta - 1);
+				energy[k] += et * dv;
+
+				for (e, &cut) in kinetic_energy_above.iter_mut().zip(gamma_beta) {
+					if u > cut {
+						*e += ek * dv;
+					}
+				}
+			}
+		}
+
+		let theta: Vec<_> = (0..num_theta).map(|k| theta_min + (k as f64 + 0.5) * dtheta).collect();
+		let isotropic_energy = energy.iter().enumerate().map(|(k, e)| {
+			let (q0, q1) = (theta_min + k as f64 * dtheta, theta_min + (k + 1) as f64 * dtheta);
+			4.0 * PI * e / (2.0 * PI * (q0.cos() - q1.cos()))
+		}).collect();
+
+		Ok(EnergyDistributions{
+			theta,
+			isotropic_energy,
+			gamma_beta: gamma_beta.to_vec(),
+			kinetic_energy_above,
 		})
 	}
+
+	/**
+	 * Compute the mass of the material faster than each of the given
+	 * four-velocities, in num_theta uniform polar angle bins and in total.
+	 * The zone masses are the conserved lab-frame masses if the products
+	 * include them (see [`ProductsContents`]), and otherwise the lab-frame
+	 * density times the cell volume. Each zone is assigned to the polar bin
+	 * containing its center. In Newtonian runs, the four-velocity is v / c.
+	 * Fails on a cylindrical mesh.
+	 */
+	pub fn mass_distributions(&self, num_theta: usize, gamma_beta: &[f64]) -> anyhow::Result<MassDistributions> {
+		if self.config.mesh.geometry == CoordinateSystem::Cylindrical {
+			anyhow::bail!("mass distributions are only available on a spherical mesh")
+		}
+		if num_theta == 0 {
+			anyhow::bail!("mass distributions require at least one polar bin")
+		}
+		let relativistic = matches!(self.config.hydro, AnyHydro::Relativistic(_));
+		let (theta_min, theta_max) = (self.config.mesh.theta_min, self.config.mesh.theta_max);
+		let dtheta = (theta_max - theta_min) / num_theta as f64;
+		let mut mass_above = vec![vec![0.0; gamma_beta.len()]; num_theta];
+
+		for block in self.blocks.values() {
+			let (rv, qv) = (&block.radial_vertices, &block.polar_vertices);
+
+			for ((i, j), p) in block.primitive.indexed_iter() {
+				let u2 = p.velocity_r.powi(2) + p.velocity_q.powi(2);
+				let (lorentz_factor, u) = if relativistic {
+					((1.0 + u2).sqrt(), u2.sqrt())
+				} else {
+					(1.0, u2.sqrt() / LIGHT_SPEED)
+				};
+				let mass = match (&block.conserved, &block.cell_volumes) {
+					(Some(conserved), _) => conserved[(i, j)][0],
+					(None, Some(cell_volumes)) => p.mass_density * lorentz_factor * cell_volumes[(i, j)],
+					(None, None) => {
+						let dv = 2.0 * PI / 3.0 * (rv[i + 1].powi(3) - rv[i].powi(3)) * (qv[j].cos() - qv[j + 1].cos());
+						p.mass_density * lorentz_factor * dv
+					}
+				};
+				let k = (((0.5 * (qv[j] + qv[j + 1]) - theta_min) / dtheta) as usize).min(num_theta - 1);
+
+				for (m, &cut) in mass_above[k].iter_mut().zip(gamma_beta) {
+					if u > cut {
+						*m += mass;
+					}
+				}
+			}
+		}
+
+		let velocity = gamma_beta.iter().map(|&u| {
+			if relativistic {
+				u / (1.0 + u * u).sqrt() * LIGHT_SPEED
+			} else {
+				u * LIGHT_SPEED
+			}
+		}).collect();
+		let total_mass_above = (0..gamma_beta.len()).map(|n| mass_above.iter().map(|bin| bin[n]).sum()).collect();
+
+		Ok(MassDistributions{
+			theta: (0..num_theta).map(|k| theta_min + (k as f64 + 0.5) * dtheta).collect(),
+			gamma_beta: gamma_beta.to_vec(),
+			velocity,
+			mass_above,
+			total_mass_above,
+		})
+	}
+
+	/**
+	 * Read a products file, keeping only the given blocks. The other blocks
+	 * are skipped over while the file is read, so selecting few blocks, or
+	 * none to query the time and configuration, uses little memory even for
+	 * large files. The saving is in memory, not time: the whole file is
+	 * still decoded, and its checksum verified.
+	 */
+	pub fn read_selected(filename: &str, blocks: &[BlockIndex]) -> Result<Self, io::Error> {
+		struct ClearSelection;
+
+		impl Drop for ClearSelection {
+			fn drop(&mut self) {
+				SELECTED_BLOCKS.with(|selected| *selected.borrow_mut() = None);
+			}
+		}
+		SELECTED_BLOCKS.with(|selected| *selected.borrow_mut() = Some(blocks.iter().copied().collect()));
+		let _clear = ClearSelection;
+		io::read_cbor(filename)
+	}
+
 	pub fn try_from_app(app: &app::App) -> Result::<Self, HydroError> {
 		match (&app.state, &app.config.hydro) {
 			(AnyState::Newtonian(state), AnyHydro::Newtonian(hydro)) => {
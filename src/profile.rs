@@ -0,0 +1,187 @@
+use std::io::Write;
+use std::str::FromStr;
+use crate::physics::AnyPrimitive;
+use crate::products::{BlockProducts, Products};
+
+
+
+
+/**
+ * A field which can be written to a radial or polar profile
+ */
+#[derive(Clone, Copy)]
+pub enum Field {
+    Rho,
+    Pre,
+    Ur,
+    Uq,
+    Scalar,
+}
+
+
+
+
+// ============================================================================
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rho"        => Ok(Field::Rho),
+            "pre" | "p"  => Ok(Field::Pre),
+            "ur"         => Ok(Field::Ur),
+            "uq"         => Ok(Field::Uq),
+            "scalar"     => Ok(Field::Scalar),
+            _ => anyhow::bail!("unknown field '{}' (choose from rho, pre, ur, uq, scalar)", s),
+        }
+    }
+}
+
+impl Field {
+
+    /**
+     * The name of this field, as written in the profile header.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Rho    => "rho",
+            Field::Pre    => "pre",
+            Field::Ur     => "ur",
+            Field::Uq     => "uq",
+            Field::Scalar => "scalar",
+        }
+    }
+
+    fn value(&self, p: &AnyPrimitive, scalar: f64) -> f64 {
+        match self {
+            Field::Rho    => p.mass_density,
+            Field::Pre    => p.gas_pressure,
+            Field::Ur     => p.velocity_r,
+            Field::Uq     => p.velocity_q,
+            Field::Scalar => scalar,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the polar zone indexes bracketing the angle theta in a block, and
+ * the weight of the second one, for linear interpolation between zone
+ * centers. The angle is clamped to the range of zone centers.
+ */
+fn polar_bracket(block: &BlockProducts, theta: f64) -> (usize, usize, f64) {
+    let pv = &block.polar_vertices;
+    let nq = pv.len() - 1;
+    let qc = |j: usize| 0.5 * (pv[j] + pv[j + 1]);
+
+    if nq == 1 || theta <= qc(0) {
+        return (0, 0, 0.0)
+    }
+    if theta >= qc(nq - 1) {
+        return (nq - 1, nq - 1, 0.0)
+    }
+    let j = (0..nq - 1).find(|&j| qc(j + 1) >= theta).unwrap();
+    (j, j + 1, (theta - qc(j)) / (qc(j + 1) - qc(j)))
+}
+
+/**
+ * Return a radial profile of the given fields at the polar angle theta, as a
+ * list of rows, each containing the radius of the zone centroid followed by
+ * the field values. Values are linearly interpolated in theta between zone
+ * centers. Only blocks whose polar extent includes theta are sampled, so in
+ * a run with multiple radial rays, the ray containing theta is used.
+ */
+pub fn radial_profile(products: &Products, theta: f64, fields: &[Field]) -> Vec<Vec<f64>> {
+    let mut indexes: Vec<_> = products.blocks
+        .iter()
+        .filter(|(_, b)| b.polar_vertices[0] <= theta && theta <= b.polar_vertices[b.polar_vertices.len() - 1])
+        .map(|(index, _)| *index)
+        .collect();
+    indexes.sort_unstable();
+
+    let mut rows = Vec::new();
+
+    for index in indexes {
+        let block = &products.blocks[&index];
+        let (j0, j1, w) = polar_bracket(block, theta);
+        let rv = &block.radial_vertices;
+
+        for i in 0..rv.len() - 1 {
+            let mut row = vec![(rv[i] * rv[i + 1]).sqrt()];
+            for field in fields {
+                let y0 = field.value(&block.primitive[(i, j0)], block.scalar[(i, j0)]);
+                let y1 = field.value(&block.primitive[(i, j1)], block.scalar[(i, j1)]);
+                row.push(y0 * (1.0 - w) + y1 * w);
+            }
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+/**
+ * Return a polar profile of the given fields at the radius r, as a list of
+ * rows, each containing the polar angle of the zone center followed by the
+ * field values. Values are linearly interpolated in log(r) between the
+ * centroids of the two radial zones straddling r, which may be in adjacent
+ * blocks. The radius is clamped to the range of zone centroids. In a run
+ * with multiple radial rays, the rays are concatenated in order of theta.
+ */
+pub fn polar_profile(products: &Products, r: f64, fields: &[Field]) -> Vec<Vec<f64>> {
+    let mut indexes: Vec<_> = products.blocks.keys().copied().collect();
+    indexes.sort_unstable_by_key(|&(i, j)| (j, i));
+
+    let mut rows = Vec::new();
+
+    for ray in indexes.chunk_by(|a, b| a.1 == b.1) {
+
+        // The centroid radius of each radial zone in the ray, with its block
+        // and radial zone index.
+        let zones: Vec<_> = ray
+            .iter()
+            .flat_map(|index| {
+                let rv = &products.blocks[index].radial_vertices;
+                (0..rv.len() - 1).map(move |i| ((rv[i] * rv[i + 1]).sqrt(), index, i))
+            })
+            .collect();
+
+        let k = zones.partition_point(|z| z.0 <= r).max(1).min(zones.len()) - 1;
+        let (zone0, zone1) = (zones[k], zones[(k + 1).min(zones.len() - 1)]);
+        let w = if zone1.0 > zone0.0 {
+            ((r / zone0.0).ln() / (zone1.0 / zone0.0).ln()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (b0, b1) = (&products.blocks[zone0.1], &products.blocks[zone1.1]);
+        let pv = &b0.polar_vertices;
+
+        for j in 0..pv.len() - 1 {
+            let mut row = vec![0.5 * (pv[j] + pv[j + 1])];
+            for field in fields {
+                let y0 = field.value(&b0.primitive[(zone0.2, j)], b0.scalar[(zone0.2, j)]);
+                let y1 = field.value(&b1.primitive[(zone1.2, j)], b1.scalar[(zone1.2, j)]);
+                row.push(y0 * (1.0 - w) + y1 * w);
+            }
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+/**
+ * Write a radial or polar profile as comma-separated values, with a header
+ * line. The coordinate is the name of the first column, e.g. r or theta.
+ */
+pub fn write_csv<W: Write>(writer: &mut W, coordinate: &str, fields: &[Field], rows: &[Vec<f64>]) -> std::io::Result<()> {
+    let header: Vec<_> = std::iter::once(coordinate).chain(fields.iter().map(Field::name)).collect();
+    writeln!(writer, "{}", header.join(","))?;
+
+    for row in rows {
+        let line: Vec<_> = row.iter().map(|x| format!("{:.12e}", x)).collect();
+        writeln!(writer, "{}", line.join(","))?;
+    }
+    Ok(())
+}
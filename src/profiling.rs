@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::AddAssign;
+use std::time::Instant;
+use crate::mesh::BlockIndex;
+
+
+
+
+/**
+ * Wall time, in seconds, spent in each phase of the update of one block
+ */
+#[derive(Clone, Copy, Default)]
+pub struct PhaseTimes {
+
+    /// Conversion of the block's conserved quantities to primitives (the
+    /// boundary slabs shared with its neighbors are not included)
+    pub c2p: f64,
+
+    /// Filling of the ghost zones, and the PLM gradients
+    pub reconstruction: f64,
+
+    /// Riemann solves for the radial and polar fluxes
+    pub riemann: f64,
+
+    /// Source terms, and the update of the conserved quantities
+    pub source: f64,
+}
+
+
+
+
+/**
+ * Measures the wall time of successive laps, which are used to divide the
+ * update of a block into its phases
+ */
+pub struct Stopwatch(Instant);
+
+
+
+
+/**
+ * Measures the wall time spent between the Runge-Kutta stages of a step,
+ * which is the averaging of the stages, plus any scheduling overhead
+ */
+#[derive(Default)]
+pub struct StageClock {
+    last_stage_end: Option<Instant>,
+    between_stages: f64,
+}
+
+
+
+
+/**
+ * Wall time spent updating each block, and between the Runge-Kutta stages,
+ * accumulated over any number of folds
+ */
+#[derive(Clone, Default)]
+pub struct Profile {
+
+    /// Time spent in the update of each block, by phase
+    pub blocks: HashMap<BlockIndex, PhaseTimes>,
+
+    /// Time spent between Runge-Kutta stages
+    pub runge_kutta: f64,
+}
+
+
+
+
+// ============================================================================
+impl PhaseTimes {
+
+    /**
+     * The total time spent in all phases.
+     */
+    pub fn total(&self) -> f64 {
+        self.c2p + self.reconstruction + self.riemann + self.source
+    }
+}
+
+impl AddAssign for PhaseTimes {
+    fn add_assign(&mut self, other: Self) {
+        self.c2p += other.c2p;
+        self.reconstruction += other.reconstruction;
+        self.riemann += other.riemann;
+        self.source += other.source;
+    }
+}
+
+
+
+
+// ============================================================================
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self(Instant::now())
+    }
+
+    /**
+     * Add the time since the last lap to the given counter, and start a new
+     * lap.
+     */
+    pub fn lap(&mut self, counter: &mut f64) {
+        let now = Instant::now();
+        *counter += (now - self.0).as_secs_f64();
+        self.0 = now;
+    }
+}
+
+
+
+
+// ============================================================================
+impl StageClock {
+
+    /**
+     * Mark the start of a Runge-Kutta stage, adding the time since the end
+     * of the previous stage, if any.
+     */
+    pub fn stage_started(&mut self) {
+        if let Some(last_stage_end) = self.last_stage_end.take() {
+            self.between_stages += last_stage_end.elapsed().as_secs_f64();
+        }
+    }
+
+    /**
+     * Mark the end of a Runge-Kutta stage.
+     */
+    pub fn stage_finished(&mut self) {
+        self.last_stage_end = Some(Instant::now())
+    }
+
+    /**
+     * Mark the end of a Runge-Kutta step, which includes the averaging
+     * after its last stage.
+     */
+    pub fn step_finished(&mut self) {
+        self.stage_started()
+    }
+
+    /**
+     * The total time spent between stages.
+     */
+    pub fn between_stages(&self) -> f64 {
+        self.between_stages
+    }
+}
+
+
+
+
+// ============================================================================
+impl Profile {
+
+    /**
+     * Add the block times of a fold to this profile, along with the time
+     * spent between its Runge-Kutta stages.
+     */
+    pub fn accumulate(&mut self, blocks: HashMap<BlockIndex, PhaseTimes>, runge_kutta: f64) {
+        for (index, times) in blocks {
+            *self.blocks.entry(index).or_default() += times;
+        }
+        self.runge_kutta += runge_kutta;
+    }
+
+    /**
+     * The times of all blocks, summed by phase.
+     */
+    pub fn total(&self) -> PhaseTimes {
+        let mut total = PhaseTimes::default();
+
+        for times in self.blocks.values() {
+            total += *times;
+        }
+        total
+    }
+
+    /**
+     * Print the fraction of the block update time spent in each phase, and
+     * the load balance, which is the ratio of the mean to the maximum time
+     * per block. The wall time is that of the whole reporting interval.
+     */
+    pub fn print_summary(&self, wall: f64) {
+        let total = self.total();
+        let busy = total.total();
+        let percent = |t: f64| 100.0 * t / busy;
+        let max = self.blocks.values().map(PhaseTimes::total).fold(0.0, f64::max);
+        let mean = busy / self.blocks.len().max(1) as f64;
+
+        println!("profile: {:.3}s wall, {:.3}s in block updates ({} blocks)", wall, busy, self.blocks.len());
+        println!("    c2p ............... {:.1}%", percent(total.c2p));
+        println!("    reconstruction .... {:.1}%", percent(total.reconstruction));
+        println!("    riemann ........... {:.1}%", percent(total.riemann));
+        println!("    source ............ {:.1}%", percent(total.source));
+        println!("    rk averaging ...... {:.3}s", self.runge_kutta);
+        println!("    load balance ...... {:.3} (mean / max block time)", mean / max);
+    }
+
+    /**
+     * Write the time per block and phase as comma-separated values, with a
+     * header line, ordered by block index.
+     */
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut indexes: Vec<_> = self.blocks.keys().copied().collect();
+        indexes.sort_unstable();
+
+        writeln!(writer, "i,j,c2p,reconstruction,riemann,source,total")?;
+
+        for index in indexes {
+            let t = &self.blocks[&index];
+            writeln!(writer, "{},{},{:.6e},{:.6e},{:.6e},{:.6e},{:.6e}",
+                index.0,
+                index.1,
+                t.c2p,
+                t.reconstruction,
+                t.riemann,
+                t.source,
+                t.total())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::io::Write;
+use serde::{Serialize, Deserialize};
+use crate::expression::{Reduction, Zone};
+use crate::mesh::{BlockIndex, CoordinateSystem, GridGeometry};
+use crate::physics::HydroError;
+use crate::state::State;
+use crate::traits::{Conserved, Hydrodynamics, Primitive};
+
+
+
+
+/**
+ * Global measurements of the solution state, recorded in the run's time
+ * series and in products files. Totals are volume integrals of the conserved
+ * quantities, in code units (for relativistic hydro, the energy excludes the
+ * rest mass, and energy and momentum are divided by c^2 and c respectively).
+ * The conserved fields have no azimuthal component, so there is no angular
+ * momentum to report.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Reductions {
+
+    /// The simulation time
+    pub time: f64,
+
+    /// Total lab-frame mass on the mesh
+    pub mass: f64,
+
+    /// Total energy on the mesh
+    pub energy: f64,
+
+    /// Total radial momentum on the mesh (cylindrical radial momentum, for a
+    /// cylindrical mesh)
+    pub radial_momentum: f64,
+
+    /// Total momentum along the polar axis on the mesh
+    pub z_momentum: f64,
+
+    /// Total scalar mass on the mesh
+    pub scalar_mass: f64,
+
+    /// Lab-frame mass removed by the inner excision surface
+    pub excised_mass: f64,
+
+    /// Energy removed by the inner excision surface
+    pub excised_energy: f64,
+
+    /// Values of the user-defined reductions, keyed by name
+    #[serde(default)]
+    pub custom: BTreeMap<String, f64>,
+
+    /// The jet or cocoon opening angle versus radius, as a list of (radius,
+    /// angle) pairs, if an opening angle measurement is configured
+    #[serde(default)]
+    pub opening_angle: Vec<(f64, f64)>,
+
+    /// The isotropic-equivalent energy versus polar angle, in material
+    /// faster than each of the configured four-velocity cuts
+    #[serde(default)]
+    pub isotropic_energy: Vec<IsotropicEnergy>,
+}
+
+
+
+
+/**
+ * The field whose threshold contour defines the opening angle
+ */
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContourField {
+    Scalar,
+    LorentzFactor,
+}
+
+
+
+
+/**
+ * Configuration of the opening angle measurement. At each radius, the
+ * opening angle is the polar angle (measured from the theta=0 axis) at which
+ * the given field first drops below the threshold, when scanning outward
+ * from the axis through the northern hemisphere. It is linearly interpolated
+ * between the zone centers bracketing the crossing.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpeningAngle {
+
+    /// The field to threshold: `scalar` (concentration) or `lorentz_factor`
+    pub field: ContourField,
+
+    /// The contour level
+    pub threshold: f64,
+}
+
+
+
+
+/**
+ * The isotropic-equivalent energy, E_iso = 4 pi dE / dOmega, of the material
+ * whose four-velocity (gamma beta) exceeds a cut, versus polar angle. The
+ * energy is in the units of [`Reductions::energy`].
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct IsotropicEnergy {
+
+    /// The cut on the four-velocity
+    pub gamma_beta_cut: f64,
+
+    /// List of (polar angle, E_iso) pairs, at the centers of the polar zones
+    pub profile: Vec<(f64, f64)>,
+}
+
+
+
+
+// ============================================================================
+impl Reductions {
+
+    /**
+     * Compute the reductions from a solution state and the geometry of its
+     * blocks.
+     */
+    pub fn from_state<C: Conserved>(state: &State<C>, geometry: &HashMap<BlockIndex, GridGeometry>) -> Self {
+        let mut result = Self {
+            time: state.time,
+            excised_mass: state.excised.conserved.lab_frame_mass(),
+            excised_energy: state.excised.conserved.energy(),
+            ..Self::default()
+        };
+
+        for (index, block) in state.sorted_blocks() {
+            for (u, &(_, q)) in block.conserved.iter().zip(geometry[index].cell_centers.iter()) {
+                result.mass += u.lab_frame_mass();
+                result.energy += u.energy();
+                result.radial_momentum += u.radial_momentum();
+                result.z_momentum += match geometry[index].coordinates {
+                    CoordinateSystem::Spherical   => u.radial_momentum() * q.cos() - u.polar_momentum() * q.sin(),
+                    CoordinateSystem::Cylindrical => u.polar_momentum(),
+                };
+            }
+            result.scalar_mass += block.scalar_mass.sum();
+        }
+        result
+    }
+
+    /**
+     * Evaluate the given user-defined reductions on the solution state, and
+     * add them to this instance. Fails if any of the conserved states could
+     * not be converted to primitive.
+     */
+    pub fn try_with_custom<H, C>(
+        mut self,
+        state: &State<C>,
+        hydro: &H,
+        geometry: &HashMap<BlockIndex, GridGeometry>,
+        custom: &BTreeMap<String, Reduction>) -> Result<Self, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>,
+        C: Conserved,
+    {
+        if custom.is_empty() {
+            return Ok(self)
+        }
+        let mut blocks = Vec::new();
+
+        for (index, block) in state.sorted_blocks() {
+            let primitive = block.try_to_primitive(hydro, &geometry[index])?.mapv(|p| hydro.any(&p));
+            let scalar = &block.scalar_mass / &block.conserved.mapv(|u| u.lab_frame_mass());
+            blocks.push((primitive, scalar, &geometry[index]));
+        }
+
+        for (name, reduction) in custom {
+            let zones = blocks.iter().flat_map(|(primitive, scalar, geometry)| {
+                primitive.iter()
+                    .zip(scalar.iter())
+                    .zip(geometry.cell_centers.iter().zip(geometry.cell_volumes.iter()))
+                    .map(|((primitive, &scalar), (&coordinate, &dv))| (Zone{primitive, scalar, coordinate, time: state.time}, dv))
+            });
+            self.custom.insert(name.clone(), reduction.evaluate(zones));
+        }
+        Ok(self)
+    }
+
+    /**
+     * Append the time and the totals of mass, energy, radial momentum, and
+     * scalar mass to a CSV file, writing a header line if the file is new.
+     */
+    pub fn append_totals(&self, filename: &str) -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(filename)?;
+
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "time,mass,energy,radial_momentum,scalar_mass")?;
+        }
+        let values = [self.time, self.mass, self.energy, self.radial_momentum, self.scalar_mass];
+        let line: Vec<_> = values.iter().map(|x| format!("{:.12e}", x)).collect();
+        writeln!(file, "{}", line.join(","))
+    }
+
+    /**
+     * Measure the opening angle versus radius on the solution state, if a
+     * measurement is configured, and add it to this instance. No
+     * measurement is made for 1D (single polar zone) runs. Fails if any of
+     * the conserved states could not be converted to primitive.
+     */
+    pub fn try_with_opening_angle<H, C>(
+        mut self,
+        state: &State<C>,
+        hydro: &H,
+        geometry: &HashMap<BlockIndex, GridGeometry>,
+        measurement: &Option<OpeningAngle>) -> Result<Self, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>,
+        C: Conserved,
+    {
+        let measurement = match measurement {
+            Some(measurement) => measurement,
+            None => return Ok(self),
+        };
+
+        for (index, block) in state.sorted_blocks() {
+            let geometry = &geometry[index];
+            let (nr, nq) = block.conserved.dim();
+
+            if nq == 1 {
+                continue
+            }
+            let values = match measurement.field {
+                ContourField::Scalar => {
+                    &block.scalar_mass / &block.conserved.mapv(|u| u.lab_frame_mass())
+                }
+                ContourField::LorentzFactor => {
+                    block.try_to_primitive(hydro, geometry)?.mapv(|p| p.lorentz_factor())
+                }
+            };
+            for i in 0..nr {
+                let (r, _) = geometry.cell_centers[(i, 0)];
+                let q = |j: usize| geometry.cell_centers[(i, j)].1;
+                let f = |j: usize| values[(i, j)];
+                let mut angle = 0.0;
+
+                for j in (0..nq).take_while(|&j| q(j) <= 0.5 * PI) {
+                    if f(j) < measurement.threshold {
+                        if j > 0 {
+                            let w = (f(j - 1) - measurement.threshold) / (f(j - 1) - f(j));
+                            angle = q(j - 1) + (q(j) - q(j - 1)) * w;
+                        }
+                        break
+                    }
+                    angle = q(j);
+                }
+                self.opening_angle.push((r, angle));
+            }
+        }
+        self.opening_angle.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(self)
+    }
+
+    /**
+     * Measure the isotropic-equivalent energy versus polar angle on the
+     * solution state, for each of the given four-velocity cuts, and add it
+     * to this instance. The angular bins are the polar zones of the
+     * coarsest blocks, so refined blocks contribute to the coarse zone
+     * containing each of their zones. In Newtonian runs the Lorentz factor
+     * is 1, so only a cut below zero selects any material. No measurement
+     * is made on a cylindrical mesh. Fails if any of the conserved states
+     * could not be converted to primitive.
+     */
+    pub fn try_with_isotropic_energy<H, C>(
+        mut self,
+        state: &State<C>,
+        hydro: &H,
+        geometry: &HashMap<BlockIndex, GridGeometry>,
+        gamma_beta_cuts: &[f64]) -> Result<Self, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>,
+        C: Conserved,
+    {
+        if gamma_beta_cuts.is_empty() || geometry.values().any(|g| g.coordinates == CoordinateSystem::Cylindrical) {
+            return Ok(self)
+        }
+        let nq_coarse = state.solution.values().map(|block| block.conserved.dim().1).min().unwrap_or(1);
+        let mut bins = BTreeMap::new();
+
+        for (index, block) in state.sorted_blocks() {
+            let geometry = &geometry[index];
+            let qv = &geometry.polar_vertices;
+            let primitive = block.try_to_primitive(hydro, geometry)?;
+            let k = primitive.dim().1 / nq_coarse;
+
+            for ((i, j), p) in primitive.indexed_iter() {
+                let jc = j / k;
+                let (_, _, energy) = bins.entry((index.1, jc)).or_insert_with(|| {
+                    let (q0, q1) = (qv[jc * k], qv[(jc + 1) * k]);
+                    (0.5 * (q0 + q1), 2.0 * PI * (q0.cos() - q1.cos()), vec![0.0; gamma_beta_cuts.len()])
+                });
+                let gamma_beta = (p.lorentz_factor().powi(2) - 1.0).max(0.0).sqrt();
+
+                for (e, &cut) in energy.iter_mut().zip(gamma_beta_cuts) {
+                    if gamma_beta > cut {
+                        *e += block.conserved[(i, j)].energy();
+                    }
+                }
+            }
+        }
+
+        self.isotropic_energy = gamma_beta_cuts.iter().enumerate().map(|(n, &gamma_beta_cut)| {
+            IsotropicEnergy{
+                gamma_beta_cut,
+                profile: bins.values().map(|(angle, solid_angle, energy)| (*angle, 4.0 * PI * energy[n] / solid_angle)).collect(),
+            }
+        }).collect();
+        Ok(self)
+    }
+}
@@ -1,174 +1,844 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use futures::FutureExt;
 use futures::future::join_all;
+use rayon::prelude::*;
 use tokio::runtime::Runtime;
-use ndarray::{Array, Axis, concatenate, s};
+use std::ops::{Add, Mul, Range};
+use ndarray::{ArcArray, Array, ArrayView, Axis, Ix1, Ix2, concatenate, s};
+use crate::boundary::{self, Boundaries, Side};
 use crate::mesh::{BlockIndex, GridGeometry, Mesh};
 use crate::physics::{Direction, HydroError};
-use crate::state::{State, BlockState};
+use crate::profiling::{PhaseTimes, Profile, StageClock, Stopwatch};
+use crate::state::{State, BlockState, TimeStepLimit};
+use crate::probes;
+use crate::tracers;
 use crate::traits::{Conserved, Primitive, Hydrodynamics, InitialModel};
 
 
 
 
 // ============================================================================
+/**
+ * Geometry of a radial block interface at which the inner neighbor block has
+ * `factor` times as many polar zones, due to polar refinement. The fine face
+ * areas are those of the interface, and the volumes are those of the fine
+ * zones adjacent to it, which are used as weights for restriction.
+ */
+struct FineInterface {
+    factor: usize,
+    face_areas: Array<f64, Ix1>,
+    volumes: Array<f64, Ix1>,
+}
+
+
+
+
+// ============================================================================
+impl FineInterface {
+    fn at(mesh: &Mesh, index: BlockIndex, time: f64) -> Option<Self> {
+        let nq_fine = mesh.num_polar_zones_at((index.0 - 1, index.1));
+        let factor = nq_fine / mesh.num_polar_zones_at(index);
+
+        if factor == 1 {
+            return None
+        }
+        let grid = mesh.subgrid_extent(index).grid(mesh.block_size, nq_fine, mesh.polar_grading.clone(), mesh.geometry);
+        let a = mesh.expansion_factor(time);
+        let face_areas = Array::from_shape_fn(nq_fine, |j| grid.zone((0, j)).geometry_in(mesh.geometry).1 * a * a);
+        let volumes = Array::from_shape_fn(nq_fine, |j| grid.zone((0, j)).geometry_in(mesh.geometry).0);
+        Some(Self{factor, face_areas, volumes})
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return a copy of the given array with each polar zone repeated k times.
+ */
+fn prolong_polar<T: Copy>(a: ArrayView<T, Ix2>, k: usize) -> Array<T, Ix2> {
+    Array::from_shape_fn((a.dim().0, a.dim().1 * k), |(i, j)| a[(i, j / k)])
+}
+
+/**
+ * Return an array whose polar zones are the weighted averages of each group
+ * of k consecutive polar zones in the given array.
+ */
+fn restrict_polar<T>(a: ArrayView<T, Ix2>, weights: ArrayView<f64, Ix1>, k: usize) -> Array<T, Ix2>
+where
+    T: Copy + Default + Add<Output = T> + Mul<f64, Output = T>
+{
+    Array::from_shape_fn((a.dim().0, a.dim().1 / k), |(i, j)| {
+        let total: f64 = weights.slice(s![j * k..(j + 1) * k]).sum();
+        (j * k..(j + 1) * k).fold(T::default(), |x, m| x + a[(i, m)] * (weights[m] / total))
+    })
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the flux through a radial face moving with speed w, given the flux
+ * (f, g) through a stationary face, by subtracting the conserved state
+ * upwind of the face (relative to the face motion), advected at speed w.
+ */
+fn moving_face_flux<H, C, P>(hydro: &H, flux: (C, f64), pl: P, pr: P, sl: f64, sr: f64, w: f64) -> (C, f64)
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P>,
+    C: Conserved,
+    P: Primitive
+{
+    let (p, s) = if 0.5 * (hydro.radial_velocity(&pl) + hydro.radial_velocity(&pr)) > w {
+        (pl, sl)
+    } else {
+        (pr, sr)
+    };
+    (flux.0 - hydro.to_conserved(p) * w, flux.1 - s * p.lorentz_factor() * w)
+}
+
+
+
+
+// ============================================================================
+/**
+ * Primitive and scalar concentration data on a block, computed at the start
+ * of a Runge-Kutta stage
+ */
+type Stage<P> = (ArcArray<P, Ix2>, ArcArray<f64, Ix2>);
+
+/**
+ * Stage data on the two innermost and the two outermost radial zones of a
+ * block, which are the ghost zones of its inner and outer neighbors
+ */
+type Slabs<P> = (Stage<P>, Stage<P>);
+
+/**
+ * Primitive arrays which were computed from the state at a given time to
+ * find the time step. They are reused by the first Runge-Kutta stage from
+ * that state, so that the conserved-to-primitive conversion is not done
+ * twice.
+ */
+struct PrimitiveCache<P> {
+    time: f64,
+    blocks: HashMap<BlockIndex, ArcArray<P, Ix2>>,
+}
+
+impl<P> PrimitiveCache<P> {
+
+    /**
+     * Return the cached primitive array of a block, if the stage is from
+     * the state at the time of the cache.
+     */
+    fn get(&self, time: f64, index: BlockIndex) -> Option<&ArcArray<P, Ix2>> {
+        if time == self.time {
+            self.blocks.get(&index)
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * Return the stage data on the given radial zones of a block. If the
+ * primitive array of the whole block is given, it is used instead of
+ * converting the conserved quantities.
+ */
+fn try_stage_rows<H, C, P>(
+    state: &BlockState<C>,
+    hydro: &H,
+    geometry: &GridGeometry,
+    rows: Range<usize>,
+    primitive: Option<&ArcArray<P, Ix2>>) -> Result<Stage<P>, HydroError>
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P>,
+    C: Conserved,
+    P: Primitive
+{
+    let p = match primitive {
+        Some(p) if rows == (0..p.dim().0) => p.clone(),
+        Some(p) => p.slice(s![rows.clone(), ..]).to_shared(),
+        None => state.try_to_primitive_rows(hydro, geometry, rows.clone())?.into_shared(),
+    };
+    let s = &state.scalar_mass.slice(s![rows.clone(), ..]) / &geometry.cell_volumes.slice(s![rows, ..]) / p.map(P::lorentz_factor);
+    Ok((p, s.into_shared()))
+}
+
+/**
+ * Return the stage data on the whole of a block.
+ */
+fn try_stage<H, C, P>(
+    state: &BlockState<C>,
+    hydro: &H,
+    geometry: &GridGeometry,
+    primitive: Option<&ArcArray<P, Ix2>>) -> Result<Stage<P>, HydroError>
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P>,
+    C: Conserved,
+    P: Primitive
+{
+    try_stage_rows(state, hydro, geometry, 0..state.conserved.dim().0, primitive)
+}
+
+/**
+ * Return the stage data on the inner and outer slabs of a block, which are
+ * all that its neighbors need from it.
+ */
+fn try_stage_slabs<H, C, P>(
+    state: &BlockState<C>,
+    hydro: &H,
+    geometry: &GridGeometry,
+    primitive: Option<&ArcArray<P, Ix2>>) -> Result<Slabs<P>, HydroError>
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P>,
+    C: Conserved,
+    P: Primitive
+{
+    let nr = state.conserved.dim().0;
+    let inner = try_stage_rows(state, hydro, geometry, 0..2, primitive)?;
+    let outer = try_stage_rows(state, hydro, geometry, nr - 2..nr, primitive)?;
+    Ok((inner, outer))
+}
+
+
+
+
+// ============================================================================
+/**
+ * Work arrays for the update of one block, which are filled in place on each
+ * Runge-Kutta stage rather than allocated anew. The primitive and scalar
+ * arrays are extended by two ghost zones on either radial edge, and one on
+ * either polar edge. The gradient arrays have the same shape, and hold the
+ * radial gradients and then the polar gradients. The flux arrays hold the
+ * fluxes through the radial and polar faces, multiplied by the face areas.
+ * The wall time spent updating the block is also accumulated here.
+ */
+struct BlockScratch<P, C> {
+    pe: Array<P, Ix2>,
+    se: Array<f64, Ix2>,
+    gp: Array<P, Ix2>,
+    gs: Array<f64, Ix2>,
+    fx: Array<C, Ix2>,
+    sx: Array<f64, Ix2>,
+    fy: Array<C, Ix2>,
+    sy: Array<f64, Ix2>,
+    times: PhaseTimes,
+}
+
+impl<P: Primitive, C: Conserved> BlockScratch<P, C> {
+    fn new(dim: (usize, usize)) -> Self {
+        let (nr, nq) = dim;
+        Self {
+            pe: Array::default((nr + 4, nq + 2)),
+            se: Array::zeros((nr + 4, nq + 2)),
+            gp: Array::default((nr + 4, nq + 2)),
+            gs: Array::zeros((nr + 4, nq + 2)),
+            fx: Array::default((nr + 1, nq)),
+            sx: Array::zeros((nr + 1, nq)),
+            fy: Array::default((nr, nq + 1)),
+            sy: Array::zeros((nr, nq + 1)),
+            times: PhaseTimes::default(),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * The work arrays of each block, which persist over the iterations of a
+ * fold. Each block's arrays are behind their own lock, which is only ever
+ * taken by the task updating that block.
+ */
+struct Scratch<P, C> {
+    blocks: Mutex<HashMap<BlockIndex, Arc<Mutex<BlockScratch<P, C>>>>>,
+}
+
+impl<P: Primitive, C: Conserved> Scratch<P, C> {
+    fn new() -> Self {
+        Self{blocks: Mutex::new(HashMap::new())}
+    }
+
+    /**
+     * Return the work arrays for the block at the given index, creating them
+     * for a block of the given shape if needed.
+     */
+    fn get(&self, index: BlockIndex, dim: (usize, usize)) -> Arc<Mutex<BlockScratch<P, C>>> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .entry(index)
+            .or_insert_with(|| Arc::new(Mutex::new(BlockScratch::new(dim))))
+            .clone()
+    }
+
+    /**
+     * Return the wall time spent updating each block so far.
+     */
+    fn times(&self) -> HashMap<BlockIndex, PhaseTimes> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&index, scratch)| (index, scratch.lock().unwrap().times))
+            .collect()
+    }
+
+    /**
+     * Return the total wall time spent updating each block so far, which is
+     * used as the cost of the block for load balancing. Blocks which have not
+     * been updated yet are absent.
+     */
+    fn costs(&self) -> HashMap<BlockIndex, f64> {
+        self.times().into_iter().map(|(index, times)| (index, times.total())).collect()
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the indexes of the blocks in the given state, in order of
+ * decreasing cost, so that a single expensive block is started first rather
+ * than holding up the end of a stage. Blocks with no measured cost come
+ * last, in order of decreasing size.
+ */
+fn schedule<C: Conserved>(state: &State<C>, costs: &HashMap<BlockIndex, f64>) -> Vec<BlockIndex> {
+    let mut order: Vec<_> = state.solution
+        .iter()
+        .map(|(&index, block)| (costs.get(&index).copied(), block.conserved.len(), index))
+        .collect();
+    order.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
+    order.into_iter().map(|(_, _, index)| index).collect()
+}
+
+
+
+
+// ============================================================================
+/**
+ * Assignment of blocks to groups, each of which is updated in sequence by a
+ * single rayon task. The groups are contiguous runs of block indexes with
+ * roughly equal cost, so most radial neighbors are in the same group. The
+ * cost of a block is its measured update time, or until that is known, its
+ * number of zones. Groups are only rebuilt when blocks are added or
+ * removed, or when measured costs first become available. The groups are
+ * not bound to threads: rayon may run a group on any of its threads in
+ * each stage.
+ */
+struct BlockGroups {
+    blocks: Vec<BlockIndex>,
+    groups: Vec<Vec<BlockIndex>>,
+    measured: bool,
+}
+
+impl BlockGroups {
+    fn new() -> Self {
+        Self{blocks: Vec::new(), groups: Vec::new(), measured: false}
+    }
+
+    /**
+     * Rebuild the groups with the given number of them, if the blocks in the
+     * state are different from those which were last assigned, or if the
+     * groups were assigned without any measured costs. Blocks without a
+     * measured cost are assigned the mean cost per zone of the others.
+     */
+    fn update<C: Conserved>(&mut self, state: &State<C>, num_groups: usize, costs: &HashMap<BlockIndex, f64>) {
+        let mut blocks: Vec<_> = state.solution.keys().copied().collect();
+        blocks.sort_unstable();
+
+        if blocks == self.blocks && !self.groups.is_empty() && (self.measured || costs.is_empty()) {
+            return
+        }
+        let zones = |index: &BlockIndex| state.solution[index].conserved.len() as f64;
+        let measured_zones: f64 = blocks.iter().filter(|index| costs.contains_key(index)).map(zones).sum();
+        let measured_cost: f64 = blocks.iter().filter_map(|index| costs.get(index)).sum();
+        let cost_per_zone = if measured_cost > 0.0 { measured_cost / measured_zones } else { 1.0 };
+        let cost = |index: &BlockIndex| costs.get(index).copied().unwrap_or_else(|| cost_per_zone * zones(index));
+
+        let total: f64 = blocks.iter().map(cost).sum();
+        let mut groups = vec![Vec::new()];
+        let mut count = 0.0;
+
+        for index in &blocks {
+            if count * num_groups as f64 >= total * groups.len() as f64 {
+                groups.push(Vec::new());
+            }
+            groups.last_mut().unwrap().push(*index);
+            count += cost(index);
+        }
+        self.blocks = blocks;
+        self.groups = groups;
+        self.measured = !costs.is_empty();
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Redistribute the total conserved quantities and scalar mass of the zones
+ * merged at each polar axis of a block (see
+ * [`GridGeometry::polar_axis_zones`]) among them in proportion to their
+ * volumes, in each radial row. The totals are unchanged.
+ */
+fn merge_polar_axis_zones<C: Conserved>(
+    conserved: &mut Array<C, Ix2>,
+    scalar_mass: &mut Array<f64, Ix2>,
+    geometry: &GridGeometry,
+    count: usize)
+{
+    for range in geometry.polar_axis_zones(count) {
+        for i in 0..conserved.dim().0 {
+            let volume: f64 = range.clone().map(|j| geometry.cell_volumes[(i, j)]).sum();
+            let u = range.clone().fold(C::default(), |u, j| u + conserved[(i, j)]);
+            let s: f64 = range.clone().map(|j| scalar_mass[(i, j)]).sum();
+
+            for j in range.clone() {
+                let w = geometry.cell_volumes[(i, j)] / volume;
+                conserved[(i, j)] = u * w;
+                scalar_mass[(i, j)] = s * w;
+            }
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the state of one block, advanced by the time step dt, given the
+ * stage data on the block and the adjacent slabs of its inner and outer
+ * neighbors (at least two radial zones each). If a neighbor is absent, its
+ * ghost zones are generated from the radial boundary condition, which is an
+ * error if the boundary condition takes them from blocks generated by the
+ * model. This function is shared by the parallel runtimes, and does no
+ * scheduling of its own. The only arrays it allocates are those of the new
+ * block state, and the ghost zones at refinement interfaces.
+ */
+#[allow(clippy::too_many_arguments)]
+fn advance_block<H, M, C, P>(
+    index: BlockIndex,
+    state: &BlockState<C>,
+    hydro: &H,
+    model: &M,
+    geometry: &GridGeometry,
+    boundaries: &Boundaries,
+    fine_interface: Option<&FineInterface>,
+    stage: &Stage<P>,
+    neighbors: (Option<&Stage<P>>, Option<&Stage<P>>),
+    scratch: &mut BlockScratch<P, C>,
+    time: f64,
+    dt: f64,
+    homologous: bool,
+    one_dimensional: bool,
+    polar_merge_zones: usize) -> anyhow::Result<BlockState<C>>
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P>,
+    M: InitialModel,
+    C: Conserved,
+    P: Primitive
+{
+    let BlockScratch{pe, se, gp, gs, fx, sx, fy, sy, times} = scratch;
+    let mut stopwatch = Stopwatch::start();
+    let (p0, s0) = stage;
+    let (nr, nq) = p0.dim();
+    let (pl, sl) = match neighbors.0 {
+        Some(stage) => stage.clone(),
+        None => boundary::radial_ghost_zones(&boundaries.inner, Side::Inner, p0.view(), s0.view(), hydro)
+            .ok_or_else(|| anyhow::anyhow!("block {:?} has no inner neighbor for the inflow_from_model boundary", index))?,
+    };
+    let (pr, sr) = match neighbors.1 {
+        Some(stage) => stage.clone(),
+        None => boundary::radial_ghost_zones(&boundaries.outer, Side::Outer, p0.view(), s0.view(), hydro)
+            .ok_or_else(|| anyhow::anyhow!("block {:?} has no outer neighbor for the inflow_from_model boundary", index))?,
+    };
+
+    // Ghost zones from a neighbor block with a different polar zone
+    // count are restricted (if the neighbor is finer) or prolonged
+    // (if it is coarser) to this block's polar zones.
+    let pl = pl.slice(s![-2.., ..]);
+    let sl = sl.slice(s![-2.., ..]);
+    let pr = pr.slice(s![..2, ..]);
+    let sr = sr.slice(s![..2, ..]);
+    let kr = nq / pr.dim().1;
+
+    match &fine_interface {
+        Some(fine) => {
+            pe.slice_mut(s![..2, 1..nq + 1]).assign(&restrict_polar(pl, fine.volumes.view(), fine.factor));
+            se.slice_mut(s![..2, 1..nq + 1]).assign(&restrict_polar(sl, fine.volumes.view(), fine.factor));
+        }
+        None => {
+            pe.slice_mut(s![..2, 1..nq + 1]).assign(&pl);
+            se.slice_mut(s![..2, 1..nq + 1]).assign(&sl);
+        }
+    }
+    pe.slice_mut(s![2..nr + 2, 1..nq + 1]).assign(p0);
+    se.slice_mut(s![2..nr + 2, 1..nq + 1]).assign(s0);
+
+    if kr == 1 {
+        pe.slice_mut(s![nr + 2.., 1..nq + 1]).assign(&pr);
+        se.slice_mut(s![nr + 2.., 1..nq + 1]).assign(&sr);
+    } else {
+        pe.slice_mut(s![nr + 2.., 1..nq + 1]).assign(&prolong_polar(pr, kr));
+        se.slice_mut(s![nr + 2.., 1..nq + 1]).assign(&prolong_polar(sr, kr));
+    }
+
+    for i in 1..nr + 3 {
+        for j in 1..nq + 1 {
+            gp[(i, j)] = hydro.plm_gradient_primitive(&pe[(i - 1, j)], &pe[(i, j)], &pe[(i + 1, j)]);
+            gs[(i, j)] = hydro.plm_gradient_scalar(&se[(i - 1, j)], &se[(i, j)], &se[(i + 1, j)]);
+        }
+    }
+    stopwatch.lap(&mut times.reconstruction);
+
+    // The radial face i is between the extended zones i + 1 and i + 2.
+    for i in 0..nr + 1 {
+        for j in 0..nq {
+            let (l, r) = ((i + 1, j + 1), (i + 2, j + 1));
+            let (pl, pr) = (pe[l] + gp[l] * 0.5, pe[r] - gp[r] * 0.5);
+            let (sl, sr) = (se[l] + gs[l] * 0.5, se[r] - gs[r] * 0.5);
+            let flux = hydro.intercell_flux(pl, pr, sl, sr, Direction::Radial);
+            let (f, g) = if homologous {
+                moving_face_flux(hydro, flux, pl, pr, sl, sr, geometry.radial_vertices[i] / time)
+            } else {
+                flux
+            };
+            fx[(i, j)] = f * geometry.radial_face_areas[(i, j)];
+            sx[(i, j)] = g * geometry.radial_face_areas[(i, j)];
+        }
+    }
+
+    // At an interface with a finer inner neighbor, the fluxes through
+    // the inner face are the sums of fluxes through the fine faces.
+    // These are computed from the same stencil the fine block uses
+    // at its outer face, so the two blocks exchange identical
+    // fluxes.
+    if let Some(fine) = &fine_interface {
+        let k = fine.factor;
+        let pf = concatenate(Axis(0), &[pl, prolong_polar(p0.slice(s![..2, ..]), k).view()]).unwrap();
+        let sf = concatenate(Axis(0), &[sl, prolong_polar(s0.slice(s![..2, ..]), k).view()]).unwrap();
+        let gf = ndarray_ops::map_stencil3(&pf, Axis(0), |a, b, c| hydro.plm_gradient_primitive(a, b, c));
+        let hf = ndarray_ops::map_stencil3(&sf, Axis(0), |a, b, c| hydro.plm_gradient_scalar(a, b, c));
+
+        for j in 0..nq {
+            let (mut f, mut g) = (C::default(), 0.0);
+
+            for m in j * k..(j + 1) * k {
+                let (pl, pr) = (pf[(1, m)] + gf[(0, m)] * 0.5, pf[(2, m)] - gf[(1, m)] * 0.5);
+                let (sl, sr) = (sf[(1, m)] + hf[(0, m)] * 0.5, sf[(2, m)] - hf[(1, m)] * 0.5);
+                let flux = hydro.intercell_flux(pl, pr, sl, sr, Direction::Radial);
+                let (df, dg) = if homologous {
+                    moving_face_flux(hydro, flux, pl, pr, sl, sr, geometry.radial_vertices[0] / time)
+                } else {
+                    flux
+                };
+                f = f + df * fine.face_areas[m];
+                g += dg * fine.face_areas[m];
+            }
+            fx[(0, j)] = f;
+            sx[(0, j)] = g;
+        }
+    }
+    stopwatch.lap(&mut times.riemann);
+
+    // In 1D there are no polar fluxes, so no pressure forces on the polar
+    // faces either; each ray is a spherically symmetric flow. Otherwise the
+    // polar ghost zones of the interior rows are filled, and the radial
+    // gradients there are overwritten with polar ones.
+    if !one_dimensional {
+        boundary::fill_polar_ghost_zones(pe.slice_mut(s![2..nr + 2, ..]), &boundaries.polar, |p| boundary::reflect(hydro, p, Direction::Polar));
+        boundary::fill_polar_ghost_zones(se.slice_mut(s![2..nr + 2, ..]), &boundaries.polar, |s| *s);
+
+        for i in 2..nr + 2 {
+            gp[(i, 0)] = P::default();
+            gs[(i, 0)] = 0.0;
+            gp[(i, nq + 1)] = P::default();
+            gs[(i, nq + 1)] = 0.0;
+
+            for j in 1..nq + 1 {
+                gp[(i, j)] = hydro.plm_gradient_primitive(&pe[(i, j - 1)], &pe[(i, j)], &pe[(i, j + 1)]);
+                gs[(i, j)] = hydro.plm_gradient_scalar(&se[(i, j - 1)], &se[(i, j)], &se[(i, j + 1)]);
+            }
+        }
+        stopwatch.lap(&mut times.reconstruction);
+
+        // The polar face j is between the extended zones j and j + 1.
+        for i in 0..nr {
+            for j in 0..nq + 1 {
+                let (l, r) = ((i + 2, j), (i + 2, j + 1));
+                let (f, g) = hydro.intercell_flux(
+                    pe[l] + gp[l] * 0.5, pe[r] - gp[r] * 0.5,
+                    se[l] + gs[l] * 0.5, se[r] - gs[r] * 0.5, Direction::Polar);
+                fy[(i, j)] = f * geometry.polar_face_areas[(i, j)];
+                sy[(i, j)] = g * geometry.polar_face_areas[(i, j)];
+            }
+        }
+        stopwatch.lap(&mut times.riemann);
+    }
+
+    let coordinates = geometry.coordinates;
+    let mut conserved = state.conserved.to_owned();
+    let mut scalar_mass = state.scalar_mass.to_owned();
+
+    for i in 0..nr {
+        for j in 0..nq {
+            let c = geometry.cell_centers[(i, j)];
+            let dv = geometry.cell_volumes[(i, j)];
+            let da_r = geometry.radial_face_areas[(i + 1, j)] - geometry.radial_face_areas[(i, j)];
+            let da_q = if one_dimensional {
+                0.0
+            } else {
+                geometry.polar_face_areas[(i, j + 1)] - geometry.polar_face_areas[(i, j)]
+            };
+            let source = model.conserved_source_at(coordinates.to_spherical(c), time);
+            let source = hydro.interpret_conserved(&coordinates.conserved_from_spherical(c, source)) * dv;
+            let injection = model.scalar_injection_at(coordinates.to_spherical(c), time) * dv;
+
+            let mut du = hydro.geometrical_source_terms(p0[(i, j)], c, dv, (da_r, da_q), coordinates) - (fx[(i + 1, j)] - fx[(i, j)]) + source;
+            let mut ds = injection - (sx[(i + 1, j)] - sx[(i, j)]);
+
+            if !one_dimensional {
+                du = du - (fy[(i, j + 1)] - fy[(i, j)]);
+                ds -= sy[(i, j + 1)] - sy[(i, j)];
+            }
+            conserved[(i, j)] = conserved[(i, j)] + du * dt;
+            scalar_mass[(i, j)] += ds * dt;
+        }
+    }
+    merge_polar_axis_zones(&mut conserved, &mut scalar_mass, geometry, polar_merge_zones);
+    stopwatch.lap(&mut times.source);
+
+    BlockState {
+        conserved: conserved.into_shared(),
+        scalar_mass: scalar_mass.into_shared(),
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Advance the state by one Runge-Kutta stage. The geometry map is that of
+ * the mesh at `geometry_time`; for a homologous mesh, it is rescaled to the
+ * time of the stage. Block tasks are spawned in order of decreasing cost, as
+ * measured in the scratch arrays (see [`schedule`]).
+ */
+#[allow(clippy::too_many_arguments)]
 async fn try_advance_rk<H, M, C, P>(
     state: State<C>,
     hydro: &H,
     model: &M,
     mesh: &Mesh,
     geometry: &HashMap<BlockIndex, GridGeometry>,
+    geometry_time: f64,
     dt: f64,
-    runtime: &Runtime) -> anyhow::Result<State<C>, HydroError>
+    scratch: &Scratch<P, C>,
+    primitive: &PrimitiveCache<P>,
+    runtime: &Runtime) -> anyhow::Result<State<C>>
 where
     H: Hydrodynamics<Conserved = C, Primitive = P>,
     M: InitialModel,
     C: Conserved,
     P: Primitive
 {
-    let mut stage_map = HashMap::new();
+    let homologous = mesh.is_homologous();
+    let expansion = mesh.expansion_factor(state.time) / mesh.expansion_factor(geometry_time);
+    let scaled_geometry: HashMap<_, _>;
+    let geometry = if homologous {
+        scaled_geometry = geometry.iter().map(|(&index, g)| (index, g.scaled(expansion))).collect();
+        &scaled_geometry
+    } else {
+        geometry
+    };
+    // Only the slabs of each block which are ghost zones of its neighbors
+    // are shared between tasks. Each block task computes the stage data on
+    // its own interior.
+    let mut slab_map = HashMap::new();
     let mut new_state_vec = Vec::new();
-    let mut stage_primitive_and_scalar = |index: BlockIndex, state: BlockState<C>, hydro: H, geometry: GridGeometry| {
-        let stage = async move {
-            let p = state.try_to_primitive(&hydro, &geometry)?;
-            let s = state.scalar_mass / &geometry.cell_volumes / p.map(P::lorentz_factor);
-            Ok::<_, HydroError>( ( p.to_shared(), s.to_shared() ) )
+    let mut stage_slabs = |index: BlockIndex, state: BlockState<C>, hydro: H, geometry: GridGeometry, primitive: Option<ArcArray<P, Ix2>>| {
+        let slabs = async move {
+            try_stage_slabs(&state, &hydro, &geometry, primitive.as_ref())
         };
-        stage_map.insert(index, runtime.spawn(stage).map(|f| f.unwrap()).shared());
+        slab_map.insert(index, runtime.spawn(slabs).map(|f| f.unwrap()).shared());
     };
 
-    for (index, state) in &state.solution {
-        stage_primitive_and_scalar(index.clone(), state.clone(), hydro.clone(), geometry[index].clone())
+    let time = state.time;
+    let order = schedule(&state, &scratch.costs());
+
+    for index in &order {
+        stage_slabs(*index, state.solution[index].clone(), hydro.clone(), geometry[index].clone(), primitive.get(time, *index).cloned())
     }
 
     let one_dimensional = mesh.num_polar_zones == 1;
-    let (inner_bnd_index, outer_bnd_index) = state.inner_outer_boundary_indexes();
-    let inner_bnd_geom = mesh.subgrid(inner_bnd_index).geometry();
-    let outer_bnd_geom = mesh.subgrid(outer_bnd_index).geometry();
-    let inner_bnd_state = BlockState::from_model(model, hydro, &inner_bnd_geom, state.time);
-    let outer_bnd_state = BlockState::from_model(model, hydro, &outer_bnd_geom, state.time);
-    stage_primitive_and_scalar(inner_bnd_index, inner_bnd_state, hydro.clone(), inner_bnd_geom);
-    stage_primitive_and_scalar(outer_bnd_index, outer_bnd_state, hydro.clone(), outer_bnd_geom);
-
-    // Putting the stage map under Arc can be important for performance.
+    let polar_merge_zones = mesh.polar_merge_zones;
+
+    // Ghost blocks are only staged for boundaries whose ghost zones come
+    // from the model. For other boundary types, the ghost zones are
+    // generated below from the data on the edge block.
+    for (inner_bnd_index, outer_bnd_index) in state.inner_outer_boundary_indexes() {
+        for (bnd_index, bnd) in [(inner_bnd_index, &mesh.boundaries.inner), (outer_bnd_index, &mesh.boundaries.outer)].iter() {
+            if bnd.is_from_model() {
+                let bnd_geom = mesh.subgrid_geometry(*bnd_index, state.time);
+                let bnd_state = BlockState::from_model(model, hydro, &bnd_geom, state.time, mesh.quadrature_points);
+                stage_slabs(*bnd_index, bnd_state, hydro.clone(), bnd_geom, None);
+            }
+        }
+    }
+
+    // Putting the slab map under Arc can be important for performance.
     // Without it, the map is deep-copied for each block in the loop below.
     // Although each entry is a lightweight object, the map itself can have
     // ~1000 entries, and cloning it ~1000 times can induce measurable
     // overhead.
-    let stage_map = Arc::new(stage_map);
+    let slab_map = Arc::new(slab_map);
 
-    for (&index, state) in &state.solution {
+    for index in order {
 
         let hydro = hydro.clone();
-        let state = state.clone();
-        let stage_map = stage_map.clone();
+        let model = model.clone();
+        let state = state.solution[&index].clone();
+        let slab_map = slab_map.clone();
         let geometry = geometry[&index].clone();
+        let boundaries = mesh.boundaries.clone();
+        let fine_interface = FineInterface::at(mesh, index, time);
+        let scratch = scratch.get(index, state.conserved.dim());
+        let primitive = primitive.get(time, index).cloned();
 
         let entry = async move {
             let il = (index.0 - 1, index.1);
-            let i0 = (index.0,     index.1);
             let ir = (index.0 + 1, index.1);
 
-            let (pl, sl) = stage_map[&il].clone().await?;
-            let (p0, s0) = stage_map[&i0].clone().await?;
-            let (pr, sr) = stage_map[&ir].clone().await?;
-            let pe = concatenate(Axis(0), &[pl.slice(s![-2.., ..]), p0.view(), pr.slice(s![..2, ..])]).unwrap();
-            let se = concatenate(Axis(0), &[sl.slice(s![-2.., ..]), s0.view(), sr.slice(s![..2, ..])]).unwrap();
-
-            let gx = ndarray_ops::map_stencil3(&pe, Axis(0), |a, b, c| hydro.plm_gradient_primitive(a, b, c));
-            let hx = ndarray_ops::map_stencil3(&se, Axis(0), |a, b, c| hydro.plm_gradient_scalar(a, b, c));
-            let pxl = pe.slice(s![1..-2, ..]);
-            let pxr = pe.slice(s![2..-1, ..]);
-            let gxl = gx.slice(s![ ..-1, ..]);
-            let gxr = gx.slice(s![1..  , ..]);
-            let sxl = se.slice(s![1..-2, ..]);
-            let sxr = se.slice(s![2..-1, ..]);
-            let hxl = hx.slice(s![ ..-1, ..]);
-            let hxr = hx.slice(s![1..  , ..]);
-
-            let godunov_x = Array::from_shape_fn(pxl.dim(), |i| {
-                hydro.intercell_flux(
-                    pxl[i] + gxl[i] * 0.5, pxr[i] - gxr[i] * 0.5,
-                    sxl[i] + hxl[i] * 0.5, sxr[i] - hxr[i] * 0.5, Direction::Radial)
-            });
-
-            let fx = godunov_x.mapv(|(f, _)| f) * &geometry.radial_face_areas;
-            let gx = godunov_x.mapv(|(_, g)| g) * &geometry.radial_face_areas;
-
-            let (du, ds) = if one_dimensional {
-                let sc = ndarray::azip![&p0, &geometry.cell_centers, &geometry.cell_volumes]
-                    .apply_collect(|&p, &c, &dv| hydro.geometrical_source_terms(p, c) * dv);
-                let du = ndarray::azip![&sc, fx.slice(s![..-1,..]), fx.slice(s![ 1..,..])].apply_collect(|&s, &a, &b| (s - (b - a)) * dt);
-                let ds = ndarray::azip![     gx.slice(s![..-1,..]), gx.slice(s![ 1..,..])].apply_collect(|&a, &b| (b - a) * -dt);
-
-                (du, ds)
-            } else {
-                let gy = ndarray_ops::map_stencil3(&pe, Axis(1), |a, b, c| hydro.plm_gradient_primitive(a, b, c));
-                let gy = ndarray_ops::extend_default_2d(gy, 0, 0, 1, 1);
-                let hy = ndarray_ops::map_stencil3(&se, Axis(1), |a, b, c| hydro.plm_gradient_scalar(a, b, c));
-                let hy = ndarray_ops::extend_default_2d(hy, 0, 0, 1, 1);
-
-                let pyl = pe.slice(s![2..-2,  ..-1]);
-                let pyr = pe.slice(s![2..-2, 1..  ]);
-                let gyl = gy.slice(s![2..-2,  ..-1]);
-                let gyr = gy.slice(s![2..-2, 1..  ]);
-                let syl = se.slice(s![2..-2,  ..-1]);
-                let syr = se.slice(s![2..-2, 1..  ]);
-                let hyl = hy.slice(s![2..-2,  ..-1]);
-                let hyr = hy.slice(s![2..-2, 1..  ]);
-
-                let godunov_y = Array::from_shape_fn(pyl.dim(), |i| {
-                    hydro.intercell_flux(
-                        pyl[i] + gyl[i] * 0.5, pyr[i] - gyr[i] * 0.5,
-                        syl[i] + hyl[i] * 0.5, syr[i] - hyr[i] * 0.5, Direction::Polar)
-                });
-
-                let fy = ndarray_ops::extend_default_2d(godunov_y.mapv(|(f, _)| f), 0, 0, 1, 1) * &geometry.polar_face_areas;
-                let gy = ndarray_ops::extend_default_2d(godunov_y.mapv(|(_, g)| g), 0, 0, 1, 1) * &geometry.polar_face_areas;
-
-                let sc = ndarray::azip![
-                    &p0,
-                    &geometry.cell_centers,
-                    &geometry.cell_volumes]
-                .apply_collect(|&p, &c, &dv| hydro.geometrical_source_terms(p, c) * dv);
-
-                let du = ndarray::azip![
-                    &sc,
-                    fx.slice(s![..-1,..]),
-                    fx.slice(s![ 1..,..]),
-                    fy.slice(s![..,..-1]),
-                    fy.slice(s![.., 1..])]
-                .apply_collect(|&s, &a, &b, &c, &d| (s - (b - a) - (d - c)) * dt);
-
-                let ds = ndarray::azip![
-                    gx.slice(s![..-1,..]),
-                    gx.slice(s![ 1..,..]),
-                    gy.slice(s![..,..-1]),
-                    gy.slice(s![.., 1..])]
-                .apply_collect(|&a, &b, &c, &d| ((b - a) + (d - c)) * -dt);
-
-                (du, ds)
+            let mut stopwatch = Stopwatch::start();
+            let mut c2p = 0.0;
+            let stage = try_stage(&state, &hydro, &geometry, primitive.as_ref())?;
+            stopwatch.lap(&mut c2p);
+            let inner = match slab_map.get(&il) {
+                Some(slabs) => Some(slabs.clone().await?.1),
+                None => None,
             };
-
-            let new_state = BlockState {
-                conserved: (&state.conserved + &du).to_shared(),
-                scalar_mass: (&state.scalar_mass + &ds).to_shared(),
+            let outer = match slab_map.get(&ir) {
+                Some(slabs) => Some(slabs.clone().await?.0),
+                None => None,
             };
-            Ok::<_, HydroError>((index, new_state))
+            let neighbors = (inner.as_ref(), outer.as_ref());
+            let mut scratch = scratch.lock().unwrap();
+            scratch.times.c2p += c2p;
+            let new_state = advance_block(index, &state, &hydro, &model, &geometry, &boundaries, fine_interface.as_ref(), &stage, neighbors, &mut scratch, time, dt, homologous, one_dimensional, polar_merge_zones)?;
+            Ok::<_, anyhow::Error>((index, new_state))
         };
         new_state_vec.push(runtime.spawn(entry));
     }
     let solution = join_all(new_state_vec).await
         .into_iter()
         .map(|f| f.unwrap())
-        .collect::<Result<_, _>>()
-        .map_err(|e| e.with_model())?;
+        .collect::<anyhow::Result<_>>()?;
 
     Ok(State {
         time: state.time + dt,
         iteration: state.iteration + 1,
         solution: solution,
+        excised: state.excised,
+        zone_updates: state.zone_updates,
+        tracers: state.tracers,
+        time_step_limit: state.time_step_limit,
+        probe_samples: state.probe_samples,
+    })
+}
+
+/**
+ * Advance the state by one Runge-Kutta stage, as in [`try_advance_rk`], but
+ * parallelized over the given groups of blocks on the current rayon thread
+ * pool. The slabs exchanged between blocks are computed in one parallel
+ * pass, and the block updates in a second, so no tasks are spawned.
+ */
+#[allow(clippy::too_many_arguments)]
+fn try_advance_rk_rayon<H, M, C, P>(
+    state: State<C>,
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    geometry: &HashMap<BlockIndex, GridGeometry>,
+    geometry_time: f64,
+    dt: f64,
+    scratch: &Scratch<P, C>,
+    primitive: &PrimitiveCache<P>,
+    groups: &[Vec<BlockIndex>]) -> anyhow::Result<State<C>>
+where
+    H: Hydrodynamics<Conserved = C, Primitive = P> + Sync,
+    M: InitialModel + Sync,
+    C: Conserved,
+    P: Primitive
+{
+    let homologous = mesh.is_homologous();
+    let expansion = mesh.expansion_factor(state.time) / mesh.expansion_factor(geometry_time);
+    let scaled_geometry: HashMap<_, _>;
+    let geometry = if homologous {
+        scaled_geometry = geometry.iter().map(|(&index, g)| (index, g.scaled(expansion))).collect();
+        &scaled_geometry
+    } else {
+        geometry
+    };
+    let mut boundary_blocks = Vec::new();
+
+    for (inner_bnd_index, outer_bnd_index) in state.inner_outer_boundary_indexes() {
+        for (bnd_index, bnd) in [(inner_bnd_index, &mesh.boundaries.inner), (outer_bnd_index, &mesh.boundaries.outer)].iter() {
+            if bnd.is_from_model() {
+                let bnd_geom = mesh.subgrid_geometry(*bnd_index, state.time);
+                let bnd_state = BlockState::from_model(model, hydro, &bnd_geom, state.time, mesh.quadrature_points);
+                boundary_blocks.push((*bnd_index, bnd_state, bnd_geom));
+            }
+        }
+    }
+
+    let slab_map: HashMap<_, _> = groups
+        .par_iter()
+        .flat_map_iter(|group| group.iter().map(|&index| {
+            try_stage_slabs(&state.solution[&index], hydro, &geometry[&index], primitive.get(state.time, index)).map(|s| (index, s))
+        }))
+        .chain(boundary_blocks.par_iter().map(|(index, block, geometry)| try_stage_slabs(block, hydro, geometry, None).map(|s| (*index, s))))
+        .collect::<Result<_, HydroError>>()
+        .map_err(|e| e.with_model())?;
+
+    let time = state.time;
+    let one_dimensional = mesh.num_polar_zones == 1;
+    let polar_merge_zones = mesh.polar_merge_zones;
+
+    let solution = groups
+        .par_iter()
+        .flat_map_iter(|group| group.iter().map(|&index| {
+            let block = &state.solution[&index];
+            let mut stopwatch = Stopwatch::start();
+            let mut c2p = 0.0;
+            let stage = try_stage(block, hydro, &geometry[&index], primitive.get(time, index))?;
+            stopwatch.lap(&mut c2p);
+            let inner = slab_map.get(&(index.0 - 1, index.1)).map(|slabs| &slabs.1);
+            let outer = slab_map.get(&(index.0 + 1, index.1)).map(|slabs| &slabs.0);
+            let fine_interface = FineInterface::at(mesh, index, time);
+            let scratch = scratch.get(index, block.conserved.dim());
+            let mut scratch = scratch.lock().unwrap();
+            scratch.times.c2p += c2p;
+            let new_state = advance_block(index, block, hydro, model, &geometry[&index], &mesh.boundaries, fine_interface.as_ref(), &stage, (inner, outer), &mut scratch, time, dt, homologous, one_dimensional, polar_merge_zones)?;
+            Ok::<_, anyhow::Error>((index, new_state))
+        }))
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(State {
+        time: state.time + dt,
+        iteration: state.iteration + 1,
+        solution,
+        excised: state.excised,
+        zone_updates: state.zone_updates,
+        tracers: state.tracers,
+        time_step_limit: state.time_step_limit,
+        probe_samples: state.probe_samples,
     })
 }
 
@@ -176,65 +846,441 @@ where
 
 
 // ============================================================================
+/**
+ * Remove blocks which are inside the inner excision surface, and add blocks
+ * which are inside the outer excision surface. If the mesh tracks the shock,
+ * the OES is placed ahead of the given outermost shocked radius. Return the
+ * net change in the number of zones on the mesh.
+ */
 fn add_remove_blocks<H, M, C>(
     state: &mut State<C>,
     hydro: &H,
     model: &M,
     mesh: &Mesh,
-    geometry: &mut HashMap<BlockIndex, GridGeometry>)
+    geometry: &mut HashMap<BlockIndex, GridGeometry>,
+    shock_radius: Option<f64>) -> isize
 where
     H: Hydrodynamics<Conserved = C>,
     M: InitialModel,
     C: Conserved
 {
-    let (inner_index, outer_index) = state.inner_outer_block_indexes();
-    let solution = &mut state.solution;
+    let mut zones_added = 0;
+    let outer_excision_surface = mesh
+        .shock_tracking_excision_surface(shock_radius)
+        .unwrap_or_else(|| mesh.outer_excision_surface(state.time));
 
-    if mesh.subgrid_extent(inner_index).outer_radius < mesh.inner_excision_surface(state.time) {
-        geometry.remove(&inner_index);
-        solution.remove(&inner_index);
-    }
+    for (inner_index, outer_index) in state.inner_outer_block_indexes() {
+        if mesh.subgrid_extent(inner_index).outer_radius < mesh.inner_excision_surface(state.time) {
+            geometry.remove(&inner_index);
+
+            if let Some(block) = state.solution.remove(&inner_index) {
+                state.excised.record(&block);
+                zones_added -= block.conserved.len() as isize;
+            }
+        }
 
-    if mesh.subgrid_extent(outer_index).outer_radius < mesh.outer_excision_surface(state.time) {
-        let new_block_index = (outer_index.0 + 1, outer_index.1);
-        let new_block_geometry = mesh.subgrid(new_block_index).geometry();
-        let new_block_state = BlockState::from_model(model, hydro, &new_block_geometry, state.time);
+        if mesh.subgrid_extent(outer_index).outer_radius < outer_excision_surface {
+            let new_block_index = (outer_index.0 + 1, outer_index.1);
+            let new_block_geometry = mesh.subgrid_geometry(new_block_index, state.time);
+            let new_block_state = BlockState::from_model(model, hydro, &new_block_geometry, state.time, mesh.quadrature_points);
 
-        geometry.insert(new_block_index, new_block_geometry);
-        solution.insert(new_block_index, new_block_state);
-    }    
+            zones_added += new_block_state.conserved.len() as isize;
+            geometry.insert(new_block_index, new_block_geometry);
+            state.solution.insert(new_block_index, new_block_state);
+        }
+    }
+    zones_added
 }
 
 
 
 
 // ============================================================================
+/**
+ * Advance the state by `fold` iterations, parallelized over blocks on the
+ * given tokio runtime. The probe points are sampled after each iteration
+ * (see [`State::probe_samples`]). If a profile is given, the wall time spent
+ * updating each block and between the Runge-Kutta stages is added to it.
+ */
+#[allow(clippy::too_many_arguments)]
 pub fn advance<H, M, C>(
-    mut state: State<C>,
+    state: State<C>,
     hydro: &H,
     model: &M,
     mesh: &Mesh,
     geometry: &mut HashMap<BlockIndex, GridGeometry>,
     runtime: &Runtime,
-    fold: usize) -> anyhow::Result<State<C>, HydroError>
+    fold: usize,
+    dt_factor: f64,
+    probe_points: &[(f64, f64)],
+    profile: Option<&mut Profile>) -> anyhow::Result<State<C>>
 where
     H: Hydrodynamics<Conserved = C>,
     M: InitialModel,
     C: Conserved
 {
     let runge_kutta = hydro.runge_kutta_order();
-    let dt = state.time_step(hydro, mesh)?;
+    let scratch = Scratch::new();
+    let clock = Mutex::new(StageClock::default());
+
+    let state = advance_with(state, hydro, model, mesh, geometry, fold, dt_factor, probe_points, |state, geometry, geometry_time, dt, primitive| {
+        let update = |state| async {
+            clock.lock().unwrap().stage_started();
+            let state = try_advance_rk(state, hydro, model, mesh, geometry, geometry_time, dt, &scratch, primitive, &runtime).await;
+            clock.lock().unwrap().stage_finished();
+            state
+        };
+        let state = runtime.block_on(runge_kutta.try_advance_async(state, update, runtime));
+        clock.lock().unwrap().step_finished();
+        state
+    })?;
+
+    if let Some(profile) = profile {
+        profile.accumulate(scratch.times(), clock.into_inner().unwrap().between_stages());
+    }
+    Ok(state)
+}
+
+/**
+ * Advance the state by `fold` iterations, parallelized over blocks on the
+ * current rayon thread pool. This is an alternative to [`advance`] without
+ * the overhead of spawning tasks on each Runge-Kutta stage, and whose
+ * call stacks are easier to profile. Blocks are updated in cost-balanced
+ * groups (see [`BlockGroups`]) rather than scheduled individually. The
+ * profile is as for [`advance`].
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn advance_rayon<H, M, C>(
+    state: State<C>,
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    geometry: &mut HashMap<BlockIndex, GridGeometry>,
+    fold: usize,
+    dt_factor: f64,
+    probe_points: &[(f64, f64)],
+    profile: Option<&mut Profile>) -> anyhow::Result<State<C>>
+where
+    H: Hydrodynamics<Conserved = C> + Sync,
+    M: InitialModel + Sync,
+    C: Conserved
+{
+    let runge_kutta = hydro.runge_kutta_order();
+    let scratch = Scratch::new();
+    let mut block_groups = BlockGroups::new();
+    let clock = Mutex::new(StageClock::default());
+
+    let state = advance_with(state, hydro, model, mesh, geometry, fold, dt_factor, probe_points, |state, geometry, geometry_time, dt, primitive| {
+        block_groups.update(&state, rayon::current_num_threads(), &scratch.costs());
+        let groups = &block_groups.groups;
+
+        let state = runge_kutta.try_advance(state, |state| {
+            clock.lock().unwrap().stage_started();
+            let state = try_advance_rk_rayon(state, hydro, model, mesh, geometry, geometry_time, dt, &scratch, primitive, groups);
+            clock.lock().unwrap().stage_finished();
+            state
+        });
+        clock.lock().unwrap().step_finished();
+        state
+    })?;
+
+    if let Some(profile) = profile {
+        profile.accumulate(scratch.times(), clock.into_inner().unwrap().between_stages());
+    }
+    Ok(state)
+}
+
+/**
+ * Advance the state by `fold` iterations, adding and removing blocks at the
+ * excision surfaces, rescaling the geometry of a homologous mesh, and moving
+ * the tracers, whose histories are sampled at the end of the fold. The
+ * time step and what limited it, and the samples at the given probe points
+ * after each iteration, are recorded on the returned state. The
+ * Runge-Kutta step is taken by the given function, which receives the
+ * state, the geometry map at the start of the step, its time, the time
+ * step size, and the primitive arrays computed to find the time step.
+ */
+#[allow(clippy::too_many_arguments)]
+fn advance_with<H, M, C, F>(
+    mut state: State<C>,
+    hydro: &H,
+    model: &M,
+    mesh: &Mesh,
+    geometry: &mut HashMap<BlockIndex, GridGeometry>,
+    fold: usize,
+    dt_factor: f64,
+    probe_points: &[(f64, f64)],
+    mut step: F) -> anyhow::Result<State<C>>
+where
+    H: Hydrodynamics<Conserved = C>,
+    M: InitialModel,
+    C: Conserved,
+    F: FnMut(State<C>, &HashMap<BlockIndex, GridGeometry>, f64, f64, &PrimitiveCache<H::Primitive>) -> anyhow::Result<State<C>>
+{
+    let (limit, primitive) = state.time_step_and_primitive(hydro, mesh, geometry)?;
+    let dt = limit.dt * dt_factor;
+    let primitive = PrimitiveCache{time: state.time, blocks: primitive};
+    let mut num_zones = state.total_zones() as isize;
+
+    let shock_radius = if mesh.outer_excision_shock_margin.is_some() {
+        state.outermost_shock_radius(hydro, geometry)?.map(|r| r / mesh.expansion_factor(state.time))
+    } else {
+        None
+    };
+
+    // The tracers are held outside the state while it is advanced, so
+    // they are not copied with the state in each Runge-Kutta stage.
+    let mut tracers = std::mem::take(&mut state.tracers);
+    let mut probe_samples = Vec::new();
 
     for _ in 0..fold {
 
         if mesh.moving_excision_surfaces() {
-            add_remove_blocks(&mut state, hydro, model, mesh, geometry);
+            num_zones += add_remove_blocks(&mut state, hydro, model, mesh, geometry, shock_radius);
         }
-        let update = |state| async {
-            try_advance_rk(state, hydro, model, mesh, geometry, dt, &runtime).await
-        };
+        tracers::advect(&mut tracers, &state, hydro, geometry, dt)?;
+
+        let geometry_time = state.time;
+        state = step(state, geometry, geometry_time, dt, &primitive)?;
+
+        if mesh.is_homologous() {
+            let expansion = mesh.expansion_factor(state.time) / mesh.expansion_factor(geometry_time);
+            for block_geometry in geometry.values_mut() {
+                *block_geometry = block_geometry.scaled(expansion);
+            }
+        }
+        state.zone_updates += num_zones as u64;
 
-        state = runtime.block_on(runge_kutta.try_advance_async(state, update, runtime))?;
+        if !probe_points.is_empty() {
+            probe_samples.push((state.time, probes::sample(&state, hydro, mesh, geometry, probe_points)?));
+        }
     }
+    tracers::record(&mut tracers, &state, hydro, geometry)?;
+    state.tracers = tracers;
+    state.time_step_limit = Some(TimeStepLimit{dt, ..limit});
+    state.probe_samples = probe_samples;
+
     Ok(state)
 }
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::PolarGrading;
+    use crate::physics::{AnyPrimitive, NewtonianHydro};
+
+    #[derive(Clone)]
+    struct UniformMedium;
+
+    impl InitialModel for UniformMedium {
+        fn validate(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn primitive_at(&self, _coordinate: (f64, f64), _time: f64) -> AnyPrimitive {
+            AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 1.0, gas_pressure: 1.0}
+        }
+        fn scalar_at(&self, _coordinate: (f64, f64), _time: f64) -> f64 {
+            0.0
+        }
+    }
+
+    fn moving_excision_mesh() -> Mesh {
+        serde_yaml::from_str("
+            reference_radius: 1.0
+            inner_radius: 1.0
+            outer_radius: 10.0
+            inner_excision_speed: 1.0
+            outer_excision_speed: 2.0
+            num_radial_zones: 32
+            num_polar_zones: 4
+            block_size: 8
+        ").unwrap()
+    }
+
+    fn hydro() -> NewtonianHydro {
+        serde_yaml::from_str("
+            gamma_law_index: 1.333
+            plm_theta: 1.5
+            cfl_number: 0.3
+            runge_kutta_order: RK2
+        ").unwrap()
+    }
+
+    fn run_until<M: InitialModel>(model: &M, mesh: &Mesh, tfinal: f64) -> (State<hydro_euler::euler_2d::Conserved>, HashMap<BlockIndex, GridGeometry>) {
+        let hydro = hydro();
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).build().unwrap();
+        let mut geometry = mesh.grid_blocks_geometry(0.0);
+        let mut state = State::from_model(model, &hydro, &geometry, 0.0, mesh.quadrature_points);
+
+        while state.time < tfinal {
+            state = advance(state, &hydro, model, mesh, &mut geometry, &runtime, 10, 1.0, &[], None).unwrap();
+        }
+        (state, geometry)
+    }
+
+    #[test]
+    fn uniform_medium_stays_uniform_with_moving_excision_surfaces() {
+        let mesh = moving_excision_mesh();
+        let hydro = hydro();
+        let (state, geometry) = run_until(&UniformMedium, &mesh, 2.0);
+
+        for (index, block) in &state.solution {
+            for p in block.try_to_primitive(&hydro, &geometry[index]).unwrap().iter() {
+                let p = hydro.any(p);
+                assert!((p.mass_density - 1.0).abs() < 1e-10);
+                assert!((p.gas_pressure - 1.0).abs() < 1e-10);
+                assert!(p.velocity_r.abs() < 1e-10 && p.velocity_q.abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn block_map_stays_contiguous_with_moving_excision_surfaces() {
+        let mesh = moving_excision_mesh();
+        let initial_indexes = State::from_model(&UniformMedium, &hydro(), &mesh.grid_blocks_geometry(0.0), 0.0, 1).inner_outer_block_indexes();
+        let (state, geometry) = run_until(&UniformMedium, &mesh, 2.0);
+        let final_indexes = state.inner_outer_block_indexes();
+
+        assert_eq!(state.solution.len(), geometry.len());
+        assert!(state.solution.keys().all(|index| geometry.contains_key(index)));
+        assert!(final_indexes[0].0.0 > initial_indexes[0].0.0, "no blocks were removed");
+        assert!(final_indexes[0].1.0 > initial_indexes[0].1.0, "no blocks were added");
+
+        for (inner, outer) in final_indexes {
+            for i in inner.0..outer.0 {
+                let this = (i, inner.1);
+                let next = (i + 1, inner.1);
+                assert!(state.solution.contains_key(&this) && state.solution.contains_key(&next), "gap at block {:?}", this);
+
+                let r0 = geometry[&this].radial_vertices[geometry[&this].radial_vertices.len() - 1];
+                let r1 = geometry[&next].radial_vertices[0];
+                assert!((r0 - r1).abs() < 1e-12 * r0, "blocks {:?} and {:?} do not abut", this, next);
+            }
+        }
+    }
+
+    #[test]
+    fn block_groups_are_contiguous_and_balanced() {
+        let mesh = moving_excision_mesh();
+        let state = State::from_model(&UniformMedium, &hydro(), &mesh.grid_blocks_geometry(0.0), 0.0, 1);
+        let mut blocks: Vec<_> = state.solution.keys().copied().collect();
+        blocks.sort_unstable();
+
+        let mut block_groups = BlockGroups::new();
+        block_groups.update(&state, 3, &HashMap::new());
+
+        assert_eq!(block_groups.groups.len(), 3);
+        assert_eq!(block_groups.groups.concat(), blocks);
+        assert!(block_groups.groups.iter().all(|group| group.len() <= 2));
+    }
+
+    #[test]
+    fn block_groups_are_balanced_by_measured_cost() {
+        let mesh = moving_excision_mesh();
+        let state = State::from_model(&UniformMedium, &hydro(), &mesh.grid_blocks_geometry(0.0), 0.0, 1);
+        let mut blocks: Vec<_> = state.solution.keys().copied().collect();
+        blocks.sort_unstable();
+
+        let mut costs: HashMap<_, _> = blocks.iter().map(|&index| (index, 1.0)).collect();
+        costs.insert(blocks[0], blocks.len() as f64);
+
+        let mut block_groups = BlockGroups::new();
+        block_groups.update(&state, 2, &HashMap::new());
+        block_groups.update(&state, 2, &costs);
+
+        assert_eq!(block_groups.groups[0], vec![blocks[0]]);
+        assert_eq!(block_groups.groups.concat(), blocks);
+        assert_eq!(schedule(&state, &costs)[0], blocks[0]);
+    }
+
+    #[test]
+    fn merging_polar_axis_zones_conserves_totals() {
+        #[derive(Clone)]
+        struct PolarGradient;
+
+        impl InitialModel for PolarGradient {
+            fn validate(&self) -> anyhow::Result<()> {
+                Ok(())
+            }
+            fn primitive_at(&self, coordinate: (f64, f64), _time: f64) -> AnyPrimitive {
+                AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 1.0 + coordinate.1, gas_pressure: 1.0}
+            }
+            fn scalar_at(&self, coordinate: (f64, f64), _time: f64) -> f64 {
+                coordinate.1
+            }
+        }
+
+        let mesh = moving_excision_mesh();
+        let geometry = mesh.subgrid_geometry((0, 0), 0.0);
+        let block = BlockState::from_model(&PolarGradient, &hydro(), &geometry, 0.0, 1);
+        let mut conserved = block.conserved.to_owned();
+        let mut scalar_mass = block.scalar_mass.to_owned();
+
+        merge_polar_axis_zones(&mut conserved, &mut scalar_mass, &geometry, 2);
+
+        let mass0: f64 = block.conserved.iter().map(Conserved::lab_frame_mass).sum();
+        let mass1: f64 = conserved.iter().map(Conserved::lab_frame_mass).sum();
+        let scalar0: f64 = block.scalar_mass.sum();
+        let scalar1: f64 = scalar_mass.sum();
+
+        assert!((mass0 - mass1).abs() < 1e-12 * mass0);
+        assert!((scalar0 - scalar1).abs() < 1e-12 * scalar0);
+
+        for i in 0..conserved.dim().0 {
+            let rho = |j: usize| conserved[(i, j)].lab_frame_mass() / geometry.cell_volumes[(i, j)];
+            assert!((rho(0) - rho(1)).abs() < 1e-12 * rho(0));
+            assert!((rho(2) - rho(3)).abs() < 1e-12 * rho(2));
+        }
+    }
+
+    #[test]
+    fn merging_polar_axis_zones_is_stable_for_an_axial_jet_and_increases_the_time_step() {
+        #[derive(Clone)]
+        struct AxialJet;
+
+        impl InitialModel for AxialJet {
+            fn validate(&self) -> anyhow::Result<()> {
+                Ok(())
+            }
+            fn primitive_at(&self, coordinate: (f64, f64), _time: f64) -> AnyPrimitive {
+                if coordinate.1 < 0.2 || coordinate.1 > std::f64::consts::PI - 0.2 {
+                    AnyPrimitive{velocity_r: 1.0, velocity_q: 0.0, mass_density: 0.1, gas_pressure: 0.01}
+                } else {
+                    AnyPrimitive{velocity_r: 0.0, velocity_q: 0.0, mass_density: 1.0, gas_pressure: 0.01}
+                }
+            }
+            fn scalar_at(&self, _coordinate: (f64, f64), _time: f64) -> f64 {
+                0.0
+            }
+        }
+
+        let mesh = |polar_merge_zones| Mesh {
+            num_polar_zones: 64,
+            polar_grading: PolarGrading::Tanh{clustering: 1.82},
+            polar_merge_zones,
+            inner_excision_speed: 0.0,
+            outer_excision_speed: 0.0,
+            ..moving_excision_mesh()
+        };
+        let hydro = hydro();
+        let first_dt = |polar_merge_zones| run_until(&AxialJet, &mesh(polar_merge_zones), 1e-12).0.time_step_limit.unwrap().dt;
+        let (merged_dt, unmerged_dt) = (first_dt(4), first_dt(0));
+
+        assert!(merged_dt > unmerged_dt, "merged dt {:.3e} <= unmerged dt {:.3e}", merged_dt, unmerged_dt);
+
+        let (state, geometry) = run_until(&AxialJet, &mesh(4), 0.2);
+
+        for (index, block) in &state.solution {
+            for p in block.try_to_primitive(&hydro, &geometry[index]).unwrap().iter() {
+                let p = hydro.any(p);
+                assert!(p.mass_density > 0.0 && p.gas_pressure > 0.0);
+                assert!(p.velocity_r.is_finite() && p.velocity_q.is_finite());
+            }
+        }
+    }
+}
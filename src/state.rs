@@ -1,10 +1,15 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
 use num::ToPrimitive;
 use num::rational::Rational64;
 use serde::{Serialize, Deserialize};
-use ndarray::{Array, ArcArray, Ix2};
+use ndarray::{Array, ArcArray, Ix2, s};
 use godunov_core::runge_kutta;
-use crate::physics::HydroError;
+use crate::physics::{HydroError, HydroErrorType};
+use crate::probes;
+use crate::products;
+use crate::tracers::Tracer;
 use crate::traits::{
     Conserved,
     Hydrodynamics,
@@ -13,6 +18,7 @@ use crate::traits::{
 };
 use crate::mesh::{
     BlockIndex,
+    CoordinateSystem,
     Mesh,
     GridGeometry
 };
@@ -32,6 +38,44 @@ pub struct BlockState<C: Conserved> {
 
 
 
+/**
+ * Running totals of the conserved quantities which have been discarded from
+ * the solution when blocks were removed by the inner excision surface
+ */
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ExcisionLedger<C: Conserved> {
+
+    /// Volume-integrated conserved quantities in the removed blocks
+    pub conserved: C,
+
+    /// Volume-integrated scalar mass in the removed blocks
+    pub scalar_mass: f64,
+}
+
+
+
+
+/**
+ * The time step size of a fold of iterations, and what limited it
+ */
+#[derive(Clone, Copy)]
+pub struct TimeStepLimit {
+
+    /// The time step size, including any reduction by the safety ramp
+    pub dt: f64,
+
+    /// The block and zone whose signal crossing time set the time step, or
+    /// None if it was set by a global signal speed
+    pub zone: Option<(BlockIndex, (usize, usize))>,
+
+    /// The largest signal speed on the mesh, or the global signal speed if
+    /// the hydrodynamics has one
+    pub max_signal_speed: f64,
+}
+
+
+
+
 /**
  * The full solution state for the simulation
  */
@@ -39,7 +83,72 @@ pub struct BlockState<C: Conserved> {
 pub struct State<C: Conserved> {
     pub time: f64,
     pub iteration: Rational64,
+    #[serde(serialize_with = "crate::io::serialize_sorted")]
     pub solution: HashMap<BlockIndex, BlockState<C>>,
+
+    /// Content removed through the inner excision surface. This field is
+    /// absent from checkpoints written by older versions of the code.
+    #[serde(default)]
+    pub excised: ExcisionLedger<C>,
+
+    /// Running total of zone updates (the number of zones on the mesh,
+    /// summed over iterations), maintained by the scheme so that the
+    /// performance report needs no traversal of the blocks. This field is
+    /// absent from checkpoints written by older versions of the code.
+    #[serde(default)]
+    pub zone_updates: u64,
+
+    /// Lagrangian tracer particles, with their histories. This field is
+    /// absent from checkpoints written by older versions of the code.
+    #[serde(default)]
+    pub tracers: Vec<Tracer>,
+
+    /// The time step of the last fold of iterations, and what limited it,
+    /// maintained by the scheme so that the time step history needs no
+    /// traversal of the blocks. This is not written to checkpoints.
+    #[serde(skip)]
+    pub time_step_limit: Option<TimeStepLimit>,
+
+    /// The probe samples taken after each iteration of the last fold, with
+    /// their times, maintained by the scheme so that the probes are sampled
+    /// at every time step. This is not written to checkpoints.
+    #[serde(skip)]
+    pub probe_samples: Vec<(f64, probes::Samples)>,
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the radial and polar zone edges of a block, for remapping. The polar
+ * edges of a block on a 1D mesh with a single ray are taken to be the full
+ * polar range of the mesh, rather than the narrow band used for its geometry.
+ */
+fn remap_edges(mesh: &Mesh, geometry: &GridGeometry) -> (Vec<f64>, Vec<f64>) {
+    let r = geometry.radial_vertices.to_vec();
+    let q = if mesh.geometry == CoordinateSystem::Spherical && mesh.num_polar_zones == 1 && mesh.num_rays == 1 {
+        vec![mesh.theta_min, mesh.theta_max]
+    } else {
+        geometry.polar_vertices.to_vec()
+    };
+    (r, q)
+}
+
+/**
+ * Return the fraction of the interval a which is covered by the interval b,
+ * where the size of an interval is measured by differences of the function
+ * f.
+ */
+fn overlap_fraction(a: (f64, f64), b: (f64, f64), f: fn(f64) -> f64) -> f64 {
+    let lower = a.0.max(b.0);
+    let upper = a.1.min(b.1);
+
+    if upper <= lower {
+        0.0
+    } else {
+        (f(upper) - f(lower)) / (f(a.1) - f(a.0))
+    }
 }
 
 
@@ -50,17 +159,29 @@ impl<C: Conserved> BlockState<C> {
 
     /**
      * Generate a block state from the given initial model, hydrodynamics
-     * instance and grid geometry.
+     * instance and grid geometry. The conserved quantities and scalar mass in
+     * each zone are volume averages over a quadrature rule with the given
+     * number of points per dimension.
      */
-    pub fn from_model<M, H>(model: &M, hydro: &H, geometry: &GridGeometry, time: f64) -> Self
+    pub fn from_model<M, H>(model: &M, hydro: &H, geometry: &GridGeometry, time: f64, quadrature_points: usize) -> Self
     where
         M: InitialModel,
         H: Hydrodynamics<Conserved = C>
     {
-        let scalar      = geometry.cell_centers.mapv(|c| model.scalar_at(c, time));
-        let primitive   = geometry.cell_centers.mapv(|c| hydro.interpret(&model.primitive_at(c, time)));
-        let conserved   = primitive.mapv(|p| hydro.to_conserved(p)) * &geometry.cell_volumes;
-        let scalar_mass = conserved.mapv(|u| u.lab_frame_mass()) * scalar;
+        let zones = Array::from_shape_fn(geometry.cell_volumes.dim(), |index| {
+            geometry
+                .quadrature(index, quadrature_points)
+                .into_iter()
+                .map(|(c, w)| {
+                    let coordinates = geometry.coordinates;
+                    let p = coordinates.primitive_from_spherical(c, model.primitive_at(coordinates.to_spherical(c), time));
+                    let u = hydro.to_conserved(hydro.interpret(&p));
+                    (u * w, u.lab_frame_mass() * model.scalar_at(coordinates.to_spherical(c), time) * w)
+                })
+                .fold((C::default(), 0.0), |(u, s), (du, ds)| (u + du, s + ds))
+        });
+        let conserved   = zones.mapv(|(u, _)| u) * &geometry.cell_volumes;
+        let scalar_mass = zones.mapv(|(_, s)| s) * &geometry.cell_volumes;
 
         Self {
             conserved: conserved.to_shared(),
@@ -82,10 +203,28 @@ impl<C: Conserved> BlockState<C> {
         C: Conserved,
         P: Primitive  
     {
-        let u = &self.conserved / &geometry.cell_volumes;
+        self.try_to_primitive_rows(hydro, geometry, 0..self.conserved.dim().0)
+    }
+
+    /**
+     * Like [`BlockState::try_to_primitive`], but only convert the given range
+     * of radial zones. This is used to produce the ghost zone slabs which
+     * are exchanged between neighboring blocks.
+     */
+    pub fn try_to_primitive_rows<H, P>(
+        &self,
+        hydro: &H,
+        geometry: &GridGeometry,
+        rows: Range<usize>) -> anyhow::Result<Array<P, Ix2>, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C, Primitive = P>,
+        C: Conserved,
+        P: Primitive
+    {
+        let u = &self.conserved.slice(s![rows.clone(), ..]) / &geometry.cell_volumes.slice(s![rows.clone(), ..]);
         let x: Result<Vec<_>, _> = u
             .iter()
-            .zip(geometry.cell_centers.iter())
+            .zip(geometry.cell_centers.slice(s![rows, ..]).iter())
             .map(|(&u, &rq)| hydro
                 .try_to_primitive(u)
                 .map_err(|e| e.at_position(rq)))
@@ -97,21 +236,143 @@ impl<C: Conserved> BlockState<C> {
 
 
 
+// ============================================================================
+impl<C: Conserved> ExcisionLedger<C> {
+
+    /**
+     * Add the contents of a block which is being removed from the solution
+     * to the running totals.
+     */
+    pub fn record(&mut self, block: &BlockState<C>) {
+        self.conserved = block.conserved.iter().fold(self.conserved, |total, &u| total + u);
+        self.scalar_mass += block.scalar_mass.sum();
+    }
+}
+
+
+
+
 // ============================================================================
 impl<C: Conserved> State<C> {
 
     /**
      * Generate a state from the given initial model, hydrodynamics instance,
-     * and map of grid geometry.
+     * and map of grid geometry, using the given number of quadrature points
+     * per dimension in each zone.
      */
-    pub fn from_model<M, H>(model: &M, hydro: &H, geometry: &HashMap<BlockIndex, GridGeometry>, time: f64) -> Self
+    pub fn from_model<M, H>(model: &M, hydro: &H, geometry: &HashMap<BlockIndex, GridGeometry>, time: f64, quadrature_points: usize) -> Self
     where
         M: InitialModel,
         H: Hydrodynamics<Conserved = C>
     {
         let iteration = Rational64::new(0, 1);
-        let solution = geometry.iter().map(|(&i, g)| (i, BlockState::from_model(model, hydro, g, time))).collect();
-        Self{time, iteration, solution}
+        let solution = geometry.iter().map(|(&i, g)| (i, BlockState::from_model(model, hydro, g, time, quadrature_points))).collect();
+        let excised = ExcisionLedger::default();
+        Self{time, iteration, solution, excised, zone_updates: 0, tracers: Vec::new(), time_step_limit: None, probe_samples: Vec::new()}
+    }
+
+    /**
+     * Return a state on a new mesh, conservatively remapped from this state
+     * on the old mesh. The conserved quantities and scalar mass of each old
+     * zone are divided among the new zones it overlaps, in proportion to the
+     * overlapping volume. Any part of a new zone not covered by the old mesh
+     * is filled in from the model. Both meshes must have the same coordinate
+     * system.
+     */
+    pub fn remap<M, H>(&self, model: &M, hydro: &H, old_mesh: &Mesh, new_mesh: &Mesh) -> Self
+    where
+        M: InitialModel,
+        H: Hydrodynamics<Conserved = C>
+    {
+        let (r3, mcos): (fn(f64) -> f64, fn(f64) -> f64) = match new_mesh.geometry {
+            CoordinateSystem::Spherical   => (|r| r * r * r, |q| -q.cos()),
+            CoordinateSystem::Cylindrical => (|r| r * r, |z| z),
+        };
+
+        let old_blocks: Vec<_> = self
+            .sorted_blocks()
+            .into_iter()
+            .map(|(index, block)| (block, remap_edges(old_mesh, &old_mesh.subgrid_geometry(*index, self.time))))
+            .collect();
+
+        // The old mesh may extend beyond its outer excision surface, if the
+        // OES tracks the shock, so the new blocks covering it are included.
+        let old_outer_radius = self
+            .solution
+            .keys()
+            .map(|&index| old_mesh.subgrid_extent(index).outer_radius * old_mesh.expansion_factor(self.time))
+            .fold(0.0, f64::max);
+        let mut new_geometry = new_mesh.grid_blocks_geometry(self.time);
+
+        for j in 0..new_mesh.num_rays {
+            for i in 0.. {
+                if new_mesh.subgrid_extent((i, j)).inner_radius * new_mesh.expansion_factor(self.time) >= old_outer_radius {
+                    break
+                }
+                new_geometry.entry((i, j)).or_insert_with(|| new_mesh.subgrid_geometry((i, j), self.time));
+            }
+        }
+        let mut solution = HashMap::new();
+
+        for (index, geometry) in new_geometry {
+            if new_mesh.subgrid_extent(index).outer_radius < new_mesh.inner_excision_surface(self.time) {
+                continue
+            }
+            let model_block = BlockState::from_model(model, hydro, &geometry, self.time, new_mesh.quadrature_points);
+            let mut conserved = model_block.conserved.to_owned();
+            let mut scalar_mass = model_block.scalar_mass.to_owned();
+            let (rn, qn) = remap_edges(new_mesh, &geometry);
+
+            for i in 0..rn.len() - 1 {
+                for j in 0..qn.len() - 1 {
+                    let (rz, qz) = ((rn[i], rn[i + 1]), (qn[j], qn[j + 1]));
+                    let (mut u, mut s, mut covered) = (C::default(), 0.0, 0.0);
+
+                    for (block, (ro, qo)) in &old_blocks {
+                        if ro[ro.len() - 1] <= rz.0 || ro[0] >= rz.1 || qo[qo.len() - 1] <= qz.0 || qo[0] >= qz.1 {
+                            continue
+                        }
+                        let k0 = ro.partition_point(|&r| r <= rz.0).saturating_sub(1);
+                        let l0 = qo.partition_point(|&q| q <= qz.0).saturating_sub(1);
+
+                        for k in (k0..ro.len() - 1).take_while(|&k| ro[k] < rz.1) {
+                            for l in (l0..qo.len() - 1).take_while(|&l| qo[l] < qz.1) {
+                                let (rok, qol) = ((ro[k], ro[k + 1]), (qo[l], qo[l + 1]));
+                                let f = overlap_fraction(rok, rz, r3) * overlap_fraction(qol, qz, mcos);
+                                u = u + block.conserved[(k, l)] * f;
+                                s += block.scalar_mass[(k, l)] * f;
+                                covered += overlap_fraction(rz, rok, r3) * overlap_fraction(qz, qol, mcos);
+                            }
+                        }
+                    }
+                    let uncovered = (1.0 - covered).max(0.0);
+                    conserved[(i, j)] = u + conserved[(i, j)] * uncovered;
+                    scalar_mass[(i, j)] = s + scalar_mass[(i, j)] * uncovered;
+                }
+            }
+            solution.insert(index, BlockState{conserved: conserved.to_shared(), scalar_mass: scalar_mass.to_shared()});
+        }
+
+        Self {
+            time: self.time,
+            iteration: self.iteration,
+            solution,
+            excised: self.excised,
+            zone_updates: self.zone_updates,
+            tracers: self.tracers.clone(),
+            time_step_limit: None,
+            probe_samples: Vec::new(),
+        }
+    }
+
+    /**
+     * Return a map of the geometry of each block in this state, at the time
+     * of this state. Unlike [`Mesh::grid_blocks_geometry`], this includes
+     * any blocks added beyond the outer excision surface when it tracks the
+     * shock.
+     */
+    pub fn geometry(&self, mesh: &Mesh) -> HashMap<BlockIndex, GridGeometry> {
+        self.solution.keys().map(|&index| (index, mesh.subgrid_geometry(index, self.time))).collect()
     }
 
     /**
@@ -121,55 +382,257 @@ impl<C: Conserved> State<C> {
         self.solution.values().map(|solution| solution.conserved.len()).sum()
     }
 
+    /**
+     * Return the blocks of this state, ordered by block index. Floating
+     * point sums over the blocks are taken in this order, so that they do
+     * not depend on the iteration order of the block map, which differs
+     * from run to run.
+     */
+    pub fn sorted_blocks(&self) -> Vec<(&BlockIndex, &BlockState<C>)> {
+        let mut blocks: Vec<_> = self.solution.iter().collect();
+        blocks.sort_unstable_by_key(|(index, _)| **index);
+        blocks
+    }
+
     /**
      * Return the indexes of "ghost blocks" just inside and outside the mesh
-     * radial extent.
+     * radial extent, for each column of blocks at a given polar index.
      */
-    pub fn inner_outer_boundary_indexes(&self) -> (BlockIndex, BlockIndex) {
+    pub fn inner_outer_boundary_indexes(&self) -> Vec<(BlockIndex, BlockIndex)> {
         self.min_max_block_indexes_offset_by(1)
     }
 
     /**
-     * Return the indexes of the innermost and outermost block indexes.
+     * Return the indexes of the innermost and outermost blocks, for each
+     * column of blocks at a given polar index.
      */
-    pub fn inner_outer_block_indexes(&self) -> (BlockIndex, BlockIndex) {
+    pub fn inner_outer_block_indexes(&self) -> Vec<(BlockIndex, BlockIndex)> {
         self.min_max_block_indexes_offset_by(0)
     }
 
     /**
      * Return the time step size, computed from the mesh, the hydrodynamics
-     * state, and internal parameters such as the CFL number.
+     * state, and internal parameters such as the CFL number. The geometry
+     * map must contain the geometry of every block in this state, at the
+     * current time.
+     */
+    pub fn time_step<H>(&self, hydro: &H, mesh: &Mesh, geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<f64, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>
+    {
+        Ok(self.time_step_and_primitive(hydro, mesh, geometry)?.0.dt)
+    }
+
+    /**
+     * Return the time step size as in [`State::time_step`], and what limited
+     * it, along with the primitive arrays of each block which were computed
+     * to find it. The map is empty if the hydrodynamics has a global signal
+     * speed, in which case no primitives are computed.
      */
-    pub fn time_step<H>(&self, hydro: &H, mesh: &Mesh) -> Result<f64, HydroError>
+    pub fn time_step_and_primitive<H>(
+        &self,
+        hydro: &H,
+        mesh: &Mesh,
+        geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<(TimeStepLimit, HashMap<BlockIndex, ArcArray<H::Primitive, Ix2>>), HydroError>
     where
         H: Hydrodynamics<Conserved = C>
     {
         if let Some(max_signal_speed) = hydro.global_signal_speed() {
-            let (index, ..) = self.inner_outer_block_indexes();
-            Ok(hydro.cfl_number() * mesh.smallest_spacing(index) / max_signal_speed)
+            let (index, ..) = self.inner_outer_block_indexes()[0];
+            let dt = hydro.cfl_number() * mesh.smallest_spacing(index, self.time) / max_signal_speed;
+            Ok((TimeStepLimit{dt, zone: None, max_signal_speed}, HashMap::new()))
         } else {
-            Ok(self.solution.iter().try_fold(f64::MAX, |dt, (index, state)| {
-                let geometry = mesh.subgrid(*index).geometry();
-                let block_dt = state
-                    .try_to_primitive(hydro, &geometry)?
-                    .iter()
-                    .zip(&geometry.cell_linear_dimension())
-                    .fold(dt, |dt, (p, dl)| dt.min(dl / hydro.max_signal_speed(*p))
-                );
-                Ok(dt.min(block_dt))
-            })? * hydro.cfl_number())
+            let mut limit = TimeStepLimit{dt: f64::MAX, zone: None, max_signal_speed: 0.0};
+            let mut primitive = HashMap::new();
+
+            for (index, state) in self.sorted_blocks() {
+                let geometry = &geometry[index];
+                let p = state.try_to_primitive(hydro, geometry)?;
+                let dl = geometry.cell_linear_dimension_merged(mesh.polar_merge_zones);
+
+                for (zone, p) in p.indexed_iter() {
+                    let a = hydro.max_signal_speed(*p);
+
+                    if dl[zone] / a < limit.dt {
+                        limit.dt = dl[zone] / a;
+                        limit.zone = Some((*index, zone));
+                    }
+                    limit.max_signal_speed = limit.max_signal_speed.max(a);
+                }
+                primitive.insert(*index, p.into_shared());
+            }
+            limit.dt *= hydro.cfl_number();
+            Ok((limit, primitive))
+        }
+    }
+
+    /**
+     * Check that the given time step does not exceed the local signal
+     * crossing time (the zone's smallest linear dimension divided by its
+     * maximum signal speed) in any zone. If it does, an error is returned
+     * at the zone where the ratio of the time step to the crossing time is
+     * largest. This can catch e.g. a light-speed time step assumption which
+     * is violated by the hydrodynamics in use. The geometry map is as for
+     * [`State::time_step`].
+     */
+    pub fn check_cfl<H>(
+        &self,
+        hydro: &H,
+        mesh: &Mesh,
+        geometry: &HashMap<BlockIndex, GridGeometry>,
+        dt: f64) -> Result<(), HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>
+    {
+        let mut worst: Option<(f64, (f64, f64))> = None;
+
+        for (index, state) in self.sorted_blocks() {
+            let geometry = &geometry[index];
+            let primitive = state.try_to_primitive(hydro, geometry)?;
+            let dl = geometry.cell_linear_dimension_merged(mesh.polar_merge_zones);
+
+            for (zone, p) in primitive.indexed_iter() {
+                let ratio = dt * hydro.max_signal_speed(*p) / dl[zone];
+                if ratio > 1.0 && worst.map_or(true, |(r, _)| ratio > r) {
+                    worst = Some((ratio, geometry.cell_centers[zone]));
+                }
+            }
+        }
+        match worst {
+            Some((ratio, position)) => Err(HydroErrorType::CflViolation(ratio).at_position(position)),
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * Return the largest radial coordinate of a zone flagged as shocked by
+     * [`products::shock_flag`], or `None` if no zones are shocked. The
+     * geometry map is as for [`State::time_step`].
+     */
+    pub fn outermost_shock_radius<H>(
+        &self,
+        hydro: &H,
+        geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<Option<f64>, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>
+    {
+        let mut radius: Option<f64> = None;
+
+        for (index, state) in &self.solution {
+            let geometry = &geometry[index];
+            let primitive = state.try_to_primitive(hydro, geometry)?.map(|p| hydro.any(p));
+            let shocked = products::shock_flag(primitive.view(), geometry);
+
+            for (&flag, &(r, _)) in shocked.iter().zip(geometry.cell_centers.iter()) {
+                if flag {
+                    radius = Some(radius.map_or(r, |r0| r0.max(r)));
+                }
+            }
+        }
+        Ok(radius)
+    }
+
+    /**
+     * Return the largest radial coordinate of a zone flagged as shocked, in
+     * each polar angle bin, as a list of (polar angle, radius) pairs. The
+     * bins are the polar zones of the coarsest blocks, as for the isotropic
+     * energy reduction, and the radius is NaN in bins with no shocked zones.
+     */
+    pub fn shock_radius_profile<H>(
+        &self,
+        hydro: &H,
+        geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<Vec<(f64, f64)>, HydroError>
+    where
+        H: Hydrodynamics<Conserved = C>
+    {
+        let nq_coarse = self.solution.values().map(|block| block.conserved.dim().1).min().unwrap_or(1);
+        let mut bins = std::collections::BTreeMap::new();
+
+        for (index, state) in &self.solution {
+            let geometry = &geometry[index];
+            let qv = &geometry.polar_vertices;
+            let primitive = state.try_to_primitive(hydro, geometry)?.map(|p| hydro.any(p));
+            let shocked = products::shock_flag(primitive.view(), geometry);
+            let k = shocked.dim().1 / nq_coarse;
+
+            for ((i, j), &flag) in shocked.indexed_iter() {
+                let jc = j / k;
+                let (_, radius) = bins
+                    .entry((index.1, jc))
+                    .or_insert_with(|| (0.5 * (qv[jc * k] + qv[(jc + 1) * k]), f64::NAN));
+
+                if flag {
+                    *radius = f64::max(*radius, geometry.cell_centers[(i, j)].0);
+                }
+            }
         }
+        Ok(bins.into_values().collect())
     }
 
-    fn min_max_block_indexes_offset_by(&self, delta: i32) -> (BlockIndex, BlockIndex) {
-        let mut min = (i32::MAX, 0);
-        let mut max = (i32::MIN, 0);
+    fn min_max_block_indexes_offset_by(&self, delta: i32) -> Vec<(BlockIndex, BlockIndex)> {
+        let mut min = i32::MAX;
+        let mut max = i32::MIN;
+        let mut columns: Vec<_> = self.solution.keys().map(|i| i.1).collect();
         for i in self.solution.keys() {
-            min = (min.0.min(i.0 - delta), min.1);
-            max = (max.0.max(i.0 + delta), max.1);
+            min = min.min(i.0 - delta);
+            max = max.max(i.0 + delta);
         }
-        (min, max)
+        columns.sort_unstable();
+        columns.dedup();
+        columns.into_iter().map(|j| ((min, j), (max, j))).collect()
+    }
+}
+
+
+
+
+// ============================================================================
+impl TimeStepLimit {
+
+    /**
+     * Append a line with the given time and this time step limit to the
+     * time step history file, writing a header line first if the file is new
+     * or empty. The block and zone indexes are written as -1 if the time
+     * step was set by a global signal speed.
+     */
+    pub fn append(&self, filename: &str, time: f64) -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(filename)?;
+
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "time dt max_signal_speed block_i block_j zone_i zone_j")?;
+        }
+        let (block, zone) = match self.zone {
+            Some(((bi, bj), (zi, zj))) => ((bi as i64, bj as i64), (zi as i64, zj as i64)),
+            None => ((-1, -1), (-1, -1)),
+        };
+        writeln!(file, "{:.12e} {:.12e} {:.12e} {} {} {} {}", time, self.dt, self.max_signal_speed, block.0, block.1, zone.0, zone.1)
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * Append a line with the given time and the shock radius in each polar
+ * angle bin (see [`State::shock_radius_profile`]) to the shock radius
+ * history file, writing a header line first if the file is new or empty.
+ * The header names each column by the polar angle of its bin.
+ */
+pub fn append_shock_radius_profile(filename: &str, time: f64, profile: &[(f64, f64)]) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(filename)?;
+
+    if file.metadata()?.len() == 0 {
+        let header: Vec<_> = std::iter::once("time".to_string())
+            .chain(profile.iter().map(|(q, _)| format!("r({:.6})", q)))
+            .collect();
+        writeln!(file, "{}", header.join(" "))?;
     }
+    let line: Vec<_> = std::iter::once(time)
+        .chain(profile.iter().map(|(_, r)| *r))
+        .map(|x| format!("{:.12e}", x))
+        .collect();
+    writeln!(file, "{}", line.join(" "))
 }
 
 
@@ -207,6 +670,11 @@ impl<C: Conserved> runge_kutta::WeightedAverage for State<C> {
             time:      self.time      * (-bf + 1.) + s0.time      * bf,
             iteration: self.iteration * (-br + 1 ) + s0.iteration * br,
             solution: s_avg.into_iter().collect(),
+            excised: self.excised,
+            zone_updates: self.zone_updates,
+            tracers: self.tracers,
+            time_step_limit: self.time_step_limit,
+            probe_samples: self.probe_samples,
         }
     }
 }
@@ -240,6 +708,11 @@ impl<C: Conserved> runge_kutta::WeightedAverageAsync for State<C> {
             time:      self.time      * (-bf + 1.) + s0.time      * bf,
             iteration: self.iteration * (-br + 1 ) + s0.iteration * br,
             solution: join_all(s_avg).await.into_iter().collect(),
+            excised: self.excised,
+            zone_updates: self.zone_updates,
+            tracers: self.tracers,
+            time_step_limit: self.time_step_limit,
+            probe_samples: self.probe_samples,
         }
     }
 }
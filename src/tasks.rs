@@ -45,6 +45,19 @@ pub struct Tasks {
 
     /// Summarize the simulation performance
     pub report_progress: RecurringTask,
+
+    /// Append the global totals to the time series file
+    #[serde(default)]
+    pub time_series: RecurringTask,
+
+    /// Append the shock radius versus polar angle to its history file
+    #[serde(default)]
+    pub shock_radius: RecurringTask,
+
+    /// The state's running total of zone updates when the loop message was
+    /// last printed
+    #[serde(skip)]
+    pub zone_updates_at_last_message: u64,
 }
 
 
@@ -83,6 +96,20 @@ impl RecurringTask {
 
 
 
+impl Default for RecurringTask {
+
+    /**
+     * A fresh task which is first due at t = 0.0, for tasks missing from
+     * checkpoints written by older versions of the code.
+     */
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+
+
+
 // ============================================================================
 impl Tasks {
     pub fn new(start_time: f64) -> Self {
@@ -91,6 +118,9 @@ impl Tasks {
             write_products: RecurringTask::new(start_time),
             iteration_message: RecurringTask::new(start_time),
             report_progress: RecurringTask::new(start_time),
+            time_series: RecurringTask::new(start_time),
+            shock_radius: RecurringTask::new(start_time),
+            zone_updates_at_last_message: 0,
         }
     }
 }
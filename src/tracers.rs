@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+use crate::mesh::{BlockIndex, BlockLocator, CoordinateSystem, GridGeometry};
+use crate::physics::HydroError;
+use crate::state::State;
+use crate::traits::{Conserved, Hydrodynamics};
+
+
+
+
+/**
+ * The state of the fluid at the position of a tracer particle, at one time
+ * in its history
+ */
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct TracerSample {
+
+    /// The simulation time
+    pub time: f64,
+
+    /// Radial coordinate (the cylindrical radius on a cylindrical mesh)
+    pub radius: f64,
+
+    /// Polar angle (z on a cylindrical mesh)
+    pub theta: f64,
+
+    /// Mass density (comoving for relativistic hydro)
+    pub mass_density: f64,
+
+    /// Gas pressure
+    pub gas_pressure: f64,
+
+    /// Passive scalar concentration
+    pub scalar: f64,
+}
+
+
+
+
+/**
+ * A Lagrangian tracer particle, which is carried with the fluid velocity
+ * and records the thermodynamic history of the fluid element it follows
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tracer {
+
+    /// Index of the tracer in the list of seeded tracers
+    pub id: usize,
+
+    /// Lab-frame mass represented by the tracer (g)
+    pub mass: f64,
+
+    /// Current (r, theta) position
+    pub position: (f64, f64),
+
+    /// False once the tracer has left the mesh, e.g. through the inner
+    /// excision surface. Such tracers are no longer moved or sampled.
+    pub active: bool,
+
+    /// Samples of the fluid state at the tracer, in order of time
+    pub history: Vec<TracerSample>,
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the primitive state and scalar concentration of the zone
+ * containing the given position, or None if the position is not on the
+ * mesh. Fails if the zone could not be converted to primitive.
+ */
+fn zone_state<H, C>(
+    state: &State<C>,
+    hydro: &H,
+    geometry: &HashMap<BlockIndex, GridGeometry>,
+    locator: &BlockLocator,
+    position: (f64, f64)) -> Result<Option<(H::Primitive, f64)>, HydroError>
+where
+    H: Hydrodynamics<Conserved = C>,
+    C: Conserved,
+{
+    let (index, zone) = match locator.locate_zone(position) {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let block = &state.solution[&index];
+    let geometry = &geometry[&index];
+    let u = block.conserved[zone];
+    let p = hydro
+        .try_to_primitive(u / geometry.cell_volumes[zone])
+        .map_err(|e| e.at_position(geometry.cell_centers[zone]))?;
+
+    Ok(Some((p, block.scalar_mass[zone] / u.lab_frame_mass())))
+}
+
+
+
+
+// ============================================================================
+/**
+ * Return the given number of tracers, each representing an equal share of
+ * the total mass of the state. Tracers are placed at zone centers, at equal
+ * intervals of the mass accumulated over the zones (in block index order),
+ * so the number of tracers in a region is proportional to its mass. The
+ * state is normally the initial state generated from the model.
+ */
+pub fn seed<C: Conserved>(state: &State<C>, geometry: &HashMap<BlockIndex, GridGeometry>, count: usize) -> Vec<Tracer> {
+    let zones: Vec<_> = state
+        .sorted_blocks()
+        .into_iter()
+        .flat_map(|(index, block)| block.conserved
+            .iter()
+            .map(|u| u.lab_frame_mass())
+            .zip(geometry[index].cell_centers.iter().copied())
+            .collect::<Vec<_>>())
+        .collect();
+
+    let total: f64 = zones.iter().map(|(m, _)| m).sum();
+    let mass = total / count as f64;
+    let mut tracers = Vec::with_capacity(count);
+    let mut cumulative = 0.0;
+
+    for (m, position) in zones {
+        cumulative += m;
+
+        while tracers.len() < count && (tracers.len() as f64 + 0.5) * mass <= cumulative {
+            tracers.push(Tracer{id: tracers.len(), mass, position, active: true, history: Vec::new()});
+        }
+    }
+    tracers
+}
+
+/**
+ * Move each active tracer by the velocity of the zone containing it, over
+ * the time step dt. The geometry map is that of the state. On a spherical
+ * mesh, the polar angle is kept within [0, pi]. Tracers which are not on
+ * the mesh are deactivated. Fails if a zone containing a tracer could not be
+ * converted to primitive.
+ */
+pub fn advect<H, C>(
+    tracers: &mut [Tracer],
+    state: &State<C>,
+    hydro: &H,
+    geometry: &HashMap<BlockIndex, GridGeometry>,
+    dt: f64) -> Result<(), HydroError>
+where
+    H: Hydrodynamics<Conserved = C>,
+    C: Conserved,
+{
+    let coordinates = geometry.values().next().map(|g| g.coordinates);
+    let locator = BlockLocator::new(geometry);
+
+    for tracer in tracers.iter_mut().filter(|tracer| tracer.active) {
+        let p = match zone_state(state, hydro, geometry, &locator, tracer.position)? {
+            Some((p, _)) => p,
+            None => {
+                tracer.active = false;
+                continue
+            }
+        };
+        let (r, q) = tracer.position;
+        let vr = hydro.radial_velocity(&p);
+        let vq = hydro.polar_velocity(&p);
+
+        tracer.position = match coordinates {
+            Some(CoordinateSystem::Cylindrical) => ((r + vr * dt).max(0.0), q + vq * dt),
+            _ => (r + vr * dt, (q + vq / r * dt).clamp(0.0, PI)),
+        };
+    }
+    Ok(())
+}
+
+/**
+ * Append a sample of the fluid state to the history of each active tracer,
+ * taken from the zone containing it. The geometry map is that of the state.
+ * Tracers which are not on the mesh are deactivated. Fails if a zone
+ * containing a tracer could not be converted to primitive.
+ */
+pub fn record<H, C>(
+    tracers: &mut [Tracer],
+    state: &State<C>,
+    hydro: &H,
+    geometry: &HashMap<BlockIndex, GridGeometry>) -> Result<(), HydroError>
+where
+    H: Hydrodynamics<Conserved = C>,
+    C: Conserved,
+{
+    let locator = BlockLocator::new(geometry);
+
+    for tracer in tracers.iter_mut().filter(|tracer| tracer.active) {
+        match zone_state(state, hydro, geometry, &locator, tracer.position)? {
+            Some((p, scalar)) => {
+                let p = hydro.any(&p);
+                tracer.history.push(TracerSample{
+                    time: state.time,
+                    radius: tracer.position.0,
+                    theta: tracer.position.1,
+                    mass_density: p.mass_density,
+                    gas_pressure: p.gas_pressure,
+                    scalar,
+                });
+            }
+            None => tracer.active = false,
+        }
+    }
+    Ok(())
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::Mesh;
+    use crate::physics::{AnyPrimitive, NewtonianHydro};
+    use crate::traits::InitialModel;
+
+    #[derive(Clone)]
+    struct UniformFlow;
+
+    impl InitialModel for UniformFlow {
+        fn validate(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn primitive_at(&self, _coordinate: (f64, f64), _time: f64) -> AnyPrimitive {
+            AnyPrimitive{velocity_r: 1e-3, velocity_q: 0.0, mass_density: 1.0, gas_pressure: 1.0}
+        }
+        fn scalar_at(&self, _coordinate: (f64, f64), _time: f64) -> f64 {
+            0.5
+        }
+    }
+
+    #[test]
+    fn tracers_are_carried_by_a_uniform_flow() {
+        let mesh: Mesh = serde_yaml::from_str("
+            reference_radius: 1.0
+            inner_radius: 1.0
+            outer_radius: 10.0
+            num_radial_zones: 32
+            num_polar_zones: 16
+            block_size: 8
+        ").unwrap();
+        let hydro: NewtonianHydro = serde_yaml::from_str("
+            gamma_law_index: 1.333
+            plm_theta: 1.5
+            cfl_number: 0.3
+            runge_kutta_order: RK2
+        ").unwrap();
+        let geometry = mesh.grid_blocks_geometry(0.0);
+        let state = State::from_model(&UniformFlow, &hydro, &geometry, 0.0, 1);
+        let total_mass: f64 = state.solution.values().flat_map(|block| block.conserved.iter().map(|u| u.lab_frame_mass())).sum();
+
+        let mut tracers = seed(&state, &geometry, 100);
+        let initial: Vec<_> = tracers.iter().map(|tracer| tracer.position).collect();
+
+        assert_eq!(tracers.len(), 100);
+        assert!(tracers.iter().all(|tracer| (tracer.mass * 100.0 - total_mass).abs() < 1e-12 * total_mass));
+
+        advect(&mut tracers, &state, &hydro, &geometry, 10.0).unwrap();
+        record(&mut tracers, &state, &hydro, &geometry).unwrap();
+
+        for (tracer, (r, q)) in tracers.iter().zip(initial) {
+            assert!(tracer.active);
+            assert!((tracer.position.0 - (r + 1e-2)).abs() < 1e-12);
+            assert!((tracer.position.1 - q).abs() < 1e-12);
+            assert_eq!(tracer.history.len(), 1);
+            assert!((tracer.history[0].mass_density - 1.0).abs() < 1e-12);
+            assert!((tracer.history[0].scalar - 0.5).abs() < 1e-12);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::ops::{Add, Sub, Mul, Div};
 use serde::Serialize;
 use godunov_core::runge_kutta::RungeKuttaOrder;
-use crate::physics::{AnyPrimitive, Direction, HydroErrorType};
+use crate::mesh::CoordinateSystem;
+use crate::physics::{AnyConserved, AnyPrimitive, Direction, HydroErrorType};
+use crate::reductions::Reductions;
 
 
 
@@ -20,6 +22,9 @@ pub trait Arithmetic: Add<Output=Self> + Sub<Output=Self> + Mul<f64, Output=Self
  */
 pub trait Conserved: 'static + Clone + Copy + Send + Sync + Arithmetic + Default {
     fn lab_frame_mass(&self) -> f64;
+    fn radial_momentum(&self) -> f64;
+    fn polar_momentum(&self) -> f64;
+    fn energy(&self) -> f64;
 }
 
 
@@ -97,6 +102,17 @@ pub trait Hydrodynamics: 'static + Clone + Send {
      */
     fn max_signal_speed(&self, p: Self::Primitive) -> f64;
 
+    /**
+     * Return the radial velocity (cm/s) of a primitive state.
+     */
+    fn radial_velocity(&self, p: &Self::Primitive) -> f64;
+
+    /**
+     * Return the polar velocity (cm/s) of a primitive state. This is the
+     * velocity along the theta direction, or along z on a cylindrical mesh.
+     */
+    fn polar_velocity(&self, p: &Self::Primitive) -> f64;
+
     /**
      * Return on optional maximum speed (probably the speed of light) to be used
      * instead of computing one from the solution state.
@@ -115,6 +131,13 @@ pub trait Hydrodynamics: 'static + Clone + Send {
      */
     fn any(&self, p: &Self::Primitive) -> AnyPrimitive;
 
+    /**
+     * Convert from an any-conserved source rate (cgs units) to the conserved
+     * type specific to this hydrodynamics system, in the units of the
+     * conserved quantities per unit time.
+     */
+    fn interpret_conserved(&self, any: &AnyConserved) -> Self::Conserved;
+
     /**
      * Return the Godunov flux of the conserved quantities and the passive
      * scalar, given reconstructued values of the primitives (`pl`, `pr`) and
@@ -124,10 +147,22 @@ pub trait Hydrodynamics: 'static + Clone + Send {
     fn intercell_flux(&self, pl: Self::Primitive, pr: Self::Primitive, sl: f64, sr: f64, direction: Direction) -> (Self::Conserved, f64);
 
     /**
-     * Return the geometrical source terms (conserved quantity per unit volume)
-     * for the given primitive state and r-theta coordinate.
+     * Return the volume-integrated geometrical source terms for a zone with
+     * the given primitive state, coordinate, and volume, in the given
+     * coordinate system.
+     * `face_area_differences` are the differences in area between the outer
+     * and inner radial faces, and the upper and lower polar faces, of the
+     * zone. The pressure terms are written as the pressure times these
+     * differences, so that they exactly cancel the pressure part of the flux
+     * differences when the pressure is uniform.
      */
-    fn geometrical_source_terms(&self, p: Self::Primitive, coordinate: (f64, f64)) -> Self::Conserved;
+    fn geometrical_source_terms(
+        &self,
+        p: Self::Primitive,
+        coordinate: (f64, f64),
+        volume: f64,
+        face_area_differences: (f64, f64),
+        coordinates: CoordinateSystem) -> Self::Conserved;
 
     /**
      * Return the CFL number to be used
@@ -142,7 +177,7 @@ pub trait Hydrodynamics: 'static + Clone + Send {
  * Implemented by types that can generate primitive fields to be used as an
  * initial or boundary value
  */
-pub trait InitialModel: Clone {
+pub trait InitialModel: 'static + Clone + Send {
 
     /**
      * Return an error if this model was not configured to yield acceptable
@@ -161,4 +196,45 @@ pub trait InitialModel: Clone {
       * Return the scalar concentration at the given r-theta coordinate.
       */
      fn scalar_at(&self, coordinate: (f64, f64), time: f64) -> f64;
+
+     /**
+      * Return the rate of scalar mass injection (scalar mass per unit volume
+      * per unit time) at the given r-theta coordinate. This is applied as a
+      * source term, in addition to the scalar advected in from the boundary
+      * blocks. Models which do not inject a scalar can use the default
+      * implementation, which returns zero.
+      */
+     fn scalar_injection_at(&self, _coordinate: (f64, f64), _time: f64) -> f64 {
+         0.0
+     }
+
+     /**
+      * Return the rates of mass, momentum, and energy deposition per unit
+      * volume at the given r-theta coordinate, e.g. from an engine. This is
+      * applied as a source term. Models without sources can use the default
+      * implementation, which returns zero.
+      */
+     fn conserved_source_at(&self, _coordinate: (f64, f64), _time: f64) -> AnyConserved {
+         AnyConserved::default()
+     }
+
+     /**
+      * Update the model from quantities measured on the solution, e.g. to
+      * regulate an engine by the accreted mass. This is called by the driver
+      * before each fold of iterations. Models without feedback can use the
+      * default implementation, which does nothing.
+      */
+     fn regulate(&mut self, _measured: &Reductions) {
+     }
+
+     /**
+      * Return true if this model uses the measured quantities passed to
+      * [`InitialModel::regulate`]. The driver only measures them, which
+      * takes a pass over the whole mesh, if this returns true. Models
+      * without feedback can use the default implementation, which returns
+      * false.
+      */
+     fn needs_regulation(&self) -> bool {
+         false
+     }
 }